@@ -212,7 +212,7 @@ async def test_copy(conns):
             count = await conn.fetch("SELECT COUNT(*) FROM sharded")
             assert len(count) == 1
             assert count[0][0] == records
-            await conn.execute("DELETE FROM sharded")
+            await conn.execute("TRUNCATE TABLE sharded")
 
 
 @pytest.mark.asyncio
@@ -40,7 +40,7 @@ async fn test_insert() {
                 .unwrap();
             assert_eq!(results.len(), 1);
 
-            conn.execute("DELETE FROM rust_test_insert.sharded", &[])
+            conn.execute("TRUNCATE TABLE rust_test_insert.sharded", &[])
                 .await
                 .unwrap();
         }
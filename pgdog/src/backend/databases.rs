@@ -11,7 +11,7 @@ use parking_lot::{Mutex, RawMutex};
 use tracing::{info, warn};
 
 use crate::config::PoolerMode;
-use crate::frontend::router::sharding::Mapping;
+use crate::frontend::router::{parser::QueryRoles, sharding::Mapping};
 use crate::frontend::PreparedStatements;
 use crate::{
     backend::pool::PoolConfig,
@@ -347,8 +347,20 @@ pub(crate) fn new_pool(
                     }
                 })
                 .collect::<Vec<_>>();
+            let analytics = user_databases
+                .iter()
+                .filter(|d| d.role == Role::Analytics)
+                .map(|replica| PoolConfig {
+                    address: Address::new(replica, user),
+                    config: Config::new(general, replica, user),
+                })
+                .collect::<Vec<_>>();
 
-            shard_configs.push(ClusterShardConfig { primary, replicas });
+            shard_configs.push(ClusterShardConfig {
+                primary,
+                replicas,
+                analytics,
+            });
         }
 
         let mut sharded_tables = sharded_tables
@@ -406,6 +418,11 @@ pub(crate) fn new_pool(
             sharded_tables,
             mirror_of,
             config.multi_tenant(),
+            QueryRoles::new(config.query_roles.iter().filter(|role| {
+                role.database
+                    .as_ref()
+                    .is_none_or(|database| database == &user.database)
+            })),
         );
 
         Some((
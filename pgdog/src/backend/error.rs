@@ -84,11 +84,33 @@ pub enum Error {
     #[error("decoder is missing required data to decode row")]
     DecoderRowError,
 
+    #[error("shards returned different columns for the same query")]
+    RowDescriptionMismatch,
+
     #[error("read timeout")]
     ReadTimeout,
 
     #[error("router error: {0}")]
     Router(String),
+
+    #[error("cursor is larger than cursor_buffer_limit ({0} bytes)")]
+    CursorTooLarge(usize),
+
+    #[error(
+        "rows deduplicated by UNION or DISTINCT, or matched by INTERSECT and EXCEPT, are larger than distinct_buffer_limit ({0} bytes)"
+    )]
+    DistinctTooLarge(usize),
+
+    #[error(
+        "input values of ordered-set aggregates are larger than ordered_set_buffer_limit ({0} bytes)"
+    )]
+    OrderedSetTooLarge(usize),
+
+    #[error("percentile_cont across shards only supports numbers, not type {0}")]
+    PercentileContType(i32),
+
+    #[error("server {0} can't take part in the snapshot: {1}")]
+    SnapshotUnavailable(String, Box<Error>),
 }
 
 impl Error {
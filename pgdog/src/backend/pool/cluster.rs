@@ -12,12 +12,17 @@ use crate::{
         Schema, ShardedTables,
     },
     config::{
-        General, MultiTenant, PoolerMode, ReadWriteSplit, ReadWriteStrategy, ShardedTable, User,
+        General, MultiKeyPolicy, MultiTenant, PoolerMode, ReadWriteSplit, ReadWriteStrategy,
+        ShardedTable, User,
     },
+    frontend::router::parser::{route, QueryRoles},
     net::messages::BackendKeyData,
 };
 
-use super::{Address, Config, Error, Guard, Request, Shard};
+use super::{
+    shard_sql::{shard_sql, ShardSql},
+    Address, Config, Error, Guard, Request, Shard,
+};
 use crate::config::LoadBalancingStrategy;
 
 #[derive(Clone, Debug)]
@@ -45,6 +50,14 @@ pub struct Cluster {
     multi_tenant: Option<MultiTenant>,
     rw_strategy: ReadWriteStrategy,
     rw_split: ReadWriteSplit,
+    require_order_by: bool,
+    max_fanout_shards: Option<usize>,
+    shard_range_filters: bool,
+    sticky_replicas: bool,
+    query_parser_fast_path: bool,
+    multi_key_policy: MultiKeyPolicy,
+    query_roles: QueryRoles,
+    shard_sql: Option<Arc<dyn ShardSql>>,
 }
 
 /// Sharding configuration from the cluster.
@@ -54,17 +67,30 @@ pub struct ShardingSchema {
     pub shards: usize,
     /// Sharded tables.
     pub tables: ShardedTables,
+    /// Routing of filters on sharding keys with no shard in common.
+    pub multi_key_policy: MultiKeyPolicy,
 }
 
 impl ShardingSchema {
     pub fn tables(&self) -> &ShardedTables {
         &self.tables
     }
+
+    /// Only the sharded tables visible to a query.
+    /// See [`ShardedTables::resolve`].
+    pub fn resolve(&self, qualified: &[(&str, &str)], search_path: &[&str]) -> Self {
+        Self {
+            shards: self.shards,
+            tables: self.tables.resolve(qualified, search_path),
+            multi_key_policy: self.multi_key_policy,
+        }
+    }
 }
 
 pub struct ClusterShardConfig {
     pub primary: Option<PoolConfig>,
     pub replicas: Vec<PoolConfig>,
+    pub analytics: Vec<PoolConfig>,
 }
 
 /// Cluster creation config.
@@ -81,6 +107,14 @@ pub struct ClusterConfig<'a> {
     pub multi_tenant: &'a Option<MultiTenant>,
     pub rw_strategy: ReadWriteStrategy,
     pub rw_split: ReadWriteSplit,
+    pub require_order_by: bool,
+    pub max_fanout_shards: Option<usize>,
+    pub shard_range_filters: bool,
+    pub sticky_replicas: bool,
+    pub query_parser_fast_path: bool,
+    pub multi_key_policy: MultiKeyPolicy,
+    pub query_roles: QueryRoles,
+    pub shard_sql: Option<Arc<dyn ShardSql>>,
 }
 
 impl<'a> ClusterConfig<'a> {
@@ -91,6 +125,7 @@ impl<'a> ClusterConfig<'a> {
         sharded_tables: ShardedTables,
         mirror_of: Option<&'a str>,
         multi_tenant: &'a Option<MultiTenant>,
+        query_roles: QueryRoles,
     ) -> Self {
         Self {
             name: &user.database,
@@ -105,6 +140,14 @@ impl<'a> ClusterConfig<'a> {
             multi_tenant,
             rw_strategy: general.read_write_strategy,
             rw_split: general.read_write_split,
+            require_order_by: general.cross_shard_require_order_by,
+            max_fanout_shards: general.max_fanout_shards,
+            shard_range_filters: general.shard_range_filters,
+            sticky_replicas: general.sticky_replicas,
+            query_parser_fast_path: general.query_parser_fast_path,
+            multi_key_policy: general.multi_key_policy,
+            query_roles,
+            shard_sql: shard_sql(),
         }
     }
 }
@@ -125,12 +168,28 @@ impl Cluster {
             multi_tenant,
             rw_strategy,
             rw_split,
+            require_order_by,
+            max_fanout_shards,
+            shard_range_filters,
+            sticky_replicas,
+            query_parser_fast_path,
+            multi_key_policy,
+            query_roles,
+            shard_sql,
         } = config;
 
         Self {
             shards: shards
                 .iter()
-                .map(|config| Shard::new(&config.primary, &config.replicas, lb_strategy, rw_split))
+                .map(|config| {
+                    Shard::new(
+                        &config.primary,
+                        &config.replicas,
+                        &config.analytics,
+                        lb_strategy,
+                        rw_split,
+                    )
+                })
                 .collect(),
             name: name.to_owned(),
             password: password.to_owned(),
@@ -143,6 +202,14 @@ impl Cluster {
             multi_tenant: multi_tenant.clone(),
             rw_strategy,
             rw_split,
+            require_order_by,
+            max_fanout_shards,
+            shard_range_filters,
+            sticky_replicas,
+            query_parser_fast_path,
+            multi_key_policy,
+            query_roles,
+            shard_sql,
         }
     }
 
@@ -158,6 +225,28 @@ impl Cluster {
         shard.replica(request).await
     }
 
+    /// Get the shards a query is routed to, along with their numbers.
+    ///
+    /// Connections are taken from the shard's primary or its replicas,
+    /// depending on whether the query is a read or a write.
+    pub fn shards_for(&self, shard: &route::Shard) -> Result<Vec<(usize, &Shard)>, Error> {
+        match shard {
+            route::Shard::Direct(number) => {
+                let shard = self.shards.get(*number).ok_or(Error::NoShard(*number))?;
+                Ok(vec![(*number, shard)])
+            }
+
+            route::Shard::Multi(numbers) => Ok(self
+                .shards
+                .iter()
+                .enumerate()
+                .filter(|(number, _)| numbers.contains(number))
+                .collect()),
+
+            route::Shard::All => Ok(self.shards.iter().enumerate().collect()),
+        }
+    }
+
     /// The two clusters have the same databases.
     pub(crate) fn can_move_conns_to(&self, other: &Cluster) -> bool {
         self.shards.len() == other.shards.len()
@@ -193,6 +282,14 @@ impl Cluster {
             multi_tenant: self.multi_tenant.clone(),
             rw_strategy: self.rw_strategy,
             rw_split: self.rw_split,
+            require_order_by: self.require_order_by,
+            max_fanout_shards: self.max_fanout_shards,
+            shard_range_filters: self.shard_range_filters,
+            sticky_replicas: self.sticky_replicas,
+            query_parser_fast_path: self.query_parser_fast_path,
+            multi_key_policy: self.multi_key_policy,
+            query_roles: self.query_roles.clone(),
+            shard_sql: self.shard_sql.clone(),
         }
     }
 
@@ -290,6 +387,7 @@ impl Cluster {
         ShardingSchema {
             shards: self.shards.len(),
             tables: self.sharded_tables.clone(),
+            multi_key_policy: self.multi_key_policy,
         }
     }
 
@@ -320,6 +418,41 @@ impl Cluster {
         &self.rw_strategy
     }
 
+    /// Cross-shard queries returning rows must have an `ORDER BY`.
+    pub fn require_order_by(&self) -> bool {
+        self.require_order_by
+    }
+
+    /// Maximum number of shards a query can go to.
+    pub fn max_fanout_shards(&self) -> Option<usize> {
+        self.max_fanout_shards
+    }
+
+    /// Add shard ranges to cross-shard queries.
+    pub fn shard_range_filters(&self) -> bool {
+        self.shard_range_filters
+    }
+
+    /// Use the same replicas for cross-shard reads in a session.
+    pub fn sticky_replicas(&self) -> bool {
+        self.sticky_replicas
+    }
+
+    /// Route point lookups without parsing them.
+    pub fn query_parser_fast_path(&self) -> bool {
+        self.query_parser_fast_path
+    }
+
+    /// Query patterns sent to the primary or the replicas.
+    pub fn query_roles(&self) -> &QueryRoles {
+        &self.query_roles
+    }
+
+    /// Transform of the SQL sent to each shard, if one is registered.
+    pub fn shard_sql(&self) -> Option<&Arc<dyn ShardSql>> {
+        self.shard_sql.as_ref()
+    }
+
     /// Launch the connection pools.
     pub(crate) fn launch(&self) {
         for shard in self.shards() {
@@ -348,27 +481,53 @@ impl Cluster {
 mod test {
     use crate::{
         backend::{Pool, Replicas, Shard, ShardedTables},
-        config::{DataType, Hasher, ReadWriteStrategy, ShardedTable},
+        config::{
+            DataType, Hasher, MultiKeyPolicy, ReadWriteStrategy, ShardedSequence, ShardedTable,
+        },
+        frontend::router::parser::{QueryRoles, Shard as RouteShard},
     };
 
-    use super::Cluster;
+    use super::{Cluster, Error};
 
     impl Cluster {
         pub fn new_test() -> Self {
             Cluster {
                 sharded_tables: ShardedTables::new(
-                    vec![ShardedTable {
-                        database: "pgdog".into(),
-                        name: Some("sharded".into()),
-                        column: "id".into(),
-                        primary: true,
-                        centroids: vec![],
-                        data_type: DataType::Bigint,
-                        centroids_path: None,
-                        centroid_probes: 1,
-                        hasher: Hasher::Postgres,
-                        mapping: None,
-                    }],
+                    vec![
+                        ShardedTable {
+                            database: "pgdog".into(),
+                            name: Some("sharded".into()),
+                            schema: None,
+                            column: "id".into(),
+                            primary: true,
+                            centroids: vec![],
+                            data_type: DataType::Bigint,
+                            centroids_path: None,
+                            centroid_probes: 1,
+                            hasher: Hasher::Postgres,
+                            mapping: None,
+                            coalesce_default: false,
+                            replicated: false,
+                            sequence: ShardedSequence::Global,
+                            array: false,
+                            composite: vec![],
+                            views: vec![],
+                        },
+                        ShardedTable {
+                            database: "pgdog".into(),
+                            name: Some("sharded_replicated".into()),
+                            replicated: true,
+                            ..Default::default()
+                        },
+                        ShardedTable {
+                            database: "pgdog".into(),
+                            name: Some("users".into()),
+                            schema: Some("myschema".into()),
+                            column: "tenant_id".into(),
+                            data_type: DataType::Bigint,
+                            ..Default::default()
+                        },
+                    ],
                     vec!["sharded_omni".into()],
                     false,
                 ),
@@ -404,5 +563,55 @@ mod test {
         pub fn set_read_write_strategy(&mut self, rw_strategy: ReadWriteStrategy) {
             self.rw_strategy = rw_strategy;
         }
+
+        pub fn set_require_order_by(&mut self, require_order_by: bool) {
+            self.require_order_by = require_order_by;
+        }
+
+        pub fn set_max_fanout_shards(&mut self, max_fanout_shards: Option<usize>) {
+            self.max_fanout_shards = max_fanout_shards;
+        }
+
+        pub fn set_query_roles(&mut self, query_roles: QueryRoles) {
+            self.query_roles = query_roles;
+        }
+
+        pub fn set_query_parser_fast_path(&mut self, query_parser_fast_path: bool) {
+            self.query_parser_fast_path = query_parser_fast_path;
+        }
+
+        pub fn set_multi_key_policy(&mut self, multi_key_policy: MultiKeyPolicy) {
+            self.multi_key_policy = multi_key_policy;
+        }
+
+        pub fn set_sharded_tables(&mut self, sharded_tables: ShardedTables) {
+            self.sharded_tables = sharded_tables;
+        }
+    }
+
+    #[test]
+    fn test_shards_for() {
+        let cluster = Cluster::new_test();
+        let numbers = |shard: RouteShard| {
+            cluster
+                .shards_for(&shard)
+                .unwrap()
+                .into_iter()
+                .map(|(number, shard)| {
+                    assert!(std::ptr::eq(shard, &cluster.shards()[number]));
+                    number
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(numbers(RouteShard::Direct(1)), vec![1]);
+        assert_eq!(numbers(RouteShard::Multi(vec![1, 0])), vec![0, 1]);
+        assert_eq!(numbers(RouteShard::Multi(vec![1])), vec![1]);
+        assert_eq!(numbers(RouteShard::All), vec![0, 1]);
+
+        assert!(matches!(
+            cluster.shards_for(&RouteShard::Direct(2)),
+            Err(Error::NoShard(2))
+        ));
     }
 }
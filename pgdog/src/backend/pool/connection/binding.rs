@@ -9,8 +9,8 @@ use super::*;
 pub(super) enum Binding {
     Server(Option<Guard>),
     Admin(Backend),
-    MultiShard(Vec<Guard>, MultiShard),
-    Replication(Option<Guard>, Buffer),
+    MultiShard(Vec<Guard>, Box<MultiShard>),
+    Replication(Option<Guard>, Box<Buffer>),
 }
 
 impl Default for Binding {
@@ -84,14 +84,14 @@ impl Binding {
                             return Ok(message);
                         }
                         let mut read = false;
-                        for server in shards.iter_mut() {
+                        for (shard, server) in shards.iter_mut().enumerate() {
                             if !server.has_more_messages() {
                                 continue;
                             }
 
                             let message = server.read().await?;
                             read = true;
-                            if let Some(message) = state.forward(message)? {
+                            if let Some(message) = state.forward(shard, message)? {
                                 return Ok(message);
                             }
                         }
@@ -143,7 +143,9 @@ impl Binding {
             }
 
             Binding::Admin(backend) => Ok(backend.send(messages).await?),
-            Binding::MultiShard(servers, _state) => {
+            Binding::MultiShard(servers, state) => {
+                let in_transaction = servers.iter().any(|server| server.in_transaction());
+                state.request(messages, in_transaction);
                 for server in servers.iter_mut() {
                     server.send(messages).await?;
                 }
@@ -160,6 +162,31 @@ impl Binding {
         }
     }
 
+    /// Send different messages to each shard, in the same order as the servers.
+    pub(super) async fn send_each(
+        &mut self,
+        messages: &[&crate::frontend::Buffer],
+    ) -> Result<(), Error> {
+        match self {
+            Binding::MultiShard(servers, state) => {
+                let in_transaction = servers.iter().any(|server| server.in_transaction());
+                if let Some(request) = messages.first() {
+                    state.request(request, in_transaction);
+                }
+                for (server, messages) in servers.iter_mut().zip(messages) {
+                    server.send(messages).await?;
+                }
+
+                Ok(())
+            }
+
+            _ => match messages.first() {
+                Some(messages) => self.send(messages).await,
+                None => Ok(()),
+            },
+        }
+    }
+
     /// Send copy messages to shards they are destined to go.
     pub(super) async fn send_copy(&mut self, rows: Vec<CopyRow>) -> Result<(), Error> {
         match self {
@@ -259,6 +286,18 @@ impl Binding {
         Ok(())
     }
 
+    /// Start a read-only transaction on all servers, taking their snapshots together.
+    pub(super) async fn begin_snapshot(&mut self, query: &str) -> Result<(), Error> {
+        match self {
+            Binding::MultiShard(ref mut servers, _) => {
+                snapshot::synchronize(servers, query).await?;
+                Ok(())
+            }
+
+            _ => self.execute(query).await,
+        }
+    }
+
     pub(super) async fn link_client(&mut self, params: &Parameters) -> Result<usize, Error> {
         match self {
             Binding::Server(Some(ref mut server)) => server.link_client(params).await,
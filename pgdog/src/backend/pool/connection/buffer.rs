@@ -2,37 +2,131 @@
 
 use std::{
     cmp::Ordering,
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
 };
 
 use crate::{
-    frontend::router::parser::{Aggregate, DistinctBy, DistinctColumn, OrderBy},
+    frontend::router::{
+        parser::{
+            Aggregate, DistinctBy, DistinctColumn, Limit, OrderBy, OrderedSetFunction,
+            OrderedSetTarget, SetOperator,
+        },
+        Route,
+    },
     net::{
-        messages::{DataRow, FromBytes, Message, Protocol, ToBytes, Vector},
-        Decoder,
+        messages::{
+            data_row::Data, DataRow, Field, Format, FromBytes, Message, Numeric, Protocol,
+            RowDescription, ToBytes, Vector,
+        },
+        Datum, Decoder,
     },
 };
 
-use super::Aggregates;
+use super::{
+    reducer::{ResultReducer, ResultSet},
+    Aggregates,
+};
+
+/// OID of the NUMERIC type.
+const NUMERIC: i32 = 1700;
+
+/// OIDs of the types `percentile_cont` interpolates:
+/// int2, int4, int8, float4, float8 and numeric.
+const NUMBERS: [i32; 6] = [21, 23, 20, 700, 701, NUMERIC];
+
+/// Maximum size, in bytes, of buffered rows and the error returned when they're larger.
+type SizeLimit = (usize, fn(usize) -> super::Error);
 
 /// Sort and aggregate rows received from multiple shards.
 #[derive(Default, Debug, Clone)]
 pub(super) struct Buffer {
     buffer: VecDeque<DataRow>,
     full: bool,
-    distinct: HashSet<DataRow>,
+    size_limit: Option<SizeLimit>,
+    /// Size, in bytes, of the buffered rows.
+    size: usize,
 }
 
 impl Buffer {
+    /// Buffer for rows deduplicated by UNION or DISTINCT, or matched
+    /// by INTERSECT and EXCEPT, keeping at most `limit` bytes of them.
+    pub(super) fn with_distinct_limit(limit: usize) -> Self {
+        Self {
+            size_limit: Some((limit, super::Error::DistinctTooLarge)),
+            ..Default::default()
+        }
+    }
+
+    /// Buffer for input values of ordered-set aggregates,
+    /// keeping at most `limit` bytes of them.
+    pub(super) fn with_ordered_set_limit(limit: usize) -> Self {
+        Self {
+            size_limit: Some((limit, super::Error::OrderedSetTooLarge)),
+            ..Default::default()
+        }
+    }
+
     /// Add message to buffer.
+    ///
+    /// Rows are kept until all shards sent theirs, so UNION, DISTINCT, INTERSECT, EXCEPT
+    /// and ordered-set aggregates fail as soon as they're larger than the limit.
     pub(super) fn add(&mut self, message: Message) -> Result<(), super::Error> {
         let dr = DataRow::from_bytes(message.to_bytes()?)?;
 
+        if let Some((limit, error)) = self.size_limit {
+            self.size += (0..dr.len())
+                .filter_map(|index| dr.column(index))
+                .map(|data| data.len())
+                .sum::<usize>();
+            if self.size > limit {
+                return Err(error(limit));
+            }
+        }
+
         self.buffer.push_back(dr);
 
         Ok(())
     }
 
+    /// Add row to buffer.
+    pub(super) fn push(&mut self, row: DataRow) {
+        self.buffer.push_back(row);
+    }
+
+    /// Take all rows from the buffer.
+    pub(super) fn into_rows(self) -> Vec<DataRow> {
+        self.buffer.into()
+    }
+
+    /// Take the buffered rows, leaving it empty.
+    pub(super) fn take_rows(&mut self) -> Vec<DataRow> {
+        std::mem::take(&mut self.buffer).into()
+    }
+
+    /// Replace buffered rows with the result of a reducer.
+    pub(super) fn reduce(
+        &mut self,
+        reducer: &dyn ResultReducer,
+        per_shard: Vec<ResultSet>,
+    ) -> Result<(), super::Error> {
+        self.buffer = reducer.reduce(per_shard)?.into_rows().into();
+        Ok(())
+    }
+
+    /// Merge rows from all shards, as required by the route.
+    ///
+    /// Same order as Postgres: DISTINCT is applied to sorted rows
+    /// and LIMIT to distinct ones. ORDER BY and LIMIT of INTERSECT
+    /// and EXCEPT apply to their result.
+    pub(super) fn merge(&mut self, route: &Route, decoder: &Decoder) -> Result<(), super::Error> {
+        self.set_operation(route.set_operator());
+        self.aggregate(route.aggregate(), decoder)?;
+        self.sort(route.order_by(), decoder);
+        self.distinct(route.distinct(), decoder);
+        self.limit(route.limit(), route.order_by(), decoder);
+        Ok(())
+    }
+
     /// Mark the buffer as full. It will start returning messages now.
     /// Caller is responsible for sorting the buffer if needed.
     pub(super) fn full(&mut self) {
@@ -42,10 +136,50 @@ impl Buffer {
     pub(super) fn reset(&mut self) {
         self.buffer.clear();
         self.full = false;
+        self.size = 0;
     }
 
     /// Sort the buffer.
     pub(super) fn sort(&mut self, columns: &[OrderBy], decoder: &Decoder) {
+        let order_by = Self::comparator(columns, decoder);
+        self.buffer.make_contiguous().sort_by(order_by);
+    }
+
+    /// Apply LIMIT to sorted rows. With `WITH TIES`, rows that compare
+    /// equal to the last row are kept too, no matter which shard they came from.
+    ///
+    /// OFFSET is applied by each shard, so we can't do anything about it here.
+    pub(super) fn limit(&mut self, limit: &Limit, columns: &[OrderBy], decoder: &Decoder) {
+        let Some(count) = limit.limit else {
+            return;
+        };
+
+        if self.buffer.len() <= count {
+            return;
+        }
+
+        let mut end = count;
+
+        if limit.with_ties && count > 0 {
+            let order_by = Self::comparator(columns, decoder);
+            let last = &self.buffer[count - 1];
+
+            while end < self.buffer.len() && order_by(&self.buffer[end], last) == Ordering::Equal {
+                end += 1;
+            }
+        }
+
+        self.buffer.truncate(end);
+    }
+
+    /// Compare rows using the ORDER BY clause.
+    ///
+    /// Columns are compared using their type from the RowDescription. If we don't have one,
+    /// e.g. the client didn't describe the statement, they are compared byte by byte.
+    fn comparator<'a>(
+        columns: &[OrderBy],
+        decoder: &'a Decoder,
+    ) -> impl Fn(&DataRow, &DataRow) -> Ordering + 'a {
         // Calculate column indices once, since
         // fetching indices by name is O(number of columns).
         let mut cols = vec![];
@@ -72,8 +206,23 @@ impl Buffer {
             };
         }
 
-        // Sort rows.
-        let order_by = move |a: &DataRow, b: &DataRow| -> Ordering {
+        let value = move |row: &DataRow, index: usize| {
+            let data = row.column(index)?;
+            let format = decoder.format(index);
+            match decoder.rd().field(index) {
+                // NUMERIC is compared as a float here only. Everywhere else,
+                // e.g. in aggregates, it keeps its exact value.
+                Some(field) if field.type_oid == NUMERIC && !data.is_empty() => {
+                    Numeric::decode_numeric(&data, format)
+                        .ok()
+                        .map(Datum::Numeric)
+                }
+                Some(field) => Datum::new(&data, field.data_type(), format).ok(),
+                None => Some(Datum::Unknown(data)),
+            }
+        };
+
+        move |a: &DataRow, b: &DataRow| -> Ordering {
             for col in cols.iter() {
                 let index = col.index();
                 let asc = col.asc();
@@ -82,15 +231,15 @@ impl Buffer {
                 } else {
                     continue;
                 };
-                let left = a.get_column(index, decoder);
-                let right = b.get_column(index, decoder);
+                let left = value(a, index);
+                let right = value(b, index);
 
                 let ordering = match (left, right) {
-                    (Ok(Some(left)), Ok(Some(right))) => {
+                    (Some(left), Some(right)) => {
                         // Handle the special vector case.
                         if let OrderBy::AscVectorL2(_, vector) = col {
-                            let left: Option<Vector> = left.value.try_into().ok();
-                            let right: Option<Vector> = right.value.try_into().ok();
+                            let left: Option<Vector> = left.try_into().ok();
+                            let right: Option<Vector> = right.try_into().ok();
 
                             if let (Some(left), Some(right)) = (left, right) {
                                 let left = left.distance_l2(vector);
@@ -101,9 +250,9 @@ impl Buffer {
                                 Some(Ordering::Equal)
                             }
                         } else if asc {
-                            left.value.partial_cmp(&right.value)
+                            left.partial_cmp(&right)
                         } else {
-                            right.value.partial_cmp(&left.value)
+                            right.partial_cmp(&left)
                         }
                     }
 
@@ -116,9 +265,154 @@ impl Buffer {
             }
 
             Ordering::Equal
+        }
+    }
+
+    /// Replace the input values of ordered-set aggregates, one column per aggregate,
+    /// with the aggregates computed from all of them. NULLs are ignored, like Postgres does.
+    fn ordered_sets(
+        &mut self,
+        targets: &[OrderedSetTarget],
+        decoder: &Decoder,
+    ) -> Result<(), super::Error> {
+        let mut result = DataRow::new();
+
+        for (index, target) in targets.iter().enumerate() {
+            let mut rows = self
+                .buffer
+                .iter()
+                .filter(|row| !row.is_null(index))
+                .collect::<Vec<_>>();
+            let order_by = Self::comparator(&[OrderBy::Asc(index + 1)], decoder);
+            rows.sort_by(|a, b| order_by(a, b));
+
+            match target.function() {
+                OrderedSetFunction::PercentileCont(fraction) => {
+                    let type_oid = decoder.rd().field(index).map(|field| field.type_oid);
+                    if type_oid.is_some_and(|type_oid| !NUMBERS.contains(&type_oid)) {
+                        return Err(super::Error::PercentileContType(type_oid.unwrap_or(0)));
+                    }
+                    let text = decoder.format(index) == Format::Text;
+                    let values = rows
+                        .iter()
+                        .map(|row| row.get_float(index, text))
+                        .collect::<Option<Vec<_>>>()
+                        .ok_or(super::Error::DecoderRowError)?;
+                    match Self::percentile_cont(&values, fraction.parse().unwrap_or_default()) {
+                        Some(value) => result.add(value),
+                        None => result.add(Data::null()),
+                    };
+                }
+
+                OrderedSetFunction::PercentileDisc(fraction) => {
+                    let fraction: f64 = fraction.parse().unwrap_or_default();
+                    // First value whose position in the sorted values is at least the fraction.
+                    let position = (fraction * rows.len() as f64).ceil() as usize;
+                    match rows.get(position.saturating_sub(1)) {
+                        Some(row) => result.add(row.column(index).unwrap_or_default()),
+                        None => result.add(Data::null()),
+                    };
+                }
+
+                OrderedSetFunction::Mode => {
+                    // Most frequent value. Ties go to the smallest one.
+                    let mut mode: Option<(&DataRow, usize)> = None;
+                    let mut start = 0;
+                    for end in 1..=rows.len() {
+                        if end == rows.len() || order_by(rows[start], rows[end]) != Ordering::Equal
+                        {
+                            if mode.is_none_or(|(_, count)| end - start > count) {
+                                mode = Some((rows[start], end - start));
+                            }
+                            start = end;
+                        }
+                    }
+                    match mode {
+                        Some((row, _)) => result.add(row.column(index).unwrap_or_default()),
+                        None => result.add(Data::null()),
+                    };
+                }
+            }
+        }
+
+        self.buffer = VecDeque::from([result]);
+
+        Ok(())
+    }
+
+    /// Interpolate between the two values closest to the fraction of sorted values.
+    fn percentile_cont(values: &[f64], fraction: f64) -> Option<f64> {
+        let position = fraction * (values.len().checked_sub(1)? as f64);
+        let (lower, upper) = (
+            values[position.floor() as usize],
+            values[position.ceil() as usize],
+        );
+        Some(lower + (upper - lower) * (position - position.floor()))
+    }
+
+    /// Describe the ordered-set aggregates, given the description of their input values.
+    /// `percentile_cont` returns a double precision, the others the type of their input.
+    pub(super) fn ordered_set_description(
+        targets: &[OrderedSetTarget],
+        inputs: &RowDescription,
+    ) -> RowDescription {
+        let fields = targets
+            .iter()
+            .zip(inputs.fields.iter())
+            .map(|(target, input)| match target.function() {
+                OrderedSetFunction::PercentileCont(_) => Field::double(target.name()),
+                _ => Field {
+                    name: target.name().to_owned(),
+                    table_oid: 0,
+                    column: 0,
+                    ..input.clone()
+                },
+            })
+            .collect::<Vec<_>>();
+
+        RowDescription::new(&fields)
+    }
+
+    /// Compute INTERSECT or EXCEPT of the rows from all shards.
+    ///
+    /// Shards return the rows of both sides, with the side in the last column,
+    /// see [`SetOperator::sides`]. Rows are returned in the order we first saw them,
+    /// without the side.
+    pub(super) fn set_operation(&mut self, operator: Option<SetOperator>) {
+        let Some(operator) = operator else {
+            return;
         };
 
-        self.buffer.make_contiguous().sort_by(order_by);
+        // Position of each row in `rows`.
+        let mut seen = HashMap::new();
+        // Rows, with how many times they're on the left and right sides.
+        let mut rows: Vec<(DataRow, usize, usize)> = vec![];
+
+        for mut row in self.buffer.drain(..) {
+            let left = row.pop().is_some_and(|side| side.as_ref() == b"1");
+            let index = *seen.entry(row.clone()).or_insert_with(|| {
+                rows.push((row, 0, 0));
+                rows.len() - 1
+            });
+            if left {
+                rows[index].1 += 1;
+            } else {
+                rows[index].2 += 1;
+            }
+        }
+
+        for (row, left, right) in rows {
+            for _ in 0..operator.count(left, right) {
+                self.buffer.push_back(row.clone());
+            }
+        }
+    }
+
+    /// Describe the result of INTERSECT or EXCEPT, given the description
+    /// of the rows returned by shards, which have their side in the last column.
+    pub(super) fn set_operation_description(rd: &RowDescription) -> RowDescription {
+        let fields = rd.fields.split_last().map_or(&[][..], |(_, fields)| fields);
+        RowDescription::new(fields)
     }
 
     /// Execute aggregate functions.
@@ -134,6 +428,10 @@ impl Buffer {
         aggregate: &Aggregate,
         decoder: &Decoder,
     ) -> Result<(), super::Error> {
+        if !aggregate.ordered_sets().is_empty() {
+            return self.ordered_sets(aggregate.ordered_sets(), decoder);
+        }
+
         let buffer: VecDeque<DataRow> = std::mem::take(&mut self.buffer);
         if aggregate.is_empty() {
             self.buffer = buffer;
@@ -151,11 +449,20 @@ impl Buffer {
         Ok(())
     }
 
+    /// Remove duplicate rows, keeping the first one we see.
+    ///
+    /// Called after sorting, so the order of the rows we keep
+    /// is preserved. For `DISTINCT ON`, that's the first row
+    /// of each group according to `ORDER BY`, just like Postgres does.
     pub(super) fn distinct(&mut self, distinct: &Option<DistinctBy>, decoder: &Decoder) {
+        // Rows seen so far. Each call handles one result set,
+        // so rows from previous ones don't count.
+        let mut seen = HashSet::new();
+
         if let Some(distinct) = distinct {
             match distinct {
                 DistinctBy::Row => {
-                    self.buffer.retain(|row| self.distinct.insert(row.clone()));
+                    self.buffer.retain(|row| seen.insert(row.clone()));
                 }
 
                 DistinctBy::Columns(ref columns) => {
@@ -179,7 +486,7 @@ impl Buffer {
                             }
                         }
 
-                        self.distinct.insert(dr)
+                        seen.insert(dr)
                     });
                 }
             }
@@ -207,8 +514,13 @@ impl Buffer {
 
 #[cfg(test)]
 mod test {
+    use bytes::Bytes;
+
     use super::*;
-    use crate::net::{Field, Format, RowDescription};
+    use crate::{
+        frontend::router::parser::Shard,
+        net::{Field, Format, RowDescription},
+    };
 
     #[test]
     fn test_sort_buffer() {
@@ -344,4 +656,349 @@ mod test {
 
         assert_eq!(buf.buffer.len(), 3);
     }
+
+    #[test]
+    fn test_distinct_limit() {
+        let rd = RowDescription::new(&[Field::text("email")]);
+        let decoder = Decoder::from(&rd);
+        let row = |email: &str| {
+            let mut dr = DataRow::new();
+            dr.add(email);
+            dr.message().unwrap()
+        };
+
+        let mut buf = Buffer::with_distinct_limit(16);
+        buf.add(row("a@b.com")).unwrap();
+        buf.add(row("c@d.com")).unwrap();
+        // Rows are rejected as they arrive, not once they're all buffered.
+        assert!(matches!(
+            buf.add(row("e@f.com")),
+            Err(crate::backend::Error::DistinctTooLarge(16))
+        ));
+        assert_eq!(buf.len(), 2);
+
+        buf.reset();
+        buf.add(row("e@f.com")).unwrap();
+        buf.distinct(&Some(DistinctBy::Row), &decoder);
+        assert_eq!(buf.len(), 1);
+
+        // Only UNION and DISTINCT are limited.
+        let mut buf = Buffer::default();
+        for _ in 0..3 {
+            buf.add(row("a@b.com")).unwrap();
+        }
+        assert_eq!(buf.len(), 3);
+    }
+
+    #[test]
+    fn test_ordered_set_limit() {
+        let row = |value: i64| {
+            let mut dr = DataRow::new();
+            dr.add(value);
+            dr.message().unwrap()
+        };
+
+        let mut buf = Buffer::with_ordered_set_limit(4);
+        buf.add(row(10)).unwrap();
+        buf.add(row(20)).unwrap();
+        assert!(matches!(
+            buf.add(row(30)),
+            Err(crate::backend::Error::OrderedSetTooLarge(4))
+        ));
+    }
+
+    #[test]
+    fn test_percentile_cont() {
+        let values = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(Buffer::percentile_cont(&values, 0.0), Some(1.0));
+        assert_eq!(Buffer::percentile_cont(&values, 0.5), Some(2.5));
+        assert_eq!(Buffer::percentile_cont(&values, 0.25), Some(1.75));
+        assert_eq!(Buffer::percentile_cont(&values, 1.0), Some(4.0));
+        assert_eq!(Buffer::percentile_cont(&[], 0.5), None);
+    }
+
+    #[test]
+    fn test_distinct_order_by() {
+        // SELECT DISTINCT status FROM events ORDER BY status
+        let rd = RowDescription::new(&[Field::text("status")]);
+        let decoder = Decoder::from(&rd);
+        let columns = [OrderBy::AscColumn("status".into())];
+
+        let mut buf = Buffer::default();
+
+        // Each shard returns its own distinct, sorted rows,
+        // with some overlap between them.
+        for statuses in [
+            ["active", "deleted", "pending"],
+            ["active", "archived", "pending"],
+            ["archived", "deleted", "new"],
+        ] {
+            for status in statuses {
+                let mut dr = DataRow::new();
+                dr.add(status);
+                buf.add(dr.message().unwrap()).unwrap();
+            }
+        }
+
+        buf.sort(&columns, &decoder);
+        buf.distinct(&Some(DistinctBy::Row), &decoder);
+        buf.full();
+
+        let mut statuses = vec![];
+        while let Some(message) = buf.take() {
+            let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+            statuses.push(dr.get::<String>(0, Format::Text).unwrap());
+        }
+        assert_eq!(
+            statuses,
+            vec!["active", "archived", "deleted", "new", "pending"]
+        );
+
+        // The next result set doesn't remember rows from the previous one.
+        let mut dr = DataRow::new();
+        dr.add("active");
+        buf.reset();
+        buf.add(dr.message().unwrap()).unwrap();
+        buf.distinct(&Some(DistinctBy::Row), &decoder);
+        assert_eq!(buf.len(), 1);
+    }
+
+    #[test]
+    fn test_group_by_order_by_aggregate() {
+        // SELECT tenant_id, count(*) c FROM events GROUP BY tenant_id ORDER BY c DESC, tenant_id LIMIT 5
+        let rd = RowDescription::new(&[Field::bigint("tenant_id"), Field::bigint("c")]);
+        let decoder = Decoder::from(&rd);
+        let route = Route::select(
+            Shard::All,
+            vec![
+                OrderBy::DescColumn("c".into()),
+                OrderBy::AscColumn("tenant_id".into()),
+            ],
+            Aggregate::new_count_group_by(1, &[0]),
+            Limit {
+                limit: Some(5),
+                offset: None,
+                with_ties: false,
+            },
+            None,
+        );
+        let events = |shard: i64, tenant: i64| (tenant * (7 + shard * 2)) % (11 + shard * 2);
+
+        // Single node sees every event.
+        let mut expected = (0..20_i64)
+            .map(|tenant| (tenant, events(0, tenant) + events(1, tenant)))
+            .collect::<Vec<_>>();
+        expected.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        expected.truncate(5);
+
+        // Each shard returns its partial counts, in its own order.
+        let mut buf = Buffer::default();
+        for shard in 0..2 {
+            let mut partials = (0..20_i64)
+                .map(|tenant| (tenant, events(shard, tenant)))
+                .filter(|(_, count)| *count > 0)
+                .collect::<Vec<_>>();
+            partials.sort_by_key(|p| std::cmp::Reverse(p.1));
+            for (tenant, count) in partials {
+                let mut dr = DataRow::new();
+                dr.add(tenant).add(count);
+                buf.add(dr.message().unwrap()).unwrap();
+            }
+        }
+
+        buf.merge(&route, &decoder).unwrap();
+        buf.full();
+
+        let mut result = vec![];
+        while let Some(message) = buf.take() {
+            let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+            result.push((
+                dr.get::<i64>(0, Format::Text).unwrap(),
+                dr.get::<i64>(1, Format::Text).unwrap(),
+            ));
+        }
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_limit_with_ties() {
+        let rd = RowDescription::new(&[Field::bigint("id"), Field::text("shard")]);
+        let decoder = Decoder::from(&rd);
+        let columns = [OrderBy::Asc(1)];
+
+        let mut buf = Buffer::default();
+
+        // Two shards, with the tied value 2 on both.
+        for (shard, ids) in [("0", [1_i64, 2, 2]), ("1", [2, 2, 3])] {
+            for id in ids {
+                let mut dr = DataRow::new();
+                dr.add(id).add(shard);
+                buf.add(dr.message().unwrap()).unwrap();
+            }
+        }
+
+        let mut without_ties = buf.clone();
+        without_ties.sort(&columns, &decoder);
+        without_ties.limit(
+            &Limit {
+                limit: Some(2),
+                offset: None,
+                with_ties: false,
+            },
+            &columns,
+            &decoder,
+        );
+        assert_eq!(without_ties.len(), 2);
+
+        buf.sort(&columns, &decoder);
+        buf.limit(
+            &Limit {
+                limit: Some(2),
+                offset: None,
+                with_ties: true,
+            },
+            &columns,
+            &decoder,
+        );
+        buf.full();
+
+        assert_eq!(buf.len(), 5);
+        let mut shards = HashSet::new();
+        let mut ids = vec![];
+        while let Some(message) = buf.take() {
+            let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+            ids.push(dr.get::<i64>(0, Format::Text).unwrap());
+            shards.insert(dr.get::<String>(1, Format::Text).unwrap());
+        }
+        assert_eq!(ids, vec![1, 2, 2, 2, 2]);
+        assert_eq!(shards.len(), 2);
+    }
+
+    #[test]
+    fn test_sort_by_type() {
+        fn sort(decoder: &Decoder, order_by: OrderBy, values: &[&str]) -> Vec<String> {
+            let mut buf = Buffer::default();
+            for value in values {
+                let mut dr = DataRow::new();
+                dr.add(value.to_string());
+                buf.add(dr.message().unwrap()).unwrap();
+            }
+            buf.sort(&[order_by], decoder);
+            buf.full();
+
+            let mut values = vec![];
+            while let Some(message) = buf.take() {
+                let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+                values.push(dr.get::<String>(0, Format::Text).unwrap());
+            }
+            values
+        }
+
+        // Rows from two shards, each sorted already.
+        let numeric = Decoder::from(&RowDescription::new(&[Field::numeric("price")]));
+        assert_eq!(
+            sort(&numeric, OrderBy::Asc(1), &["-1", "10.5", "9.75", "100"]),
+            vec!["-1", "9.75", "10.5", "100"]
+        );
+        assert_eq!(
+            sort(&numeric, OrderBy::Desc(1), &["10.5", "-1", "100", "9.75"]),
+            vec!["100", "10.5", "9.75", "-1"]
+        );
+
+        let timestamp = Decoder::from(&RowDescription::new(&[Field {
+            type_oid: 1114,
+            ..Field::text("created_at")
+        }]));
+        assert_eq!(
+            sort(
+                &timestamp,
+                OrderBy::Asc(1),
+                &[
+                    "2025-01-01 12:00:00.5",
+                    "2025-01-02 00:00:00",
+                    "2025-01-01 12:00:00.25",
+                    "2025-01-01 12:00:01",
+                ]
+            ),
+            vec![
+                "2025-01-01 12:00:00.25",
+                "2025-01-01 12:00:00.5",
+                "2025-01-01 12:00:01",
+                "2025-01-02 00:00:00",
+            ]
+        );
+
+        // No RowDescription, so they're compared as text.
+        let untyped = Decoder::new();
+        assert_eq!(
+            sort(&untyped, OrderBy::Asc(1), &["10", "9", "100"]),
+            vec!["10", "100", "9"]
+        );
+        assert_eq!(
+            sort(&untyped, OrderBy::Asc(1), &["b", "10", "a", "9"]),
+            vec!["10", "9", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_sort_bytea() {
+        fn sort(decoder: &Decoder, order_by: OrderBy, values: &[&[u8]]) -> Vec<Bytes> {
+            let mut buf = Buffer::default();
+            for value in values {
+                let mut dr = DataRow::new();
+                dr.add(Bytes::copy_from_slice(value));
+                buf.add(dr.message().unwrap()).unwrap();
+            }
+            buf.sort(&[order_by], decoder);
+            buf.full();
+
+            let mut values = vec![];
+            while let Some(message) = buf.take() {
+                let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+                values.push(dr.column(0).unwrap());
+            }
+            values
+        }
+        let bytea = |format| {
+            Decoder::from(&RowDescription::new(&[Field {
+                type_oid: 17,
+                format,
+                ..Field::text("data")
+            }]))
+        };
+
+        // Rows from two shards, each sorted already. Escaped bytes
+        // are compared by value, not by how they're printed.
+        let text = bytea(0);
+        assert_eq!(
+            sort(&text, OrderBy::Asc(1), &[b"\\000", b"\\377", b"a", b"b"]),
+            vec!["\\000", "a", "b", "\\377"]
+        );
+        assert_eq!(
+            sort(&text, OrderBy::Desc(1), &[b"\\x0aff", b"\\x00ff", b"\\x0a"]),
+            vec!["\\x0aff", "\\x0a", "\\x00ff"]
+        );
+
+        // Binary values aren't UTF-8.
+        let binary = bytea(1);
+        assert_eq!(
+            sort(
+                &binary,
+                OrderBy::Asc(1),
+                &[&[0xff], &[0x00, 0xff], &[0xc3], &[0x00]]
+            ),
+            vec![&[0x00][..], &[0x00, 0xff], &[0xc3], &[0xff]]
+        );
+
+        // No RowDescription, so they're compared as bytes.
+        let untyped = Decoder::new();
+        assert_eq!(
+            sort(
+                &untyped,
+                OrderBy::Asc(1),
+                &[&[0xff], b"a", &[0xc3, 0x28], b"1"]
+            ),
+            vec![&b"1"[..], b"a", &[0xc3, 0x28], &[0xff]]
+        );
+    }
 }
@@ -0,0 +1,214 @@
+//! Cross-shard cursor kept in memory.
+//!
+//! Fetching rows from a cursor declared on every shard returns
+//! a few rows from each one, which can't be put in the cursor's order.
+//! Instead, we run the cursor's query once, keep the merged rows
+//! and serve FETCH and MOVE from them.
+
+use crate::{
+    frontend::router::parser::Direction,
+    net::{Message, Protocol},
+};
+
+use super::Error;
+
+/// Rows of a cross-shard cursor, in the order of its query.
+#[derive(Debug, Clone, Default)]
+pub struct BufferedCursor {
+    row_description: Option<Message>,
+    rows: Vec<Message>,
+    /// 1-based, like in Postgres: 0 is before the first row
+    /// and `rows.len() + 1` is after the last one.
+    position: usize,
+    size: usize,
+    limit: usize,
+    too_large: bool,
+}
+
+impl BufferedCursor {
+    /// Create a cursor holding at most `limit` bytes of rows.
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    /// Add a message returned by the cursor's query. Rows are expected
+    /// to be merged from all shards already.
+    pub fn add(&mut self, message: &Message) {
+        match message.code() {
+            'T' => self.row_description = Some(message.clone()),
+            'D' if !self.too_large => {
+                self.size += message.len();
+                if self.size > self.limit {
+                    self.too_large = true;
+                    self.rows = vec![];
+                } else {
+                    self.rows.push(message.clone());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// The query returned all its rows.
+    pub fn done(self) -> Result<Self, Error> {
+        if self.too_large {
+            Err(Error::CursorTooLarge(self.limit))
+        } else {
+            Ok(self)
+        }
+    }
+
+    /// Columns returned by FETCH.
+    pub fn row_description(&self) -> Option<&Message> {
+        self.row_description.as_ref()
+    }
+
+    /// Move the cursor and return the rows it passed, like FETCH does.
+    pub fn fetch(&mut self, direction: Direction) -> Vec<Message> {
+        let len = self.rows.len();
+
+        match direction {
+            Direction::Forward(count) => {
+                let start = self.position.min(len);
+                let end = self.position.saturating_add(count).min(len);
+                self.position = self.position.saturating_add(count).min(len + 1);
+                self.rows[start..end].to_vec()
+            }
+
+            Direction::Backward(count) => {
+                let end = self.position.saturating_sub(1).min(len);
+                let start = self.position.saturating_sub(count).max(1) - 1;
+                self.position = self.position.saturating_sub(count);
+                self.rows[start.min(end)..end]
+                    .iter()
+                    .rev()
+                    .cloned()
+                    .collect()
+            }
+
+            Direction::Absolute(position) => {
+                let position = if position < 0 {
+                    (len as i64 + 1 + position).max(0)
+                } else {
+                    position
+                };
+                self.position = (position as usize).min(len + 1);
+                self.current()
+            }
+
+            Direction::Relative(offset) => {
+                self.position = (self.position as i64 + offset).clamp(0, len as i64 + 1) as usize;
+                self.current()
+            }
+        }
+    }
+
+    fn current(&self) -> Vec<Message> {
+        self.position
+            .checked_sub(1)
+            .and_then(|index| self.rows.get(index))
+            .cloned()
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::multi_shard::MultiShard;
+    use super::*;
+    use crate::{
+        frontend::router::{
+            parser::{Aggregate, Limit, OrderBy, Shard},
+            Route,
+        },
+        net::{CommandComplete, DataRow, Field, Format, FromBytes, RowDescription, ToBytes},
+    };
+
+    fn ids(rows: Vec<Message>) -> Vec<i64> {
+        rows.into_iter()
+            .map(|row| {
+                DataRow::from_bytes(row.to_bytes().unwrap())
+                    .unwrap()
+                    .get::<i64>(0, Format::Text)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    /// `DECLARE c CURSOR FOR SELECT id FROM sharded ORDER BY id DESC`
+    /// with even ids on shard 0 and odd ones on shard 1.
+    fn cursor(limit: usize) -> Result<BufferedCursor, Error> {
+        let route = Route::select(
+            Shard::All,
+            vec![OrderBy::Desc(1)],
+            Aggregate::default(),
+            Limit::default(),
+            None,
+        );
+        let rd = RowDescription::new(&[Field::bigint("id")]);
+        let mut multi_shard = MultiShard::new(2, &route);
+        let mut cursor = BufferedCursor::new(limit);
+
+        for shard in [0, 1] {
+            if let Some(message) = multi_shard.forward(shard, rd.message()?.backend())? {
+                cursor.add(&message);
+            }
+        }
+
+        for (shard, id) in [(0, 10_i64), (1, 9), (0, 8), (1, 7), (1, 5), (0, 6)] {
+            let mut dr = DataRow::new();
+            dr.add(id);
+            multi_shard.forward(shard, dr.message()?.backend())?;
+        }
+
+        for shard in [0, 1] {
+            let cc = CommandComplete::from_str("SELECT 3").message()?.backend();
+            multi_shard.forward(shard, cc)?;
+        }
+
+        while let Some(message) = multi_shard.message() {
+            cursor.add(&message);
+        }
+
+        cursor.done()
+    }
+
+    #[test]
+    fn test_fetch_forward_backward() {
+        let mut cursor = cursor(usize::MAX).unwrap();
+        assert!(cursor.row_description().is_some());
+
+        assert_eq!(ids(cursor.fetch(Direction::Forward(2))), vec![10, 9]);
+        assert_eq!(ids(cursor.fetch(Direction::Forward(3))), vec![8, 7, 6]);
+        assert_eq!(ids(cursor.fetch(Direction::Backward(2))), vec![7, 8]);
+        assert_eq!(ids(cursor.fetch(Direction::Relative(0))), vec![8]);
+        assert_eq!(ids(cursor.fetch(Direction::Forward(10))), vec![7, 6, 5]);
+        assert!(cursor.fetch(Direction::Forward(1)).is_empty());
+        assert_eq!(ids(cursor.fetch(Direction::Backward(1))), vec![5]);
+        assert_eq!(
+            ids(cursor.fetch(Direction::Backward(usize::MAX))),
+            vec![6, 7, 8, 9, 10]
+        );
+        assert!(cursor.fetch(Direction::Backward(1)).is_empty());
+        assert_eq!(ids(cursor.fetch(Direction::Forward(1))), vec![10]);
+
+        assert_eq!(ids(cursor.fetch(Direction::Absolute(-1))), vec![5]);
+        assert_eq!(ids(cursor.fetch(Direction::Absolute(3))), vec![8]);
+        assert_eq!(ids(cursor.fetch(Direction::Relative(-2))), vec![10]);
+        assert!(cursor.fetch(Direction::Relative(-1)).is_empty());
+        assert!(cursor.fetch(Direction::Absolute(100)).is_empty());
+        assert_eq!(ids(cursor.fetch(Direction::Backward(1))), vec![5]);
+        assert!(cursor.fetch(Direction::Absolute(0)).is_empty());
+        assert_eq!(ids(cursor.fetch(Direction::Forward(1))), vec![10]);
+    }
+
+    #[test]
+    fn test_too_large() {
+        assert!(matches!(cursor(64), Err(Error::CursorTooLarge(64))));
+        assert!(cursor(1024).is_ok());
+    }
+}
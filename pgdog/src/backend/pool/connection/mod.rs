@@ -25,13 +25,16 @@ use super::{
     Address, Cluster, Request, ShardingSchema,
 };
 
-use std::{mem::replace, time::Duration};
+use std::{collections::HashMap, mem::replace, time::Duration};
 
 pub mod aggregate;
 pub mod binding;
 pub mod buffer;
+pub mod cursor;
 pub mod mirror;
 pub mod multi_shard;
+pub mod reducer;
+pub mod snapshot;
 
 use aggregate::Aggregates;
 use binding::Binding;
@@ -47,6 +50,8 @@ pub struct Connection {
     cluster: Option<Cluster>,
     mirrors: Vec<MirrorHandler>,
     locked: bool,
+    /// Replica pool used by cross-shard reads, for each shard.
+    sticky_replicas: HashMap<usize, u64>,
 }
 
 impl Connection {
@@ -63,6 +68,7 @@ impl Connection {
             database: database.to_owned(),
             mirrors: vec![],
             locked: false,
+            sticky_replicas: HashMap::new(),
         };
 
         if !admin {
@@ -119,7 +125,7 @@ impl Connection {
     ) -> Result<(), Error> {
         self.binding = Binding::Replication(
             None,
-            Buffer::new(shard, replication_config, sharding_schema),
+            Box::new(Buffer::new(shard, replication_config, sharding_schema)),
         );
         Ok(())
     }
@@ -133,11 +139,27 @@ impl Connection {
 
     /// Try to get a connection for the given route.
     async fn try_conn(&mut self, request: &Request, route: &Route) -> Result<(), Error> {
-        if let Shard::Direct(shard) = route.shard() {
-            let mut server = if route.is_read() {
-                self.cluster()?.replica(*shard, request).await?
+        let mut servers = vec![];
+        let mut sticky = vec![];
+        let cluster = self.cluster()?;
+        // Cross-shard reads go to the same replicas as before in this session.
+        let sticky_replicas = cluster.sticky_replicas() && route.is_cross_shard();
+        for (number, shard) in cluster.shards_for(route.shard())? {
+            let mut server = if route.is_analytics() {
+                shard.analytics(request).await?
+            } else if route.is_read() {
+                match route.max_staleness() {
+                    Some(max_staleness) => shard.replica_within(request, max_staleness).await?,
+                    None if sticky_replicas => {
+                        let preferred = self.sticky_replicas.get(&number).copied();
+                        let server = shard.replica_preferred(request, preferred).await?;
+                        sticky.push((number, server.pool.id()));
+                        server
+                    }
+                    None => shard.replica(request).await?,
+                }
             } else {
-                self.cluster()?.primary(*shard, request).await?
+                shard.primary(request).await?
             };
 
             // Cleanup session mode connections when
@@ -146,44 +168,32 @@ impl Connection {
                 server.reset = true;
             }
 
+            servers.push(server);
+        }
+        self.sticky_replicas.extend(sticky);
+
+        if let Shard::Direct(_) = route.shard() {
+            let server = servers.pop();
+
             match &mut self.binding {
                 Binding::Server(existing) => {
-                    let _ = replace(existing, Some(server));
+                    let _ = replace(existing, server);
                 }
 
                 Binding::Replication(existing, _) => {
-                    let _ = replace(existing, Some(server));
+                    let _ = replace(existing, server);
                 }
 
                 Binding::MultiShard(_, _) => {
-                    self.binding = Binding::Server(Some(server));
+                    self.binding = Binding::Server(server);
                 }
 
                 _ => (),
             };
         } else {
-            let mut shards = vec![];
-            for (i, shard) in self.cluster()?.shards().iter().enumerate() {
-                if let Shard::Multi(numbers) = route.shard() {
-                    if !numbers.contains(&i) {
-                        continue;
-                    }
-                };
-                let mut server = if route.is_read() {
-                    shard.replica(request).await?
-                } else {
-                    shard.primary(request).await?
-                };
-
-                if self.session_mode() {
-                    server.reset = true;
-                }
-
-                shards.push(server);
-            }
-            let num_shards = shards.len();
-
-            self.binding = Binding::MultiShard(shards, MultiShard::new(num_shards, route));
+            let num_shards = servers.len();
+            self.binding =
+                Binding::MultiShard(servers, Box::new(MultiShard::new(num_shards, route)));
         }
 
         Ok(())
@@ -260,8 +270,51 @@ impl Connection {
                 self.send(messages).await?;
             }
         } else {
-            // Send query to server.
-            self.send(messages).await?;
+            let route = router.route();
+            let shard_sql = self
+                .cluster
+                .as_ref()
+                .and_then(|cluster| cluster.shard_sql())
+                .cloned();
+
+            if route.shard_queries().is_empty() && shard_sql.is_none() {
+                // Send query to server.
+                self.send(messages).await?;
+            } else {
+                // Each shard gets its own version of the query,
+                // if it's any different.
+                let mut buffers = vec![];
+                for (shard, _) in self.cluster()?.shards_for(route.shard())? {
+                    let mut buffer = None;
+                    if let Some(query) = route.shard_queries().get(&shard) {
+                        let mut rewritten = messages.clone();
+                        rewritten
+                            .rewrite(query)
+                            .map_err(|e| Error::Router(e.to_string()))?;
+                        buffer = Some(rewritten);
+                    }
+                    if let Some(ref shard_sql) = shard_sql {
+                        let transformed = buffer
+                            .as_ref()
+                            .unwrap_or(messages)
+                            .transformed(shard, shard_sql.as_ref());
+                        if transformed.is_some() {
+                            buffer = transformed;
+                        }
+                    }
+                    buffers.push(buffer);
+                }
+
+                if buffers.iter().all(|buffer| buffer.is_none()) {
+                    self.send(messages).await?;
+                } else {
+                    let buffers = buffers
+                        .iter()
+                        .map(|buffer| buffer.as_ref().unwrap_or(messages))
+                        .collect::<Vec<_>>();
+                    self.binding.send_each(&buffers).await?;
+                }
+            }
         }
 
         Ok(())
@@ -276,6 +329,8 @@ impl Connection {
                 let cluster = databases.cluster(user)?;
 
                 self.cluster = Some(cluster);
+                // Pools are recreated on reload.
+                self.sticky_replicas.clear();
                 self.mirrors = databases
                     .mirrors(user)?
                     .unwrap_or(&[])
@@ -379,6 +434,12 @@ impl Connection {
         self.binding.execute(query).await
     }
 
+    /// Start a read-only transaction on the binding,
+    /// taking the snapshots of all shards together.
+    pub(crate) async fn begin_snapshot(&mut self, query: &str) -> Result<(), Error> {
+        self.binding.begin_snapshot(query).await
+    }
+
     pub(crate) async fn link_client(&mut self, params: &Parameters) -> Result<usize, Error> {
         self.binding.link_client(params).await
     }
@@ -1,19 +1,30 @@
 //! Multi-shard connection state.
 
+use std::collections::{BTreeMap, VecDeque};
+
 use context::Context;
+use tracing::debug;
 
 use crate::{
-    frontend::{router::Route, PreparedStatements},
+    config::{config, FanOutErrors},
+    frontend::{
+        buffer::BufferedQuery,
+        router::{parser::Shard, Route},
+        Buffer as Request, PreparedStatements,
+    },
     net::{
         messages::{
-            command_complete::CommandComplete, FromBytes, Message, Protocol, RowDescription,
-            ToBytes,
+            command_complete::CommandComplete, DataRow, ErrorResponse, FromBytes, Message,
+            NoticeResponse, Protocol, RowDescription, ToBytes,
         },
         Decoder,
     },
 };
 
-use super::buffer::Buffer;
+use super::{
+    buffer::Buffer,
+    reducer::{self, ResultSet},
+};
 
 mod context;
 #[cfg(test)]
@@ -26,6 +37,8 @@ struct Counters {
     command_complete_count: usize,
     empty_query_response: usize,
     copy_in: usize,
+    copy_out: usize,
+    copy_done: usize,
     parse_complete: usize,
     parameter_description: usize,
     no_data: usize,
@@ -33,6 +46,16 @@ struct Counters {
     close_complete: usize,
     bind_complete: usize,
     command_complete: Option<Message>,
+    /// Shards that failed, with their errors, if we're returning partial results.
+    failed: Vec<(usize, ErrorResponse)>,
+    /// Rows from shards that haven't finished yet, if we're returning partial results.
+    pending: BTreeMap<usize, Vec<Message>>,
+    /// RowDescription waiting for the other shards.
+    row_description_message: Option<Message>,
+    /// CommandComplete waiting for the other shards.
+    waiting: Option<CommandComplete>,
+    /// Messages to send before the buffered rows.
+    queue: VecDeque<Message>,
 }
 
 /// Multi-shard state.
@@ -48,7 +71,15 @@ pub(super) struct MultiShard {
 
     /// Sorting/aggregate buffer.
     buffer: Buffer,
+    /// Rows from each shard, kept separately for custom reducers.
+    results: Option<BTreeMap<usize, ResultSet>>,
     decoder: Decoder,
+    /// SQLSTATEs of errors that return partial results instead, if the query
+    /// is a read and `fan_out_errors` is `best_effort`. All errors if empty.
+    best_effort: Option<Vec<String>>,
+    /// The request is one statement sent with the simple protocol,
+    /// outside of a transaction, so a shard can fail without the others noticing.
+    simple: bool,
 }
 
 impl MultiShard {
@@ -58,13 +89,61 @@ impl MultiShard {
             shards,
             route: route.clone(),
             counters: Counters::default(),
+            results: route.reducer().map(|_| BTreeMap::new()),
+            buffer: if route.distinct().is_some() || route.set_operator().is_some() {
+                Buffer::with_distinct_limit(config().config.general.distinct_buffer_limit)
+            } else if !route.aggregate().ordered_sets().is_empty() {
+                Buffer::with_ordered_set_limit(config().config.general.ordered_set_buffer_limit)
+            } else {
+                Buffer::default()
+            },
+            best_effort: Self::best_effort(route),
+            simple: true,
             ..Default::default()
         }
     }
 
+    fn best_effort(route: &Route) -> Option<Vec<String>> {
+        let general = &config().config.general;
+        if route.is_read() && general.fan_out_errors == FanOutErrors::BestEffort {
+            Some(general.best_effort_errors.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Check the request before it's sent to the shards.
+    ///
+    /// A shard failing in the extended protocol wouldn't send ParseComplete or BindComplete
+    /// like the others do, and inside a transaction, it would be left aborted
+    /// while the client thinks the query succeeded. The client gets the error instead.
+    pub(super) fn request(&mut self, request: &Request, in_transaction: bool) {
+        self.simple = !in_transaction
+            && match request.query() {
+                Ok(Some(BufferedQuery::Query(query))) => {
+                    pg_query::split_with_scanner(query.query())
+                        .is_ok_and(|statements| statements.len() == 1)
+                }
+                _ => false,
+            };
+    }
+
+    /// SQLSTATEs of errors the current request returns partial results for.
+    fn partial_results(&self) -> Option<&Vec<String>> {
+        self.best_effort.as_ref().filter(|_| self.simple)
+    }
+
+    /// All shards that haven't failed sent the message.
+    fn all(&self, count: usize) -> bool {
+        (count + self.counters.failed.len()) % self.shards == 0
+    }
+
     pub(super) fn reset(&mut self) {
         self.counters = Counters::default();
         self.buffer.reset();
+        if let Some(ref mut results) = self.results {
+            results.clear();
+        }
         // Don't reset:
         //  1. Route to keep routing decision
         //  2. Number of shards
@@ -72,8 +151,12 @@ impl MultiShard {
     }
 
     /// Check if the message should be sent to the client, skipped,
-    /// or modified.
-    pub(super) fn forward(&mut self, message: Message) -> Result<Option<Message>, super::Error> {
+    /// or modified. Shards are numbered in the order we're connected to them.
+    pub(super) fn forward(
+        &mut self,
+        shard: usize,
+        message: Message,
+    ) -> Result<Option<Message>, super::Error> {
         let mut forward = None;
 
         match message.code() {
@@ -92,46 +175,89 @@ impl MultiShard {
             // we can start aggregating and sorting.
             'C' => {
                 let cc = CommandComplete::from_bytes(message.to_bytes()?)?;
-                let has_rows = if let Some(rows) = cc.rows()? {
+                if let Some(rows) = cc.rows()? {
                     self.counters.rows += rows;
-                    true
-                } else {
-                    false
-                };
+                }
                 self.counters.command_complete_count += 1;
 
-                if self.counters.command_complete_count % self.shards == 0 {
-                    self.buffer.full();
-                    self.buffer
-                        .aggregate(self.route.aggregate(), &self.decoder)?;
-                    self.buffer.sort(self.route.order_by(), &self.decoder);
-                    self.buffer.distinct(self.route.distinct(), &self.decoder);
-
-                    if has_rows {
-                        let rows = if self.route.should_buffer() {
-                            self.buffer.len()
-                        } else {
-                            self.counters.rows
-                        };
-                        self.counters.command_complete = Some(cc.rewrite(rows)?.message()?);
-                    } else {
-                        forward = Some(cc.message()?);
+                if let Some(ref mut results) = self.results {
+                    results
+                        .entry(shard)
+                        .or_insert_with(|| ResultSet::new(shard, &self.decoder));
+                }
+
+                // Rows of this shard are complete, so they can be returned.
+                for row in self.counters.pending.remove(&shard).unwrap_or_default() {
+                    self.buffer.add(row)?;
+                }
+
+                if self.all(self.counters.command_complete_count) {
+                    forward = self.complete(cc)?;
+                } else {
+                    self.counters.waiting = Some(cc);
+                }
+            }
+
+            // An error from one of the shards of a read returns
+            // the rows from the other shards, if configured.
+            'E' if self.tolerate(&message)? => {
+                let error = ErrorResponse::from_bytes(message.to_bytes()?)?;
+                debug!(
+                    "shard {} failed, returning partial results: {}",
+                    shard, error
+                );
+                self.counters.failed.push((self.shard_number(shard), error));
+                self.counters.pending.remove(&shard);
+                if let Some(ref mut results) = self.results {
+                    results.remove(&shard);
+                }
+
+                // The other shards could be waiting for this one.
+                if self.counters.row_description + self.counters.failed.len() == self.shards {
+                    if let Some(rd) = self.counters.row_description_message.take() {
+                        self.counters.queue.push_back(rd);
+                    }
+                }
+                if self.all(self.counters.command_complete_count) {
+                    if let Some(cc) = self.counters.waiting.take() {
+                        if let Some(cc) = self.complete(cc)? {
+                            self.counters.queue.push_back(cc);
+                        }
                     }
                 }
             }
 
             'T' => {
                 self.counters.row_description += 1;
+                let rd = RowDescription::from_bytes(message.to_bytes()?)?;
                 // Set row description info as soon as we have it,
                 // so it's available to the aggregator and sorter.
-                if self.counters.row_description == 1 {
-                    let rd = RowDescription::from_bytes(message.to_bytes()?)?;
+                if (self.counters.row_description - 1) % self.shards == 0 {
                     self.decoder.row_description(&rd);
+                } else if !rd.equivalent(self.decoder.rd()) {
+                    // Rows from all shards are sent to the client
+                    // with the first description.
+                    return Err(super::Error::RowDescriptionMismatch);
                 }
-                if self.counters.row_description == self.shards {
+                // Shards return the input values of ordered-set aggregates,
+                // the client gets the aggregates.
+                // Shards return the rows of both sides of INTERSECT and EXCEPT,
+                // with their side, the client gets the result.
+                let message = match self.route.aggregate().ordered_sets() {
+                    targets if !targets.is_empty() && self.route.is_cross_shard() => {
+                        Buffer::ordered_set_description(targets, &rd).message()?
+                    }
+                    _ if self.route.set_operator().is_some() && self.route.is_cross_shard() => {
+                        Buffer::set_operation_description(&rd).message()?
+                    }
+                    _ => message,
+                };
+                if self.counters.row_description + self.counters.failed.len() == self.shards {
                     // Only send it to the client once all shards sent it,
                     // so we don't get early requests from clients.
                     forward = Some(message);
+                } else if self.partial_results().is_some() {
+                    self.counters.row_description_message = Some(message);
                 }
             }
 
@@ -143,8 +269,23 @@ impl MultiShard {
             }
 
             'D' => {
-                if !self.route.should_buffer() && self.counters.row_description % self.shards == 0 {
+                if self.partial_results().is_none()
+                    && !self.route.should_buffer()
+                    && self.counters.row_description % self.shards == 0
+                {
                     forward = Some(message);
+                } else if let Some(ref mut results) = self.results {
+                    results
+                        .entry(shard)
+                        .or_insert_with(|| ResultSet::new(shard, &self.decoder))
+                        .push(DataRow::from_bytes(message.to_bytes()?)?);
+                } else if self.partial_results().is_some() {
+                    // Rows of a shard that fails later aren't returned.
+                    self.counters
+                        .pending
+                        .entry(shard)
+                        .or_default()
+                        .push(message);
                 } else {
                     self.buffer.add(message)?;
                 }
@@ -157,6 +298,22 @@ impl MultiShard {
                 }
             }
 
+            // COPY ... TO STDOUT: rows from all shards are sent
+            // between one CopyOutResponse and one CopyDone.
+            'H' => {
+                self.counters.copy_out += 1;
+                if (self.counters.copy_out - 1) % self.shards == 0 {
+                    forward = Some(message);
+                }
+            }
+
+            'c' => {
+                self.counters.copy_done += 1;
+                if self.counters.copy_done % self.shards == 0 {
+                    forward = Some(message);
+                }
+            }
+
             'n' => {
                 self.counters.no_data += 1;
                 if self.counters.no_data % self.shards == 0 {
@@ -199,9 +356,80 @@ impl MultiShard {
         Ok(forward)
     }
 
+    /// All shards that didn't fail finished the command:
+    /// merge their rows and return the CommandComplete, unless it has to wait for them.
+    fn complete(&mut self, cc: CommandComplete) -> Result<Option<Message>, super::Error> {
+        self.buffer.full();
+        // Custom reducers get the rows of each shard, the built-in merge
+        // gets all the rows we buffered.
+        let custom = self.route.reducer().and_then(reducer::get);
+        let merge = reducer::Merge::new(&self.route);
+        let reducer = match custom {
+            Some(ref custom) => custom.as_ref(),
+            None => &merge as &dyn reducer::ResultReducer,
+        };
+        let per_shard = match self.results.as_mut() {
+            Some(results) => std::mem::take(results).into_values().collect(),
+            None => {
+                let mut result = ResultSet::new(0, &self.decoder);
+                for row in self.buffer.take_rows() {
+                    result.push(row);
+                }
+                vec![result]
+            }
+        };
+        self.buffer.reduce(reducer, per_shard)?;
+
+        if !self.counters.failed.is_empty() {
+            let notice = ErrorResponse::partial_result(&self.counters.failed);
+            self.counters
+                .queue
+                .push_back(NoticeResponse::from(notice).message()?);
+        }
+
+        if cc.rows()?.is_some() {
+            let rows = if self.route.should_buffer() || self.partial_results().is_some() {
+                self.buffer.len()
+            } else {
+                self.counters.rows
+            };
+            self.counters.command_complete = Some(cc.rewrite(rows)?.message()?);
+            Ok(None)
+        } else if self.counters.queue.is_empty() {
+            Ok(Some(cc.message()?))
+        } else {
+            self.counters.command_complete = Some(cc.message()?);
+            Ok(None)
+        }
+    }
+
+    /// The error is from one of the shards of a read,
+    /// and the client prefers rows from the other shards to the error.
+    fn tolerate(&self, message: &Message) -> Result<bool, super::Error> {
+        let Some(sqlstates) = self.partial_results() else {
+            return Ok(false);
+        };
+        // Return the error if every shard failed.
+        if self.counters.failed.len() + 1 >= self.shards {
+            return Ok(false);
+        }
+        let error = ErrorResponse::from_bytes(message.to_bytes()?)?;
+        Ok(sqlstates.is_empty() || sqlstates.contains(&error.code))
+    }
+
+    /// Shard number of the n-th shard we're connected to.
+    fn shard_number(&self, shard: usize) -> usize {
+        match self.route.shard() {
+            Shard::Multi(shards) => shards.get(shard).copied().unwrap_or(shard),
+            _ => shard,
+        }
+    }
+
     /// Multi-shard state is ready to send messages.
     pub(super) fn message(&mut self) -> Option<Message> {
-        if let Some(data_row) = self.buffer.take() {
+        if let Some(message) = self.counters.queue.pop_front() {
+            Some(message)
+        } else if let Some(data_row) = self.buffer.take() {
             Some(data_row)
         } else {
             self.counters.command_complete.take()
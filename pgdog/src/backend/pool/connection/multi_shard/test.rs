@@ -1,4 +1,10 @@
-use crate::net::{DataRow, Field};
+use std::sync::Arc;
+
+use bytes::BufMut;
+
+use crate::net::{
+    messages::data_row::Data, CopyData, CopyDone, DataRow, Field, Format, Parse, Payload, Query,
+};
 
 use super::*;
 
@@ -10,16 +16,16 @@ fn test_rd_before_dr() {
     dr.add(1i64);
     for _ in 0..2 {
         let result = multi_shard
-            .forward(rd.message().unwrap().backend())
+            .forward(0, rd.message().unwrap().backend())
             .unwrap();
         assert!(result.is_none()); // dropped
         let result = multi_shard
-            .forward(dr.message().unwrap().backend())
+            .forward(0, dr.message().unwrap().backend())
             .unwrap();
         assert!(result.is_none()); // buffered.
     }
 
-    let result = multi_shard.forward(rd.message().unwrap()).unwrap();
+    let result = multi_shard.forward(0, rd.message().unwrap()).unwrap();
     assert_eq!(result, Some(rd.message().unwrap()));
     let result = multi_shard.message();
     // Waiting for command complete
@@ -28,6 +34,7 @@ fn test_rd_before_dr() {
     for _ in 0..3 {
         let result = multi_shard
             .forward(
+                0,
                 CommandComplete::from_str("SELECT 1")
                     .message()
                     .unwrap()
@@ -59,3 +66,435 @@ fn test_rd_before_dr() {
     // Buffer is empty.
     assert!(multi_shard.message().is_none());
 }
+
+#[test]
+fn test_returning_row_description() {
+    let route = Route::write(None);
+    let rd = RowDescription::new(&[Field::bigint("id"), Field::text("email")]);
+
+    let mut multi_shard = MultiShard::new(2, &route);
+    let result = multi_shard.forward(0, rd.message().unwrap()).unwrap();
+    assert!(result.is_none());
+    // Table OIDs are different on each shard.
+    let mut other = rd.clone();
+    Arc::make_mut(&mut other.fields)[0].table_oid = 1234;
+    let result = multi_shard.forward(0, other.message().unwrap()).unwrap();
+    assert_eq!(result, Some(other.message().unwrap()));
+
+    for _ in 0..2 {
+        let mut dr = DataRow::new();
+        dr.add(1i64).add("test@test.com");
+        let result = multi_shard.forward(0, dr.message().unwrap()).unwrap();
+        assert!(result.is_some());
+    }
+
+    let mut multi_shard = MultiShard::new(2, &route);
+    multi_shard.forward(0, rd.message().unwrap()).unwrap();
+    let other = RowDescription::new(&[Field::text("id"), Field::text("email")]);
+    assert!(matches!(
+        multi_shard.forward(0, other.message().unwrap()),
+        Err(super::super::Error::RowDescriptionMismatch)
+    ));
+}
+
+#[test]
+fn test_reducer() {
+    reducer::register("test_multi_shard", reducer::Concat);
+    let route = Route::read(None).set_reducer(Some("test_multi_shard".into()));
+    let rd = RowDescription::new(&[Field::bigint("id")]);
+    let mut multi_shard = MultiShard::new(2, &route);
+
+    for shard in [1, 0] {
+        multi_shard.forward(shard, rd.message().unwrap()).unwrap();
+    }
+
+    // Shard 1 answers first, but rows are returned in shard order.
+    for (shard, id) in [(1, 11_i64), (0, 1), (1, 12), (0, 2)] {
+        let mut dr = DataRow::new();
+        dr.add(id);
+        let result = multi_shard.forward(shard, dr.message().unwrap()).unwrap();
+        assert!(result.is_none());
+    }
+
+    for shard in [1, 0] {
+        multi_shard
+            .forward(
+                shard,
+                CommandComplete::from_str("SELECT 2").message().unwrap(),
+            )
+            .unwrap();
+    }
+
+    let mut ids = vec![];
+    while let Some(message) = multi_shard.message() {
+        if message.code() == 'D' {
+            let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+            ids.push(dr.get::<i64>(0, Format::Text).unwrap());
+        }
+    }
+    assert_eq!(ids, vec![1, 2, 11, 12]);
+}
+
+#[test]
+fn test_copy_out() {
+    let mut multi_shard = MultiShard::new(2, &Route::write(None));
+    let mut copy_out = Payload::named('H');
+    copy_out.put_u8(0);
+    copy_out.put_i16(0);
+    let copy_out = Message::new(copy_out.freeze()).backend();
+
+    let result = multi_shard.forward(0, copy_out.clone()).unwrap();
+    assert_eq!(result, Some(copy_out.clone()));
+    let result = multi_shard.forward(1, copy_out).unwrap();
+    assert!(result.is_none());
+
+    // Rows from both shards are sent as they arrive.
+    for (shard, row) in [(1, "11\n"), (0, "1\n")] {
+        let data = CopyData::new(row.as_bytes()).message().unwrap();
+        let result = multi_shard.forward(shard, data.clone()).unwrap();
+        assert_eq!(result, Some(data));
+    }
+
+    let done = CopyDone.message().unwrap();
+    assert!(multi_shard.forward(0, done.clone()).unwrap().is_none());
+    assert_eq!(multi_shard.forward(1, done.clone()).unwrap(), Some(done));
+
+    for shard in [0, 1] {
+        multi_shard
+            .forward(
+                shard,
+                CommandComplete::from_str("COPY 1").message().unwrap(),
+            )
+            .unwrap();
+    }
+    assert_eq!(
+        multi_shard.message(),
+        Some(CommandComplete::from_str("COPY 2").message().unwrap())
+    );
+}
+
+#[test]
+fn test_union() {
+    use crate::frontend::router::parser::{Aggregate, DistinctBy, Limit, Shard};
+
+    // SELECT id FROM a UNION [ALL] SELECT id FROM b, on two shards.
+    let union = |distinct| {
+        let route = Route::select(
+            Shard::All,
+            vec![],
+            Aggregate::default(),
+            Limit::default(),
+            distinct,
+        );
+        let rd = RowDescription::new(&[Field::bigint("id")]);
+        let mut multi_shard = MultiShard::new(2, &route);
+        let mut ids = vec![];
+        let mut collect = |message: Option<Message>| {
+            if let Some(message) = message.filter(|message| message.code() == 'D') {
+                let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+                ids.push(dr.get::<i64>(0, Format::Text).unwrap());
+            }
+        };
+
+        for shard in [0, 1] {
+            multi_shard.forward(shard, rd.message().unwrap()).unwrap();
+        }
+        // Each shard removed its own duplicates already.
+        for (shard, id) in [(0, 1_i64), (1, 1), (0, 2), (1, 3), (1, 2)] {
+            let mut dr = DataRow::new();
+            dr.add(id);
+            collect(multi_shard.forward(shard, dr.message().unwrap()).unwrap());
+        }
+        for (shard, rows) in [(0, "SELECT 2"), (1, "SELECT 3")] {
+            let cc = CommandComplete::from_str(rows).message().unwrap();
+            collect(multi_shard.forward(shard, cc).unwrap());
+        }
+        while let Some(message) = multi_shard.message() {
+            collect(Some(message));
+        }
+        ids
+    };
+
+    assert_eq!(union(Some(DistinctBy::Row)), vec![1, 2, 3]);
+    assert_eq!(union(None), vec![1, 1, 2, 3, 2]);
+}
+
+#[test]
+fn test_intersect() {
+    use crate::frontend::router::parser::{Aggregate, Limit, OrderBy, SetOperator, Shard};
+
+    // SELECT id FROM a INTERSECT SELECT id FROM b ORDER BY 1, on two shards.
+    // Shards return the rows of both sides, with their side.
+    let set_operation = |operator| {
+        let route = Route::select(
+            Shard::All,
+            vec![OrderBy::Asc(1)],
+            Aggregate::default(),
+            Limit::default(),
+            None,
+        )
+        .set_set_operator(Some(operator));
+        let rd = RowDescription::new(&[Field::bigint("id"), Field::integer("__pgdog_side")]);
+        let mut multi_shard = MultiShard::new(2, &route);
+        let mut ids = vec![];
+        let mut tag = None;
+
+        let mut forwarded = None;
+        for shard in [0, 1] {
+            forwarded = multi_shard.forward(shard, rd.message().unwrap()).unwrap();
+        }
+        // The client doesn't get the side.
+        let forwarded = RowDescription::from_bytes(forwarded.unwrap().to_bytes().unwrap()).unwrap();
+        assert_eq!(forwarded.fields.len(), 1);
+        assert_eq!(forwarded.field(0).unwrap().name, "id");
+
+        // 2 is on the left side on shard 0 and on the right side on shard 1.
+        for (shard, id, side) in [
+            (0, 1_i64, 1),
+            (0, 2, 1),
+            (0, 3, 2),
+            (1, 3, 1),
+            (1, 2, 2),
+            (1, 4, 2),
+        ] {
+            let mut dr = DataRow::new();
+            dr.add(id).add(side as i64);
+            assert!(multi_shard
+                .forward(shard, dr.message().unwrap())
+                .unwrap()
+                .is_none());
+        }
+        for (shard, rows) in [(0, "SELECT 3"), (1, "SELECT 3")] {
+            let cc = CommandComplete::from_str(rows).message().unwrap();
+            assert!(multi_shard.forward(shard, cc).unwrap().is_none());
+        }
+        while let Some(message) = multi_shard.message() {
+            match message.code() {
+                'D' => {
+                    let dr = DataRow::from_bytes(message.to_bytes().unwrap()).unwrap();
+                    assert_eq!(dr.len(), 1);
+                    ids.push(dr.get::<i64>(0, Format::Text).unwrap());
+                }
+                'C' => {
+                    let cc = CommandComplete::from_bytes(message.to_bytes().unwrap()).unwrap();
+                    tag = Some(cc.command().to_string());
+                }
+                _ => (),
+            }
+        }
+        (ids, tag.unwrap())
+    };
+
+    assert_eq!(
+        set_operation(SetOperator::Intersect),
+        (vec![2, 3], "SELECT 2".to_string())
+    );
+    assert_eq!(
+        set_operation(SetOperator::Except),
+        (vec![1], "SELECT 1".to_string())
+    );
+}
+
+#[test]
+fn test_ordered_sets() {
+    use crate::frontend::router::parser::{Aggregate, Limit, Shard};
+    use pg_query::NodeEnum;
+
+    let ast = pg_query::parse(
+        "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v), \
+        percentile_cont(0.25) WITHIN GROUP (ORDER BY v) q, \
+        percentile_disc(0.5) WITHIN GROUP (ORDER BY v), \
+        mode() WITHIN GROUP (ORDER BY v) FROM t",
+    )
+    .unwrap();
+    let Some(NodeEnum::SelectStmt(ref stmt)) = ast.protobuf.stmts[0].stmt.as_ref().unwrap().node
+    else {
+        panic!("not a select");
+    };
+    let aggregate = Aggregate::parse(stmt).unwrap();
+    let route = Route::select(Shard::All, vec![], aggregate, Limit::default(), None);
+    let mut multi_shard = MultiShard::new(2, &route);
+
+    // Shards return `SELECT v, v, v, v FROM t` instead.
+    let rd = RowDescription::new(&[
+        Field::integer("v"),
+        Field::integer("v"),
+        Field::integer("v"),
+        Field::integer("v"),
+    ]);
+    let mut messages = vec![];
+    for shard in [0, 1] {
+        messages.extend(multi_shard.forward(shard, rd.message().unwrap()).unwrap());
+    }
+    let values = [
+        (0, Some(1)),
+        (0, Some(3)),
+        (0, Some(5)),
+        (0, Some(7)),
+        (0, Some(9)),
+        (1, Some(10)),
+        (1, None),
+        (1, Some(2)),
+        (1, Some(4)),
+        (1, Some(6)),
+        (1, Some(8)),
+        (1, Some(10)),
+    ];
+    for (shard, value) in values {
+        let mut dr = DataRow::new();
+        for _ in 0..4 {
+            match value {
+                Some(value) => dr.add(value as i64),
+                None => dr.add(Data::null()),
+            };
+        }
+        messages.extend(multi_shard.forward(shard, dr.message().unwrap()).unwrap());
+    }
+    for (shard, rows) in [(0, "SELECT 5"), (1, "SELECT 7")] {
+        let cc = CommandComplete::from_str(rows).message().unwrap();
+        messages.extend(multi_shard.forward(shard, cc).unwrap());
+    }
+    while let Some(message) = multi_shard.message() {
+        messages.push(message);
+    }
+
+    assert_eq!(messages.iter().map(|m| m.code()).collect::<String>(), "TDC");
+
+    let rd = RowDescription::from_bytes(messages[0].to_bytes().unwrap()).unwrap();
+    let fields = rd
+        .fields
+        .iter()
+        .map(|field| (field.name.as_str(), field.type_oid))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec![
+            ("percentile_cont", 701),
+            ("q", 701),
+            ("percentile_disc", 23),
+            ("mode", 23)
+        ]
+    );
+
+    // Same as Postgres with all the rows on one node.
+    let dr = DataRow::from_bytes(messages[1].to_bytes().unwrap()).unwrap();
+    let row = (0..4)
+        .map(|index| dr.get::<String>(index, Format::Text).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(row, vec!["6", "3.5", "6", "10"]);
+
+    let cc = CommandComplete::from_bytes(messages[2].to_bytes().unwrap()).unwrap();
+    assert_eq!(cc.rows().unwrap(), Some(1));
+}
+
+#[test]
+fn test_fan_out_errors() {
+    use crate::net::{ErrorResponse, ReadyForQuery};
+
+    // Shard 1 fails after sending one row, e.g. on statement timeout.
+    // The error arrives before or after the other shards' RowDescription.
+    let run = |best_effort: Option<Vec<&str>>, error_first: bool| {
+        let mut multi_shard = MultiShard::new(3, &Route::read(None));
+        multi_shard.best_effort =
+            best_effort.map(|codes| codes.into_iter().map(String::from).collect());
+
+        let rd = RowDescription::new(&[Field::bigint("id")])
+            .message()
+            .unwrap();
+        let row = |id: i64| {
+            let mut dr = DataRow::new();
+            dr.add(id);
+            dr.message().unwrap()
+        };
+        let mut error = ErrorResponse::default();
+        error.code = "57014".into();
+        error.message = "canceling statement due to statement timeout".into();
+        let error = error.message().unwrap();
+        let cc = CommandComplete::from_str("SELECT 1").message().unwrap();
+
+        let mut messages = if error_first {
+            vec![(1, error)]
+        } else {
+            vec![(1, rd.clone()), (1, row(11)), (1, error)]
+        };
+        for shard in [0, 2] {
+            messages.extend([
+                (shard, rd.clone()),
+                (shard, row(shard as i64)),
+                (shard, cc.clone()),
+            ]);
+        }
+        if error_first {
+            messages.swap(0, 2);
+        }
+        for shard in 0..3 {
+            messages.push((shard, ReadyForQuery::idle().message().unwrap()));
+        }
+
+        let mut sent = vec![];
+        for (shard, message) in messages {
+            sent.extend(multi_shard.forward(shard, message).unwrap());
+            while let Some(message) = multi_shard.message() {
+                sent.push(message);
+            }
+        }
+        sent
+    };
+    let codes = |sent: &[Message]| sent.iter().map(|m| m.code()).collect::<String>();
+    let ids = |sent: &[Message]| {
+        sent.iter()
+            .filter(|m| m.code() == 'D')
+            .map(|m| {
+                DataRow::from_bytes(m.to_bytes().unwrap())
+                    .unwrap()
+                    .get::<i64>(0, Format::Text)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // The client gets the error.
+    let sent = run(None, false);
+    assert!(codes(&sent).contains('E'));
+    assert!(!codes(&sent).contains('C'));
+
+    // Errors not on the list fail the query too.
+    let sent = run(Some(vec!["08006"]), false);
+    assert!(codes(&sent).contains('E'));
+
+    for (best_effort, error_first) in [(vec![], false), (vec![], true), (vec!["57014"], false)] {
+        let sent = run(Some(best_effort), error_first);
+        assert_eq!(codes(&sent), "TNDDCZ");
+        assert_eq!(ids(&sent), vec![0, 2]);
+        assert_eq!(
+            sent[4],
+            CommandComplete::from_str("SELECT 2").message().unwrap()
+        );
+
+        let notice = ErrorResponse::from_bytes(sent[1].to_bytes().unwrap()).unwrap();
+        assert_eq!(notice.code, "01000");
+        assert!(notice.message.contains("shards 1 failed"));
+        assert!(notice.detail.unwrap().contains("57014"));
+    }
+
+    // Writes always fail.
+    let multi_shard = MultiShard::new(3, &Route::write(None));
+    assert!(multi_shard.best_effort.is_none());
+
+    // So do queries in the extended protocol, in transactions,
+    // and with more than one statement.
+    let mut multi_shard = MultiShard::new(3, &Route::read(None));
+    multi_shard.best_effort = Some(vec![]);
+    let query = |query: &str| Request::from(vec![Query::new(query).into()]);
+    multi_shard.request(&query("SELECT * FROM sharded"), false);
+    assert!(multi_shard.partial_results().is_some());
+    multi_shard.request(&query("SELECT * FROM sharded"), true);
+    assert!(multi_shard.partial_results().is_none());
+    multi_shard.request(&query("SELECT 1; SELECT 2"), false);
+    assert!(multi_shard.partial_results().is_none());
+    multi_shard.request(
+        &Request::from(vec![Parse::new_anonymous("SELECT 1").into()]),
+        false,
+    );
+    assert!(multi_shard.partial_results().is_none());
+}
@@ -0,0 +1,227 @@
+//! Combine rows returned by multiple shards into one result.
+//!
+//! The built-in reducers handle aggregates, ORDER BY, DISTINCT and LIMIT.
+//! Custom reducers can be registered by name and picked per query
+//! with a comment, e.g. `/* pgdog_reducer: percentiles */`.
+
+use std::{collections::HashMap, fmt::Debug, sync::Arc};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+use crate::{
+    frontend::router::Route,
+    net::{messages::DataRow, Decoder},
+};
+
+use super::{buffer::Buffer, Error};
+
+static REDUCERS: Lazy<Mutex<HashMap<String, Arc<dyn ResultReducer>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Rows returned by one shard, or the rows produced by a reducer.
+#[derive(Debug, Clone, Default)]
+pub struct ResultSet {
+    shard: usize,
+    rows: Vec<DataRow>,
+    decoder: Decoder,
+}
+
+impl ResultSet {
+    /// Create empty result set for a shard. Shards are numbered
+    /// in the order we connected to them for this query.
+    pub fn new(shard: usize, decoder: &Decoder) -> Self {
+        Self {
+            shard,
+            rows: vec![],
+            decoder: decoder.clone(),
+        }
+    }
+
+    /// Shard that returned these rows.
+    pub fn shard(&self) -> usize {
+        self.shard
+    }
+
+    /// Rows in the order they were received.
+    pub fn rows(&self) -> &[DataRow] {
+        &self.rows
+    }
+
+    /// Decoder for the rows' columns.
+    pub fn decoder(&self) -> &Decoder {
+        &self.decoder
+    }
+
+    /// Add a row.
+    pub fn push(&mut self, row: DataRow) {
+        self.rows.push(row);
+    }
+
+    /// Take the rows.
+    pub fn into_rows(self) -> Vec<DataRow> {
+        self.rows
+    }
+}
+
+/// Combines results from all shards into one.
+///
+/// Reducers can't change the columns, so the result uses the
+/// same RowDescription as the shards.
+pub trait ResultReducer: Debug + Send + Sync {
+    fn reduce(&self, per_shard: Vec<ResultSet>) -> Result<ResultSet, Error>;
+}
+
+/// Register a reducer so queries can use it by name.
+pub fn register(name: &str, reducer: impl ResultReducer + 'static) {
+    REDUCERS.lock().insert(name.to_string(), Arc::new(reducer));
+}
+
+/// Get a registered reducer.
+pub fn get(name: &str) -> Option<Arc<dyn ResultReducer>> {
+    REDUCERS.lock().get(name).cloned()
+}
+
+/// Rows from all shards, one shard after another.
+#[derive(Debug, Default)]
+pub struct Concat;
+
+impl ResultReducer for Concat {
+    fn reduce(&self, per_shard: Vec<ResultSet>) -> Result<ResultSet, Error> {
+        let decoder = per_shard
+            .first()
+            .map(|result| result.decoder().clone())
+            .unwrap_or_default();
+        let mut result = ResultSet::new(0, &decoder);
+
+        for shard in per_shard {
+            result.rows.extend(shard.into_rows());
+        }
+
+        Ok(result)
+    }
+}
+
+/// Aggregates, ORDER BY, DISTINCT and LIMIT, as required by the route.
+#[derive(Debug)]
+pub struct Merge<'a> {
+    route: &'a Route,
+}
+
+impl<'a> Merge<'a> {
+    pub fn new(route: &'a Route) -> Self {
+        Self { route }
+    }
+}
+
+impl ResultReducer for Merge<'_> {
+    fn reduce(&self, per_shard: Vec<ResultSet>) -> Result<ResultSet, Error> {
+        let result = Concat.reduce(per_shard)?;
+        let mut buffer = Buffer::default();
+        let decoder = result.decoder().clone();
+
+        for row in result.into_rows() {
+            buffer.push(row);
+        }
+        buffer.merge(self.route, &decoder)?;
+
+        let mut result = ResultSet::new(0, &decoder);
+        result.rows = buffer.into_rows();
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        frontend::router::parser::OrderBy,
+        net::{Field, Format, RowDescription},
+    };
+
+    /// Tags each row with the shard it came from.
+    #[derive(Debug)]
+    struct Tag;
+
+    impl ResultReducer for Tag {
+        fn reduce(&self, per_shard: Vec<ResultSet>) -> Result<ResultSet, Error> {
+            let decoder = per_shard[0].decoder().clone();
+            let mut result = ResultSet::new(0, &decoder);
+
+            for shard in per_shard {
+                let tag = format!("shard_{}", shard.shard());
+                for row in shard.rows() {
+                    let mut tagged = DataRow::new();
+                    tagged.add(row.get::<i64>(0, Format::Text).unwrap());
+                    tagged.add(tag.as_str());
+                    result.push(tagged);
+                }
+            }
+
+            Ok(result)
+        }
+    }
+
+    fn results(decoder: &Decoder) -> Vec<ResultSet> {
+        [[3_i64, 1], [4, 2]]
+            .into_iter()
+            .enumerate()
+            .map(|(shard, ids)| {
+                let mut result = ResultSet::new(shard, decoder);
+                for id in ids {
+                    let mut dr = DataRow::new();
+                    dr.add(id).add("");
+                    result.push(dr);
+                }
+                result
+            })
+            .collect()
+    }
+
+    fn ids(result: &ResultSet) -> Vec<i64> {
+        result
+            .rows()
+            .iter()
+            .map(|row| row.get::<i64>(0, Format::Text).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_custom_reducer() {
+        let rd = RowDescription::new(&[Field::bigint("id"), Field::text("tag")]);
+        let decoder = Decoder::from(&rd);
+
+        register("test_tag", Tag);
+        let reducer = get("test_tag").unwrap();
+        assert!(get("test_missing").is_none());
+
+        let result = reducer.reduce(results(&decoder)).unwrap();
+        assert_eq!(ids(&result), vec![3, 1, 4, 2]);
+        let tags = result
+            .rows()
+            .iter()
+            .map(|row| row.get::<String>(1, Format::Text).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(tags, vec!["shard_0", "shard_0", "shard_1", "shard_1"]);
+    }
+
+    #[test]
+    fn test_built_in_reducers() {
+        let rd = RowDescription::new(&[Field::bigint("id"), Field::text("tag")]);
+        let decoder = Decoder::from(&rd);
+
+        let result = Concat.reduce(results(&decoder)).unwrap();
+        assert_eq!(ids(&result), vec![3, 1, 4, 2]);
+
+        let route = Route::select(
+            None.into(),
+            vec![OrderBy::Asc(1)],
+            Default::default(),
+            Default::default(),
+            None,
+        );
+        let result = Merge::new(&route).reduce(results(&decoder)).unwrap();
+        assert_eq!(ids(&result), vec![1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,204 @@
+//! Snapshots of cross-shard read-only transactions.
+//!
+//! Shards are separate servers, so they can't share a snapshot. Instead, we start
+//! the transaction on every shard and export their snapshots at the same time,
+//! so the shards see their data from as close to the same moment as we can get.
+//! It's best effort: a write committing on one shard while the snapshots are taken
+//! can still be seen by some shards and not others. If a shard can't take part,
+//! the transaction starts like any other, without the snapshots.
+
+use async_trait::async_trait;
+use futures::future::join_all;
+use tracing::{debug, warn};
+
+use super::{Error, Guard};
+use crate::backend::Server;
+
+/// Server taking part in a coordinated snapshot.
+#[async_trait]
+pub trait Participant: Send {
+    /// Server name, used in errors.
+    fn name(&self) -> String;
+
+    /// Start the transaction.
+    async fn begin(&mut self, query: &str) -> Result<(), Error>;
+
+    /// Export the transaction's snapshot and return its identifier.
+    async fn export_snapshot(&mut self) -> Result<String, Error>;
+
+    /// Run a query without checking for errors,
+    /// like transactions without snapshots are started.
+    async fn query(&mut self, query: &str) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl Participant for Guard {
+    fn name(&self) -> String {
+        self.addr().to_string()
+    }
+
+    async fn begin(&mut self, query: &str) -> Result<(), Error> {
+        self.execute_checked(query).await?;
+        Ok(())
+    }
+
+    async fn export_snapshot(&mut self) -> Result<String, Error> {
+        self.fetch_all::<String>("SELECT pg_export_snapshot()")
+            .await?
+            .pop()
+            .ok_or(Error::DecoderRowError)
+    }
+
+    async fn query(&mut self, query: &str) -> Result<(), Error> {
+        Server::execute(self, query).await?;
+        Ok(())
+    }
+}
+
+/// Start the transaction on all servers and take their snapshots together.
+/// Returns the snapshot identifiers, in the order of the servers.
+///
+/// If any server can't start the transaction or export its snapshot, we roll back
+/// the transaction on all of them and start it again without the snapshots.
+/// Returns `None` in that case.
+pub async fn synchronize<P: Participant>(
+    participants: &mut [P],
+    begin: &str,
+) -> Result<Option<Vec<String>>, Error> {
+    match snapshots(participants, begin).await {
+        Ok(snapshots) => Ok(Some(snapshots)),
+        Err(err) => {
+            warn!("{}, starting the transaction without snapshots", err);
+
+            for participant in participants.iter_mut() {
+                participant.query("ROLLBACK").await?;
+                participant.query(begin).await?;
+            }
+
+            Ok(None)
+        }
+    }
+}
+
+/// Start the transaction and export the snapshot on all servers.
+async fn snapshots<P: Participant>(
+    participants: &mut [P],
+    begin: &str,
+) -> Result<Vec<String>, Error> {
+    for participant in participants.iter_mut() {
+        participant
+            .begin(begin)
+            .await
+            .map_err(|err| Error::SnapshotUnavailable(participant.name(), Box::new(err)))?;
+    }
+
+    let names = participants.iter().map(|p| p.name()).collect::<Vec<_>>();
+    let snapshots = join_all(participants.iter_mut().map(|p| p.export_snapshot())).await;
+
+    let snapshots = snapshots
+        .into_iter()
+        .zip(names)
+        .map(|(snapshot, name)| {
+            snapshot.map_err(|err| Error::SnapshotUnavailable(name, Box::new(err)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    debug!("synchronized snapshots: {}", snapshots.join(", "));
+
+    Ok(snapshots)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[derive(Default)]
+    struct Mock {
+        shard: usize,
+        fail_begin: bool,
+        fail_export: bool,
+        queries: Vec<String>,
+    }
+
+    #[async_trait]
+    impl Participant for Mock {
+        fn name(&self) -> String {
+            format!("shard {}", self.shard)
+        }
+
+        async fn begin(&mut self, query: &str) -> Result<(), Error> {
+            self.queries.push(query.to_string());
+            if self.fail_begin {
+                Err(Error::NotConnected)
+            } else {
+                Ok(())
+            }
+        }
+
+        async fn export_snapshot(&mut self) -> Result<String, Error> {
+            self.queries.push("SELECT pg_export_snapshot()".into());
+            if self.fail_export {
+                Err(Error::ReadTimeout)
+            } else {
+                Ok(format!("00000003-0000000{}-1", self.shard))
+            }
+        }
+
+        async fn query(&mut self, query: &str) -> Result<(), Error> {
+            self.queries.push(query.to_string());
+            Ok(())
+        }
+    }
+
+    fn mocks() -> Vec<Mock> {
+        (0..3)
+            .map(|shard| Mock {
+                shard,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_synchronize() {
+        let begin = "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY";
+        let mut participants = mocks();
+
+        let snapshots = synchronize(&mut participants, begin).await.unwrap();
+        assert_eq!(
+            snapshots.unwrap(),
+            vec![
+                "00000003-00000000-1",
+                "00000003-00000001-1",
+                "00000003-00000002-1"
+            ]
+        );
+        for participant in &participants {
+            assert_eq!(
+                participant.queries,
+                vec![begin, "SELECT pg_export_snapshot()"]
+            );
+        }
+
+        // A shard can't export its snapshot: the transaction
+        // starts again on all of them, without snapshots.
+        let mut participants = mocks();
+        participants[1].fail_export = true;
+        let snapshots = synchronize(&mut participants, begin).await.unwrap();
+        assert!(snapshots.is_none());
+        for participant in &participants {
+            assert_eq!(
+                participant.queries,
+                vec![begin, "SELECT pg_export_snapshot()", "ROLLBACK", begin]
+            );
+        }
+
+        let mut participants = mocks();
+        participants[2].fail_begin = true;
+        let snapshots = synchronize(&mut participants, begin).await.unwrap();
+        assert!(snapshots.is_none());
+        for participant in &participants {
+            assert_eq!(participant.queries, vec![begin, "ROLLBACK", begin]);
+        }
+    }
+}
@@ -42,6 +42,9 @@ pub enum Error {
     #[error("no databases")]
     NoDatabases,
 
+    #[error("no analytics replicas")]
+    NoAnalytics,
+
     #[error("config values contain null bytes")]
     NullBytes,
 
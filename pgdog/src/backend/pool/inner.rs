@@ -8,7 +8,7 @@ use crate::net::messages::BackendKeyData;
 
 use tokio::time::Instant;
 
-use super::{Ban, Config, Error, Mapping, Oids, Pool, Request, Stats, Taken, Waiter};
+use super::{Ban, Config, Error, Mapping, Oids, Pool, ReplicaLag, Request, Stats, Taken, Waiter};
 
 /// Pool internals protected by a mutex.
 #[derive(Default)]
@@ -41,6 +41,8 @@ pub(super) struct Inner {
     pub(super) stats: Stats,
     /// OIDs.
     pub(super) oids: Option<Oids>,
+    /// Replication lag, measured during healthchecks.
+    pub(super) replica_lag: ReplicaLag,
     /// The pool has been changed and connections should be returned
     /// to the new pool.
     moved: Option<Pool>,
@@ -76,6 +78,7 @@ impl Inner {
             errors: 0,
             stats: Stats::default(),
             oids: None,
+            replica_lag: ReplicaLag::default(),
             moved: None,
             id,
         }
@@ -0,0 +1,47 @@
+//! Replication lag of a replica.
+
+use std::time::Duration;
+
+use crate::backend::{Error, Server};
+use crate::net::messages::{DataRow, Format};
+
+/// How far behind the primary, in milliseconds, going by the time of the last
+/// replayed transaction. NULL on the primary and when we can't tell, e.g. the replica
+/// isn't receiving WAL from the primary, so it could be any amount behind.
+static QUERY: &str = "SELECT CASE
+    WHEN NOT pg_is_in_recovery() THEN NULL
+    WHEN NOT EXISTS (SELECT 1 FROM pg_stat_wal_receiver) THEN NULL
+    ELSE (EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp()) * 1000)::bigint
+END";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ReplicaLag {
+    lag: Option<Duration>,
+}
+
+impl From<DataRow> for ReplicaLag {
+    fn from(value: DataRow) -> Self {
+        let lag = value
+            .get::<i64>(0, Format::Text)
+            .map(|millis| Duration::from_millis(millis.max(0) as u64));
+
+        Self { lag }
+    }
+}
+
+impl ReplicaLag {
+    /// Replica this far behind the primary.
+    pub fn new(lag: Duration) -> Self {
+        Self { lag: Some(lag) }
+    }
+
+    pub(super) async fn load(server: &mut Server) -> Result<Self, Error> {
+        let lag: Vec<ReplicaLag> = server.fetch_all(QUERY).await?;
+        Ok(lag.first().copied().unwrap_or_default())
+    }
+
+    /// Lag, if the server is a replica.
+    pub fn lag(&self) -> Option<Duration> {
+        self.lag
+    }
+}
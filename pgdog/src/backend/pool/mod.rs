@@ -11,6 +11,7 @@ pub mod error;
 pub mod guard;
 pub mod healthcheck;
 pub mod inner;
+pub mod lag;
 pub mod mapping;
 pub mod monitor;
 pub mod oids;
@@ -18,6 +19,7 @@ pub mod pool_impl;
 pub mod replicas;
 pub mod request;
 pub mod shard;
+pub mod shard_sql;
 pub mod state;
 pub mod stats;
 pub mod taken;
@@ -30,6 +32,7 @@ pub use connection::Connection;
 pub use error::Error;
 pub use guard::Guard;
 pub use healthcheck::Healtcheck;
+pub use lag::ReplicaLag;
 use monitor::Monitor;
 pub use oids::Oids;
 pub use pool_impl::Pool;
@@ -34,7 +34,7 @@
 
 use std::time::Duration;
 
-use super::{Error, Guard, Healtcheck, Oids, Pool, Request};
+use super::{Error, Guard, Healtcheck, Oids, Pool, ReplicaLag, Request};
 use crate::backend::Server;
 
 use tokio::time::{interval, sleep, timeout, Instant};
@@ -255,6 +255,19 @@ impl Monitor {
         Ok(())
     }
 
+    /// Measure how far behind the primary the server is, so reads
+    /// with a maximum staleness can skip it. Uses the healthcheck's connection,
+    /// so it doesn't wait behind clients for one.
+    async fn replica_lag(pool: &Pool, server: &mut Server) {
+        let healthcheck_timeout = pool.config().healthcheck_timeout;
+
+        let lag = timeout(healthcheck_timeout, ReplicaLag::load(server)).await;
+
+        // If we can't tell, the replica won't be used
+        // for reads with a maximum staleness.
+        pool.lock().replica_lag = lag.ok().and_then(|lag| lag.ok()).unwrap_or_default();
+    }
+
     /// Perform a periodic healthcheck on the pool.
     async fn healthcheck(pool: &Pool) -> Result<bool, Error> {
         let conn = {
@@ -269,13 +282,11 @@ impl Monitor {
 
         // Have an idle connection, use that for the healthcheck.
         if let Some(conn) = conn {
-            Healtcheck::mandatory(
-                &mut Guard::new(pool.clone(), conn, Instant::now()),
-                pool,
-                healthcheck_timeout,
-            )
-            .healthcheck()
-            .await?;
+            let mut guard = Guard::new(pool.clone(), conn, Instant::now());
+            Healtcheck::mandatory(&mut guard, pool, healthcheck_timeout)
+                .healthcheck()
+                .await?;
+            Self::replica_lag(pool, &mut guard).await;
 
             Ok(true)
         } else {
@@ -289,6 +300,7 @@ impl Monitor {
             Healtcheck::mandatory(&mut server, pool, healthcheck_timeout)
                 .healthcheck()
                 .await?;
+            Self::replica_lag(pool, &mut server).await;
 
             Ok(true)
         }
@@ -416,4 +416,10 @@ impl Pool {
     pub fn oids(&self) -> Option<Oids> {
         self.lock().oids
     }
+
+    /// How far behind the primary this replica is.
+    /// `None` for the primary or if we don't know yet.
+    pub fn replica_lag(&self) -> Option<Duration> {
+        self.lock().replica_lag.lag()
+    }
 }
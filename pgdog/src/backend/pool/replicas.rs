@@ -47,7 +47,23 @@ impl Replicas {
 
     /// Get a live connection from the pool.
     pub async fn get(&self, request: &Request, primary: &Option<Pool>) -> Result<Guard, Error> {
-        match timeout(self.checkout_timeout, self.get_internal(request, primary)).await {
+        self.get_preferred(request, primary, None).await
+    }
+
+    /// Get a live connection, trying the pool with the `preferred` ID first.
+    /// Other pools are used if it's down or no longer exists.
+    pub async fn get_preferred(
+        &self,
+        request: &Request,
+        primary: &Option<Pool>,
+        preferred: Option<u64>,
+    ) -> Result<Guard, Error> {
+        match timeout(
+            self.checkout_timeout,
+            self.get_internal(request, primary, preferred),
+        )
+        .await
+        {
             Ok(Ok(conn)) => Ok(conn),
             Ok(Err(err)) => Err(err),
             Err(_) => Err(Error::ReplicaCheckoutTimeout),
@@ -102,39 +118,72 @@ impl Replicas {
         Ok(())
     }
 
+    /// Replicas at most `max_staleness` behind the primary.
+    /// Replicas with unknown lag aren't included.
+    pub fn fresh(&self, max_staleness: Duration) -> Replicas {
+        Self {
+            pools: self
+                .pools
+                .iter()
+                .filter(|pool| pool.replica_lag().is_some_and(|lag| lag <= max_staleness))
+                .cloned()
+                .collect(),
+            ..self.clone()
+        }
+    }
+
     /// Pools handle.
     pub fn pools(&self) -> &[Pool] {
         &self.pools
     }
 
+    /// Pools to try, in order.
+    pub(super) fn candidates<'a>(
+        &'a self,
+        primary: &'a Option<Pool>,
+        preferred: Option<u64>,
+    ) -> Vec<&'a Pool> {
+        let mut candidates = self.pools.iter().collect::<Vec<_>>();
+
+        if let Some(primary) = primary {
+            candidates.push(primary);
+        }
+
+        use LoadBalancingStrategy::*;
+
+        match self.lb_strategy {
+            Random => candidates.shuffle(&mut rand::thread_rng()),
+            RoundRobin => {
+                let first = self.round_robin.fetch_add(1, Ordering::Relaxed) % candidates.len();
+                let mut reshuffled = vec![];
+                reshuffled.extend_from_slice(&candidates[first..]);
+                reshuffled.extend_from_slice(&candidates[..first]);
+                candidates = reshuffled;
+            }
+            LeastActiveConnections => {
+                candidates.sort_by_cached_key(|pool| pool.lock().idle());
+            }
+        }
+
+        if let Some(position) =
+            preferred.and_then(|id| candidates.iter().position(|pool| pool.id() == id))
+        {
+            let pool = candidates.remove(position);
+            candidates.insert(0, pool);
+        }
+
+        candidates
+    }
+
     async fn get_internal(
         &self,
         request: &Request,
         primary: &Option<Pool>,
+        preferred: Option<u64>,
     ) -> Result<Guard, Error> {
         let mut unbanned = false;
         loop {
-            let mut candidates = self.pools.iter().collect::<Vec<_>>();
-
-            if let Some(primary) = primary {
-                candidates.push(primary);
-            }
-
-            use LoadBalancingStrategy::*;
-
-            match self.lb_strategy {
-                Random => candidates.shuffle(&mut rand::thread_rng()),
-                RoundRobin => {
-                    let first = self.round_robin.fetch_add(1, Ordering::Relaxed) % candidates.len();
-                    let mut reshuffled = vec![];
-                    reshuffled.extend_from_slice(&candidates[first..]);
-                    reshuffled.extend_from_slice(&candidates[..first]);
-                    candidates = reshuffled;
-                }
-                LeastActiveConnections => {
-                    candidates.sort_by_cached_key(|pool| pool.lock().idle());
-                }
-            }
+            let candidates = self.candidates(primary, preferred);
 
             let mut banned = 0;
 
@@ -1,5 +1,7 @@
 //! A shard is a collection of replicas and a primary.
 
+use std::time::Duration;
+
 use crate::{
     config::{LoadBalancingStrategy, ReadWriteSplit, Role},
     net::messages::BackendKeyData,
@@ -12,6 +14,8 @@ use super::{Error, Guard, Pool, PoolConfig, Replicas, Request};
 pub struct Shard {
     pub(super) primary: Option<Pool>,
     pub(super) replicas: Replicas,
+    /// Replicas used only by analytics queries.
+    pub(super) analytics: Replicas,
     pub(super) rw_split: ReadWriteSplit,
 }
 
@@ -20,15 +24,18 @@ impl Shard {
     pub fn new(
         primary: &Option<PoolConfig>,
         replicas: &[PoolConfig],
+        analytics: &[PoolConfig],
         lb_strategy: LoadBalancingStrategy,
         rw_split: ReadWriteSplit,
     ) -> Self {
         let primary = primary.as_ref().map(Pool::new);
         let replicas = Replicas::new(replicas, lb_strategy);
+        let analytics = Replicas::new(analytics, lb_strategy);
 
         Self {
             primary,
             replicas,
+            analytics,
             rw_split,
         }
     }
@@ -44,6 +51,16 @@ impl Shard {
 
     /// Get a connection to a shard replica, if any.
     pub async fn replica(&self, request: &Request) -> Result<Guard, Error> {
+        self.replica_preferred(request, None).await
+    }
+
+    /// Get a connection to a shard replica, trying the pool
+    /// with the `preferred` ID first.
+    pub async fn replica_preferred(
+        &self,
+        request: &Request,
+        preferred: Option<u64>,
+    ) -> Result<Guard, Error> {
         if self.replicas.is_empty() {
             self.primary
                 .as_ref()
@@ -58,7 +75,46 @@ impl Shard {
                 ExcludePrimary => &None,
             };
 
-            self.replicas.get(request, primary).await
+            self.replicas
+                .get_preferred(request, primary, preferred)
+                .await
+        }
+    }
+
+    /// Get a connection to an analytics replica. Analytics queries
+    /// are kept away from the primary and the other replicas.
+    pub async fn analytics(&self, request: &Request) -> Result<Guard, Error> {
+        if self.analytics.is_empty() {
+            return Err(Error::NoAnalytics);
+        }
+
+        self.analytics.get(request, &None).await
+    }
+
+    /// Get a connection to a replica at most `max_staleness` behind the primary.
+    /// The primary is used if none of the replicas are fresh enough.
+    pub async fn replica_within(
+        &self,
+        request: &Request,
+        max_staleness: Duration,
+    ) -> Result<Guard, Error> {
+        let replicas = self.replicas.fresh(max_staleness);
+
+        if replicas.is_empty() {
+            self.primary
+                .as_ref()
+                .ok_or(Error::NoPrimary)?
+                .get(request)
+                .await
+        } else {
+            use ReadWriteSplit::*;
+
+            let primary = match self.rw_split {
+                IncludePrimary => &self.primary,
+                ExcludePrimary => &None,
+            };
+
+            replicas.get(request, primary).await
         }
     }
 
@@ -72,6 +128,7 @@ impl Shard {
         }
 
         self.replicas.move_conns_to(&destination.replicas);
+        self.analytics.move_conns_to(&destination.analytics);
     }
 
     /// The two shards have the same databases.
@@ -87,6 +144,7 @@ impl Shard {
         }
 
         self.replicas.can_move_conns_to(&other.replicas)
+            && self.analytics.can_move_conns_to(&other.analytics)
     }
 
     /// Create new identical connection pool.
@@ -94,6 +152,7 @@ impl Shard {
         Self {
             primary: self.primary.as_ref().map(|primary| primary.duplicate()),
             replicas: self.replicas.duplicate(),
+            analytics: self.analytics.duplicate(),
             rw_split: self.rw_split,
         }
     }
@@ -104,6 +163,7 @@ impl Shard {
             primary.cancel(id).await?;
         }
         self.replicas.cancel(id).await?;
+        self.analytics.cancel(id).await?;
 
         Ok(())
     }
@@ -127,6 +187,12 @@ impl Shard {
                 .iter()
                 .map(|p| (Role::Replica, p.clone())),
         );
+        pools.extend(
+            self.analytics
+                .pools()
+                .iter()
+                .map(|p| (Role::Analytics, p.clone())),
+        );
 
         pools
     }
@@ -146,10 +212,160 @@ impl Shard {
 mod test {
     use std::collections::BTreeSet;
 
-    use crate::backend::pool::{Address, Config};
+    use crate::backend::pool::{Address, Config, ReplicaLag};
 
     use super::*;
 
+    #[test]
+    fn test_fresh_replicas() {
+        let config = || PoolConfig {
+            address: Address::new_test(),
+            config: Config::default(),
+        };
+
+        let shard = Shard::new(
+            &Some(config()),
+            &[config(), config()],
+            &[],
+            LoadBalancingStrategy::Random,
+            ReadWriteSplit::ExcludePrimary,
+        );
+        let (stale, fresh) = (&shard.replicas.pools[0], &shard.replicas.pools[1]);
+        let max_staleness = Duration::from_millis(500);
+
+        // Lag isn't known until the first healthcheck.
+        assert!(shard.replicas.fresh(max_staleness).is_empty());
+
+        stale.lock().replica_lag = ReplicaLag::new(Duration::from_secs(5));
+        fresh.lock().replica_lag = ReplicaLag::new(Duration::from_millis(100));
+
+        let replicas = shard.replicas.fresh(max_staleness);
+        assert_eq!(replicas.len(), 1);
+        assert_eq!(replicas.pools()[0].id(), fresh.id());
+
+        // Both are too far behind, reads go to the primary.
+        fresh.lock().replica_lag = ReplicaLag::new(Duration::from_secs(1));
+        assert!(shard.replicas.fresh(max_staleness).is_empty());
+        assert_eq!(shard.replicas.fresh(Duration::from_secs(10)).len(), 2);
+    }
+
+    #[test]
+    fn test_preferred_replica() {
+        let config = || PoolConfig {
+            address: Address::new_test(),
+            config: Config::default(),
+        };
+
+        for lb_strategy in [
+            LoadBalancingStrategy::Random,
+            LoadBalancingStrategy::RoundRobin,
+            LoadBalancingStrategy::LeastActiveConnections,
+        ] {
+            let shard = Shard::new(
+                &Some(config()),
+                &[config(), config(), config()],
+                &[],
+                lb_strategy,
+                ReadWriteSplit::IncludePrimary,
+            );
+            let preferred = shard.replicas.pools[1].id();
+
+            for _ in 0..10 {
+                let candidates = shard.replicas.candidates(&shard.primary, Some(preferred));
+                assert_eq!(candidates.len(), 4);
+                assert_eq!(candidates[0].id(), preferred);
+            }
+
+            // The pool is gone, e.g. after a config reload.
+            let candidates = shard.replicas.candidates(&shard.primary, Some(u64::MAX));
+            assert_eq!(candidates.len(), 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_analytics() {
+        let config = || PoolConfig {
+            address: Address::new_test(),
+            config: Config::default(),
+        };
+
+        let shard = Shard::new(
+            &Some(config()),
+            &[config()],
+            &[],
+            LoadBalancingStrategy::Random,
+            ReadWriteSplit::IncludePrimary,
+        );
+        // Never falls back to the primary or the other replicas.
+        assert!(matches!(
+            shard.analytics(&Request::default()).await,
+            Err(Error::NoAnalytics)
+        ));
+
+        let shard = Shard::new(
+            &Some(config()),
+            &[config()],
+            &[config(), config()],
+            LoadBalancingStrategy::Random,
+            ReadWriteSplit::IncludePrimary,
+        );
+        let roles = shard
+            .pools_with_roles()
+            .into_iter()
+            .map(|(role, _)| role)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            roles,
+            [
+                Role::Primary,
+                Role::Replica,
+                Role::Analytics,
+                Role::Analytics
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replica_within() {
+        crate::logger();
+
+        let config = || PoolConfig {
+            address: Address::new_test(),
+            config: Config::default(),
+        };
+
+        let shard = Shard::new(
+            &Some(config()),
+            &[config()],
+            &[],
+            LoadBalancingStrategy::Random,
+            ReadWriteSplit::ExcludePrimary,
+        );
+        shard.launch();
+
+        let primary_id = shard.primary.as_ref().unwrap().id();
+        let replica = &shard.replicas.pools[0];
+        let max_staleness = Duration::from_millis(500);
+
+        replica.lock().replica_lag = ReplicaLag::new(Duration::from_secs(5));
+        let conn = shard
+            .replica_within(&Request::default(), max_staleness)
+            .await
+            .unwrap();
+        assert_eq!(conn.pool.id(), primary_id);
+        drop(conn);
+
+        replica.lock().replica_lag = ReplicaLag::new(Duration::from_millis(100));
+        let conn = shard
+            .replica_within(&Request::default(), max_staleness)
+            .await
+            .unwrap();
+        assert_eq!(conn.pool.id(), replica.id());
+        drop(conn);
+
+        shard.shutdown();
+    }
+
     #[tokio::test]
     async fn test_exclude_primary() {
         crate::logger();
@@ -167,6 +383,7 @@ mod test {
         let shard = Shard::new(
             primary,
             replicas,
+            &[],
             LoadBalancingStrategy::Random,
             ReadWriteSplit::ExcludePrimary,
         );
@@ -199,6 +416,7 @@ mod test {
         let shard = Shard::new(
             primary,
             replicas,
+            &[],
             LoadBalancingStrategy::Random,
             ReadWriteSplit::IncludePrimary,
         );
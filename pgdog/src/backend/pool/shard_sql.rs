@@ -0,0 +1,91 @@
+//! Rewrite the SQL sent to each shard.
+//!
+//! Applications embedding pgdog can register a transform, e.g. to qualify
+//! tables with a schema that's named differently on each shard.
+//! It's called with the shard number and the query of every simple query
+//! and prepared statement, right before they're sent to the shard.
+//!
+//! The result should be one statement, since prepared statements can't have more,
+//! and shouldn't change session state, e.g. with `SET`: server connections
+//! are shared by all clients.
+
+use std::{fmt::Debug, sync::Arc};
+
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+
+static SHARD_SQL: Lazy<Mutex<Option<Arc<dyn ShardSql>>>> = Lazy::new(|| Mutex::new(None));
+
+/// Transform of the SQL sent to a shard.
+pub trait ShardSql: Debug + Send + Sync {
+    /// Query to send to the shard instead, or `None` to send it unchanged.
+    fn transform(&self, shard: usize, sql: &str) -> Option<String>;
+}
+
+/// Register the transform used by clusters created after this call,
+/// so it should be set before the configuration is loaded.
+pub fn set_shard_sql(shard_sql: Option<Arc<dyn ShardSql>>) {
+    *SHARD_SQL.lock() = shard_sql;
+}
+
+/// The registered transform, if any.
+pub fn shard_sql() -> Option<Arc<dyn ShardSql>> {
+    SHARD_SQL.lock().clone()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::ProtocolMessage,
+        frontend::Buffer,
+        net::{Parse, Query, Sync},
+    };
+
+    #[derive(Debug)]
+    struct SchemaPerShard;
+
+    impl ShardSql for SchemaPerShard {
+        fn transform(&self, shard: usize, sql: &str) -> Option<String> {
+            sql.contains(" users")
+                .then(|| sql.replace(" users", &format!(" shard_{}.users", shard)))
+        }
+    }
+
+    #[test]
+    fn test_transform() {
+        let buffer = Buffer::from(vec![
+            ProtocolMessage::from(Query::new("SELECT * FROM users")),
+            ProtocolMessage::from(Parse::named(
+                "test",
+                "UPDATE users SET name = $1 WHERE id = $2",
+            )),
+            ProtocolMessage::from(Sync),
+        ]);
+
+        for shard in [0, 1] {
+            let buffer = buffer.transformed(shard, &SchemaPerShard).unwrap();
+
+            let ProtocolMessage::Query(ref query) = buffer[0] else {
+                panic!("not a query");
+            };
+            assert_eq!(
+                query.query(),
+                format!("SELECT * FROM shard_{}.users", shard)
+            );
+            let ProtocolMessage::Parse(ref parse) = buffer[1] else {
+                panic!("not a parse");
+            };
+            assert_eq!(parse.name(), "test");
+            assert_eq!(
+                parse.query(),
+                format!("UPDATE shard_{}.users SET name = $1 WHERE id = $2", shard)
+            );
+            assert!(matches!(buffer[2], ProtocolMessage::Sync(_)));
+        }
+
+        // Nothing to change, nothing copied.
+        let buffer = Buffer::from(vec![ProtocolMessage::from(Query::new("SELECT 1"))]);
+        assert!(buffer.transformed(0, &SchemaPerShard).is_none());
+    }
+}
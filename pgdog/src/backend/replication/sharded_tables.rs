@@ -20,9 +20,31 @@ impl From<&[ShardedTable]> for ShardedTables {
 
 impl ShardedTables {
     pub fn new(tables: Vec<ShardedTable>, omnisharded_tables: Vec<String>, dry_run: bool) -> Self {
+        // Views are sharded like the table they select from.
+        let views = tables
+            .iter()
+            .flat_map(|table| {
+                table.views.iter().map(|view| ShardedTable {
+                    name: Some(view.clone()),
+                    views: vec![],
+                    ..table.clone()
+                })
+            })
+            .collect::<Vec<_>>();
+        let tables = tables.into_iter().chain(views);
+
+        // Replicated tables aren't sharded, so they can't be used
+        // to find sharding keys.
+        let (replicated, tables): (Vec<_>, Vec<_>) =
+            tables.into_iter().partition(|table| table.replicated);
+        let omnisharded = omnisharded_tables
+            .into_iter()
+            .chain(replicated.into_iter().filter_map(|table| table.name))
+            .collect();
+
         Self {
-            tables: Arc::new(tables.to_vec()),
-            omnisharded: Arc::new(omnisharded_tables.into_iter().collect()),
+            tables: Arc::new(tables),
+            omnisharded: Arc::new(omnisharded),
             dry_run,
         }
     }
@@ -80,6 +102,53 @@ impl ShardedTables {
         None
     }
 
+    /// At least one table is in a specific schema.
+    pub fn has_schemas(&self) -> bool {
+        self.tables().iter().any(|table| table.schema.is_some())
+    }
+
+    /// Tables visible to a query.
+    ///
+    /// Tables without a schema are always visible. Tables the query references
+    /// with a schema, as `(schema, name)`, are looked up in that schema. The rest use
+    /// the `search_path`: if tables with the same name are in several schemas,
+    /// the first one in the search path wins, like in Postgres.
+    pub fn resolve(&self, qualified: &[(&str, &str)], search_path: &[&str]) -> Self {
+        let position = |table: &ShardedTable| {
+            table
+                .schema
+                .as_deref()
+                .and_then(|schema| search_path.iter().position(|path| *path == schema))
+        };
+
+        let tables = self
+            .tables()
+            .iter()
+            .filter(|table| {
+                let (Some(schema), Some(name)) = (table.schema.as_deref(), table.name.as_deref())
+                else {
+                    return true;
+                };
+                if qualified.iter().any(|(_, qualified)| *qualified == name) {
+                    return qualified.contains(&(schema, name));
+                }
+                let Some(found) = position(table) else {
+                    return false;
+                };
+                !self.tables().iter().any(|other| {
+                    other.name == table.name && position(other).is_some_and(|p| p < found)
+                })
+            })
+            .cloned()
+            .collect();
+
+        Self {
+            tables: Arc::new(tables),
+            omnisharded: self.omnisharded.clone(),
+            dry_run: self.dry_run,
+        }
+    }
+
     pub(crate) fn dry_run(&self) -> bool {
         self.dry_run
     }
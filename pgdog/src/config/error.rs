@@ -22,6 +22,9 @@ pub enum Error {
 
     #[error("incomplete startup")]
     IncompleteStartup,
+
+    #[error("hash operator class \"{0}\" isn't the default for the data type of column \"{1}\"")]
+    OperatorClass(super::Hasher, String),
 }
 
 impl Error {
@@ -45,6 +45,8 @@ pub fn load(config: &PathBuf, users: &PathBuf) -> Result<ConfigAndUsers, Error>
 pub fn set(mut config: ConfigAndUsers) -> Result<ConfigAndUsers, Error> {
     config.config.check();
     for table in config.config.sharded_tables.iter_mut() {
+        table.unquote();
+        table.check_hasher()?;
         table.load_centroids()?;
     }
     CONFIG.store(Arc::new(config.clone()));
@@ -183,6 +185,8 @@ pub struct Config {
     pub omnisharded_tables: Vec<OmnishardedTables>,
     #[serde(default)]
     pub sharded_mappings: Vec<ShardedMapping>,
+    #[serde(default)]
+    pub query_roles: Vec<QueryRole>,
 }
 
 impl Config {
@@ -396,6 +400,54 @@ pub struct General {
     /// Disable cross-shard queries.
     #[serde(default)]
     pub cross_shard_disabled: bool,
+    /// Reject cross-shard queries returning rows without `ORDER BY`,
+    /// since their row order depends on which shard answers first.
+    #[serde(default)]
+    pub cross_shard_require_order_by: bool,
+    /// Include values in queries, route decisions and route plans logged
+    /// at debug level. They are redacted by default since they can contain PII.
+    #[serde(default)]
+    pub log_route_values: bool,
+    /// Maximum size, in bytes, of a cross-shard cursor's rows kept in memory.
+    #[serde(default = "General::cursor_buffer_limit")]
+    pub cursor_buffer_limit: usize,
+    /// Maximum size, in bytes, of the rows kept in memory
+    /// to remove duplicates from cross-shard UNION and DISTINCT,
+    /// and to compute cross-shard INTERSECT and EXCEPT.
+    #[serde(default = "General::distinct_buffer_limit")]
+    pub distinct_buffer_limit: usize,
+    /// Maximum size, in bytes, of the input values kept in memory to compute
+    /// cross-shard ordered-set aggregates, e.g. `percentile_cont`.
+    #[serde(default = "General::ordered_set_buffer_limit")]
+    pub ordered_set_buffer_limit: usize,
+    /// Reject SELECT, INSERT, UPDATE, DELETE and MERGE queries that would go
+    /// to more shards than this. Unlimited by default.
+    #[serde(default)]
+    pub max_fanout_shards: Option<usize>,
+    /// Add each shard's range to cross-shard queries on range-sharded tables,
+    /// so shards can prune partitions. String bounds use `COLLATE "C"`, so
+    /// only keys with the "C" collation can be pruned.
+    #[serde(default)]
+    pub shard_range_filters: bool,
+    /// Cross-shard reads in a session go to the same replica of each shard
+    /// every time, instead of load balancing each query, so they hit warm caches.
+    #[serde(default)]
+    pub sticky_replicas: bool,
+    /// Route point lookups, e.g. `SELECT * FROM users WHERE id = $1`, with a
+    /// tokenizer instead of the query parser. Other queries are parsed as usual.
+    #[serde(default)]
+    pub query_parser_fast_path: bool,
+    /// What cross-shard reads do when some of the shards return an error.
+    #[serde(default)]
+    pub fan_out_errors: FanOutErrors,
+    /// Errors, by SQLSTATE, that `best_effort` returns partial results for.
+    /// Other errors fail the query. All errors are used if empty.
+    #[serde(default)]
+    pub best_effort_errors: Vec<String>,
+    /// What to do when filters on different sharding keys, e.g. `id = 1 AND tenant_id = 2`,
+    /// have no shard in common.
+    #[serde(default)]
+    pub multi_key_policy: MultiKeyPolicy,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -457,6 +509,35 @@ pub enum ReadWriteStrategy {
     Aggressive,
 }
 
+/// Handling of errors from some of the shards of a cross-shard read.
+/// Writes always fail if any shard fails.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum FanOutErrors {
+    /// Return the error to the client.
+    #[default]
+    FailFast,
+    /// Return rows from the other shards, with a notice listing the failed ones.
+    /// Only for queries with one statement sent outside of a transaction
+    /// with the simple protocol. Other queries return the error.
+    BestEffort,
+}
+
+/// Routing of queries filtering on more than one sharding key,
+/// when the keys have no shard in common.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MultiKeyPolicy {
+    /// Send the query to the shards both keys are on. There are none,
+    /// so no rows can match and any one shard returns the empty result.
+    #[default]
+    Intersect,
+    /// Return an error to the client.
+    Error,
+    /// Send the query to all shards.
+    Fanout,
+}
+
 impl Default for General {
     fn default() -> Self {
         Self {
@@ -496,6 +577,18 @@ impl Default for General {
             mirror_queue: Self::mirror_queue(),
             auth_type: AuthType::default(),
             cross_shard_disabled: bool::default(),
+            cross_shard_require_order_by: bool::default(),
+            log_route_values: bool::default(),
+            cursor_buffer_limit: Self::cursor_buffer_limit(),
+            distinct_buffer_limit: Self::distinct_buffer_limit(),
+            ordered_set_buffer_limit: Self::ordered_set_buffer_limit(),
+            max_fanout_shards: None,
+            shard_range_filters: bool::default(),
+            sticky_replicas: bool::default(),
+            query_parser_fast_path: bool::default(),
+            fan_out_errors: FanOutErrors::default(),
+            best_effort_errors: vec![],
+            multi_key_policy: MultiKeyPolicy::default(),
         }
     }
 }
@@ -597,6 +690,18 @@ impl General {
         128
     }
 
+    fn cursor_buffer_limit() -> usize {
+        64 * 1024 * 1024
+    }
+
+    fn distinct_buffer_limit() -> usize {
+        64 * 1024 * 1024
+    }
+
+    fn ordered_set_buffer_limit() -> usize {
+        64 * 1024 * 1024
+    }
+
     fn prepared_statements_limit() -> usize {
         usize::MAX
     }
@@ -721,6 +826,9 @@ pub enum Role {
     #[default]
     Primary,
     Replica,
+    /// Replica reserved for queries tagged with `/* pgdog_pool: analytics */`.
+    /// Other reads don't use it.
+    Analytics,
 }
 
 impl std::fmt::Display for Role {
@@ -728,6 +836,7 @@ impl std::fmt::Display for Role {
         match self {
             Self::Primary => write!(f, "primary"),
             Self::Replica => write!(f, "replica"),
+            Self::Analytics => write!(f, "analytics"),
         }
     }
 }
@@ -884,6 +993,11 @@ pub struct ShardedTable {
     /// Table name. If none specified, all tables with the specified
     /// column are considered sharded.
     pub name: Option<String>,
+    /// Schema the table is in. If none specified, the table
+    /// is sharded in all schemas. Unqualified table names are
+    /// resolved using the client's `search_path`.
+    #[serde(default)]
+    pub schema: Option<String>,
     /// Table sharded on this column.
     #[serde(default)]
     pub column: String,
@@ -901,15 +1015,83 @@ pub struct ShardedTable {
     /// How many centroids to probe.
     #[serde(default)]
     pub centroid_probes: usize,
-    /// Hasher function.
+    /// Hash function, or the name of the hash operator class
+    /// used by the partitions, e.g. `text_ops`. Only the default
+    /// operator class of the column's data type is supported.
     #[serde(default)]
     pub hasher: Hasher,
     /// Explicit routing rules.
     #[serde(skip, default)]
     pub mapping: Option<Mapping>,
+    /// Rows with a NULL sharding key are stored on the shard
+    /// of the default used in `COALESCE(column, default)` filters.
+    #[serde(default)]
+    pub coalesce_default: bool,
+    /// Every shard has a full copy of this table, e.g. a small reference table.
+    /// Reads go to one shard and writes go to all of them,
+    /// same as tables listed in `omnisharded_tables`.
+    #[serde(default)]
+    pub replicated: bool,
+    /// Sequence generating the sharding key when INSERTs use `DEFAULT`.
+    #[serde(default)]
+    pub sequence: ShardedSequence,
+    /// The column is an array of keys, e.g. `tenant_ids bigint[]`,
+    /// and all keys in a row belong to the row's shard.
+    #[serde(default)]
+    pub array: bool,
+    /// Columns of a composite sharding key, in order. Rows are sharded
+    /// by the hash of `concat(a, b)`, like Citus tables distributed by
+    /// a generated column; `column` and `data_type` aren't used.
+    #[serde(default)]
+    pub composite: Vec<String>,
+    /// Views selecting from this table and nothing else, e.g.
+    /// `CREATE VIEW active_users AS SELECT * FROM users WHERE active`.
+    /// Queries on them are sharded like queries on the table.
+    #[serde(default)]
+    pub views: Vec<String>,
 }
 
 impl ShardedTable {
+    /// Remove double quotes around the table, schema and column names,
+    /// e.g. `"Users"` becomes `Users`. Postgres doesn't fold the case of
+    /// quoted identifiers, so neither do we. Unquoted names are used as written.
+    pub fn unquote(&mut self) {
+        for identifier in [
+            self.name.as_mut(),
+            self.schema.as_mut(),
+            Some(&mut self.column),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.views.iter_mut())
+        {
+            if identifier.len() >= 2 && identifier.starts_with('"') && identifier.ends_with('"') {
+                *identifier = identifier[1..identifier.len() - 1].replace("\"\"", "\"");
+            }
+        }
+    }
+
+    /// Reject hash operator classes other than the default one of the
+    /// column's data type. Custom operator classes hash with functions
+    /// we can't run, so rows would be routed to the wrong shards.
+    pub fn check_hasher(&self) -> Result<(), Error> {
+        let data_type = match self.hasher {
+            Hasher::Postgres | Hasher::Sha1 => return Ok(()),
+            Hasher::Int8Ops => DataType::Bigint,
+            Hasher::TextOps => DataType::Varchar,
+            Hasher::UuidOps => DataType::Uuid,
+        };
+
+        if data_type == self.data_type && self.composite.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::OperatorClass(
+                self.hasher.clone(),
+                self.column.clone(),
+            ))
+        }
+    }
+
     /// Load centroids from file, if provided.
     ///
     /// Centroids can be very large vectors (1000+ columns).
@@ -939,12 +1121,50 @@ impl ShardedTable {
     }
 }
 
+/// Hash function used to shard a column.
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum Hasher {
+    /// Default hash operator class of the column's data type.
     #[default]
+    #[serde(alias = "default")]
     Postgres,
     Sha1,
+    /// `int8_ops`, the default for `bigint`. `int2_ops` and `int4_ops`
+    /// hash the same values the same way.
+    #[serde(alias = "int2_ops", alias = "int4_ops")]
+    Int8Ops,
+    /// `text_ops`, the default for `varchar` and `text`.
+    #[serde(alias = "varchar_ops")]
+    TextOps,
+    /// `uuid_ops`, the default for `uuid`.
+    UuidOps,
+}
+
+impl std::fmt::Display for Hasher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Postgres => "postgres",
+            Self::Sha1 => "sha1",
+            Self::Int8Ops => "int8_ops",
+            Self::TextOps => "text_ops",
+            Self::UuidOps => "uuid_ops",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Sequence used as the sharding key's default value.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ShardedSequence {
+    /// One sequence for all shards. We can't know the value
+    /// it will generate, so the key has to be provided.
+    #[default]
+    Global,
+    /// Each shard has its own sequence, generating keys that
+    /// belong to it. Any shard can take the row.
+    Local,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default, Copy)]
@@ -1011,6 +1231,22 @@ pub struct ManualQuery {
     pub fingerprint: String,
 }
 
+/// Send queries matching a pattern to the primary or the replicas,
+/// whatever the read/write split decided. A `pgdog_role` comment
+/// in the query still takes precedence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub struct QueryRole {
+    /// Database the pattern applies to. All databases if not set.
+    #[serde(default)]
+    pub database: Option<String>,
+    /// Regular expression matched against the normalized query,
+    /// i.e. with values replaced by placeholders, e.g. `WHERE id = $1`.
+    pub pattern: String,
+    /// Role to send matching queries to.
+    pub role: Role,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
 #[serde(rename_all = "snake_case")]
 pub struct Tcp {
@@ -1140,6 +1376,36 @@ pub mod test {
         init();
     }
 
+    pub fn load_test_sharded() {
+        let mut config = ConfigAndUsers::default();
+        config.config.databases = (0..2)
+            .map(|shard| Database {
+                name: "pgdog".into(),
+                host: "127.0.0.1".into(),
+                port: 5432,
+                shard,
+                database_name: Some(format!("shard_{}", shard)),
+                ..Default::default()
+            })
+            .collect();
+        config.config.sharded_tables = vec![ShardedTable {
+            database: "pgdog".into(),
+            name: Some("sharded".into()),
+            column: "id".into(),
+            data_type: DataType::Bigint,
+            ..Default::default()
+        }];
+        config.users.users = vec![User {
+            name: "pgdog".into(),
+            database: "pgdog".into(),
+            password: Some("pgdog".into()),
+            ..Default::default()
+        }];
+
+        set(config).unwrap();
+        init();
+    }
+
     #[test]
     fn test_basic() {
         let source = r#"
@@ -1183,4 +1449,81 @@ column = "tenant_id"
         assert_eq!(config.tcp.retries().unwrap(), 5);
         assert_eq!(config.multi_tenant.unwrap().column, "tenant_id");
     }
+
+    #[test]
+    fn test_unquote_sharded_tables() {
+        let source = r#"
+[[sharded_tables]]
+database = "production"
+name = '"Users"'
+schema = '"My""Schema"'
+column = '"TenantId"'
+
+[[sharded_tables]]
+database = "production"
+name = "users"
+column = "tenant_id"
+"#;
+
+        let mut config: Config = toml::from_str(source).unwrap();
+        for table in config.sharded_tables.iter_mut() {
+            table.unquote();
+        }
+
+        assert_eq!(config.sharded_tables[0].name.as_deref(), Some("Users"));
+        assert_eq!(
+            config.sharded_tables[0].schema.as_deref(),
+            Some("My\"Schema")
+        );
+        assert_eq!(config.sharded_tables[0].column, "TenantId");
+        assert_eq!(config.sharded_tables[1].name.as_deref(), Some("users"));
+        assert_eq!(config.sharded_tables[1].column, "tenant_id");
+    }
+
+    #[test]
+    fn test_hasher_operator_class() {
+        let source = r#"
+[[sharded_tables]]
+database = "production"
+column = "tenant_id"
+data_type = "varchar"
+hasher = "text_ops"
+
+[[sharded_tables]]
+database = "production"
+column = "account_id"
+hasher = "int4_ops"
+
+[[sharded_tables]]
+database = "production"
+column = "user_id"
+
+[[sharded_tables]]
+database = "production"
+column = "org_id"
+hasher = "text_ops"
+"#;
+
+        let config: Config = toml::from_str(source).unwrap();
+        assert_eq!(config.sharded_tables[0].hasher, Hasher::TextOps);
+        assert_eq!(config.sharded_tables[1].hasher, Hasher::Int8Ops);
+        assert_eq!(config.sharded_tables[2].hasher, Hasher::Postgres);
+
+        for table in &config.sharded_tables[..3] {
+            table.check_hasher().unwrap();
+        }
+        // text_ops can't be used by a bigint column.
+        assert!(matches!(
+            config.sharded_tables[3].check_hasher(),
+            Err(Error::OperatorClass(Hasher::TextOps, _))
+        ));
+
+        let custom = r#"
+[[sharded_tables]]
+database = "production"
+column = "tenant_id"
+hasher = "tenant_hash_ops"
+"#;
+        assert!(toml::from_str::<Config>(custom).is_err());
+    }
 }
@@ -3,7 +3,7 @@
 use std::ops::{Deref, DerefMut};
 
 use crate::{
-    backend::ProtocolMessage,
+    backend::{pool::shard_sql::ShardSql, ProtocolMessage},
     net::{
         messages::{parse::Parse, Bind, CopyData, Protocol, Query},
         Error,
@@ -164,6 +164,36 @@ impl Buffer {
         self.buffer.push(Query::new(query).into());
         Ok(())
     }
+
+    /// Queries and prepared statements rewritten for a shard,
+    /// or `None` if the transform didn't change any of them.
+    pub fn transformed(&self, shard: usize, shard_sql: &dyn ShardSql) -> Option<Buffer> {
+        let mut transformed: Option<Buffer> = None;
+
+        for (position, message) in self.buffer.iter().enumerate() {
+            let message = match message {
+                ProtocolMessage::Query(query) => shard_sql
+                    .transform(shard, query.query())
+                    .map(|sql| Query::new(sql).into()),
+                ProtocolMessage::Parse(parse) => shard_sql
+                    .transform(shard, parse.query())
+                    .map(|sql| parse.with_query(&sql).into()),
+                ProtocolMessage::Prepare { name, statement } => shard_sql
+                    .transform(shard, statement)
+                    .map(|sql| ProtocolMessage::Prepare {
+                        name: name.clone(),
+                        statement: sql,
+                    }),
+                _ => None,
+            };
+
+            if let Some(message) = message {
+                transformed.get_or_insert_with(|| self.clone()).buffer[position] = message;
+            }
+        }
+
+        transformed
+    }
 }
 
 impl From<Buffer> for Vec<ProtocolMessage> {
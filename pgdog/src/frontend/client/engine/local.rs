@@ -0,0 +1,346 @@
+//! Answer statements without a backend: the ones the router marked
+//! as local, e.g. `SELECT 1`, and the ones that match no rows,
+//! e.g. `SELECT id FROM users WHERE id = ANY('{}')`.
+
+use std::sync::Arc;
+
+use pg_query::{NodeEnum, ParseResult};
+
+use crate::{
+    backend::ProtocolMessage,
+    frontend::{
+        buffer::BufferedQuery,
+        router::parser::{Cache, Local, NoRows},
+        Buffer, Error,
+    },
+    net::{
+        Bind, BindComplete, CommandComplete, DataRow, Message, NoData, ParameterDescription,
+        ParseComplete, Protocol, ReadyForQuery, RowDescription,
+    },
+};
+
+/// Type OID of parameters we describe, see [`Local::describe`].
+const TEXT: i32 = 25;
+
+/// Result of a statement we answer ourselves.
+struct Answer {
+    /// Columns, if the statement returns rows.
+    rd: Option<RowDescription>,
+    rows: Vec<DataRow>,
+    tag: String,
+}
+
+/// Build the responses to a request for a local statement.
+///
+/// Returns `None` if we can't answer the request ourselves, e.g. it asks
+/// for results in binary format or has more than one statement in it.
+/// It goes to a backend instead.
+pub fn answer(buffer: &Buffer, in_transaction: bool) -> Result<Option<Vec<Message>>, Error> {
+    let Some((query, ast)) = statement(buffer)? else {
+        return Ok(None);
+    };
+
+    // Types declared by the client change the result columns.
+    if let BufferedQuery::Prepared(ref parse) = query {
+        if parse.data_types().any(|oid| oid != 0) {
+            return Ok(None);
+        }
+    }
+
+    let Some(NodeEnum::SelectStmt(stmt)) = node(&ast) else {
+        return Ok(None);
+    };
+    let Some(local) = Local::new(stmt) else {
+        return Ok(None);
+    };
+    let columns = local.describe().map(|rd| (local.parameters(), rd));
+
+    respond(buffer, in_transaction, columns, |bind| {
+        local.evaluate(bind).map(|(rd, rows)| Answer {
+            rd: Some(rd),
+            tag: format!("SELECT {}", rows.len()),
+            rows,
+        })
+    })
+}
+
+/// Build the responses to a request for a statement that matches no rows,
+/// see [`NoRows`]. Returns `None` if we can't answer the request ourselves,
+/// and the statement goes to one shard instead.
+pub fn no_rows(buffer: &Buffer, in_transaction: bool) -> Result<Option<Vec<Message>>, Error> {
+    let Some((_, ast)) = statement(buffer)? else {
+        return Ok(None);
+    };
+    let Some(no_rows) = NoRows::new(&ast.protobuf) else {
+        return Ok(None);
+    };
+
+    // We don't know the types of the parameters.
+    respond(buffer, in_transaction, None, |_| {
+        Some(Answer {
+            rd: no_rows.row_description().cloned(),
+            rows: vec![],
+            tag: no_rows.tag().to_string(),
+        })
+    })
+}
+
+/// The statement in a request that ends with a Sync or is a simple query.
+fn statement(buffer: &Buffer) -> Result<Option<(BufferedQuery, Arc<ParseResult>)>, Error> {
+    if !matches!(
+        buffer.last(),
+        Some(ProtocolMessage::Sync(_)) | Some(ProtocolMessage::Query(_))
+    ) {
+        return Ok(None);
+    }
+
+    let Some(query) = buffer.query()? else {
+        return Ok(None);
+    };
+
+    // Syntax errors are reported by the router. Simple queries aren't cached,
+    // like in the router.
+    let ast = match query {
+        BufferedQuery::Prepared(ref parse) => Cache::get().parse(parse.query()).ok(),
+        BufferedQuery::Query(ref query) => pg_query::parse(query.query()).ok().map(Arc::new),
+    };
+
+    Ok(ast.map(|ast| (query, ast)))
+}
+
+/// The only statement in the query.
+fn node(ast: &ParseResult) -> Option<&NodeEnum> {
+    match ast.protobuf.stmts.as_slice() {
+        [stmt] => stmt.stmt.as_ref().and_then(|stmt| stmt.node.as_ref()),
+        _ => None,
+    }
+}
+
+/// Respond to each message in the request. `columns` has the number of parameters
+/// and the columns of the statement, and `execute` evaluates it with the bound parameters.
+fn respond(
+    buffer: &Buffer,
+    in_transaction: bool,
+    columns: Option<(usize, RowDescription)>,
+    execute: impl Fn(Option<&Bind>) -> Option<Answer>,
+) -> Result<Option<Vec<Message>>, Error> {
+    let mut messages = vec![];
+    let mut bind: Option<&Bind> = None;
+    let mut statements = vec![];
+    let mut executes = 0;
+
+    for message in buffer.iter() {
+        match message {
+            ProtocolMessage::Query(_) => {
+                let Some(answer) = execute(None) else {
+                    return Ok(None);
+                };
+                if let Some(ref rd) = answer.rd {
+                    messages.push(rd.message()?);
+                }
+                messages.extend(results(&answer)?);
+                messages.push(ReadyForQuery::in_transaction(in_transaction).message()?);
+            }
+
+            ProtocolMessage::Parse(parse) => {
+                statements.push(parse.name());
+                messages.push(ParseComplete.message()?);
+            }
+
+            ProtocolMessage::Bind(message) => {
+                // We only return text.
+                if bind.is_some() || message.results().iter().any(|format| *format != 0) {
+                    return Ok(None);
+                }
+                statements.push(message.statement());
+                bind = Some(message);
+                messages.push(BindComplete.message()?);
+            }
+
+            ProtocolMessage::Describe(describe) => {
+                if describe.is_statement() {
+                    statements.push(describe.statement());
+                    let Some((parameters, ref rd)) = columns else {
+                        return Ok(None);
+                    };
+                    messages.push(ParameterDescription::new(vec![TEXT; parameters]).message()?);
+                    messages.push(description(Some(rd))?);
+                } else {
+                    // The portal must be bound in this request.
+                    let Some(answer) = bind.and_then(|bind| execute(Some(bind))) else {
+                        return Ok(None);
+                    };
+                    messages.push(description(answer.rd.as_ref())?);
+                }
+            }
+
+            ProtocolMessage::Execute(message) => {
+                executes += 1;
+                let Some(answer) = bind.and_then(|bind| execute(Some(bind))) else {
+                    return Ok(None);
+                };
+                // Suspending the portal needs a backend.
+                if message.max_rows() > 0 && answer.rows.len() > message.max_rows() as usize {
+                    return Ok(None);
+                }
+                messages.extend(results(&answer)?);
+            }
+
+            ProtocolMessage::Sync(_) => {
+                messages.push(ReadyForQuery::in_transaction(in_transaction).message()?);
+            }
+
+            // Flush.
+            ProtocolMessage::Other(message) if message.code() == 'H' => (),
+
+            _ => return Ok(None),
+        }
+    }
+
+    // One statement, executed once.
+    if executes > 1 || statements.iter().any(|name| *name != statements[0]) {
+        return Ok(None);
+    }
+
+    Ok(Some(messages))
+}
+
+/// Rows followed by their CommandComplete.
+fn results(answer: &Answer) -> Result<Vec<Message>, Error> {
+    let mut messages = answer
+        .rows
+        .iter()
+        .map(|row| row.message())
+        .collect::<Result<Vec<_>, _>>()?;
+    messages.push(CommandComplete::new(&answer.tag).message()?);
+    Ok(messages)
+}
+
+/// RowDescription, or NoData if the statement doesn't return rows.
+fn description(rd: Option<&RowDescription>) -> Result<Message, Error> {
+    match rd {
+        Some(rd) if !rd.fields.is_empty() => Ok(rd.message()?),
+        _ => Ok(NoData.message()?),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::net::{bind::Parameter, Describe, Execute, FromBytes, Parse, Query, Sync, ToBytes};
+
+    use super::*;
+
+    fn codes(messages: &[Message]) -> String {
+        messages.iter().map(|message| message.code()).collect()
+    }
+
+    #[test]
+    fn test_answer_simple() {
+        let buffer = Buffer::from(vec![Query::new("SELECT 1").into()]);
+        let messages = answer(&buffer, false).unwrap().unwrap();
+        assert_eq!(codes(&messages), "TDCZ");
+
+        let row = DataRow::from_bytes(messages[1].to_bytes().unwrap()).unwrap();
+        assert_eq!(row.get_text(0).unwrap(), "1");
+        let cc = CommandComplete::from_bytes(messages[2].to_bytes().unwrap()).unwrap();
+        assert_eq!(cc.command(), "SELECT 1");
+        let rfq = ReadyForQuery::from_bytes(messages[3].to_bytes().unwrap()).unwrap();
+        assert_eq!(rfq.status, 'I');
+
+        for query in ["SELECT now()", "SELECT 1; SELECT 2", "SELECT $1"] {
+            let buffer = Buffer::from(vec![Query::new(query).into()]);
+            assert!(answer(&buffer, false).unwrap().is_none(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_answer_values() {
+        let buffer = Buffer::from(vec![Query::new("VALUES (1, 'one'), (2, NULL)").into()]);
+        let messages = answer(&buffer, false).unwrap().unwrap();
+        assert_eq!(codes(&messages), "TDDCZ");
+
+        let rd = RowDescription::from_bytes(messages[0].to_bytes().unwrap()).unwrap();
+        assert_eq!(rd.field(0).unwrap().name, "column1");
+        assert_eq!(rd.field(1).unwrap().name, "column2");
+        let row = DataRow::from_bytes(messages[2].to_bytes().unwrap()).unwrap();
+        assert_eq!(row.get_text(0).unwrap(), "2");
+        assert!(row.is_null(1));
+        let cc = CommandComplete::from_bytes(messages[3].to_bytes().unwrap()).unwrap();
+        assert_eq!(cc.command(), "SELECT 2");
+
+        let buffer = Buffer::from(vec![
+            Parse::named("__pgdog_1", "VALUES ($1), ($2)").into(),
+            Bind::test_params(
+                "__pgdog_1",
+                &[
+                    Parameter {
+                        len: 1,
+                        data: "a".as_bytes().to_vec(),
+                    },
+                    Parameter {
+                        len: 1,
+                        data: "b".as_bytes().to_vec(),
+                    },
+                ],
+            )
+            .into(),
+            Describe::new_portal("").into(),
+            Execute::new().into(),
+            Sync.into(),
+        ]);
+        let messages = answer(&buffer, false).unwrap().unwrap();
+        assert_eq!(codes(&messages), "12TDDCZ");
+        let row = DataRow::from_bytes(messages[4].to_bytes().unwrap()).unwrap();
+        assert_eq!(row.get_text(0).unwrap(), "b");
+
+        // Postgres infers the parameter types from the other values.
+        let buffer = Buffer::from(vec![
+            Parse::named("__pgdog_1", "VALUES (1), ($1)").into(),
+            Describe::new_statement("__pgdog_1").into(),
+            Sync.into(),
+        ]);
+        assert!(answer(&buffer, false).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_answer_extended() {
+        let bind = Bind::test_params(
+            "__pgdog_1",
+            &[Parameter {
+                len: 3,
+                data: "one".as_bytes().to_vec(),
+            }],
+        );
+        let buffer = Buffer::from(vec![
+            Parse::named("__pgdog_1", "SELECT $1").into(),
+            Describe::new_statement("__pgdog_1").into(),
+            bind.clone().into(),
+            Execute::new().into(),
+            Sync.into(),
+        ]);
+        let messages = answer(&buffer, true).unwrap().unwrap();
+        assert_eq!(codes(&messages), "1tT2DCZ");
+
+        let row = DataRow::from_bytes(messages[4].to_bytes().unwrap()).unwrap();
+        assert_eq!(row.get_text(0).unwrap(), "one");
+        let rfq = ReadyForQuery::from_bytes(messages[6].to_bytes().unwrap()).unwrap();
+        assert_eq!(rfq.status, 'T');
+
+        // Binary results need a backend.
+        let binary = Bind::test_params_codes_results("__pgdog_1", &[], &[], &[1]);
+        let buffer = Buffer::from(vec![
+            Parse::named("__pgdog_1", "SELECT 1").into(),
+            binary.into(),
+            Execute::new().into(),
+            Sync.into(),
+        ]);
+        assert!(answer(&buffer, false).unwrap().is_none());
+
+        // Not synced yet.
+        let buffer = Buffer::from(vec![
+            Parse::named("__pgdog_1", "SELECT 1").into(),
+            Describe::new_statement("__pgdog_1").into(),
+        ]);
+        assert!(answer(&buffer, false).unwrap().is_none());
+    }
+}
@@ -5,6 +5,7 @@ use crate::{
 
 pub mod action;
 pub mod intercept;
+pub mod local;
 
 pub use action::Action;
 
@@ -31,6 +31,9 @@ pub(super) struct Inner {
     pub(super) stats: Stats,
     /// Start transaction statement, intercepted by the router.
     pub(super) start_transaction: Option<BufferedQuery>,
+    /// Savepoints created before the transaction picked its shard,
+    /// sent after the start transaction statement.
+    pub(super) savepoints: Vec<BufferedQuery>,
     /// Client-wide comms.
     pub(super) comms: Comms,
 }
@@ -62,6 +65,7 @@ impl Inner {
             router,
             stats: Stats::new(),
             start_transaction: None,
+            savepoints: vec![],
             comms: client.comms.clone(),
         })
     }
@@ -91,13 +95,31 @@ impl Inner {
             })
             .transpose()?;
 
-        if let Some(Command::Rewrite(query)) = command {
+        if let Some(Command::Rewrite(query, _)) | Some(Command::DeclareCursor { query, .. }) =
+            command
+        {
             buffer.rewrite(query)?;
         }
 
         Ok(command)
     }
 
+    /// Split Binds pipelined for one statement by shard, see [`Router::pipeline`].
+    pub(super) fn pipeline(&self, buffer: &Buffer) -> Result<Option<Vec<Buffer>>, RouterError> {
+        match self.backend.cluster() {
+            Ok(cluster) => self.router.pipeline(buffer, cluster),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Send a query that matches no rows to one shard, see [`Router::one_shard`].
+    pub(super) fn one_shard(&mut self) -> Option<&Command> {
+        match self.backend.cluster() {
+            Ok(cluster) => Some(self.router.one_shard(cluster)),
+            Err(_) => None,
+        }
+    }
+
     /// Reset query router context.
     pub(super) fn reset_router(&mut self) {
         self.router.reset();
@@ -1,6 +1,6 @@
 //! Frontend client.
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
 use std::time::Instant;
 
@@ -14,16 +14,17 @@ use super::{Buffer, Command, Comms, Error, PreparedStatements};
 use crate::auth::{md5, scram::Server};
 use crate::backend::{
     databases,
-    pool::{Connection, Request},
+    pool::{connection::cursor::BufferedCursor, Connection, Request},
     ProtocolMessage,
 };
 use crate::config::{self, AuthType};
 use crate::frontend::buffer::BufferedQuery;
+use crate::frontend::router::parser::Fetch;
 #[cfg(debug_assertions)]
 use crate::frontend::QueryLogger;
 use crate::net::messages::{
     Authentication, BackendKeyData, CommandComplete, ErrorResponse, FromBytes, Message, Password,
-    Protocol, ReadyForQuery, ToBytes,
+    Protocol, Query, ReadyForQuery, ToBytes,
 };
 use crate::net::{parameter::Parameters, Stream};
 use crate::net::{DataRow, EmptyQueryResponse, Field, NoticeResponse, RowDescription};
@@ -57,6 +58,14 @@ pub struct Client {
     stream_buffer: BytesMut,
     message_buffer: VecDeque<ProtocolMessage>,
     cross_shard_disabled: bool,
+    // Executing a multi-statement query one statement at a time.
+    hold_ready_for_query: bool,
+    held_ready_for_query: Option<Message>,
+    batch_error: bool,
+    // Cross-shard cursors, kept in memory until the transaction ends.
+    cursors: HashMap<String, BufferedCursor>,
+    // Cursor whose rows we're receiving.
+    declaring: Option<(String, BufferedCursor)>,
 }
 
 impl Client {
@@ -207,6 +216,11 @@ impl Client {
             message_buffer: VecDeque::new(),
             shutdown: false,
             cross_shard_disabled: false,
+            hold_ready_for_query: false,
+            held_ready_for_query: None,
+            batch_error: false,
+            cursors: HashMap::new(),
+            declaring: None,
         };
 
         drop(conn);
@@ -249,6 +263,11 @@ impl Client {
             message_buffer: VecDeque::new(),
             shutdown: false,
             cross_shard_disabled: false,
+            hold_ready_for_query: false,
+            held_ready_for_query: None,
+            batch_error: false,
+            cursors: HashMap::new(),
+            declaring: None,
         }
     }
 
@@ -367,8 +386,17 @@ impl Client {
         };
 
         let connected = inner.connected();
+        let begin_deferred = inner.start_transaction.is_some();
 
-        let command = match inner.command(
+        // Binds pipelined for one statement can be for keys on different shards.
+        // Each run of Binds going to the same shard is sent on its own.
+        if !connected && !self.in_transaction && self.prepared_statements.enabled {
+            if let Some(buffers) = inner.pipeline(&self.request_buffer)? {
+                return self.batch(inner, buffers).await;
+            }
+        }
+
+        let mut command = match inner.command(
             &mut self.request_buffer,
             &mut self.prepared_statements,
             &self.params,
@@ -383,12 +411,11 @@ impl Client {
                         .await?;
                 } else {
                     error!("{:?} [{}]", err, self.addr);
-                    self.stream
-                        .error(
-                            ErrorResponse::syntax(err.to_string().as_str()),
-                            self.in_transaction,
-                        )
-                        .await?;
+                    let response = match err.route_error() {
+                        Some(err) => ErrorResponse::from(err),
+                        None => ErrorResponse::syntax(err.to_string().as_str()),
+                    };
+                    self.stream.error(response, self.in_transaction).await?;
                 }
                 inner.done(self.in_transaction);
                 return Ok(false);
@@ -397,6 +424,48 @@ impl Client {
 
         self.streaming = matches!(command, Some(Command::StartReplication));
 
+        // Cross-shard cursors are served from memory.
+        match command {
+            Some(Command::DeclareCursor { name, .. }) => {
+                let limit = config::config().config.general.cursor_buffer_limit;
+                self.declaring = Some((name.clone(), BufferedCursor::new(limit)));
+            }
+            Some(Command::Fetch(fetch)) => {
+                let fetch = fetch.clone();
+                self.fetch(&mut inner, fetch).await?;
+                return Ok(false);
+            }
+            Some(Command::CloseCursor(name)) => {
+                self.cursors.remove(name);
+                self.stream
+                    .send_many(&[
+                        CommandComplete::from_str("CLOSE CURSOR").message()?,
+                        ReadyForQuery::in_transaction(self.in_transaction).message()?,
+                    ])
+                    .await?;
+                inner.done(self.in_transaction);
+                return Ok(false);
+            }
+            _ => (),
+        }
+
+        // Statements we don't prepare on servers can't be used by later requests.
+        let prepared = self.prepared_statements.enabled
+            || !self.request_buffer.iter().any(|message| message.extended());
+
+        // No shard has rows for the query, e.g. the sharding key is an empty list.
+        if matches!(command, Some(Command::Query(route)) if route.shard().is_empty()) {
+            if !connected && !self.in_transaction && prepared {
+                if let Some(messages) =
+                    engine::local::no_rows(&self.request_buffer, self.in_transaction)?
+                {
+                    self.answer(&mut inner, messages).await?;
+                    return Ok(false);
+                }
+            }
+            command = inner.one_shard();
+        }
+
         if !connected {
             // Simulate transaction starting
             // until client sends an actual query.
@@ -417,8 +486,26 @@ impl Client {
                         return Ok(false);
                     }
                 }
+                Some(Command::Savepoint(query)) if begin_deferred => {
+                    let tag = match query.split_whitespace().next() {
+                        Some(word) if word.eq_ignore_ascii_case("release") => "RELEASE",
+                        Some(word) if word.eq_ignore_ascii_case("rollback") => "ROLLBACK",
+                        _ => "SAVEPOINT",
+                    };
+                    let query = query.clone();
+                    inner.savepoints.push(query);
+                    self.stream
+                        .send_many(&[
+                            CommandComplete::from_str(tag).message()?,
+                            ReadyForQuery::in_transaction(true).message()?,
+                        ])
+                        .await?;
+                    inner.done(self.in_transaction);
+                    return Ok(false);
+                }
                 Some(Command::RollbackTransaction) => {
                     inner.start_transaction = None;
+                    inner.savepoints.clear();
                     self.end_transaction(true).await?;
                     self.in_transaction = false;
                     inner.done(self.in_transaction);
@@ -426,6 +513,7 @@ impl Client {
                 }
                 Some(Command::CommitTransaction) => {
                     inner.start_transaction = None;
+                    inner.savepoints.clear();
                     self.end_transaction(false).await?;
                     self.in_transaction = false;
                     inner.done(self.in_transaction);
@@ -462,6 +550,14 @@ impl Client {
                     return Ok(false);
                 }
 
+                Some(Command::Batch(statements)) => {
+                    let buffers = statements
+                        .iter()
+                        .map(|statement| Buffer::from(vec![Query::new(statement.query()).into()]))
+                        .collect();
+                    return self.batch(inner, buffers).await;
+                }
+
                 Some(Command::Query(query)) => {
                     if query.is_cross_shard() && self.cross_shard_disabled {
                         self.stream
@@ -471,6 +567,16 @@ impl Client {
                         inner.reset_router();
                         return Ok(false);
                     }
+
+                    // `SELECT 1`, `SELECT $1`, etc., don't need a server.
+                    if query.is_local() && !self.in_transaction && prepared {
+                        if let Some(messages) =
+                            engine::local::answer(&self.request_buffer, self.in_transaction)?
+                        {
+                            self.answer(&mut inner, messages).await?;
+                            return Ok(false);
+                        }
+                    }
                 }
                 _ => (),
             };
@@ -508,7 +614,14 @@ impl Client {
         // This prevents us holding open connections to multiple servers
         if self.request_buffer.executable() {
             if let Some(query) = inner.start_transaction.take() {
-                inner.backend.execute(&query).await?;
+                if inner.router.route().read_only_snapshot() {
+                    inner.backend.begin_snapshot(&query).await?;
+                } else {
+                    inner.backend.execute(&query).await?;
+                }
+                for savepoint in std::mem::take(&mut inner.savepoints) {
+                    inner.backend.execute(&savepoint).await?;
+                }
             }
         }
 
@@ -546,6 +659,107 @@ impl Client {
         Ok(false)
     }
 
+    /// Execute statements from a multi-statement query, or runs of pipelined Binds,
+    /// one at a time, since they need to go to different shards.
+    ///
+    /// The client expects one ReadyForQuery for the whole request, so we hold it back
+    /// for all requests but the last. Execution stops at the first error,
+    /// like it does in Postgres.
+    async fn batch(
+        &mut self,
+        mut inner: InnerBorrow<'_>,
+        buffers: Vec<Buffer>,
+    ) -> Result<bool, Error> {
+        inner.reset_router();
+        self.batch_error = false;
+        let last = buffers.len().saturating_sub(1);
+
+        for (i, buffer) in buffers.into_iter().enumerate() {
+            self.request_buffer = buffer;
+            self.hold_ready_for_query = i < last;
+
+            let mut disconnect = Box::pin(self.client_messages(inner.get())).await?;
+
+            // The next statement can't be sent until this one is done.
+            let query_timeout = self.timeouts.query_timeout(&inner.stats.state);
+            while !disconnect && inner.backend.has_more_messages() && !inner.backend.copy_mode() {
+                let message = timeout(query_timeout, inner.backend.read()).await??;
+                disconnect = self.server_message(&mut inner, message).await?;
+            }
+            self.hold_ready_for_query = false;
+
+            if disconnect {
+                return Ok(true);
+            }
+
+            match self.held_ready_for_query.take() {
+                Some(ready_for_query) => {
+                    if self.batch_error {
+                        self.stream.send_flush(&ready_for_query).await?;
+                        break;
+                    }
+                }
+
+                // We couldn't execute the statement and already told the client.
+                None => {
+                    if i < last {
+                        break;
+                    }
+                }
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Send the responses to a request we answered without a backend.
+    async fn answer(
+        &mut self,
+        inner: &mut InnerBorrow<'_>,
+        mut messages: Vec<Message>,
+    ) -> Result<(), Error> {
+        if self.hold_ready_for_query {
+            self.held_ready_for_query = messages.pop();
+        }
+        self.stream.send_many(&messages).await?;
+        inner.done(self.in_transaction);
+        inner.reset_router();
+        self.update_stats(inner);
+
+        Ok(())
+    }
+
+    /// Serve FETCH and MOVE from a cross-shard cursor kept in memory.
+    async fn fetch(&mut self, inner: &mut InnerBorrow<'_>, fetch: Fetch) -> Result<(), Error> {
+        let Some(cursor) = self.cursors.get_mut(&fetch.name) else {
+            self.stream
+                .error(ErrorResponse::no_cursor(&fetch.name), self.in_transaction)
+                .await?;
+            inner.done(self.in_transaction);
+            return Ok(());
+        };
+
+        let rows = cursor.fetch(fetch.direction);
+        let tag = format!(
+            "{} {}",
+            if fetch.moves { "MOVE" } else { "FETCH" },
+            rows.len()
+        );
+        let mut messages = vec![];
+
+        if !fetch.moves {
+            messages.extend(cursor.row_description().cloned());
+            messages.extend(rows);
+        }
+        messages.push(CommandComplete::from_str(&tag).message()?);
+        messages.push(ReadyForQuery::in_transaction(self.in_transaction).message()?);
+
+        self.stream.send_many(&messages).await?;
+        inner.done(self.in_transaction);
+
+        Ok(())
+    }
+
     /// Handle message from server(s).
     async fn server_message(
         &mut self,
@@ -558,8 +772,10 @@ impl Client {
 
         // Messages that we need to send to the client immediately.
         // ReadyForQuery (B) | CopyInResponse (B) | ErrorResponse(B) | NoticeResponse(B)
-        let flush =
-            matches!(code, 'Z' | 'G' | 'E' | 'N') || !has_more_messages || message.streaming();
+        // | NotificationResponse (B)
+        let flush = matches!(code, 'Z' | 'G' | 'E' | 'N' | 'A')
+            || !has_more_messages
+            || message.streaming();
 
         // Server finished executing a query.
         // ReadyForQuery (B)
@@ -584,6 +800,7 @@ impl Client {
             }
             inner.stats.transaction();
             inner.reset_router();
+            self.cursors.clear();
             debug!(
                 "transaction finished [{:.3}ms]",
                 inner.stats.last_transaction_time.as_secs_f64() * 1000.0
@@ -600,7 +817,38 @@ impl Client {
             }
         }
 
-        if flush {
+        if code == 'E' {
+            self.batch_error = true;
+        }
+
+        // Keep the rows of a cross-shard cursor and tell the client
+        // it was declared. Errors end it and go to the client as usual.
+        if let Some((name, mut cursor)) = self.declaring.take() {
+            match code {
+                'T' | 'D' | 'C' => {
+                    cursor.add(&message);
+                    self.declaring = Some((name, cursor));
+                    return Ok(false);
+                }
+                'Z' => {
+                    let reply = match cursor.done() {
+                        Ok(cursor) if self.in_transaction => {
+                            self.cursors.insert(name, cursor);
+                            CommandComplete::from_str("DECLARE CURSOR").message()?
+                        }
+                        Ok(_) => ErrorResponse::cursor_outside_transaction().message()?,
+                        Err(err) => ErrorResponse::from_err(&err).message()?,
+                    };
+                    self.stream.send(&reply).await?;
+                }
+                'E' => (),
+                _ => self.declaring = Some((name, cursor)),
+            }
+        }
+
+        if code == 'Z' && self.hold_ready_for_query {
+            self.held_ready_for_query = Some(message);
+        } else if flush {
             self.stream.send_flush(&message).await?;
         } else {
             self.stream.send(&message).await?;
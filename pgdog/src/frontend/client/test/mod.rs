@@ -12,7 +12,7 @@ use crate::{
     backend::databases::databases,
     config::{
         config, set,
-        test::{load_test, load_test_replicas},
+        test::{load_test, load_test_replicas, load_test_sharded},
         Role,
     },
     frontend::{
@@ -119,7 +119,7 @@ macro_rules! read {
 async fn test_test_client() {
     let (mut conn, mut client, mut inner) = new_client!(false);
 
-    let query = Query::new("SELECT 1").to_bytes().unwrap();
+    let query = Query::new("SELECT now()").to_bytes().unwrap();
 
     conn.write_all(&query).await.unwrap();
 
@@ -275,6 +275,127 @@ async fn test_client_extended() {
     handle.await.unwrap();
 }
 
+#[tokio::test]
+async fn test_local() {
+    // The router parses queries with replicas configured.
+    let (mut conn, mut client, mut inner) = new_client!(true);
+
+    conn.write_all(&buffer!({ Query::new("SELECT 1") }))
+        .await
+        .unwrap();
+
+    client.buffer(&State::Idle).await.unwrap();
+    client.client_messages(inner.get()).await.unwrap();
+
+    let messages = read!(conn, ['T', 'D', 'C', 'Z']);
+    let dr = DataRow::from_bytes(messages[1].clone().freeze()).unwrap();
+    assert_eq!(dr.get::<i64>(0, Format::Text), Some(1));
+    assert!(!inner.backend.connected());
+    assert!(!inner.router.routed());
+
+    conn.write_all(&buffer!(
+        { Parse::named("test", "SELECT $1") },
+        { Describe::new_statement("test") },
+        { Sync }
+    ))
+    .await
+    .unwrap();
+
+    client.buffer(&State::Idle).await.unwrap();
+    client.client_messages(inner.get()).await.unwrap();
+
+    read!(conn, ['1', 't', 'T', 'Z']);
+    assert!(!inner.backend.connected());
+
+    conn.write_all(&buffer!(
+        {
+            Bind::test_params(
+                "test",
+                &[Parameter {
+                    len: 3,
+                    data: "123".into(),
+                }],
+            )
+        },
+        { Execute::new() },
+        { Sync }
+    ))
+    .await
+    .unwrap();
+
+    client.buffer(&State::Idle).await.unwrap();
+    client.client_messages(inner.get()).await.unwrap();
+
+    let messages = read!(conn, ['2', 'D', 'C', 'Z']);
+    let dr = DataRow::from_bytes(messages[1].clone().freeze()).unwrap();
+    assert_eq!(dr.get_text(0).unwrap(), "123");
+    assert!(!inner.backend.connected());
+
+    conn.write_all(&buffer!({ Query::new("VALUES (1), (2)") }))
+        .await
+        .unwrap();
+
+    client.buffer(&State::Idle).await.unwrap();
+    client.client_messages(inner.get()).await.unwrap();
+
+    let messages = read!(conn, ['T', 'D', 'D', 'C', 'Z']);
+    let cc = CommandComplete::from_bytes(messages[3].clone().freeze()).unwrap();
+    assert_eq!(cc.command(), "SELECT 2");
+    assert!(!inner.backend.connected());
+}
+
+#[tokio::test]
+async fn test_no_rows() {
+    crate::logger();
+    load_test_sharded();
+    let (mut conn, mut client) = parallel_test_client().await;
+    let mut inner = Inner::new(&client).unwrap();
+
+    // No shard is queried.
+    conn.write_all(&buffer!({
+        Query::new("SELECT id, value FROM sharded WHERE id = ANY('{}')")
+    }))
+    .await
+    .unwrap();
+
+    client.buffer(&State::Idle).await.unwrap();
+    client.client_messages(inner.get()).await.unwrap();
+
+    let messages = read!(conn, ['T', 'C', 'Z']);
+    let rd = RowDescription::from_bytes(messages[0].clone().freeze()).unwrap();
+    assert_eq!(rd.field(1).unwrap().name, "value");
+    let cc = CommandComplete::from_bytes(messages[1].clone().freeze()).unwrap();
+    assert_eq!(cc.command(), "SELECT 0");
+    assert!(!inner.backend.connected());
+    assert!(!inner.router.routed());
+
+    conn.write_all(&buffer!(
+        { Parse::named("test", "DELETE FROM sharded WHERE id = ANY($1)") },
+        {
+            Bind::test_params(
+                "test",
+                &[Parameter {
+                    len: 2,
+                    data: "{}".into(),
+                }],
+            )
+        },
+        { Describe::new_portal("") },
+        { Execute::new() },
+        { Sync }
+    ))
+    .await
+    .unwrap();
+
+    client.buffer(&State::Idle).await.unwrap();
+    client.client_messages(inner.get()).await.unwrap();
+
+    let messages = read!(conn, ['1', '2', 'n', 'C', 'Z']);
+    let cc = CommandComplete::from_bytes(messages[3].clone().freeze()).unwrap();
+    assert_eq!(cc.command(), "DELETE 0");
+    assert!(!inner.backend.connected());
+}
+
 #[tokio::test]
 async fn test_client_with_replicas() {
     crate::logger();
@@ -384,6 +505,7 @@ async fn test_client_with_replicas() {
                 assert!(state.stats.counts.healthchecks <= idle + 1); // TODO: same
                 pool_sent -= (healthcheck_len_sent * state.stats.counts.healthchecks) as isize;
             }
+            Role::Analytics => unreachable!("no analytics replicas"),
         }
     }
 
@@ -542,7 +664,7 @@ async fn test_close_parse() {
         .await
         .unwrap();
 
-    conn.write_all(&buffer!({ Query::new("SELECT 1") }))
+    conn.write_all(&buffer!({ Query::new("SELECT now()") }))
         .await
         .unwrap();
 
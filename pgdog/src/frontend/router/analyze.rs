@@ -0,0 +1,181 @@
+//! Routing report for a set of sample queries.
+//!
+//! Shows how well a sharding configuration fits a workload before it's deployed:
+//! which queries go to one shard, and why the others go to several.
+
+use std::collections::BTreeMap;
+
+use pg_query::parse;
+
+use super::{parser::Shard, Route, Router};
+use crate::backend::Cluster;
+
+/// Why a query goes to more than one shard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FanOutReason {
+    /// The sharding key values are on different shards, e.g. `id IN (1, 2)`.
+    MultipleShards,
+    /// The query doesn't filter on a sharding key.
+    NoShardingKey,
+    /// Statements other than `SELECT`, `INSERT`, `UPDATE` and `DELETE`,
+    /// e.g. DDL, run on all shards.
+    Statement,
+}
+
+/// Where a query goes.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// One shard.
+    SingleShard(usize),
+    /// Several shards, or all of them.
+    FanOut { shard: Shard, reason: FanOutReason },
+    /// The query can't be routed, e.g. it's a syntax error
+    /// or the configuration doesn't allow it.
+    Error(String),
+}
+
+/// Route of one query.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryReport {
+    pub query: String,
+    pub outcome: Outcome,
+}
+
+/// Routes of all queries and their totals.
+#[derive(Debug, Clone, Default)]
+pub struct RoutingReport {
+    /// Each query, in the order they were given.
+    pub queries: Vec<QueryReport>,
+    /// Queries going to one shard.
+    pub single_shard: usize,
+    /// Queries going to several shards, by reason.
+    pub fan_out: BTreeMap<FanOutReason, usize>,
+    /// Queries that couldn't be routed.
+    pub errors: usize,
+}
+
+impl RoutingReport {
+    /// Percentage of queries going to one shard.
+    pub fn single_shard_pct(&self) -> f64 {
+        if self.queries.is_empty() {
+            0.0
+        } else {
+            self.single_shard as f64 * 100.0 / self.queries.len() as f64
+        }
+    }
+}
+
+/// Route each query on its own, like [`Router::try_route`] does,
+/// and report where they went.
+pub fn analyze_queries(queries: &[&str], cluster: &Cluster) -> RoutingReport {
+    let mut router = Router::new();
+    let mut report = RoutingReport::default();
+
+    for query in queries {
+        let outcome = match router.try_route(query, cluster) {
+            Ok(route) => match route.shard() {
+                Shard::Direct(shard) => Outcome::SingleShard(*shard),
+                shard => Outcome::FanOut {
+                    shard: shard.clone(),
+                    reason: fan_out_reason(query, &route),
+                },
+            },
+            Err(err) => Outcome::Error(err.to_string()),
+        };
+
+        match outcome {
+            Outcome::SingleShard(_) => report.single_shard += 1,
+            Outcome::FanOut { reason, .. } => *report.fan_out.entry(reason).or_default() += 1,
+            Outcome::Error(_) => report.errors += 1,
+        }
+
+        report.queries.push(QueryReport {
+            query: query.to_string(),
+            outcome,
+        });
+    }
+
+    report
+}
+
+fn fan_out_reason(query: &str, route: &Route) -> FanOutReason {
+    // The router parsed it already.
+    let Ok(ast) = parse(query) else {
+        return FanOutReason::Statement;
+    };
+
+    let dml = ast.statement_types().iter().all(|statement| {
+        matches!(
+            *statement,
+            "SelectStmt" | "InsertStmt" | "UpdateStmt" | "DeleteStmt"
+        )
+    });
+    if !dml {
+        return FanOutReason::Statement;
+    }
+
+    if !route.sharding_keys().is_empty() {
+        FanOutReason::MultipleShards
+    } else {
+        FanOutReason::NoShardingKey
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_analyze_queries() {
+        let cluster = Cluster::new_test();
+        let report = analyze_queries(
+            &[
+                "SELECT * FROM sharded WHERE id = 1",
+                "SELECT * FROM sharded WHERE id = 11",
+                "UPDATE sharded SET value = 'a' WHERE id = 11",
+                "SELECT * FROM sharded WHERE id IN (1, 11)",
+                "SELECT * FROM sharded WHERE value = 'a'",
+                "DELETE FROM sharded WHERE value = 'a'",
+                "CREATE INDEX ON sharded (value)",
+                "SELEKT 1",
+            ],
+            &cluster,
+        );
+
+        let outcomes = report
+            .queries
+            .iter()
+            .map(|query| query.outcome.clone())
+            .collect::<Vec<_>>();
+        let fan_out = |reason| Outcome::FanOut {
+            shard: Shard::All,
+            reason,
+        };
+        assert_eq!(outcomes[0], Outcome::SingleShard(0));
+        assert_eq!(outcomes[1], Outcome::SingleShard(1));
+        assert_eq!(outcomes[2], Outcome::SingleShard(1));
+        assert_eq!(outcomes[3], fan_out(FanOutReason::MultipleShards));
+        assert_eq!(outcomes[4], fan_out(FanOutReason::NoShardingKey));
+        assert_eq!(outcomes[5], fan_out(FanOutReason::NoShardingKey));
+        assert_eq!(outcomes[6], fan_out(FanOutReason::Statement));
+        assert!(matches!(outcomes[7], Outcome::Error(_)));
+        assert_eq!(report.queries[7].query, "SELEKT 1");
+
+        assert_eq!(report.single_shard, 3);
+        assert_eq!(report.single_shard_pct(), 37.5);
+        assert_eq!(report.errors, 1);
+        assert_eq!(
+            report.fan_out.into_iter().collect::<Vec<_>>(),
+            [
+                (FanOutReason::MultipleShards, 1),
+                (FanOutReason::NoShardingKey, 2),
+                (FanOutReason::Statement, 1),
+            ]
+        );
+        assert_eq!(
+            analyze_queries(&["SELECT * FROM sharded WHERE id = 1"], &cluster).single_shard_pct(),
+            100.0
+        );
+        assert_eq!(analyze_queries(&[], &cluster).single_shard_pct(), 0.0);
+    }
+}
@@ -31,4 +31,12 @@ impl Error {
     pub fn empty_query(&self) -> bool {
         matches!(self, Self::Parser(super::parser::Error::EmptyQuery))
     }
+
+    /// The query can't be routed as written.
+    pub fn route_error(&self) -> Option<&super::parser::RouteError> {
+        match self {
+            Self::Parser(err) => err.route_error(),
+            _ => None,
+        }
+    }
 }
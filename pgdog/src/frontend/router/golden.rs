@@ -0,0 +1,100 @@
+//! Routes of a corpus of queries, compared against a checked-in golden file.
+//!
+//! Catches parser changes that route queries differently. The queries are in
+//! `golden/corpus.sql`, one per line, and their routes in `golden/routes.txt`.
+//! If a change in routing is intended, regenerate the routes with:
+//!
+//! ```bash
+//! UPDATE_GOLDEN=1 cargo test -p pgdog golden
+//! ```
+//!
+//! and check the differences in `golden/routes.txt` before committing them.
+
+use std::{env, fs};
+
+use crate::backend::Cluster;
+
+use super::Router;
+
+static CORPUS: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/src/frontend/router/golden/corpus.sql"
+);
+static ROUTES: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/src/frontend/router/golden/routes.txt"
+);
+
+/// Queries in the corpus, skipping empty lines and `--` comments.
+fn corpus() -> Vec<String> {
+    fs::read_to_string(CORPUS)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with("--"))
+        .map(String::from)
+        .collect()
+}
+
+/// Route of one query: its shard, role, whether rows are buffered and the sharding key.
+fn route(router: &mut Router, query: &str, cluster: &Cluster) -> String {
+    match router.try_route(query, cluster) {
+        Ok(route) => format!(
+            "{}, buffered={}, key={}",
+            route,
+            route.should_buffer(),
+            route.sharding_column().unwrap_or("none")
+        ),
+        Err(err) => format!("error: {}", err),
+    }
+}
+
+/// Each query followed by its route, indented.
+fn routes() -> String {
+    let cluster = Cluster::new_test();
+    let mut router = Router::new();
+
+    corpus()
+        .iter()
+        .map(|query| format!("{}\n    {}\n", query, route(&mut router, query, &cluster)))
+        .collect()
+}
+
+#[test]
+fn test_golden_routes() {
+    let routes = routes();
+
+    if env::var("UPDATE_GOLDEN").is_ok() {
+        fs::write(ROUTES, &routes).unwrap();
+        return;
+    }
+
+    let golden = fs::read_to_string(ROUTES).unwrap();
+    let actual = routes.lines().collect::<Vec<_>>();
+    let expected = golden.lines().collect::<Vec<_>>();
+    assert_eq!(
+        actual.len(),
+        expected.len(),
+        "corpus changed, run with UPDATE_GOLDEN=1 to add its routes"
+    );
+
+    let changed = actual
+        .chunks(2)
+        .zip(expected.chunks(2))
+        .filter(|(actual, expected)| actual != expected)
+        .map(|(actual, expected)| {
+            format!(
+                "{}\n    expected: {}\n    actual:   {}",
+                actual[0],
+                expected[1].trim(),
+                actual[1].trim()
+            )
+        })
+        .collect::<Vec<_>>();
+
+    assert!(
+        changed.is_empty(),
+        "routes changed, run with UPDATE_GOLDEN=1 to accept them:\n\n{}",
+        changed.join("\n\n")
+    );
+}
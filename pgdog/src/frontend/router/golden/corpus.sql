@@ -0,0 +1,224 @@
+-- Queries checked by the route-stability test in golden.rs, one per line.
+-- Lines starting with -- and empty lines are skipped.
+--
+-- Queries routed round robin, e.g. `SELECT 1` or reads of omnisharded tables,
+-- go to a different shard every time, so they aren't here.
+
+-- Queries from the query parser tests.
+SELECT * FROM embeddings ORDER BY embedding <-> '[1,2,3]'
+SELECT * FROM sharded WHERE id = $1 FOR UPDATE
+SELECT * FROM sharded s JOIN orders o ON o.sharded_id = s.id WHERE s.id = 11 FOR UPDATE OF s
+SELECT * FROM sharded, orders WHERE sharded.id = 1 FOR UPDATE
+SELECT * FROM sharded WHERE id = 1
+LISTEN events
+UNLISTEN events
+INSERT INTO foo (id) VALUES ($1::UUID) ON CONFLICT (id) DO UPDATE SET id = excluded.id RETURNING id
+WITH s AS (SELECT 1), s2 AS (INSERT INTO test VALUES ($1) RETURNING *), s3 AS (SELECT 123) SELECT * FROM s
+SELECT * FROM sharded WHERE random() < 0.1 AND id = 1
+SELECT * FROM sharded WHERE random() < 0.1
+SELECT * FROM sharded WHERE id = 11 AND id > 5
+SELECT * FROM sharded WHERE sharded.* IS NOT NULL AND id = 1
+SELECT * FROM sharded s WHERE s.* = ROW(1, 'a')
+SELECT tenant_id, count(*) c FROM events GROUP BY tenant_id ORDER BY c DESC LIMIT 10
+SELECT tenant_id, count(*) FROM events GROUP BY tenant_id ORDER BY max(id)
+/* pgdog_reducer: test_router */ SELECT * FROM sharded
+/* pgdog_reducer: test_router */ SELECT * FROM sharded WHERE id = 1
+SELECT * FROM sharded
+/* pgdog_role: replica */ SELECT * FROM sharded WHERE id = 1
+/* pgdog_role: primary */ INSERT INTO sharded (id) VALUES (1)
+/* pgdog_pool: analytics */ SELECT * FROM sharded WHERE id = 1
+SELECT count(*) FROM sharded /* pgdog_pool: analytics */
+/* pgdog_max_staleness: 500 */ SELECT * FROM sharded WHERE id = 1
+SELECT * FROM sharded WHERE NOT NOT (id = 1)
+SELECT * FROM sharded WHERE NOT (id = 1)
+SELECT * FROM users LIMIT 25 OFFSET 5
+SELECT DISTINCT * FROM users
+SELECT DISTINCT ON(1, email) * FROM users
+SELECT DISTINCT status FROM events ORDER BY status
+SELECT * FROM sharded WHERE id = ANY('{1, 2, 3}')
+SELECT * FROM sharded WHERE id = ANY('{11,1}'::int[])
+SELECT * FROM sharded WHERE id = ANY('{{1},{1}}'::int[])
+COPY (SELECT * FROM sharded WHERE id = 11) TO STDOUT
+COPY (SELECT * FROM sharded) TO STDOUT (FORMAT CSV)
+SELECT count(*) FROM t
+SELECT * FROM sharded WHERE id = 1 ORDER BY id
+SELECT DISTINCT id, count(*) FROM sharded WHERE id = 1 GROUP BY id
+SELECT * FROM sharded ORDER BY id
+SELECT count(*) FROM sharded
+EXPLAIN ANALYZE INSERT INTO sharded (id) VALUES (1)
+EXPLAIN SELECT * FROM sharded ORDER BY id
+SELECT * FROM sharded ORDER BY id FETCH FIRST 10 ROWS WITH TIES
+SELECT * FROM sharded ORDER BY id LIMIT 10
+SELECT * FROM other o, sharded s WHERE s.id = 11
+SELECT * FROM sharded s WHERE id = 11
+SELECT * FROM sharded s, other o WHERE o.id = 11
+SELECT * FROM sharded s JOIN LATERAL (SELECT count(*) FROM other WHERE other.id = s.id) c ON true WHERE s.id = 1
+SELECT * FROM sharded s, LATERAL (SELECT id FROM other WHERE other.user_id = s.id) s2 WHERE s2.id = 1
+SELECT * FROM sharded WHERE COALESCE(id, 1) = 11
+SELECT * FROM sharded WHERE id = 11 OR id = 11
+SELECT * FROM sharded WHERE ctid = '(0,1)'
+SELECT * FROM sharded WHERE ctid = '(0,1)' AND id = 11
+SELECT COUNT(*) FROM sharded
+SELECT email FROM sharded GROUP BY 1
+DECLARE c CURSOR WITH HOLD FOR SELECT * FROM sharded WHERE id = 11
+SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM sharded WHERE id = 11
+SELECT id, row_number() OVER (ORDER BY created_at) FROM sharded WHERE id = 11
+SELECT * FROM sharded WHERE id = 11 ORDER BY random() LIMIT 5
+SELECT * FROM sharded ORDER BY id, random()
+SELECT * FROM sharded WHERE id = $1
+SELECT id FROM sharded WHERE id = 11 INTERSECT SELECT id FROM sharded WHERE id = 11
+SELECT id FROM sharded WHERE id = 1 EXCEPT SELECT id FROM sharded WHERE id = 1 ORDER BY 1
+SELECT id FROM sharded WHERE id = 1 UNION SELECT id FROM sharded WHERE id = 11
+SELECT id FROM sharded WHERE id = 1 UNION ALL SELECT id FROM sharded WHERE id = 11
+SELECT * FROM sharded WHERE id = 1; SELECT * FROM sharded WHERE id = 1
+BEGIN; UPDATE sharded SET v = 1 WHERE id = 1; UPDATE sharded SET v = 1 WHERE id = 11; COMMIT
+INSERT INTO sharded (id, email) VALUES ($1, $2)
+SELECT * FROM embeddings ORDER BY embedding  <-> $1
+SELECT * FROM embeddings ORDER BY embedding <-> $0
+SELECT * FROM myschema.users u JOIN orders ON orders.user_id = u.id WHERE u.tenant_id = $1 FOR UPDATE OF u FOR SHARE OF orders
+SELECT * FROM sharded WHERE id = 11 FOR UPDATE SKIP LOCKED
+SELECT * FROM sharded WHERE id = 11 FOR NO KEY UPDATE NOWAIT
+SELECT * FROM sharded WHERE id = 11 LIMIT 10 FOR SHARE SKIP LOCKED
+SELECT count(*) FROM sharded WHERE id = 11 FOR UPDATE
+SELECT * FROM sharded WHERE id = $1 FOR UPDATE SKIP LOCKED
+INSERT INTO sharded_replicated (id, name) VALUES (1, 'a')
+UPDATE sharded_replicated SET name = 'b' WHERE id = 1
+DELETE FROM sharded_replicated WHERE id = 11
+UPDATE sharded_omni SET name = 'b' WHERE id = 1
+SELECT * FROM sharded WHERE id = $1 AND random() < 0.1
+UPDATE sharded SET value = 'a' WHERE id IN ($1)
+SELECT * FROM sharded WHERE id = ANY($1)
+SELECT * FROM sharded WHERE id = $1 AND id > $2
+SELECT * FROM sharded WHERE id > 11
+SELECT * FROM sharded WHERE id <> 11
+SELECT * FROM sharded WHERE id BETWEEN 1 AND 11
+SELECT * FROM sharded WHERE id IS DISTINCT FROM 11
+SELECT * FROM myschema.users WHERE tenant_id = $1
+UPDATE users SET name = 'a' WHERE tenant_id = $1
+INSERT INTO users (tenant_id, name) VALUES ($1, 'a')
+SELECT * FROM other.users WHERE tenant_id = $1
+SELECT * FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM other)
+SELECT * FROM myschema.users WHERE EXISTS (SELECT 1 FROM other WHERE other.id = users.id) AND tenant_id = $1
+UPDATE myschema.users SET name = 'a' WHERE tenant_id = $1 AND NOT EXISTS (SELECT 1 FROM other)
+DELETE FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM other)
+SELECT * FROM myschema.users WHERE EXISTS (SELECT 1 FROM other WHERE tenant_id = $1)
+SELECT * FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id)
+SELECT * FROM myschema.users u WHERE EXISTS (SELECT 1 FROM o WHERE o.tenant_id = u.tenant_id AND o.id = 5) AND u.tenant_id = $1
+SELECT * FROM myschema.users WHERE tenant_id = $1 AND id IN (SELECT user_id FROM o WHERE o.tenant_id = users.tenant_id)
+SELECT *, (SELECT count(*) FROM o WHERE o.tenant_id = users.tenant_id) FROM myschema.users WHERE tenant_id = $1
+UPDATE myschema.users SET n = 1 WHERE tenant_id = $1 AND NOT EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id)
+DELETE FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id)
+SELECT * FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id AND o.tenant_id = 1)
+SELECT * FROM sharded WHERE id = $1 AND EXISTS (SELECT 1 FROM sharded s WHERE s.id = sharded.id AND s.id = 1)
+SELECT tenant_id, count(*) FROM events GROUP BY tenant_id ORDER BY count(*) DESC LIMIT 10
+SELECT tenant_id, COUNT(*) FROM events GROUP BY tenant_id ORDER BY count(*) DESC LIMIT 10
+SELECT tenant_id, count(*) FROM events GROUP BY tenant_id ORDER BY 2 DESC LIMIT 10
+UPDATE sharded SET value = 'a' WHERE id = 11
+SELECT * FROM sharded WHERE id = 1 /* pgdog_role: Primary */
+SELECT * FROM sharded WHERE id IN (1, 11)
+SELECT * FROM users LIMIT $1 OFFSET $2
+SELECT * FROM sharded WHERE id = ANY('{1}')
+SELECT * FROM sharded WHERE id = ANY('{1,NULL}'::bigint[])
+DELETE FROM sharded WHERE id = ANY('{1}')
+SELECT * FROM sharded FETCH FIRST 10 ROWS WITH TIES
+SELECT * FROM sharded WHERE id = $1::int AND id = $1::bigint
+SELECT * FROM sharded s, other o WHERE s.id = $1 AND o.user_id = s.id
+SELECT * FROM sharded s, LATERAL (SELECT * FROM other o WHERE o.user_id = s.id LIMIT 5) l WHERE s.id = $1
+SELECT * FROM (SELECT id AS i, * FROM sharded) sub WHERE sub.i = $1
+SELECT * FROM (SELECT s.id AS i FROM sharded s) sub WHERE i = $1
+SELECT * FROM (SELECT * FROM sharded) sub WHERE sub.id = $1
+SELECT * FROM (SELECT id, email FROM sharded WHERE email IS NOT NULL) sub WHERE sub.id = $1
+SELECT * FROM other o JOIN (SELECT id AS i FROM sharded) sub ON sub.i = o.user_id WHERE sub.i = $1
+SELECT * FROM (SELECT id + 1 AS i FROM sharded) sub WHERE sub.i = $1
+SELECT * FROM (SELECT id + 1 AS id, * FROM sharded) sub WHERE sub.id = $1
+SELECT * FROM (SELECT id AS i FROM sharded LIMIT 10) sub WHERE sub.i = $1
+SELECT * FROM (SELECT email AS id FROM sharded) sub WHERE sub.id = $1
+SELECT * FROM (SELECT id AS i FROM other) sub WHERE sub.i = $1
+SELECT * FROM sharded WHERE $1 = COALESCE(sharded.id, 1)
+SELECT * FROM accounts WHERE tenant_id = 1 OR status = 'a'
+SELECT * FROM accounts WHERE tenant_id = 1 OR customer_key > 5
+SELECT * FROM accounts WHERE tenant_id = 1 OR NOT customer_key = 2
+SELECT * FROM groups WHERE $1 = ANY(tenant_ids)
+SELECT * FROM groups g WHERE $1::bigint = ANY(g.tenant_ids) AND name = 'a'
+SELECT * FROM groups WHERE 5 = ANY(tenant_ids)
+SELECT * FROM groups WHERE tenant_ids = $1
+SELECT * FROM groups WHERE tenant_ids @> ARRAY[$1]
+SELECT * FROM groups WHERE tenant_ids = ANY($1)
+SELECT * FROM ledger WHERE account_id = 42 AND region = 'acme'
+SELECT * FROM ledger WHERE region = 'acme' AND account_id = 42
+SELECT * FROM ledger l WHERE l.region = $1 AND l.account_id = $2
+SELECT * FROM ledger WHERE account_id = 42
+SELECT * FROM ledger WHERE account_id = 42 AND region IN ('acme', 'globex')
+SELECT * FROM ledger WHERE (account_id = 42 OR account_id = 43) AND region = 'acme'
+SELECT * FROM events WHERE tenant_id = $1 AND created_at >= '2024-01-01' AND created_at < '2024-02-01'
+SELECT * FROM ONLY events WHERE tenant_id = $1
+SELECT count(*) FROM events e WHERE e.tenant_id = $1 AND e.created_at > now() - interval '1 day'
+SELECT * FROM users WHERE tenant_id = $1
+SELECT * FROM active_users WHERE tenant_id = $1
+SELECT u.email FROM active_users u WHERE u.tenant_id = $1
+SELECT * FROM sharded WHERE id = $3
+SELECT * FROM sharded WHERE value = $2 AND id = $3
+UPDATE sharded SET value = $2 WHERE id = $3
+DELETE FROM sharded WHERE id = $3
+INSERT INTO sharded (id, value) VALUES ($3, $2)
+SELECT * FROM sharded WHERE id = 11
+UPDATE sharded SET email = 'test' WHERE id = 11
+DELETE FROM sharded WHERE id = 11
+INSERT INTO sharded (id, email) VALUES (11, 'test')
+SELECT * FROM sharded WHERE email = 'test'
+SELECT * FROM sharded WHERE id = 1 OR id = 11
+UPDATE sharded SET email = 'test' WHERE email = 'a'
+DELETE FROM myschema.users WHERE CURRENT OF c
+UPDATE myschema.users SET email = 'a' WHERE CURRENT OF c
+SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM sharded
+SELECT id, percentile_disc(0.9) WITHIN GROUP (ORDER BY v) FROM sharded GROUP BY id
+SELECT mode() WITHIN GROUP (ORDER BY v) FROM sharded WHERE id IN (1, 11)
+SELECT round(percentile_cont(0.5) WITHIN GROUP (ORDER BY v)) FROM sharded
+SELECT * FROM (SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM sharded) t
+SELECT id, row_number() OVER (ORDER BY created_at) FROM sharded
+SELECT id, sum(v) OVER w FROM sharded WINDOW w AS (PARTITION BY id)
+SELECT rank() OVER () + 1 FROM sharded WHERE id IN (1, 11)
+SELECT * FROM (SELECT lag(v) OVER (ORDER BY id) FROM sharded) t
+SELECT * FROM sharded ORDER BY random() LIMIT 5
+SELECT * FROM sharded ORDER BY random(), id
+SELECT * FROM pg_catalog.pg_class
+SELECT * FROM pg_class
+SELECT * FROM information_schema.columns WHERE table_name = 'sharded'
+SELECT c.relname FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace
+SELECT * FROM pg_class JOIN sharded ON sharded.id = pg_class.oid
+SELECT * FROM public.pg_things
+UPDATE sharded SET value = 'a' WHERE value = 'b'
+INSERT INTO sharded (id, value) VALUES (1, 'a')
+SELECT * FROM audit_log WHERE created_at > now() - interval '1 day'
+SELECT count(*) FROM audit_log
+SELECT id FROM sharded WHERE id = 1 INTERSECT SELECT id FROM sharded WHERE id = 11
+SELECT id FROM sharded EXCEPT SELECT id FROM sharded WHERE id = 11
+SELECT * FROM sharded WHERE value = 'a'
+DELETE FROM sharded WHERE value = 'a'
+
+-- Queries generated by ORMs: Rails, Django, SQLAlchemy, Hibernate and Prisma.
+SELECT "sharded".* FROM "sharded" WHERE "sharded"."id" = 11 LIMIT 1
+SELECT "sharded".* FROM "sharded" WHERE "sharded"."id" IN (1, 11) ORDER BY "sharded"."id" ASC
+SELECT 1 AS one FROM "sharded" WHERE "sharded"."id" = 1 LIMIT 1
+SELECT COUNT(*) FROM "sharded" WHERE "sharded"."value" = 'a'
+INSERT INTO "sharded" ("id", "value") VALUES (11, 'a') RETURNING "id"
+UPDATE "sharded" SET "value" = 'b' WHERE "sharded"."id" = 1
+DELETE FROM "sharded" WHERE "sharded"."id" = 11
+SELECT "sharded"."id", "sharded"."value" FROM "sharded" WHERE "sharded"."id" = 1
+SELECT COUNT(*) AS "__count" FROM "sharded" WHERE "sharded"."id" IN (1, 11)
+SELECT (1) AS "a" FROM "sharded" WHERE "sharded"."id" = 11 LIMIT 1
+SELECT sharded.id AS sharded_id, sharded.value AS sharded_value FROM sharded WHERE sharded.id = 11
+SELECT count(*) AS count_1 FROM (SELECT sharded.id AS sharded_id FROM sharded WHERE sharded.id = 1) AS anon_1
+SELECT s1_0.id,s1_0.value FROM sharded s1_0 WHERE s1_0.id=11
+SELECT "public"."sharded"."id", "public"."sharded"."value" FROM "public"."sharded" WHERE "public"."sharded"."id" = 1 OFFSET 0
+SELECT EXISTS(SELECT 1 FROM "sharded" WHERE "sharded"."id" = 1)
+SELECT "myschema"."users"."tenant_id" FROM "myschema"."users" WHERE "myschema"."users"."tenant_id" = 11
+INSERT INTO sharded (id, value) VALUES (1, 'a'), (11, 'b') ON CONFLICT (id) DO NOTHING
+INSERT INTO sharded (id, value) OVERRIDING SYSTEM VALUE VALUES (11, 'a') RETURNING id
+WITH RECURSIVE tree AS (SELECT id, parent_id FROM myschema.users WHERE tenant_id = 11 AND parent_id IS NULL UNION ALL SELECT u.id, u.parent_id FROM myschema.users u JOIN tree t ON u.parent_id = t.id WHERE u.tenant_id = 11) SELECT * FROM tree
+WITH RECURSIVE tree AS (SELECT id, parent_id FROM myschema.users WHERE tenant_id = 11 AND parent_id IS NULL UNION ALL SELECT u.id, u.parent_id FROM myschema.users u JOIN tree t ON u.parent_id = t.id) SELECT * FROM tree
+SELECT * FROM sharded WHERE id = 1 ORDER BY id DESC LIMIT 10 OFFSET 20
+SELECT * FROM sharded ORDER BY id DESC LIMIT 10
+SELECT sharded.id, count(*) FROM sharded GROUP BY sharded.id
+UPDATE sharded SET value = 'a', updated_at = now() WHERE sharded.id = 11 RETURNING *
+SELECT * FROM sharded s JOIN myschema.users u ON u.id = s.id WHERE s.id IN (1, 11) AND u.tenant_id = 11
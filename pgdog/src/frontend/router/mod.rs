@@ -1,19 +1,30 @@
 //! Query router.
 
+pub mod analyze;
 pub mod context;
 pub mod copy;
 pub mod error;
+#[cfg(test)]
+mod golden;
 pub mod parser;
 pub mod request;
 pub mod round_robin;
 pub mod search_path;
 pub mod sharding;
 
+pub use analyze::{analyze_queries, RoutingReport};
 pub use copy::CopyRow;
 pub use error::Error;
 pub use parser::{Command, QueryParser, Route};
 
-use super::Buffer;
+use super::{buffer::BufferedQuery, Buffer, PreparedStatements};
+use crate::{
+    backend::{Cluster, ProtocolMessage},
+    net::{
+        messages::{Query, Sync},
+        Parameters,
+    },
+};
 pub use context::RouterContext;
 pub use search_path::SearchPath;
 pub use sharding::{Lists, Ranges};
@@ -53,6 +64,109 @@ impl Router {
         Ok(self.query_parser.parse(context)?)
     }
 
+    /// Route a SQL string on its own, outside of any client session.
+    ///
+    /// This never panics: invalid SQL, empty strings and comments
+    /// return an error instead.
+    pub fn try_route(&mut self, sql: &str, cluster: &Cluster) -> Result<Route, Error> {
+        self.reset();
+
+        let buffer = Buffer::from(vec![Query::new(sql).into()]);
+        let mut prepared_statements = PreparedStatements::default();
+        let params = Parameters::default();
+        let context =
+            RouterContext::new(&buffer, cluster, &mut prepared_statements, &params, false)?;
+        self.query(context)?;
+
+        Ok(self.route())
+    }
+
+    /// Split Binds pipelined for one prepared statement into runs of Binds
+    /// going to the same shard, each ending with a Sync, so they can be sent
+    /// to their shards one after the other.
+    ///
+    /// Returns `None` if the Binds all go to the same shard, or we can't tell
+    /// without routing each one, e.g. the sharding key isn't a single parameter.
+    pub fn pipeline(
+        &self,
+        buffer: &Buffer,
+        cluster: &Cluster,
+    ) -> Result<Option<Vec<Buffer>>, Error> {
+        let binds = buffer
+            .iter()
+            .filter_map(|message| match message {
+                ProtocolMessage::Bind(bind) => Some(bind),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        if binds.len() < 2
+            || cluster.shards().len() < 2
+            || !matches!(buffer.last(), Some(ProtocolMessage::Sync(_)))
+            || binds
+                .iter()
+                .any(|bind| bind.statement() != binds[0].statement())
+        {
+            return Ok(None);
+        }
+
+        let Some(BufferedQuery::Prepared(query)) = buffer.query()? else {
+            return Ok(None);
+        };
+        // Syntax errors are reported by the router.
+        let Ok(ast) = parser::Cache::get().parse(query.query()) else {
+            return Ok(None);
+        };
+        let Some(resolver) = parser::BindResolver::new(&ast.protobuf, &cluster.sharding_schema())
+        else {
+            return Ok(None);
+        };
+
+        // Binds with keys we can't hash go through the router.
+        let Ok(groups) = resolver.group(binds.iter().copied()) else {
+            return Ok(None);
+        };
+        if groups.len() < 2 {
+            return Ok(None);
+        }
+
+        let mut shards = vec![None; binds.len()];
+        for (shard, positions) in &groups {
+            for position in positions {
+                shards[*position] = Some(shard);
+            }
+        }
+
+        // Runs keep the order of the Binds, so the client
+        // gets the results in the order it asked for them.
+        let mut runs = vec![vec![]];
+        let mut current = None;
+        let mut position = 0;
+        for message in buffer.iter() {
+            if let ProtocolMessage::Bind(_) = message {
+                let shard = shards[position];
+                position += 1;
+                if current.is_some() && current != shard {
+                    if let Some(run) = runs.last_mut() {
+                        run.push(Sync::new().into());
+                    }
+                    runs.push(vec![]);
+                }
+                current = shard;
+            }
+            if let Some(run) = runs.last_mut() {
+                run.push(message.clone());
+            }
+        }
+
+        Ok(Some(runs.into_iter().map(Buffer::from).collect()))
+    }
+
+    /// Send a query that matches no rows to one shard, see [`QueryParser::one_shard`].
+    pub fn one_shard(&mut self, cluster: &Cluster) -> &Command {
+        self.query_parser.one_shard(cluster.shards().len())
+    }
+
     /// Parse CopyData messages and shard them.
     pub fn copy_data(&mut self, buffer: &Buffer) -> Result<Vec<CopyRow>, Error> {
         Ok(self.query_parser.copy_data(buffer.copy_data()?)?)
@@ -78,3 +192,222 @@ impl Router {
         self.query_parser.in_transaction()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn test_try_route() {
+        let cluster = Cluster::new_test();
+        let mut router = Router::new();
+
+        let route = router
+            .try_route("SELECT * FROM sharded WHERE id = 11", &cluster)
+            .unwrap();
+        assert!(route.is_read());
+        assert!(!route.is_cross_shard());
+
+        // Statements going to the same shard are routed together.
+        let route = router
+            .try_route(
+                "SELECT * FROM sharded WHERE id = 11; UPDATE sharded SET v = 1 WHERE id = 11",
+                &cluster,
+            )
+            .unwrap();
+        assert_eq!(route.shard(), &parser::Shard::Direct(1));
+        assert!(route.is_write());
+
+        for sql in ["", " ", ";", "-- comment", "/* comment */", "SELEKT 1"] {
+            assert!(router.try_route(sql, &cluster).is_err(), "{:?}", sql);
+        }
+
+        // Postgres positions are 1-indexed, so 0 used to underflow.
+        for sql in [
+            "SELECT * FROM sharded WHERE id = $0",
+            "SELECT id, count(*) FROM sharded GROUP BY 0",
+            "SELECT DISTINCT ON (0) * FROM sharded",
+            "SELECT * FROM sharded ORDER BY 0",
+            "SELECT * FROM sharded LIMIT $0",
+            "SELECT $0",
+        ] {
+            let _ = router.try_route(sql, &cluster);
+        }
+    }
+
+    #[test]
+    fn test_try_route_random_sql() {
+        let tokens = [
+            "SELECT",
+            "INSERT INTO",
+            "UPDATE",
+            "DELETE FROM",
+            "VALUES",
+            "SET",
+            "FROM",
+            "WHERE",
+            "AND",
+            "OR",
+            "NOT",
+            "IN",
+            "ANY",
+            "IS NULL",
+            "ORDER BY",
+            "GROUP BY",
+            "HAVING",
+            "LIMIT",
+            "OFFSET",
+            "FETCH FIRST",
+            "ROWS WITH TIES",
+            "DISTINCT",
+            "DISTINCT ON",
+            "RETURNING",
+            "WITH",
+            "AS",
+            "EXPLAIN",
+            "DECLARE c CURSOR FOR",
+            "COPY",
+            "BEGIN",
+            "COMMIT",
+            "sharded",
+            "sharded_omni",
+            "s",
+            "users",
+            "id",
+            "s.id",
+            "email",
+            "count(*)",
+            "sum(id)",
+            "now()",
+            "*",
+            "=",
+            "<",
+            ",",
+            "(",
+            ")",
+            ";",
+            "::bigint",
+            "::text",
+            "0",
+            "1",
+            "-1",
+            "11",
+            "2147483647",
+            "$0",
+            "$1",
+            "$2",
+            "$99",
+            "'a'",
+            "'{1,2}'",
+            "NULL",
+            "TRUE",
+            "--",
+            "/*",
+            "*/",
+            "pgdog.shard",
+            "pgdog.shards",
+            "pgdog.sharding_key",
+            "TO",
+            "'1,1'",
+        ];
+        let templates = [
+            "SELECT * FROM sharded WHERE id = {}",
+            "SELECT * FROM sharded ORDER BY {} LIMIT {}",
+            "SELECT id, count(*) FROM sharded GROUP BY {}",
+            "SELECT DISTINCT ON ({}) * FROM sharded",
+            "INSERT INTO sharded (id) VALUES ({})",
+            "UPDATE sharded SET email = 'a' WHERE id = {}",
+            "DELETE FROM sharded WHERE id IN ({}, {})",
+            "SET pgdog.shard TO {}",
+            "SET pgdog.shards TO {}",
+            "SELECT {}",
+        ];
+        let values = [
+            "0",
+            "-1",
+            "1",
+            "11",
+            "$0",
+            "$1",
+            "$2",
+            "NULL",
+            "'a'",
+            "1.5",
+            "-2147483648",
+        ];
+
+        let cluster = Cluster::new_test();
+        let mut router = Router::new();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for _ in 0..5_000 {
+            let sql = if rng.gen_bool(0.5) {
+                let len = rng.gen_range(0..12);
+                (0..len)
+                    .map(|_| *tokens.choose(&mut rng).unwrap())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            } else {
+                let mut sql = templates.choose(&mut rng).unwrap().to_string();
+                while sql.contains("{}") {
+                    sql = sql.replacen("{}", values.choose(&mut rng).unwrap(), 1);
+                }
+                sql
+            };
+
+            // Errors are fine, panics aren't.
+            let _ = router.try_route(&sql, &cluster);
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        use crate::net::{bind::Parameter, Bind, Execute, Parse, Protocol};
+
+        let cluster = Cluster::new_test();
+        let router = Router::new();
+        let bind = |id: i64| {
+            let id = id.to_string();
+            ProtocolMessage::from(Bind::test_params(
+                "test",
+                &[Parameter {
+                    len: id.len() as i32,
+                    data: id.as_bytes().to_vec(),
+                }],
+            ))
+        };
+
+        let mut messages = vec![ProtocolMessage::from(Parse::named(
+            "test",
+            "SELECT * FROM sharded WHERE id = $1",
+        ))];
+        for id in [1, 1, 11, 11, 1] {
+            messages.push(bind(id));
+            messages.push(Execute::new().into());
+        }
+        messages.push(Sync::new().into());
+
+        let runs = router
+            .pipeline(&Buffer::from(messages), &cluster)
+            .unwrap()
+            .unwrap();
+        let codes = runs
+            .iter()
+            .map(|run| run.iter().map(|message| message.code()).collect::<String>())
+            .collect::<Vec<_>>();
+        assert_eq!(codes, ["PBEBES", "BEBES", "BES"]);
+
+        // Same shard, nothing to split.
+        let buffer = Buffer::from(vec![
+            Parse::named("test", "SELECT * FROM sharded WHERE id = $1").into(),
+            bind(1),
+            Execute::new().into(),
+            bind(1),
+            Execute::new().into(),
+            Sync::new().into(),
+        ]);
+        assert!(router.pipeline(&buffer, &cluster).unwrap().is_none());
+    }
+}
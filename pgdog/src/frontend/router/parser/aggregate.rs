@@ -1,10 +1,10 @@
-use pg_query::protobuf::Integer;
 use pg_query::protobuf::{self, a_const::Val, SelectStmt};
-use pg_query::NodeEnum;
+use pg_query::protobuf::{Float, Integer, ResTarget, SetOperation, SortByDir};
+use pg_query::{Node, NodeEnum};
 
 use super::Error;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AggregateTarget {
     column: usize,
     function: AggregateFunction,
@@ -20,7 +20,7 @@ impl AggregateTarget {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AggregateFunction {
     Count,
     Max,
@@ -29,10 +29,38 @@ pub enum AggregateFunction {
     Sum,
 }
 
-#[derive(Debug, Clone, PartialEq, Default)]
+/// Ordered-set aggregate, e.g. `percentile_cont(0.5) WITHIN GROUP (ORDER BY score)`.
+///
+/// Fractions are kept as written in the query.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum OrderedSetFunction {
+    PercentileCont(String),
+    PercentileDisc(String),
+    Mode,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct OrderedSetTarget {
+    name: String,
+    function: OrderedSetFunction,
+}
+
+impl OrderedSetTarget {
+    /// Name of the column returned to the client.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn function(&self) -> &OrderedSetFunction {
+        &self.function
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub struct Aggregate {
     targets: Vec<AggregateTarget>,
     group_by: Vec<usize>,
+    ordered_sets: Vec<OrderedSetTarget>,
 }
 
 impl Aggregate {
@@ -45,10 +73,10 @@ impl Aggregate {
             .filter_map(|node| {
                 node.node.as_ref().map(|node| match node {
                     NodeEnum::AConst(aconst) => aconst.val.as_ref().map(|val| match val {
-                        Val::Ival(Integer { ival }) => Some(*ival as usize - 1), // We use 0-indexed arrays, Postgres uses 1-indexed.
+                        Val::Ival(Integer { ival }) => (*ival as usize).checked_sub(1), // We use 0-indexed arrays, Postgres uses 1-indexed.
                         _ => None,
                     }),
-                    _ => None,
+                    node => Some(Self::target_position(&stmt.target_list, node)),
                 })
             })
             .flatten()
@@ -97,7 +125,156 @@ impl Aggregate {
             }
         }
 
-        Ok(Self { targets, group_by })
+        Ok(Self {
+            targets,
+            group_by,
+            ordered_sets: Self::parse_ordered_sets(stmt).unwrap_or_default(),
+        })
+    }
+
+    /// Ordered-set aggregates we can compute from the input values of all shards.
+    ///
+    /// Only queries returning nothing but these aggregates, over all rows, are supported,
+    /// e.g. `SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY score) FROM scores WHERE ...`.
+    fn parse_ordered_sets(stmt: &SelectStmt) -> Option<Vec<OrderedSetTarget>> {
+        if stmt.op() != SetOperation::SetopNone
+            || !stmt.group_clause.is_empty()
+            || stmt.having_clause.is_some()
+            || !stmt.distinct_clause.is_empty()
+            || stmt.limit_count.is_some()
+            || stmt.limit_offset.is_some()
+            || !stmt.window_clause.is_empty()
+            || stmt.target_list.is_empty()
+        {
+            return None;
+        }
+
+        stmt.target_list
+            .iter()
+            .map(|node| {
+                let (res, func) = Self::ordered_set_call(node)?;
+                let name = match func.funcname.as_slice() {
+                    [name] => name,
+                    [schema, name] if string(schema) == Some("pg_catalog") => name,
+                    _ => return None,
+                };
+                let name = string(name)?;
+                let function = match (name, func.args.as_slice()) {
+                    ("percentile_cont", [fraction]) => {
+                        OrderedSetFunction::PercentileCont(Self::fraction(fraction)?)
+                    }
+                    ("percentile_disc", [fraction]) => {
+                        OrderedSetFunction::PercentileDisc(Self::fraction(fraction)?)
+                    }
+                    ("mode", []) => OrderedSetFunction::Mode,
+                    _ => return None,
+                };
+
+                Some(OrderedSetTarget {
+                    name: if res.name.is_empty() {
+                        name.to_owned()
+                    } else {
+                        res.name.clone()
+                    },
+                    function,
+                })
+            })
+            .collect()
+    }
+
+    /// Ordered-set aggregate call sorting its input in ascending order,
+    /// without DISTINCT, FILTER or OVER.
+    fn ordered_set_call(node: &protobuf::Node) -> Option<(&ResTarget, &protobuf::FuncCall)> {
+        let Some(NodeEnum::ResTarget(ref res)) = node.node else {
+            return None;
+        };
+        let Some(NodeEnum::FuncCall(ref func)) = res.val.as_ref()?.node else {
+            return None;
+        };
+        if !func.agg_within_group
+            || func.agg_distinct
+            || func.agg_filter.is_some()
+            || func.over.is_some()
+        {
+            return None;
+        }
+        let [sort] = func.agg_order.as_slice() else {
+            return None;
+        };
+        let Some(NodeEnum::SortBy(ref sort)) = sort.node else {
+            return None;
+        };
+        match sort.sortby_dir() {
+            SortByDir::SortbyDefault | SortByDir::SortbyAsc => Some((res, func)),
+            _ => None,
+        }
+    }
+
+    /// Fraction between 0 and 1, e.g. `0.5` in `percentile_cont(0.5)`.
+    fn fraction(node: &protobuf::Node) -> Option<String> {
+        let Some(NodeEnum::AConst(ref aconst)) = node.node else {
+            return None;
+        };
+        let fraction = match aconst.val.as_ref()? {
+            Val::Fval(Float { fval }) => fval.clone(),
+            Val::Ival(Integer { ival }) => ival.to_string(),
+            _ => return None,
+        };
+        fraction
+            .parse::<f64>()
+            .is_ok_and(|fraction| (0.0..=1.0).contains(&fraction))
+            .then_some(fraction)
+    }
+
+    /// Statement returning the values sorted by each ordered-set aggregate instead,
+    /// one column per aggregate, e.g. `SELECT score FROM scores WHERE ...`.
+    /// Shards run it and we compute the aggregates from all of their rows.
+    pub fn ordered_set_inputs(stmt: &SelectStmt) -> SelectStmt {
+        let mut inputs = stmt.clone();
+        inputs.target_list = stmt
+            .target_list
+            .iter()
+            .filter_map(|node| {
+                let (_, func) = Self::ordered_set_call(node)?;
+                let Some(NodeEnum::SortBy(ref sort)) = func.agg_order[0].node else {
+                    return None;
+                };
+                Some(Node {
+                    node: Some(NodeEnum::ResTarget(Box::new(ResTarget {
+                        val: sort.node.clone(),
+                        location: -1,
+                        ..Default::default()
+                    }))),
+                })
+            })
+            .collect();
+        // The aggregates return one row, so it has no order.
+        inputs.sort_clause.clear();
+        inputs
+    }
+
+    /// Position of a GROUP BY or ORDER BY expression in the target list,
+    /// e.g. `tenant_id` in `SELECT tenant_id, count(*) c FROM events GROUP BY tenant_id`.
+    /// Column names, aliases and function calls are matched; they have to be
+    /// returned by the query for us to merge rows with them.
+    pub fn target_position(target_list: &[protobuf::Node], expr: &NodeEnum) -> Option<usize> {
+        target_list.iter().position(|node| {
+            let Some(NodeEnum::ResTarget(ref res)) = node.node else {
+                return false;
+            };
+            let Some(val) = res.val.as_ref().and_then(|val| val.node.as_ref()) else {
+                return false;
+            };
+
+            match expr {
+                NodeEnum::ColumnRef(_) => {
+                    let name = column_name(expr);
+                    name.is_some() && (name == Some(res.name.as_str()) || name == column_name(val))
+                }
+                NodeEnum::FuncCall(_) => same_function(expr, val),
+                _ => false,
+            }
+        })
     }
 
     pub fn targets(&self) -> &[AggregateTarget] {
@@ -108,6 +285,15 @@ impl Aggregate {
         &self.group_by
     }
 
+    pub fn ordered_sets(&self) -> &[OrderedSetTarget] {
+        &self.ordered_sets
+    }
+
+    /// No aggregation. Rows from all shards are returned as-is.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
     pub fn new_count(column: usize) -> Self {
         Self {
             targets: vec![AggregateTarget {
@@ -115,6 +301,7 @@ impl Aggregate {
                 column,
             }],
             group_by: vec![],
+            ordered_sets: vec![],
         }
     }
 
@@ -125,14 +312,193 @@ impl Aggregate {
                 column,
             }],
             group_by: group_by.to_vec(),
+            ordered_sets: vec![],
         }
     }
 
+    /// Nothing to merge across shards. A `GROUP BY` without aggregate
+    /// functions still needs merging, since the same group can come from more than one shard.
     pub fn is_empty(&self) -> bool {
-        self.len() == 0
+        self.len() == 0 && self.group_by.is_empty() && self.ordered_sets.is_empty()
     }
 
+    /// Number of aggregate functions.
     pub fn len(&self) -> usize {
         self.targets.len()
     }
 }
+
+/// Value of a string node, e.g. a function name.
+fn string(node: &protobuf::Node) -> Option<&str> {
+    match node.node {
+        Some(NodeEnum::String(protobuf::String { ref sval })) => Some(sval.as_str()),
+        _ => None,
+    }
+}
+
+/// Name of a column reference, without the table.
+fn column_name(node: &NodeEnum) -> Option<&str> {
+    let NodeEnum::ColumnRef(column) = node else {
+        return None;
+    };
+    match column.fields.last().and_then(|field| field.node.as_ref()) {
+        Some(NodeEnum::String(protobuf::String { sval })) => Some(sval.as_str()),
+        _ => None,
+    }
+}
+
+/// Same function call, ignoring where it appears in the query.
+fn same_function(left: &NodeEnum, right: &NodeEnum) -> bool {
+    let (NodeEnum::FuncCall(left), NodeEnum::FuncCall(right)) = (left, right) else {
+        return false;
+    };
+    let names = |func: &protobuf::FuncCall| {
+        func.funcname
+            .iter()
+            .filter_map(|name| match name.node {
+                Some(NodeEnum::String(protobuf::String { ref sval })) => Some(sval.to_lowercase()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let same_arg = |left: &protobuf::Node, right: &protobuf::Node| match (&left.node, &right.node) {
+        (Some(NodeEnum::AConst(left)), Some(NodeEnum::AConst(right))) => left.val == right.val,
+        (Some(left), Some(right)) => {
+            column_name(left).is_some() && column_name(left) == column_name(right)
+        }
+        _ => false,
+    };
+
+    names(left) == names(right)
+        && left.agg_star == right.agg_star
+        && left.agg_distinct == right.agg_distinct
+        && left.args.len() == right.args.len()
+        && left
+            .args
+            .iter()
+            .zip(right.args.iter())
+            .all(|(left, right)| same_arg(left, right))
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::parse;
+
+    use super::*;
+
+    fn aggregate(query: &str) -> Aggregate {
+        let ast = parse(query).unwrap();
+        match ast
+            .protobuf
+            .stmts
+            .first()
+            .unwrap()
+            .stmt
+            .as_ref()
+            .unwrap()
+            .node
+        {
+            Some(NodeEnum::SelectStmt(ref stmt)) => Aggregate::parse(stmt).unwrap(),
+            _ => panic!("not a select"),
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(Aggregate::none().is_empty());
+        assert_eq!(Aggregate::none(), Aggregate::default());
+        assert!(aggregate("SELECT * FROM users").is_empty());
+        assert!(aggregate("SELECT lower(email) FROM users").is_empty());
+
+        let count = aggregate("SELECT COUNT(*) FROM users");
+        assert!(!count.is_empty());
+        assert_eq!(count.len(), 1);
+
+        let group_by = aggregate("SELECT email FROM users GROUP BY 1");
+        assert!(!group_by.is_empty());
+        assert_eq!(group_by.len(), 0);
+    }
+
+    #[test]
+    fn test_group_by_expressions() {
+        for query in [
+            "SELECT tenant_id, count(*) c FROM events GROUP BY tenant_id",
+            "SELECT events.tenant_id, count(*) FROM events GROUP BY events.tenant_id",
+            "SELECT tenant_id AS t, count(*) FROM events GROUP BY t",
+            "SELECT tenant_id, count(*) FROM events GROUP BY 1",
+        ] {
+            let agg = aggregate(query);
+            assert_eq!(agg.group_by(), &[0], "{}", query);
+            assert_eq!(agg.targets()[0].column(), 1, "{}", query);
+        }
+
+        let agg = aggregate("SELECT count(*), lower(email) FROM users GROUP BY lower(email)");
+        assert_eq!(agg.group_by(), &[1]);
+
+        // Not returned, so can't be merged.
+        let agg = aggregate("SELECT count(*) FROM events GROUP BY tenant_id");
+        assert!(agg.group_by().is_empty());
+    }
+
+    #[test]
+    fn test_ordered_sets() {
+        let agg = aggregate(
+            "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY score), \
+            percentile_disc(1) WITHIN GROUP (ORDER BY score) p, \
+            mode() WITHIN GROUP (ORDER BY lower(email)) \
+            FROM users WHERE score > 0 ORDER BY 1",
+        );
+        assert!(!agg.is_empty());
+        let functions = agg
+            .ordered_sets()
+            .iter()
+            .map(|target| (target.name(), target.function().clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            functions,
+            vec![
+                (
+                    "percentile_cont",
+                    OrderedSetFunction::PercentileCont("0.5".into())
+                ),
+                ("p", OrderedSetFunction::PercentileDisc("1".into())),
+                ("mode", OrderedSetFunction::Mode),
+            ]
+        );
+
+        let ast = parse(
+            "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY score), \
+            mode() WITHIN GROUP (ORDER BY lower(email)) FROM users WHERE score > 0 ORDER BY 1",
+        )
+        .unwrap();
+        let Some(NodeEnum::SelectStmt(ref stmt)) =
+            ast.protobuf.stmts[0].stmt.as_ref().unwrap().node
+        else {
+            panic!("not a select");
+        };
+        let inputs = Node {
+            node: Some(NodeEnum::SelectStmt(Box::new(
+                Aggregate::ordered_set_inputs(stmt),
+            ))),
+        };
+        assert_eq!(
+            inputs.deparse().unwrap(),
+            "SELECT score, lower(email) FROM users WHERE score > 0"
+        );
+
+        // Can't be computed from all input values.
+        for query in [
+            "SELECT id, percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM t GROUP BY id",
+            "SELECT count(*), percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM t",
+            "SELECT round(percentile_cont(0.5) WITHIN GROUP (ORDER BY v)) FROM t",
+            "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v DESC) FROM t",
+            "SELECT percentile_cont(1.5) WITHIN GROUP (ORDER BY v) FROM t",
+            "SELECT percentile_cont($1) WITHIN GROUP (ORDER BY v) FROM t",
+            "SELECT percentile_cont(ARRAY[0.5, 0.9]) WITHIN GROUP (ORDER BY v) FROM t",
+            "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FILTER (WHERE v > 0) FROM t",
+            "SELECT rank(1) WITHIN GROUP (ORDER BY v) FROM t",
+        ] {
+            assert!(aggregate(query).ordered_sets().is_empty(), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! Array literals, e.g. `'{1,2,3}'`.
+
+use super::RouteError;
+
+/// Array sharding keys we can't read, e.g. multidimensional arrays.
+pub const UNSUPPORTED: RouteError =
+    RouteError::Unsupported("an array sharding key that isn't one-dimensional");
+
+/// Elements of a one-dimensional array literal, e.g. `1`, `2` and `3` in `{1,2,3}`.
+///
+/// Quoted elements are unescaped and `NULL`s are skipped, since they
+/// don't match any rows. Returns `None` for multidimensional arrays,
+/// e.g. `{{1,2},{3,4}}`, and anything that isn't an array literal.
+pub fn elements(literal: &str) -> Option<Vec<String>> {
+    let inner = literal.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut elements = vec![];
+    if inner.trim().is_empty() {
+        return Some(elements);
+    }
+
+    let mut element = String::new();
+    let mut quoted = false;
+    let mut in_quotes = false;
+    let mut chars = inner.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => element.push(chars.next()?),
+            '"' => {
+                if !quoted && element.trim().is_empty() {
+                    element.clear();
+                }
+                in_quotes = !in_quotes;
+                quoted = true;
+            }
+            _ if in_quotes => element.push(c),
+            '{' | '}' => return None,
+            ',' => {
+                finish(&mut elements, &element, quoted);
+                element.clear();
+                quoted = false;
+            }
+            // Whitespace around quoted elements isn't part of them.
+            _ if quoted && c.is_whitespace() => (),
+            _ => element.push(c),
+        }
+    }
+
+    if in_quotes {
+        return None;
+    }
+    finish(&mut elements, &element, quoted);
+
+    Some(elements)
+}
+
+/// Elements of a one-dimensional array in binary format, e.g. a bound parameter
+/// or a binary COPY column. `NULL`s are skipped, like in [`elements`].
+/// Returns `None` for multidimensional arrays and malformed data.
+pub fn binary_elements(mut data: &[u8]) -> Option<Vec<&[u8]>> {
+    let dimensions = int(&mut data)?;
+    // Flags and element type.
+    int(&mut data)?;
+    int(&mut data)?;
+    let mut elements = vec![];
+    match dimensions {
+        0 => return Some(elements),
+        1 => (),
+        _ => return None,
+    }
+    let len = usize::try_from(int(&mut data)?).ok()?;
+    // Lower bound.
+    int(&mut data)?;
+
+    for _ in 0..len {
+        let size = int(&mut data)?;
+        if size >= 0 {
+            elements.push(take(&mut data, size as usize)?);
+        }
+    }
+
+    Some(elements)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    let (head, tail) = data.split_at_checked(len)?;
+    *data = tail;
+    Some(head)
+}
+
+fn int(data: &mut &[u8]) -> Option<i32> {
+    Some(i32::from_be_bytes(take(data, 4)?.try_into().ok()?))
+}
+
+fn finish(elements: &mut Vec<String>, element: &str, quoted: bool) {
+    if quoted {
+        elements.push(element.to_string());
+    } else {
+        let element = element.trim();
+        if !element.eq_ignore_ascii_case("null") {
+            elements.push(element.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_elements() {
+        assert_eq!(elements("{1,2,3}").unwrap(), ["1", "2", "3"]);
+        assert_eq!(elements(" { 1 , 2 } ").unwrap(), ["1", "2"]);
+        assert_eq!(elements("{1,NULL,null}").unwrap(), ["1"]);
+        assert_eq!(
+            elements(r#"{"a,b", "NULL" ,"c\"d",e f}"#).unwrap(),
+            ["a,b", "NULL", "c\"d", "e f"]
+        );
+        assert!(elements("{}").unwrap().is_empty());
+
+        for literal in ["{{1,2},{3,4}}", "1", "{\"1}", "[1:2]={1,2}", "{1\\"] {
+            assert!(elements(literal).is_none(), "{}", literal);
+        }
+    }
+
+    #[test]
+    fn test_binary_elements() {
+        let array = |dimensions: i32, elements: &[Option<&[u8]>]| {
+            let mut data = vec![];
+            data.extend(dimensions.to_be_bytes());
+            data.extend(0_i32.to_be_bytes());
+            data.extend(20_i32.to_be_bytes());
+            if dimensions > 0 {
+                data.extend((elements.len() as i32).to_be_bytes());
+                data.extend(1_i32.to_be_bytes());
+            }
+            for element in elements {
+                match element {
+                    Some(element) => {
+                        data.extend((element.len() as i32).to_be_bytes());
+                        data.extend(*element);
+                    }
+                    None => data.extend((-1_i32).to_be_bytes()),
+                }
+            }
+            data
+        };
+
+        let five = 5_i64.to_be_bytes();
+        let six = 6_i64.to_be_bytes();
+        let data = array(1, &[Some(&five), None, Some(&six)]);
+        assert_eq!(
+            binary_elements(&data).unwrap(),
+            [five.as_slice(), six.as_slice()]
+        );
+        assert!(binary_elements(&array(0, &[])).unwrap().is_empty());
+
+        assert!(binary_elements(&array(2, &[Some(&five)])).is_none());
+        assert!(binary_elements(&data[..data.len() - 1]).is_none());
+        assert!(binary_elements(b"{5,6}").is_none());
+    }
+}
@@ -0,0 +1,78 @@
+//! Multiple statements sent in one simple protocol query,
+//! e.g. `SELECT 1; SELECT 2`.
+
+use pg_query::protobuf::ParseResult;
+
+use super::Route;
+
+/// Statement from a batch, routed on its own.
+#[derive(Debug, Clone)]
+pub struct Statement {
+    query: String,
+    route: Route,
+}
+
+impl Statement {
+    /// Create new statement.
+    pub fn new(query: &str, route: Route) -> Self {
+        Self {
+            query: query.to_string(),
+            route,
+        }
+    }
+
+    /// Statement text.
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Where the statement should go.
+    pub fn route(&self) -> &Route {
+        &self.route
+    }
+}
+
+/// Split the query into statements, using locations
+/// recorded by the parser.
+pub fn split<'a>(query: &'a str, ast: &ParseResult) -> Vec<&'a str> {
+    ast.stmts
+        .iter()
+        .filter_map(|stmt| {
+            let start = stmt.stmt_location as usize;
+            // Length is 0 for the last statement,
+            // which means "until the end of the string".
+            let end = if stmt.stmt_len == 0 {
+                query.len()
+            } else {
+                start + stmt.stmt_len as usize
+            };
+            query.get(start..end).map(|statement| statement.trim())
+        })
+        .filter(|statement| !statement.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::parse;
+
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let query = "SELECT 1; UPDATE t SET v = 'a;b' WHERE id = 2;\n  SELECT 3";
+        let ast = parse(query).unwrap();
+        assert_eq!(
+            split(query, &ast.protobuf),
+            vec![
+                "SELECT 1",
+                "UPDATE t SET v = 'a;b' WHERE id = 2",
+                "SELECT 3"
+            ]
+        );
+
+        let query = "SELECT 1;";
+        let ast = parse(query).unwrap();
+        assert_eq!(split(query, &ast.protobuf), vec!["SELECT 1"]);
+    }
+}
@@ -6,15 +6,36 @@ pub enum Command {
     Query(Route),
     Copy(Box<CopyParser>),
     StartTransaction(BufferedQuery),
+    /// `SAVEPOINT`, `RELEASE` or `ROLLBACK TO` before the transaction picked
+    /// its shard. Sent after `BEGIN`, like the transaction is.
+    Savepoint(BufferedQuery),
     CommitTransaction,
     RollbackTransaction,
     StartReplication,
     ReplicationMeta,
-    Set { name: String, value: ParameterValue },
+    Set {
+        name: String,
+        value: ParameterValue,
+    },
     PreparedStatement(Prepare),
-    Rewrite(String),
+    /// Query with prepared statements renamed, and where to send it.
+    Rewrite(String, Route),
     Shards(usize),
     Deallocate,
+    /// Cursor declared on more than one shard. We run its query
+    /// and keep the merged rows to serve FETCH.
+    DeclareCursor {
+        name: String,
+        query: String,
+        route: Route,
+    },
+    /// FETCH or MOVE on a cursor declared with [`Command::DeclareCursor`].
+    Fetch(Fetch),
+    /// CLOSE a cursor declared with [`Command::DeclareCursor`].
+    CloseCursor(String),
+    /// Statements that need to go to different shards,
+    /// executed one at a time.
+    Batch(Vec<Statement>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
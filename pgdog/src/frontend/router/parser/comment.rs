@@ -1,8 +1,11 @@
+use std::time::Duration;
+
 use once_cell::sync::Lazy;
 use pg_query::{protobuf::Token, scan};
 use regex::Regex;
 
 use crate::backend::ShardingSchema;
+use crate::config::Role;
 use crate::frontend::router::sharding::ContextBuilder;
 
 use super::super::parser::Shard;
@@ -11,6 +14,12 @@ use super::Error;
 static SHARD: Lazy<Regex> = Lazy::new(|| Regex::new(r#"pgdog_shard: *([0-9]+)"#).unwrap());
 static SHARDING_KEY: Lazy<Regex> =
     Lazy::new(|| Regex::new(r#"pgdog_sharding_key: *([0-9a-zA-Z]+)"#).unwrap());
+static REDUCER: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"pgdog_reducer: *([0-9a-zA-Z_]+)"#).unwrap());
+static ROLE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"pgdog_role: *([a-zA-Z_]+)"#).unwrap());
+static POOL: Lazy<Regex> = Lazy::new(|| Regex::new(r#"pgdog_pool: *([a-zA-Z_]+)"#).unwrap());
+static MAX_STALENESS: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"pgdog_max_staleness: *([0-9]+)"#).unwrap());
 
 /// Extract shard number from a comment.
 ///
@@ -48,3 +57,85 @@ pub fn shard(query: &str, schema: &ShardingSchema) -> Result<Shard, Error> {
 
     Ok(Shard::All)
 }
+
+/// Extract the name of the reducer for cross-shard results from a comment,
+/// e.g. `/* pgdog_reducer: percentiles */`.
+pub fn reducer(query: &str) -> Result<Option<String>, Error> {
+    let tokens = scan(query).map_err(Error::PgQuery)?;
+
+    for token in tokens.tokens.iter() {
+        if token.token == Token::CComment as i32 {
+            let comment = &query[token.start as usize..token.end as usize];
+            if let Some(cap) = REDUCER.captures(comment) {
+                if let Some(reducer) = cap.get(1) {
+                    return Ok(Some(reducer.as_str().to_string()));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// Extract the role from a comment, e.g. `/* pgdog_role: primary */`.
+pub fn role(query: &str) -> Result<Option<Role>, Error> {
+    let tokens = scan(query).map_err(Error::PgQuery)?;
+
+    for token in tokens.tokens.iter() {
+        if token.token == Token::CComment as i32 {
+            let comment = &query[token.start as usize..token.end as usize];
+            if let Some(cap) = ROLE.captures(comment) {
+                if let Some(role) = cap.get(1) {
+                    return match role.as_str().to_lowercase().as_str() {
+                        "primary" => Ok(Some(Role::Primary)),
+                        "replica" => Ok(Some(Role::Replica)),
+                        _ => Err(Error::UnknownRole(role.as_str().to_string())),
+                    };
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+/// The query is tagged for the analytics replicas
+/// with a comment, i.e. `/* pgdog_pool: analytics */`.
+pub fn analytics(query: &str) -> Result<bool, Error> {
+    let tokens = scan(query).map_err(Error::PgQuery)?;
+
+    for token in tokens.tokens.iter() {
+        if token.token == Token::CComment as i32 {
+            let comment = &query[token.start as usize..token.end as usize];
+            if let Some(cap) = POOL.captures(comment) {
+                if let Some(pool) = cap.get(1) {
+                    return match pool.as_str().to_lowercase().as_str() {
+                        "analytics" => Ok(true),
+                        _ => Err(Error::UnknownPool(pool.as_str().to_string())),
+                    };
+                }
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+/// Extract the maximum replica lag, in milliseconds, from a comment,
+/// e.g. `/* pgdog_max_staleness: 500 */`.
+pub fn max_staleness(query: &str) -> Result<Option<Duration>, Error> {
+    let tokens = scan(query).map_err(Error::PgQuery)?;
+
+    for token in tokens.tokens.iter() {
+        if token.token == Token::CComment as i32 {
+            let comment = &query[token.start as usize..token.end as usize];
+            if let Some(cap) = MAX_STALENESS.captures(comment) {
+                if let Some(millis) = cap.get(1) {
+                    return Ok(millis.as_str().parse().ok().map(Duration::from_millis));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
@@ -7,13 +7,14 @@ use crate::{
     config::ShardedTable,
     frontend::router::{
         parser::Shard,
-        sharding::{ContextBuilder, Tables},
+        round_robin,
+        sharding::{composite, ContextBuilder, Tables},
         CopyRow,
     },
-    net::messages::{CopyData, ToBytes},
+    net::messages::{CopyData, Format, ToBytes},
 };
 
-use super::{binary::Data, BinaryStream, Column, CsvStream, Error, Table};
+use super::{array, binary::Data, BinaryStream, Column, CsvStream, Error, Table};
 
 /// Copy information parsed from a COPY statement.
 #[derive(Debug, Clone)]
@@ -70,6 +71,8 @@ pub struct CopyParser {
     sharded_table: Option<ShardedTable>,
     /// The sharding column is in this position in each row.
     sharded_column: usize,
+    /// Positions of the composite sharding key columns, in key order.
+    composite: Vec<usize>,
 }
 
 impl Default for CopyParser {
@@ -83,6 +86,7 @@ impl Default for CopyParser {
             sharding_schema: ShardingSchema::default(),
             sharded_table: None,
             sharded_column: 0,
+            composite: vec![],
         }
     }
 }
@@ -108,9 +112,24 @@ impl CopyParser {
 
             let table = Table::from(rel);
 
-            if let Some(key) = Tables::new(&cluster.sharding_schema()).key(table, &columns) {
+            let sharding_schema = cluster.sharding_schema();
+            let tables = Tables::new(&sharding_schema);
+            if let Some(key) = tables.key(table, &columns) {
                 parser.sharded_table = Some(key.table.clone());
                 parser.sharded_column = key.position;
+            } else if let Some(sharded) = tables
+                .sharded(table)
+                .filter(|sharded| !sharded.composite.is_empty())
+            {
+                let positions = sharded
+                    .composite
+                    .iter()
+                    .map(|name| columns.iter().position(|column| column.name == name))
+                    .collect::<Option<Vec<_>>>();
+                if let Some(positions) = positions {
+                    parser.sharded_table = Some(sharded.clone());
+                    parser.composite = positions;
+                }
             }
 
             parser.columns = columns.len();
@@ -202,16 +221,50 @@ impl CopyParser {
                         let record = record?;
 
                         let shard = if let Some(table) = &self.sharded_table {
-                            let key = record
-                                .get(self.sharded_column)
-                                .ok_or(Error::NoShardingColumn)?;
-
-                            let ctx = ContextBuilder::new(table)
-                                .data(key)
-                                .shards(self.sharding_schema.shards)
-                                .build()?;
+                            if !self.composite.is_empty() {
+                                let values = self
+                                    .composite
+                                    .iter()
+                                    .map(|position| {
+                                        record
+                                            .get(*position)
+                                            .map(|value| value.to_string())
+                                            .ok_or(Error::NoShardingColumn)
+                                    })
+                                    .collect::<Result<Vec<_>, _>>()?;
+                                composite::shard(table, &values, self.sharding_schema.shards)?
+                            } else {
+                                let key = record
+                                    .get(self.sharded_column)
+                                    .ok_or(Error::NoShardingColumn)?;
+
+                                if table.array {
+                                    // NULL in text and CSV formats.
+                                    let elements = if key == "\\N" || key.is_empty() {
+                                        vec![]
+                                    } else {
+                                        array::elements(key).ok_or(array::UNSUPPORTED)?
+                                    };
+                                    match elements.first() {
+                                        Some(element) => ContextBuilder::new(table)
+                                            .data(element.as_str())
+                                            .shards(self.sharding_schema.shards)
+                                            .build()?
+                                            .apply()?,
+                                        // No keys, any shard will do.
+                                        None => Shard::Direct(
+                                            round_robin::next() % self.sharding_schema.shards,
+                                        ),
+                                    }
+                                } else {
+                                    let ctx = ContextBuilder::new(table)
+                                        .data(key)
+                                        .shards(self.sharding_schema.shards)
+                                        .build()?;
 
-                            ctx.apply()?
+                                    ctx.apply()?
+                                }
+                            }
                         } else {
                             Shard::All
                         };
@@ -235,18 +288,58 @@ impl CopyParser {
                             break;
                         }
                         let shard = if let Some(table) = &self.sharded_table {
-                            let key = tuple
-                                .get(self.sharded_column)
-                                .ok_or(Error::NoShardingColumn)?;
-                            if let Data::Column(key) = key {
-                                let ctx = ContextBuilder::new(table)
-                                    .data(&key[..])
-                                    .shards(self.sharding_schema.shards)
-                                    .build()?;
-
-                                ctx.apply()?
+                            if !self.composite.is_empty() {
+                                let values = self
+                                    .composite
+                                    .iter()
+                                    .map(|position| match tuple.get(*position) {
+                                        Some(Data::Column(value)) => {
+                                            Ok(composite::text(value, Format::Binary))
+                                        }
+                                        Some(Data::Null) => Ok(None),
+                                        None => Err(Error::NoShardingColumn),
+                                    })
+                                    .collect::<Result<Option<Vec<_>>, _>>()?;
+                                match values {
+                                    Some(values) => composite::shard(
+                                        table,
+                                        &values,
+                                        self.sharding_schema.shards,
+                                    )?,
+                                    None => Shard::All,
+                                }
                             } else {
-                                Shard::All
+                                let key = tuple
+                                    .get(self.sharded_column)
+                                    .ok_or(Error::NoShardingColumn)?;
+                                if table.array {
+                                    let elements = match key {
+                                        Data::Column(key) => {
+                                            array::binary_elements(key).ok_or(array::UNSUPPORTED)?
+                                        }
+                                        Data::Null => vec![],
+                                    };
+                                    match elements.first() {
+                                        Some(element) => ContextBuilder::new(table)
+                                            .data(*element)
+                                            .shards(self.sharding_schema.shards)
+                                            .build()?
+                                            .apply()?,
+                                        // No keys, any shard will do.
+                                        None => Shard::Direct(
+                                            round_robin::next() % self.sharding_schema.shards,
+                                        ),
+                                    }
+                                } else if let Data::Column(key) = key {
+                                    let ctx = ContextBuilder::new(table)
+                                        .data(&key[..])
+                                        .shards(self.sharding_schema.shards)
+                                        .build()?;
+
+                                    ctx.apply()?
+                                } else {
+                                    Shard::All
+                                }
                             }
                         } else {
                             Shard::All
@@ -266,6 +359,8 @@ impl CopyParser {
 mod test {
     use pg_query::parse;
 
+    use crate::backend::ShardedTables;
+
     use super::*;
 
     #[test]
@@ -367,4 +462,117 @@ mod test {
         assert_eq!(sharded[1].message().data().len(), 2 + 4 + 8 + 4 + 3);
         assert_eq!(sharded[2].message().data(), (-1_i16).to_be_bytes());
     }
+
+    #[test]
+    fn test_copy_composite_key() {
+        let mut cluster = Cluster::new_test();
+        cluster.set_sharded_tables(ShardedTables::new(
+            vec![ShardedTable {
+                name: Some("ledger".into()),
+                composite: vec!["account_id".into(), "region".into()],
+                ..Default::default()
+            }],
+            vec![],
+            false,
+        ));
+        let copy = |query: &str| {
+            let stmt = parse(query).unwrap();
+            let stmt = stmt.protobuf.stmts.first().unwrap();
+            let copy = match stmt.stmt.clone().unwrap().node.unwrap() {
+                NodeEnum::CopyStmt(copy) => copy,
+                _ => panic!("not a copy"),
+            };
+            CopyParser::new(&copy, &cluster).unwrap().unwrap()
+        };
+        // hash(concat(42, 'acme'))
+        let expected =
+            Shard::Direct(crate::frontend::router::sharding::varchar(b"42acme") as usize % 2);
+
+        let mut text = copy("COPY ledger (region, amount, account_id) FROM STDIN");
+        let sharded = text
+            .shard(vec![CopyData::new("acme\t10\t42\n".as_bytes())])
+            .unwrap();
+        assert_eq!(sharded[0].shard(), &expected);
+
+        let mut binary = copy("COPY ledger (account_id, region) FROM STDIN (FORMAT 'binary')");
+        let mut data = b"PGCOPY\n\xff\r\n\0".to_vec();
+        data.extend(0_i32.to_be_bytes());
+        data.extend(0_i32.to_be_bytes());
+        data.extend(2_i16.to_be_bytes());
+        data.extend(8_i32.to_be_bytes());
+        data.extend(42_i64.to_be_bytes());
+        data.extend(4_i32.to_be_bytes());
+        data.extend(b"acme");
+        let sharded = binary.shard(vec![CopyData::new(&data)]).unwrap();
+        assert_eq!(sharded[1].shard(), &expected);
+
+        // Not all columns of the key.
+        let mut partial = copy("COPY ledger (account_id, amount) FROM STDIN");
+        let sharded = partial
+            .shard(vec![CopyData::new("42\t10\n".as_bytes())])
+            .unwrap();
+        assert_eq!(sharded[0].shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_copy_array_key() {
+        let mut cluster = Cluster::new_test();
+        let table = ShardedTable {
+            name: Some("groups".into()),
+            column: "tenant_ids".into(),
+            array: true,
+            ..Default::default()
+        };
+        cluster.set_sharded_tables(ShardedTables::new(vec![table.clone()], vec![], false));
+        let copy = |query: &str| {
+            let stmt = parse(query).unwrap();
+            let stmt = stmt.protobuf.stmts.first().unwrap();
+            let copy = match stmt.stmt.clone().unwrap().node.unwrap() {
+                NodeEnum::CopyStmt(copy) => copy,
+                _ => panic!("not a copy"),
+            };
+            CopyParser::new(&copy, &cluster).unwrap().unwrap()
+        };
+        let expected = ContextBuilder::new(&table)
+            .data("5")
+            .shards(2)
+            .build()
+            .unwrap()
+            .apply()
+            .unwrap();
+
+        let mut text = copy("COPY groups (name, tenant_ids) FROM STDIN");
+        let sharded = text
+            .shard(vec![CopyData::new("a\t{5,6}\nb\t{}\n".as_bytes())])
+            .unwrap();
+        assert_eq!(sharded[0].shard(), &expected);
+        assert!(matches!(sharded[1].shard(), Shard::Direct(_)));
+        assert!(text
+            .shard(vec![CopyData::new("c\t{{5,6}}\n".as_bytes())])
+            .is_err());
+
+        let mut csv = copy("COPY groups (name, tenant_ids) FROM STDIN CSV");
+        let sharded = csv
+            .shard(vec![CopyData::new("a,\"{5,6}\"\n".as_bytes())])
+            .unwrap();
+        assert_eq!(sharded[0].shard(), &expected);
+
+        let mut binary = copy("COPY groups (tenant_ids) FROM STDIN (FORMAT 'binary')");
+        let mut data = b"PGCOPY\n\xff\r\n\0".to_vec();
+        data.extend(0_i32.to_be_bytes());
+        data.extend(0_i32.to_be_bytes());
+        data.extend(1_i16.to_be_bytes());
+        let mut array = vec![];
+        for int in [1_i32, 0, 20, 2, 1] {
+            array.extend(int.to_be_bytes());
+        }
+        for key in [5_i64, 6] {
+            array.extend(8_i32.to_be_bytes());
+            array.extend(key.to_be_bytes());
+        }
+        data.extend((array.len() as i32).to_be_bytes());
+        data.extend(array);
+        let sharded = binary.shard(vec![CopyData::new(&data)]).unwrap();
+        assert_eq!(sharded[1].shard(), &expected);
+    }
 }
@@ -0,0 +1,185 @@
+//! Subqueries in `FROM`, e.g. `(SELECT tenant_id AS t, * FROM users) sub`.
+//!
+//! Filters on their columns are filters on the table they select from,
+//! as long as the columns are selected as they are. Expressions aren't traced.
+
+use std::collections::HashMap;
+
+use pg_query::{protobuf::*, NodeEnum};
+
+use super::where_clause::Column;
+
+/// Subquery in `FROM` selecting from one table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedTable<'a> {
+    /// Alias of the subquery.
+    pub alias: &'a str,
+    /// Table the subquery selects from.
+    pub table: &'a str,
+    /// Columns the subquery selects, and the table's columns they come from.
+    /// `None` for expressions, e.g. `lower(email) AS email`.
+    columns: HashMap<&'a str, Option<&'a str>>,
+    /// The subquery selects `*`, so the table's columns keep their names.
+    star: bool,
+}
+
+impl<'a> DerivedTable<'a> {
+    /// Subqueries in `FROM` and in joins.
+    pub fn new(from_clause: &'a [Node]) -> Vec<Self> {
+        let mut tables = vec![];
+        for node in from_clause {
+            Self::find(node, &mut tables);
+        }
+        tables
+    }
+
+    fn find(node: &'a Node, tables: &mut Vec<Self>) {
+        match node.node {
+            Some(NodeEnum::RangeSubselect(ref subselect)) => {
+                if let Some(table) = Self::parse(subselect) {
+                    tables.push(table);
+                }
+            }
+            Some(NodeEnum::JoinExpr(ref join)) => {
+                for side in [&join.larg, &join.rarg].into_iter().flatten() {
+                    Self::find(side, tables);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn parse(subselect: &'a RangeSubselect) -> Option<Self> {
+        let alias = subselect.alias.as_ref()?.aliasname.as_str();
+        let Some(NodeEnum::SelectStmt(ref stmt)) = subselect.subquery.as_ref()?.node else {
+            return None;
+        };
+
+        // With LIMIT, the outer filter only sees some of the rows.
+        if stmt.op() != SetOperation::SetopNone
+            || stmt.limit_count.is_some()
+            || stmt.limit_offset.is_some()
+        {
+            return None;
+        }
+
+        let [Node {
+            node: Some(NodeEnum::RangeVar(ref range_var)),
+        }] = stmt.from_clause.as_slice()
+        else {
+            return None;
+        };
+        let table = range_var.relname.as_str();
+        let table_alias = range_var
+            .alias
+            .as_ref()
+            .map(|alias| alias.aliasname.as_str());
+
+        let mut columns = HashMap::new();
+        let mut star = false;
+
+        for target in &stmt.target_list {
+            let Some(NodeEnum::ResTarget(ref target)) = target.node else {
+                continue;
+            };
+
+            let column = match target.val.as_ref().and_then(|val| val.node.as_ref()) {
+                Some(NodeEnum::ColumnRef(column)) => column,
+                _ => {
+                    if !target.name.is_empty() {
+                        columns.insert(target.name.as_str(), None);
+                    }
+                    continue;
+                }
+            };
+
+            let fields = column
+                .fields
+                .iter()
+                .map(|field| field.node.as_ref())
+                .collect::<Vec<_>>();
+            let field = match fields.as_slice() {
+                [field] => field,
+                [Some(NodeEnum::String(String { sval })), field]
+                    if sval == table || Some(sval.as_str()) == table_alias =>
+                {
+                    field
+                }
+                _ => continue,
+            };
+
+            match field {
+                Some(NodeEnum::AStar(_)) => star = true,
+                Some(NodeEnum::String(String { sval })) => {
+                    let name = if target.name.is_empty() {
+                        sval.as_str()
+                    } else {
+                        target.name.as_str()
+                    };
+                    columns.insert(name, Some(sval.as_str()));
+                }
+                _ => (),
+            }
+        }
+
+        Some(Self {
+            alias,
+            table,
+            columns,
+            star,
+        })
+    }
+
+    /// Column of the table that a column of the subquery comes from, if any.
+    pub fn resolve(&self, column: &Column<'a>) -> Option<Column<'a>> {
+        let name = match self.columns.get(column.name) {
+            Some(name) => (*name)?,
+            None if self.star => column.name,
+            None => return None,
+        };
+
+        Some(Column {
+            table: Some(self.table),
+            name,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::parse;
+
+    use super::*;
+
+    #[test]
+    fn test_derived_table() {
+        let ast = parse(
+            "SELECT * FROM (SELECT u.tenant_id AS t, lower(email) AS email, id, * FROM users u) sub \
+             JOIN (SELECT 1 FROM orders LIMIT 1) o ON true",
+        )
+        .unwrap();
+        let Some(NodeEnum::SelectStmt(ref stmt)) =
+            ast.protobuf.stmts[0].stmt.as_ref().unwrap().node
+        else {
+            panic!("not a select");
+        };
+
+        let tables = DerivedTable::new(&stmt.from_clause);
+        assert_eq!(tables.len(), 1);
+        let sub = &tables[0];
+        assert_eq!(sub.alias, "sub");
+        assert_eq!(sub.table, "users");
+
+        let resolve = |name| {
+            sub.resolve(&Column {
+                table: Some("sub"),
+                name,
+            })
+            .map(|column| column.name)
+        };
+        assert_eq!(resolve("t"), Some("tenant_id"));
+        assert_eq!(resolve("id"), Some("id"));
+        assert_eq!(resolve("tenant_id"), Some("tenant_id"));
+        assert_eq!(resolve("email"), None);
+    }
+}
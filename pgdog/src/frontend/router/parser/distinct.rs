@@ -1,16 +1,16 @@
 use super::Error;
 use pg_query::{
-    protobuf::{self, a_const::Val, AConst, ColumnRef, Integer, SelectStmt},
+    protobuf::{self, a_const::Val, AConst, ColumnRef, Integer, SelectStmt, SetOperation},
     Node, NodeEnum,
 };
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum DistinctColumn {
     Name(String),
     Index(usize),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum DistinctBy {
     Row,
     Columns(Vec<DistinctColumn>),
@@ -27,6 +27,11 @@ impl<'a> Distinct<'a> {
     }
 
     pub fn distinct(&self) -> Result<Option<DistinctBy>, Error> {
+        // UNION without ALL removes duplicate rows.
+        if self.stmt.op() == SetOperation::SetopUnion && !self.stmt.all {
+            return Ok(Some(DistinctBy::Row));
+        }
+
         match self.stmt.distinct_clause.first() {
             Some(Node { node: None }) => return Ok(Some(DistinctBy::Row)),
             None => return Ok(None),
@@ -40,7 +45,9 @@ impl<'a> Distinct<'a> {
                 match node {
                     NodeEnum::AConst(AConst { val: Some(val), .. }) => match val {
                         Val::Ival(Integer { ival }) => {
-                            columns.push(DistinctColumn::Index(*ival as usize - 1))
+                            if let Some(index) = (*ival as usize).checked_sub(1) {
+                                columns.push(DistinctColumn::Index(index))
+                            }
                         }
                         _ => (),
                     },
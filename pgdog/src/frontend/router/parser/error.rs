@@ -2,7 +2,8 @@
 
 use thiserror::Error;
 
-use crate::frontend::router::sharding;
+use super::Shard;
+use crate::{frontend::router::sharding, net::messages::ErrorResponse};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -45,6 +46,40 @@ pub enum Error {
     #[error("set shard syntax error")]
     SetShard,
 
+    #[error("shard {0} is excluded by pgdog.shards")]
+    ExcludedShard(Shard),
+
+    #[error("cursors must use a single shard")]
+    CrossShardCursor,
+
+    #[error("cursor \"{0}\" uses more than one shard, so WHERE CURRENT OF can't be used with it")]
+    CurrentOfCrossShard(String),
+
+    #[error("cursor \"{0}\" does not exist")]
+    UnknownCursor(String),
+
+    #[error("sharding keys \"{0}\" and \"{1}\" route the query to different shards")]
+    ShardingKeyConflict(String, String),
+
+    #[error("window functions must use a single shard")]
+    CrossShardWindow,
+
+    #[error(
+        "ordered-set aggregates, e.g. percentile_cont, must use a single shard unless they're all the query returns"
+    )]
+    CrossShardOrderedSet,
+
+    #[error(
+        "INTERSECT and EXCEPT must use a single shard unless they're simple queries combining two plain SELECTs"
+    )]
+    CrossShardSetOperation,
+
+    #[error("multi-statement queries must use a single shard in transactions and session mode")]
+    CrossShardBatch,
+
+    #[error("cross-shard queries must have an ORDER BY")]
+    CrossShardUnordered,
+
     #[error("no multi tenant id")]
     MultiTenantId,
 
@@ -53,4 +88,170 @@ pub enum Error {
 
     #[error("missing parameter: ${0}")]
     MissingParameter(usize),
+
+    #[error("reducer \"{0}\" is not registered")]
+    UnknownReducer(String),
+
+    #[error("pgdog.allow_unfiltered_writes must be on or off")]
+    SetUnfilteredWrites,
+
+    #[error("sharding key \"{0}\" is generated by a global sequence, its value must be provided")]
+    GlobalSequence(String),
+
+    #[error("invalid pgdog.route \"{0}\", expected shard:N, all, replica or primary")]
+    SetRoute(String),
+
+    #[error("invalid pgdog_role \"{0}\", expected primary or replica")]
+    UnknownRole(String),
+
+    #[error("statements that write can't be sent to a replica")]
+    WriteToReplica,
+
+    #[error("invalid pgdog_pool \"{0}\", expected analytics")]
+    UnknownPool(String),
+
+    #[error("statements that write can't be sent to the analytics replicas")]
+    WriteToAnalytics,
+
+    #[error("{0} can't run inside a transaction block")]
+    MaintenanceInTransaction(&'static str),
+
+    #[error("{0}")]
+    Route(#[from] RouteError),
+}
+
+/// The query can't be routed as written.
+///
+/// Unlike other errors, these are caused by the query or the configuration,
+/// so the client gets a matching SQLSTATE and a hint on how to fix it.
+#[derive(Debug, Error, PartialEq)]
+pub enum RouteError {
+    #[error("{0}")]
+    ParseFailed(String),
+
+    #[error("{0} is not supported")]
+    Unsupported(&'static str),
+
+    #[error("{0} without a WHERE clause changes all rows")]
+    MissingShardingKey(&'static str),
+
+    #[error("shard {0} is out of range, cluster has {1} shards")]
+    ShardOutOfRange(Shard, usize),
+
+    #[error("query would go to {0} shards, the maximum is {1}")]
+    FanoutTooWide(usize, usize),
+    #[error("{0} can only be used in transaction blocks")]
+    NoTransaction(&'static str),
+
+    #[error("parameter ${0} is out of range, {1} parameters were bound")]
+    ParameterOutOfRange(usize, usize),
+}
+
+impl RouteError {
+    /// Query couldn't be parsed.
+    pub fn parse_failed(err: pg_query::Error) -> Self {
+        Self::ParseFailed(err.to_string())
+    }
+
+    /// SQLSTATE sent to the client.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ParseFailed(_) => "42601",            // syntax_error
+            Self::Unsupported(_) => "0A000",            // feature_not_supported
+            Self::MissingShardingKey(_) => "55000",     // object_not_in_prerequisite_state
+            Self::ShardOutOfRange(_, _) => "22023",     // invalid_parameter_value
+            Self::FanoutTooWide(_, _) => "54000",       // program_limit_exceeded
+            Self::NoTransaction(_) => "25P01",          // no_active_sql_transaction
+            Self::ParameterOutOfRange(_, _) => "08P01", // protocol_violation
+        }
+    }
+
+    /// What the client can do about it.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::Unsupported("ALTER SYSTEM") => {
+                Some("ALTER SYSTEM changes the configuration of one server, connect to each server directly to run it")
+            }
+            Self::MissingShardingKey(_) => {
+                Some("run \"SET pgdog.allow_unfiltered_writes TO on\" to allow it")
+            }
+            Self::ShardOutOfRange(_, _) => Some("check the sharding configuration"),
+            Self::FanoutTooWide(_, _) => {
+                Some("add the sharding key to the WHERE clause to route it to fewer shards")
+            }
+            Self::Unsupported("a savepoint before the first statement of a transaction") => {
+                Some("run a statement with the sharding key first, so the transaction uses one shard")
+            }
+            Self::Unsupported("a savepoint in a cross-shard transaction") => {
+                Some("use the sharding key in all statements of the transaction, so it uses one shard")
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<&RouteError> for ErrorResponse {
+    fn from(err: &RouteError) -> Self {
+        let mut response = ErrorResponse::default();
+        response.code = err.code().into();
+        response.message = err.to_string();
+        response.detail = err.hint().map(String::from);
+        response
+    }
+}
+
+impl Error {
+    /// Routing error, if that's what this is.
+    pub fn route_error(&self) -> Option<&RouteError> {
+        match self {
+            Self::Route(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_response() {
+        for (err, code, hint) in [
+            (
+                RouteError::ParseFailed("syntax error".into()),
+                "42601",
+                None,
+            ),
+            (
+                RouteError::MissingShardingKey("DELETE"),
+                "55000",
+                Some("pgdog.allow_unfiltered_writes"),
+            ),
+            (
+                RouteError::ShardOutOfRange(Shard::Direct(2), 2),
+                "22023",
+                Some("sharding configuration"),
+            ),
+            (
+                RouteError::FanoutTooWide(4, 2),
+                "54000",
+                Some("add the sharding key"),
+            ),
+            (RouteError::NoTransaction("SAVEPOINT"), "25P01", None),
+            (RouteError::ParameterOutOfRange(3, 2), "08P01", None),
+            (
+                RouteError::Unsupported("a savepoint in a cross-shard transaction"),
+                "0A000",
+                Some("use the sharding key"),
+            ),
+        ] {
+            let response = ErrorResponse::from(&err);
+            assert_eq!(response.code, code, "{}", err);
+            assert_eq!(response.message, err.to_string());
+            match hint {
+                Some(hint) => assert!(response.detail.unwrap().contains(hint), "{}", err),
+                None => assert!(response.detail.is_none(), "{}", err),
+            }
+        }
+    }
 }
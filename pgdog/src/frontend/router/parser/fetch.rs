@@ -0,0 +1,95 @@
+//! FETCH and MOVE statements.
+
+use pg_query::protobuf::{FetchDirection, FetchStmt};
+
+/// Which rows to fetch, relative to the cursor's position.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// `FORWARD n`, `NEXT` and `ALL`.
+    Forward(usize),
+    /// `BACKWARD n`, `PRIOR` and `BACKWARD ALL`.
+    Backward(usize),
+    /// `ABSOLUTE n`, `FIRST` and `LAST`. Negative counts from the end.
+    Absolute(i64),
+    /// `RELATIVE n`. Zero refetches the current row.
+    Relative(i64),
+}
+
+/// `FETCH` or `MOVE` on a cursor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fetch {
+    /// Cursor name.
+    pub name: String,
+    pub direction: Direction,
+    /// `MOVE` changes the position without returning rows.
+    pub moves: bool,
+}
+
+impl From<&FetchStmt> for Fetch {
+    fn from(stmt: &FetchStmt) -> Self {
+        let count = stmt.how_many;
+
+        // Postgres treats `FORWARD -n` as `BACKWARD n` and `FORWARD 0`
+        // as `RELATIVE 0`, and vice versa.
+        let direction = match stmt.direction() {
+            FetchDirection::FetchAbsolute => Direction::Absolute(count),
+            FetchDirection::FetchRelative => Direction::Relative(count),
+            FetchDirection::FetchBackward if count > 0 => Direction::Backward(count as usize),
+            FetchDirection::FetchBackward if count < 0 => {
+                Direction::Forward(count.unsigned_abs() as usize)
+            }
+            _ if count > 0 => Direction::Forward(count as usize),
+            _ if count < 0 => Direction::Backward(count.unsigned_abs() as usize),
+            _ => Direction::Relative(0),
+        };
+
+        Self {
+            name: stmt.portalname.clone(),
+            direction,
+            moves: stmt.ismove,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::{parse, NodeEnum};
+
+    use super::*;
+
+    #[test]
+    fn test_fetch() {
+        for (query, direction, moves) in [
+            ("FETCH c", Direction::Forward(1), false),
+            ("FETCH NEXT FROM c", Direction::Forward(1), false),
+            ("FETCH 5 FROM c", Direction::Forward(5), false),
+            ("FETCH FORWARD 5 FROM c", Direction::Forward(5), false),
+            ("FETCH FORWARD -2 FROM c", Direction::Backward(2), false),
+            ("FETCH FORWARD 0 FROM c", Direction::Relative(0), false),
+            (
+                "FETCH ALL FROM c",
+                Direction::Forward(i64::MAX as usize),
+                false,
+            ),
+            ("FETCH PRIOR FROM c", Direction::Backward(1), false),
+            ("FETCH BACKWARD 3 FROM c", Direction::Backward(3), false),
+            ("FETCH BACKWARD -3 FROM c", Direction::Forward(3), false),
+            ("FETCH FIRST FROM c", Direction::Absolute(1), false),
+            ("FETCH LAST FROM c", Direction::Absolute(-1), false),
+            ("FETCH ABSOLUTE 4 FROM c", Direction::Absolute(4), false),
+            ("FETCH RELATIVE -1 FROM c", Direction::Relative(-1), false),
+            ("MOVE FORWARD 2 IN c", Direction::Forward(2), true),
+        ] {
+            let ast = parse(query).unwrap();
+            let Some(NodeEnum::FetchStmt(ref stmt)) =
+                ast.protobuf.stmts[0].stmt.as_ref().unwrap().node
+            else {
+                panic!("not a fetch");
+            };
+            let fetch = Fetch::from(stmt);
+            assert_eq!(fetch.name, "c");
+            assert_eq!(fetch.direction, direction, "{}", query);
+            assert_eq!(fetch.moves, moves, "{}", query);
+        }
+    }
+}
@@ -16,6 +16,16 @@ static WRITE_ONLY: Lazy<HashMap<&'static str, LockingBehavior>> = Lazy::new(|| {
         ("pg_advisory_unlock_all", LockingBehavior::Unlock),
         ("nextval", LockingBehavior::None),
         ("setval", LockingBehavior::None),
+        // Not allowed on replicas.
+        ("txid_current", LockingBehavior::None),
+        ("pg_current_xact_id", LockingBehavior::None),
+        ("pg_current_wal_lsn", LockingBehavior::None),
+        ("pg_current_wal_insert_lsn", LockingBehavior::None),
+        ("pg_switch_wal", LockingBehavior::None),
+        ("pg_create_restore_point", LockingBehavior::None),
+        ("pg_notify", LockingBehavior::None),
+        ("lo_create", LockingBehavior::None),
+        ("lo_unlink", LockingBehavior::None),
     ])
 });
 
@@ -57,6 +67,15 @@ impl<'a> Function<'a> {
         }
     }
 
+    /// Function returns a different value every time it's called,
+    /// even within the same statement.
+    pub fn volatile(name: &str) -> bool {
+        matches!(
+            name,
+            "random" | "gen_random_uuid" | "uuid_generate_v4" | "clock_timestamp" | "timeofday"
+        )
+    }
+
     /// This function likely writes.
     pub fn behavior(&self) -> FunctionBehavior {
         if let Some(locks) = WRITE_ONLY.get(&self.name) {
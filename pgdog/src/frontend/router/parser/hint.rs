@@ -0,0 +1,89 @@
+//! Routing hint set with `SET pgdog.route`.
+//!
+//! Works like comment hints, but applies to all statements in the transaction
+//! (`SET LOCAL`) or session (`SET`), including those sent over the extended protocol.
+
+use std::str::FromStr;
+
+use super::{Error, Route, Shard};
+
+/// Where to send queries, regardless of what they are.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteHint {
+    /// `shard:N`
+    Shard(usize),
+    /// `all`
+    All,
+    /// `replica`
+    Replica,
+    /// `primary`
+    Primary,
+}
+
+impl FromStr for RouteHint {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hint = s.trim().to_lowercase();
+
+        match hint.as_str() {
+            "all" => Ok(Self::All),
+            "replica" => Ok(Self::Replica),
+            "primary" => Ok(Self::Primary),
+            hint => hint
+                .strip_prefix("shard:")
+                .and_then(|shard| shard.trim().parse().ok())
+                .map(Self::Shard)
+                .ok_or_else(|| Error::SetRoute(s.to_string())),
+        }
+    }
+}
+
+impl RouteHint {
+    /// Override the route picked by the query parser.
+    pub fn apply(&self, route: &mut Route) {
+        match self {
+            Self::Shard(shard) => route.set_shard_mut(*shard),
+            Self::All => {
+                route.set_shard_raw_mut(Shard::All);
+                route.set_sharding_column_mut(None);
+            }
+            Self::Replica => route.set_read_mut(true),
+            Self::Primary => route.set_read_mut(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_route_hint() {
+        assert_eq!("shard:2".parse::<RouteHint>().unwrap(), RouteHint::Shard(2));
+        assert_eq!(
+            " Shard: 2 ".parse::<RouteHint>().unwrap(),
+            RouteHint::Shard(2)
+        );
+        assert_eq!("all".parse::<RouteHint>().unwrap(), RouteHint::All);
+        assert_eq!("REPLICA".parse::<RouteHint>().unwrap(), RouteHint::Replica);
+        assert_eq!("primary".parse::<RouteHint>().unwrap(), RouteHint::Primary);
+
+        for hint in ["shard:", "shard:-1", "shard", "2", "leader"] {
+            assert!(
+                matches!(hint.parse::<RouteHint>(), Err(Error::SetRoute(_))),
+                "{}",
+                hint
+            );
+        }
+
+        let mut route = Route::read(Some(0));
+        RouteHint::Shard(2).apply(&mut route);
+        assert_eq!(route.shard(), &Shard::Direct(2));
+        assert!(route.is_read());
+
+        RouteHint::Primary.apply(&mut route);
+        assert!(route.is_write());
+        assert_eq!(route.shard(), &Shard::Direct(2));
+    }
+}
@@ -3,14 +3,15 @@ use pg_query::{protobuf::*, NodeEnum};
 
 use crate::{
     backend::ShardingSchema,
+    config::{ShardedSequence, ShardedTable},
     frontend::router::{
         round_robin,
-        sharding::{ContextBuilder, Tables, Value as ShardingValue},
+        sharding::{composite, ContextBuilder, Tables, Value as ShardingValue},
     },
-    net::Bind,
+    net::{Bind, Format},
 };
 
-use super::{Column, Error, Shard, Table, Tuple, Value};
+use super::{array, key, Column, Error, Shard, Table, Tuple, Value};
 
 /// Parse an `INSERT` statement.
 #[derive(Debug)]
@@ -56,6 +57,16 @@ impl<'a> Insert<'a> {
         vec![]
     }
 
+    /// Name of the sharding column the statement is inserting, if any.
+    pub fn sharding_column(&'a self, schema: &'a ShardingSchema) -> Option<std::string::String> {
+        let tables = Tables::new(schema);
+        let columns = self.columns();
+
+        self.table()
+            .and_then(|table| tables.key(table, &columns))
+            .map(|key| key.table.column.clone())
+    }
+
     /// Get the sharding key for the statement.
     pub fn shard(
         &'a self,
@@ -67,22 +78,93 @@ impl<'a> Insert<'a> {
 
         let table = self.table();
 
+        if let Some(sharded) = table.and_then(|table| tables.sharded(table)) {
+            if !sharded.composite.is_empty() {
+                return self.composite(sharded, &columns, schema, bind);
+            }
+        }
+
         let key = table.and_then(|table| tables.key(table, &columns));
 
         if let Some(key) = key {
-            if let Some(bind) = bind {
-                if let Ok(Some(param)) = bind.parameter(key.position) {
-                    // Arrays not supported as sharding keys at the moment.
-                    let value = ShardingValue::from_param(&param, key.table.data_type)?;
-                    let ctx = ContextBuilder::new(key.table)
-                        .value(value)
-                        .shards(schema.shards)
-                        .build()?;
-                    return Ok(ctx.apply()?);
+            // The sequence generates the key when the row is inserted.
+            let tuples = self.tuples();
+            let default = !tuples.is_empty()
+                && tuples
+                    .iter()
+                    .all(|tuple| tuple.get(key.position) == Some(&Value::Default));
+            if default {
+                return match key.table.sequence {
+                    ShardedSequence::Local => {
+                        Ok(Shard::Direct(round_robin::next() % schema.shards))
+                    }
+                    ShardedSequence::Global => Err(Error::GlobalSequence(key.table.column.clone())),
+                };
+            }
+
+            // Parameter holding the key, e.g. `$2` in `VALUES ($2, $1)`.
+            // Literal keys are used as they are, even if other values are bound.
+            let param = match tuples.first().and_then(|tuple| tuple.get(key.position)) {
+                Some(Value::Placeholder(pos)) => Value::placeholder_position(*pos),
+                _ => None,
+            };
+            let bind = bind.zip(param);
+
+            if key.table.array {
+                let table = key.table;
+
+                if let Some((bind, param)) = bind {
+                    let param = key::parameter(bind, param)?;
+                    return match param.format() {
+                        Format::Text => {
+                            let elements = param
+                                .text()
+                                .and_then(array::elements)
+                                .ok_or(array::UNSUPPORTED)?;
+                            let first = elements.first().map(|element| {
+                                ShardingValue::new(element.as_str(), table.data_type)
+                            });
+                            Self::array_shard(table, schema, first)
+                        }
+                        Format::Binary => {
+                            let elements =
+                                array::binary_elements(param.data()).ok_or(array::UNSUPPORTED)?;
+                            let first = elements
+                                .first()
+                                .map(|element| ShardingValue::new(*element, table.data_type));
+                            Self::array_shard(table, schema, first)
+                        }
+                    };
                 }
-            } else {
-                let tuples = self.tuples();
 
+                if let [tuple] = tuples.as_slice() {
+                    match tuple.get(key.position) {
+                        Some(Value::String(literal)) => {
+                            let elements = array::elements(literal).ok_or(array::UNSUPPORTED)?;
+                            let first = elements.first().map(|element| {
+                                ShardingValue::new(element.as_str(), table.data_type)
+                            });
+                            return Self::array_shard(table, schema, first);
+                        }
+                        Some(Value::Null) => return Self::array_shard(table, schema, None),
+                        // Parameter that isn't bound yet.
+                        Some(Value::Placeholder(_)) | None => (),
+                        Some(_) => return Err(array::UNSUPPORTED.into()),
+                    }
+                }
+
+                return Ok(Shard::All);
+            }
+
+            if let Some((bind, param)) = bind {
+                let param = key::parameter(bind, param)?;
+                let value = ShardingValue::from_param(&param, key.table.data_type)?;
+                let ctx = ContextBuilder::new(key.table)
+                    .value(value)
+                    .shards(schema.shards)
+                    .build()?;
+                return Ok(ctx.apply()?);
+            } else {
                 // TODO: support rewriting INSERTs to run against multiple shards.
                 if tuples.len() != 1 {
                     return Ok(Shard::All);
@@ -120,6 +202,71 @@ impl<'a> Insert<'a> {
 
         Ok(Shard::All)
     }
+
+    /// Shard of a row inserted into a table sharded by an array of keys.
+    /// All keys in the array belong to the same shard, so the first one picks it.
+    /// Rows without keys, e.g. `'{}'`, can go to any shard.
+    fn array_shard(
+        table: &ShardedTable,
+        schema: &ShardingSchema,
+        first: Option<ShardingValue<'_>>,
+    ) -> Result<Shard, Error> {
+        match first {
+            Some(value) => Ok(ContextBuilder::new(table)
+                .value(value)
+                .shards(schema.shards)
+                .build()?
+                .apply()?),
+            None => Ok(Shard::Direct(round_robin::next() % schema.shards)),
+        }
+    }
+
+    /// Shard of a row inserted into a table sharded by a composite key.
+    fn composite(
+        &'a self,
+        table: &ShardedTable,
+        columns: &[Column],
+        schema: &ShardingSchema,
+        bind: Option<&Bind>,
+    ) -> Result<Shard, Error> {
+        let positions = table
+            .composite
+            .iter()
+            .map(|name| {
+                columns
+                    .iter()
+                    .position(|column| column.name == name.as_str())
+            })
+            .collect::<Option<Vec<_>>>();
+        // Same as other sharded tables without the key in the query.
+        let Some(positions) = positions else {
+            return Ok(Shard::Direct(round_robin::next() % schema.shards));
+        };
+
+        let tuples = self.tuples();
+        let [tuple] = tuples.as_slice() else {
+            return Ok(Shard::All);
+        };
+
+        let mut values = vec![];
+        for position in positions {
+            let value = match tuple.values.get(position) {
+                Some(Value::Integer(int)) => Some(int.to_string()),
+                Some(Value::String(string)) => Some(string.to_string()),
+                Some(Value::Placeholder(pos)) => match bind {
+                    Some(bind) => composite::parameter(&key::parameter(bind, *pos as usize - 1)?),
+                    None => None,
+                },
+                _ => None,
+            };
+            let Some(value) = value else {
+                return Ok(Shard::All);
+            };
+            values.push(value);
+        }
+
+        Ok(composite::shard(table, &values, schema.shards)?)
+    }
 }
 
 #[cfg(test)]
@@ -131,7 +278,7 @@ mod test {
     use crate::net::bind::Parameter;
     use crate::net::Format;
 
-    use super::super::Value;
+    use super::super::{RouteError, Value};
     use super::*;
 
     #[test]
@@ -222,6 +369,7 @@ mod test {
                 vec![],
                 false,
             ),
+            ..Default::default()
         };
 
         match &select.node {
@@ -238,6 +386,28 @@ mod test {
                     }],
                 );
 
+                // The key is a literal, not a parameter.
+                let shard = insert.shard(&schema, Some(&bind)).unwrap();
+                assert!(matches!(shard, Shard::Direct(2)));
+            }
+
+            _ => panic!("not an insert"),
+        }
+
+        let query = parse("INSERT INTO sharded (id, value) VALUES ($1, 'test')").unwrap();
+        let select = query.protobuf.stmts.first().unwrap().stmt.as_ref().unwrap();
+
+        match &select.node {
+            Some(NodeEnum::InsertStmt(stmt)) => {
+                let insert = Insert::new(stmt);
+                let bind = Bind::test_params(
+                    "",
+                    &[Parameter {
+                        len: 1,
+                        data: "3".as_bytes().to_vec(),
+                    }],
+                );
+
                 let shard = insert.shard(&schema, Some(&bind)).unwrap();
                 assert!(matches!(shard, Shard::Direct(1)));
 
@@ -297,4 +467,264 @@ mod test {
             _ => panic!("not a select"),
         }
     }
+
+    #[test]
+    fn test_array_sharding_key() {
+        let table = ShardedTable {
+            name: Some("groups".into()),
+            column: "tenant_ids".into(),
+            array: true,
+            ..Default::default()
+        };
+        let schema = ShardingSchema {
+            shards: 3,
+            tables: ShardedTables::new(vec![table.clone()], vec![], false),
+            ..Default::default()
+        };
+        let shard = |query: &str, bind: Option<&Bind>| {
+            let query = parse(query).unwrap();
+            let stmt = query.protobuf.stmts.first().unwrap().stmt.as_ref().unwrap();
+            match &stmt.node {
+                Some(NodeEnum::InsertStmt(stmt)) => Insert::new(stmt).shard(&schema, bind),
+                _ => panic!("not an insert"),
+            }
+        };
+        let expected = ContextBuilder::new(&table)
+            .data("5")
+            .shards(3)
+            .build()
+            .unwrap()
+            .apply()
+            .unwrap();
+
+        assert_eq!(
+            shard("INSERT INTO groups (tenant_ids) VALUES ('{5,6}')", None).unwrap(),
+            expected
+        );
+        let bind = Bind::test_params(
+            "",
+            &[Parameter {
+                len: 5,
+                data: "{5,6}".as_bytes().to_vec(),
+            }],
+        );
+        assert_eq!(
+            shard("INSERT INTO groups (tenant_ids) VALUES ($1)", Some(&bind)).unwrap(),
+            expected
+        );
+
+        // Binary arrays.
+        let mut data = vec![];
+        for int in [1_i32, 0, 20, 2, 1] {
+            data.extend(int.to_be_bytes());
+        }
+        for key in [5_i64, 6] {
+            data.extend(8_i32.to_be_bytes());
+            data.extend(key.to_be_bytes());
+        }
+        let bind = Bind::test_params_codes(
+            "",
+            &[Parameter {
+                len: data.len() as i32,
+                data,
+            }],
+            &[Format::Binary],
+        );
+        assert_eq!(
+            shard("INSERT INTO groups (tenant_ids) VALUES ($1)", Some(&bind)).unwrap(),
+            expected
+        );
+
+        // No keys in the array, any shard will do.
+        for query in [
+            "INSERT INTO groups (tenant_ids) VALUES ('{}')",
+            "INSERT INTO groups (tenant_ids) VALUES ('{NULL}')",
+            "INSERT INTO groups (tenant_ids) VALUES (NULL)",
+        ] {
+            assert!(
+                matches!(shard(query, None).unwrap(), Shard::Direct(_)),
+                "{}",
+                query
+            );
+        }
+        let bind = Bind::test_params(
+            "",
+            &[Parameter {
+                len: 2,
+                data: "{}".as_bytes().to_vec(),
+            }],
+        );
+        assert!(matches!(
+            shard("INSERT INTO groups (tenant_ids) VALUES ($1)", Some(&bind)).unwrap(),
+            Shard::Direct(_)
+        ));
+
+        // Arrays we can't read.
+        for query in [
+            "INSERT INTO groups (tenant_ids) VALUES ('{{5,6},{7,8}}')",
+            "INSERT INTO groups (tenant_ids) VALUES ('5')",
+        ] {
+            assert!(
+                matches!(
+                    shard(query, None),
+                    Err(Error::Route(RouteError::Unsupported(_)))
+                ),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_composite_sharding_key() {
+        let table = ShardedTable {
+            name: Some("ledger".into()),
+            composite: vec!["account_id".into(), "region".into()],
+            ..Default::default()
+        };
+        let schema = ShardingSchema {
+            shards: 3,
+            tables: ShardedTables::new(vec![table], vec![], false),
+            ..Default::default()
+        };
+        let shard = |query: &str, bind: Option<&Bind>| {
+            let query = parse(query).unwrap();
+            let stmt = query.protobuf.stmts.first().unwrap().stmt.as_ref().unwrap();
+            match &stmt.node {
+                Some(NodeEnum::InsertStmt(stmt)) => Insert::new(stmt).shard(&schema, bind).unwrap(),
+                _ => panic!("not an insert"),
+            }
+        };
+        // hash(concat(42, 'acme'))
+        let expected =
+            Shard::Direct(crate::frontend::router::sharding::varchar(b"42acme") as usize % 3);
+
+        // Columns in any order.
+        assert_eq!(
+            shard(
+                "INSERT INTO ledger (region, amount, account_id) VALUES ('acme', 10, 42)",
+                None
+            ),
+            expected
+        );
+        let bind = Bind::test_params(
+            "",
+            &[
+                Parameter {
+                    len: 2,
+                    data: "42".as_bytes().to_vec(),
+                },
+                Parameter {
+                    len: 4,
+                    data: "acme".as_bytes().to_vec(),
+                },
+            ],
+        );
+        assert_eq!(
+            shard(
+                "INSERT INTO ledger (account_id, region) VALUES ($1, $2)",
+                Some(&bind)
+            ),
+            expected
+        );
+        // Binary integers.
+        for account_id in [42_i32.to_be_bytes().to_vec(), 42_i64.to_be_bytes().to_vec()] {
+            let bind = Bind::test_params_codes(
+                "",
+                &[
+                    Parameter {
+                        len: account_id.len() as i32,
+                        data: account_id,
+                    },
+                    Parameter {
+                        len: 4,
+                        data: "acme".as_bytes().to_vec(),
+                    },
+                ],
+                &[Format::Binary, Format::Text],
+            );
+            assert_eq!(
+                shard(
+                    "INSERT INTO ledger (account_id, region) VALUES ($1, $2)",
+                    Some(&bind)
+                ),
+                expected
+            );
+        }
+
+        assert!(matches!(
+            shard("INSERT INTO ledger (account_id) VALUES (42)", None),
+            Shard::Direct(_)
+        ));
+        assert_eq!(
+            shard(
+                "INSERT INTO ledger (account_id, region) VALUES (42, 'acme'), (43, 'acme')",
+                None
+            ),
+            Shard::All
+        );
+    }
+
+    #[test]
+    fn test_default_sharding_key() {
+        let schema = |sequence| ShardingSchema {
+            shards: 3,
+            tables: ShardedTables::new(
+                vec![ShardedTable {
+                    name: Some("sharded".into()),
+                    column: "id".into(),
+                    sequence,
+                    ..Default::default()
+                }],
+                vec![],
+                false,
+            ),
+            ..Default::default()
+        };
+        let shard = |query: &str, sequence, bind: Option<&Bind>| {
+            let query = parse(query).unwrap();
+            let stmt = query.protobuf.stmts.first().unwrap().stmt.as_ref().unwrap();
+            match &stmt.node {
+                Some(NodeEnum::InsertStmt(stmt)) => {
+                    Insert::new(stmt).shard(&schema(sequence), bind)
+                }
+                _ => panic!("not an insert"),
+            }
+        };
+        let bind = Bind::test_params(
+            "",
+            &[Parameter {
+                len: 1,
+                data: "1".as_bytes().to_vec(),
+            }],
+        );
+
+        for query in [
+            "INSERT INTO sharded (id, value) VALUES (DEFAULT, 'test')",
+            "INSERT INTO sharded (id, value) VALUES (DEFAULT, 'a'), (DEFAULT, 'b')",
+        ] {
+            assert!(matches!(
+                shard(query, ShardedSequence::Local, None),
+                Ok(Shard::Direct(shard)) if shard < 3
+            ));
+            assert!(matches!(
+                shard(query, ShardedSequence::Global, None),
+                Err(Error::GlobalSequence(column)) if column == "id"
+            ));
+        }
+
+        let query = "INSERT INTO sharded (id, value) VALUES (DEFAULT, $1)";
+        assert!(matches!(
+            shard(query, ShardedSequence::Local, Some(&bind)),
+            Ok(Shard::Direct(_))
+        ));
+        assert!(shard(query, ShardedSequence::Global, Some(&bind)).is_err());
+
+        // Only some rows use the sequence.
+        let query = "INSERT INTO sharded (id, value) VALUES (DEFAULT, 'a'), (1, 'b')";
+        assert!(matches!(
+            shard(query, ShardedSequence::Global, None),
+            Ok(Shard::All)
+        ));
+    }
 }
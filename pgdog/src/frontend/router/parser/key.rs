@@ -1,6 +1,9 @@
 //! Sharding key in a query.
 
-#[derive(Debug, PartialEq)]
+use super::{Error, RouteError};
+use crate::net::{Bind, ParameterWithFormat};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Key {
     /// Parameter, like $1, $2, referring to a value
     /// sent in a separate Bind message.
@@ -10,4 +13,15 @@ pub enum Key {
     Constant { value: String, array: bool },
     /// Null check on a column.
     Null,
+    /// `COALESCE(column, default) = value`.
+    Coalesce { value: Box<Key>, default: Box<Key> },
+    /// Element of an array column, e.g. `$1 = ANY(tenant_ids)`.
+    Element(Box<Key>),
+}
+
+/// Parameter holding a sharding key. Referencing one that wasn't bound, e.g. `$3`
+/// with two parameters, is an error: we don't know which shard to use.
+pub fn parameter(bind: &Bind, position: usize) -> Result<ParameterWithFormat<'_>, Error> {
+    bind.parameter(position)?
+        .ok_or_else(|| RouteError::ParameterOutOfRange(position + 1, bind.params_len()).into())
 }
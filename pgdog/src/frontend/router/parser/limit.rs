@@ -1,15 +1,18 @@
 use pg_query::{
-    protobuf::{a_const::Val, AConst, Integer, ParamRef, SelectStmt},
+    protobuf::{a_const::Val, AConst, Integer, LimitOption, ParamRef, SelectStmt},
     Node, NodeEnum,
 };
 
-use super::Error;
+use super::{Error, Value};
 use crate::net::Bind;
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
 pub struct Limit {
     pub limit: Option<usize>,
     pub offset: Option<usize>,
+    /// `FETCH FIRST n ROWS WITH TIES`. Postgres rejects it
+    /// without an `ORDER BY`, so we don't have to check.
+    pub with_ties: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -33,6 +36,8 @@ impl<'a> LimitClause<'a> {
             limit.offset = self.decode(limit_offset)?;
         }
 
+        limit.with_ties = self.stmt.limit_option() == LimitOption::WithTies;
+
         Ok(limit)
     }
 
@@ -45,8 +50,10 @@ impl<'a> LimitClause<'a> {
 
             Some(NodeEnum::ParamRef(ParamRef { number, .. })) => {
                 if let Some(bind) = &self.bind {
-                    let param = bind
-                        .parameter(*number as usize - 1)?
+                    let param = Value::placeholder_position(*number)
+                        .map(|pos| bind.parameter(pos))
+                        .transpose()?
+                        .flatten()
                         .ok_or(Error::MissingParameter(*number as usize))?;
 
                     Ok(Some(
@@ -0,0 +1,399 @@
+//! Statements that can be answered without a backend.
+//!
+//! We are very conservative here: only `SELECT` statements
+//! with constants and parameters in their target list qualify,
+//! e.g. `SELECT 1` or `SELECT $1`, and `VALUES` lists made of them,
+//! e.g. `VALUES (1), (2)`. Anything that could touch database state,
+//! including all function calls, goes to a backend.
+
+use pg_query::{
+    protobuf::{a_const::Val, *},
+    NodeEnum,
+};
+
+use crate::net::{
+    messages::{
+        data_row::{Data, ToDataRowColumn},
+        DataRow, Field, Format, RowDescription,
+    },
+    Bind,
+};
+
+use super::Value;
+
+/// Column name Postgres uses for unnamed expressions.
+static UNNAMED: &str = "?column?";
+
+/// A trivially constant `SELECT` or `VALUES` statement.
+#[derive(Debug)]
+pub struct Local<'a> {
+    stmt: &'a SelectStmt,
+}
+
+impl<'a> Local<'a> {
+    /// Check that the statement is trivially constant.
+    pub fn new(stmt: &'a SelectStmt) -> Option<Self> {
+        let simple = stmt.from_clause.is_empty()
+            && stmt.where_clause.is_none()
+            && stmt.group_clause.is_empty()
+            && stmt.having_clause.is_none()
+            && stmt.sort_clause.is_empty()
+            && stmt.distinct_clause.is_empty()
+            && stmt.window_clause.is_empty()
+            && stmt.locking_clause.is_empty()
+            && stmt.limit_count.is_none()
+            && stmt.limit_offset.is_none()
+            && stmt.with_clause.is_none()
+            && stmt.into_clause.is_none()
+            && stmt.op() == SetOperation::SetopNone
+            && stmt.target_list.is_empty() != stmt.values_lists.is_empty();
+
+        if !simple {
+            return None;
+        }
+
+        let constant = |node: Option<&NodeEnum>| {
+            matches!(
+                node,
+                Some(NodeEnum::AConst(_)) | Some(NodeEnum::ParamRef(_))
+            )
+        };
+
+        for target in &stmt.target_list {
+            let constant = match &target.node {
+                Some(NodeEnum::ResTarget(res)) => {
+                    constant(res.val.as_ref().and_then(|val| val.node.as_ref()))
+                }
+                _ => false,
+            };
+
+            if !constant {
+                return None;
+            }
+        }
+
+        let width = Self::values(stmt).next().map(|row| row.len());
+
+        for row in Self::values(stmt) {
+            if Some(row.len()) != width || !row.iter().all(|item| constant(item.node.as_ref())) {
+                return None;
+            }
+        }
+
+        Some(Self { stmt })
+    }
+
+    /// Rows of a `VALUES` list.
+    fn values(stmt: &SelectStmt) -> impl Iterator<Item = &Vec<Node>> {
+        stmt.values_lists.iter().filter_map(|row| match &row.node {
+            Some(NodeEnum::List(list)) => Some(&list.items),
+            _ => None,
+        })
+    }
+
+    /// Number of parameters the statement takes, i.e. the highest `$n` it references.
+    pub fn parameters(&self) -> usize {
+        self.nodes()
+            .filter_map(|node| match node {
+                Some(NodeEnum::ParamRef(param)) => usize::try_from(param.number).ok(),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Describe the columns the statement returns without binding its parameters.
+    /// Parameters are text, which is what Postgres infers for e.g. `SELECT $1`.
+    ///
+    /// Returns `None` for `VALUES` lists with parameters, since Postgres infers
+    /// their types from the other values in their column.
+    pub fn describe(&self) -> Option<RowDescription> {
+        if self.parameters() == 0 {
+            return self.evaluate(None).map(|(rd, _)| rd);
+        }
+
+        if !self.stmt.values_lists.is_empty() {
+            return None;
+        }
+
+        let mut fields = vec![];
+
+        for target in &self.stmt.target_list {
+            let Some(NodeEnum::ResTarget(ref res)) = target.node else {
+                return None;
+            };
+            let name = Self::name(res);
+
+            let field = match res.val.as_ref().and_then(|val| val.node.as_ref()) {
+                Some(NodeEnum::ParamRef(_)) => Field::text(name),
+                node => Self::column(name, node, None)?.0,
+            };
+            fields.push(field);
+        }
+
+        Some(RowDescription::new(&fields))
+    }
+
+    /// Constants and parameters in the target list and the `VALUES` list.
+    fn nodes(&self) -> impl Iterator<Item = Option<&NodeEnum>> {
+        self.stmt
+            .target_list
+            .iter()
+            .map(|target| match &target.node {
+                Some(NodeEnum::ResTarget(res)) => {
+                    res.val.as_ref().and_then(|val| val.node.as_ref())
+                }
+                _ => None,
+            })
+            .chain(
+                Self::values(self.stmt)
+                    .flatten()
+                    .map(|item| item.node.as_ref()),
+            )
+    }
+
+    /// Column name of a target list entry.
+    fn name(res: &ResTarget) -> &str {
+        if res.name.is_empty() {
+            UNNAMED
+        } else {
+            res.name.as_str()
+        }
+    }
+
+    /// Evaluate the statement, producing the row description
+    /// and the rows it returns.
+    ///
+    /// Returns `None` if we can't evaluate it, e.g. a parameter
+    /// isn't bound or is in binary format. The statement should go to a backend
+    /// in that case.
+    pub fn evaluate(&self, bind: Option<&Bind>) -> Option<(RowDescription, Vec<DataRow>)> {
+        if !self.stmt.values_lists.is_empty() {
+            return self.evaluate_values(bind);
+        }
+
+        let mut fields = vec![];
+        let mut row = DataRow::new();
+
+        for target in &self.stmt.target_list {
+            let Some(NodeEnum::ResTarget(ref res)) = target.node else {
+                return None;
+            };
+            let name = Self::name(res);
+
+            let (field, data) = Self::column(
+                name,
+                res.val.as_ref().and_then(|val| val.node.as_ref()),
+                bind,
+            )?;
+            fields.push(field);
+            row.add(data);
+        }
+
+        Some((RowDescription::new(&fields), vec![row]))
+    }
+
+    /// Evaluate a `VALUES` list. Columns are named `column1`, `column2`, etc.,
+    /// like in Postgres. We don't resolve a common type for columns mixing
+    /// types, e.g. `VALUES (1), ('a')`, and let Postgres handle them instead.
+    fn evaluate_values(&self, bind: Option<&Bind>) -> Option<(RowDescription, Vec<DataRow>)> {
+        let mut fields: Vec<Option<Field>> = vec![];
+        let mut rows = vec![];
+
+        for items in Self::values(self.stmt) {
+            let mut row = DataRow::new();
+
+            for (index, item) in items.iter().enumerate() {
+                let name = format!("column{}", index + 1);
+                let (field, data) = Self::column(&name, item.node.as_ref(), bind)?;
+
+                if fields.len() <= index {
+                    fields.push(None);
+                }
+
+                // NULL takes the type of the other values in its column.
+                if !data.is_null() {
+                    match &fields[index] {
+                        Some(existing) if existing.type_oid != field.type_oid => return None,
+                        Some(_) => (),
+                        None => fields[index] = Some(field),
+                    }
+                }
+
+                row.add(data);
+            }
+
+            rows.push(row);
+        }
+
+        let fields = fields
+            .into_iter()
+            .enumerate()
+            .map(|(index, field)| {
+                field.unwrap_or_else(|| Field::text(&format!("column{}", index + 1)))
+            })
+            .collect::<Vec<_>>();
+
+        Some((RowDescription::new(&fields), rows))
+    }
+
+    /// Evaluate one constant or parameter.
+    fn column(name: &str, node: Option<&NodeEnum>, bind: Option<&Bind>) -> Option<(Field, Data)> {
+        match node? {
+            NodeEnum::AConst(a_const) => {
+                if a_const.isnull {
+                    return Some((Field::text(name), Data::null()));
+                }
+
+                match a_const.val.as_ref()? {
+                    Val::Ival(Integer { ival }) => {
+                        Some((Field::integer(name), (*ival as i64).to_data_row_column()))
+                    }
+                    Val::Fval(Float { fval }) => {
+                        Some((Field::numeric(name), fval.as_str().to_data_row_column()))
+                    }
+                    Val::Boolval(Boolean { boolval }) => {
+                        Some((Field::bool(name), boolval.to_data_row_column()))
+                    }
+                    Val::Sval(String { sval }) => {
+                        Some((Field::text(name), sval.as_str().to_data_row_column()))
+                    }
+                    Val::Bsval(_) => None,
+                }
+            }
+
+            NodeEnum::ParamRef(param) => {
+                let param = bind?
+                    .parameter(Value::placeholder_position(param.number)?)
+                    .ok()??;
+                if param.format() != Format::Text {
+                    return None;
+                }
+                Some((Field::text(name), param.text()?.to_data_row_column()))
+            }
+
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::parse;
+
+    use super::*;
+    use crate::net::bind::Parameter;
+
+    fn select(query: &str) -> SelectStmt {
+        let ast = parse(query).unwrap();
+        match ast
+            .protobuf
+            .stmts
+            .first()
+            .unwrap()
+            .stmt
+            .as_ref()
+            .unwrap()
+            .node
+        {
+            Some(NodeEnum::SelectStmt(ref stmt)) => *stmt.clone(),
+            _ => panic!("not a select"),
+        }
+    }
+
+    #[test]
+    fn test_local() {
+        for query in [
+            "SELECT 1",
+            "SELECT $1",
+            "SELECT 1 AS one, 'two', true, NULL",
+            "VALUES (1), (2)",
+            "VALUES (1, 'a'), ($1, NULL)",
+        ] {
+            assert!(Local::new(&select(query)).is_some(), "{}", query);
+        }
+
+        for query in [
+            "SELECT count(*) FROM t",
+            "SELECT now()",
+            "SELECT nextval('seq')",
+            "SELECT 1 UNION SELECT 2",
+            "SELECT 1 FOR UPDATE",
+            "SELECT 1 WHERE false",
+            "VALUES (1), (now())",
+            "VALUES (1), (2) ORDER BY 1",
+        ] {
+            assert!(Local::new(&select(query)).is_none(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let stmt = select("SELECT 1, 'two' AS two, $1");
+        let local = Local::new(&stmt).unwrap();
+
+        // Parameter isn't bound.
+        assert!(local.evaluate(None).is_none());
+
+        let bind = Bind::test_params(
+            "",
+            &[Parameter {
+                len: 5,
+                data: "three".as_bytes().to_vec(),
+            }],
+        );
+        let (rd, rows) = local.evaluate(Some(&bind)).unwrap();
+        let dr = &rows[0];
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rd.field(0).unwrap().name, "?column?");
+        assert_eq!(rd.field(1).unwrap().name, "two");
+        assert_eq!(dr.get::<i64>(0, Format::Text), Some(1));
+        assert_eq!(dr.get_text(1).unwrap(), "two");
+        assert_eq!(dr.get_text(2).unwrap(), "three");
+    }
+
+    #[test]
+    fn test_describe() {
+        let stmt = select("SELECT 1, $2 AS two");
+        let local = Local::new(&stmt).unwrap();
+        assert_eq!(local.parameters(), 2);
+
+        let rd = local.describe().unwrap();
+        assert_eq!(rd.field(0).unwrap().name, "?column?");
+        assert_eq!(rd.field(0).unwrap().type_oid, 23);
+        assert_eq!(rd.field(1).unwrap().name, "two");
+        assert_eq!(rd.field(1).unwrap().type_oid, 25);
+
+        let stmt = select("VALUES (1), (2)");
+        let local = Local::new(&stmt).unwrap();
+        assert_eq!(local.parameters(), 0);
+        assert_eq!(local.describe().unwrap().field(0).unwrap().type_oid, 23);
+
+        // Postgres infers the parameter is an integer.
+        let stmt = select("VALUES (1), ($1)");
+        assert!(Local::new(&stmt).unwrap().describe().is_none());
+    }
+
+    #[test]
+    fn test_evaluate_values() {
+        let stmt = select("VALUES (1, NULL), (2, 'two'), (3, 'three')");
+        let (rd, rows) = Local::new(&stmt).unwrap().evaluate(None).unwrap();
+        assert_eq!(rd.field(0).unwrap().name, "column1");
+        assert_eq!(rd.field(0).unwrap().type_oid, 23);
+        assert_eq!(rd.field(1).unwrap().name, "column2");
+        assert_eq!(rd.field(1).unwrap().type_oid, 25);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(
+            rows.iter()
+                .map(|row| row.get::<i64>(0, Format::Text).unwrap())
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(rows[0].len(), 2);
+        assert_eq!(rows[2].get_text(1).unwrap(), "three");
+
+        // Postgres resolves a common type, we don't.
+        let stmt = select("VALUES (1), ('a')");
+        assert!(Local::new(&stmt).unwrap().evaluate(None).is_none());
+    }
+}
@@ -1,6 +1,8 @@
 //! Query parser.
 
 pub mod aggregate;
+pub mod array;
+pub mod batch;
 pub mod binary;
 pub mod cache;
 pub mod column;
@@ -8,41 +10,66 @@ pub mod command;
 pub mod comment;
 pub mod copy;
 pub mod csv;
+pub mod derived;
 pub mod distinct;
 pub mod error;
+pub mod fetch;
 pub mod function;
+pub mod hint;
 pub mod insert;
 pub mod key;
 pub mod limit;
+pub mod local;
 pub mod multi_tenant;
+pub mod no_rows;
 pub mod order_by;
+pub mod plan;
+pub mod point_lookup;
 pub mod prepare;
 pub mod query;
+pub mod query_roles;
+pub mod range_filter;
+pub mod resolver;
 pub mod rewrite;
 pub mod route;
+pub mod set_operator;
 pub mod table;
 pub mod tuple;
 pub mod value;
 pub mod where_clause;
 
-pub use aggregate::{Aggregate, AggregateFunction, AggregateTarget};
+pub use aggregate::{
+    Aggregate, AggregateFunction, AggregateTarget, OrderedSetFunction, OrderedSetTarget,
+};
+pub use batch::Statement;
 pub use binary::BinaryStream;
 pub use cache::Cache;
 pub use column::Column;
 pub use command::Command;
 pub use copy::{CopyFormat, CopyParser};
 pub use csv::{CsvStream, Record};
+pub use derived::DerivedTable;
 pub use distinct::{Distinct, DistinctBy, DistinctColumn};
-pub use error::Error;
+pub use error::{Error, RouteError};
+pub use fetch::{Direction, Fetch};
 pub use function::Function;
 pub use function::{FunctionBehavior, LockingBehavior};
+pub use hint::RouteHint;
 pub use insert::Insert;
 pub use key::Key;
 pub use limit::{Limit, LimitClause};
+pub use local::Local;
+pub use no_rows::NoRows;
 pub use order_by::OrderBy;
+pub use plan::RoutePlan;
+pub use point_lookup::PointLookup;
 pub use prepare::Prepare;
 pub use query::QueryParser;
-pub use route::{Route, Shard};
+pub use query_roles::QueryRoles;
+pub use range_filter::range_filters;
+pub use resolver::BindResolver;
+pub use route::{Attribute, Route, Shard};
+pub use set_operator::SetOperator;
 pub use table::Table;
 pub use tuple::Tuple;
 pub use value::Value;
@@ -0,0 +1,167 @@
+//! Statements that match no rows, e.g. their sharding key is an empty list,
+//! can be answered without querying a shard.
+//!
+//! We only answer statements that do nothing when no rows match
+//! and whose columns we can name without a backend. Aggregates, for example,
+//! return a row even if nothing matches, so they go to a shard.
+
+use pg_query::{
+    protobuf::{ParseResult, ResTarget, SetOperation},
+    Node, NodeEnum,
+};
+
+use crate::net::messages::{Field, RowDescription};
+
+/// Column name Postgres uses for unnamed expressions.
+static UNNAMED: &str = "?column?";
+
+/// The empty result of a statement.
+#[derive(Debug, Clone)]
+pub struct NoRows {
+    row_description: Option<RowDescription>,
+    tag: &'static str,
+}
+
+impl NoRows {
+    /// Describe the empty result of the statement.
+    ///
+    /// Returns `None` if the statement could return rows or do something
+    /// even if no rows match, or we can't name its columns.
+    pub fn new(ast: &ParseResult) -> Option<Self> {
+        let [stmt] = ast.stmts.as_slice() else {
+            return None;
+        };
+
+        match stmt.stmt.as_ref()?.node.as_ref()? {
+            NodeEnum::SelectStmt(stmt) => {
+                let simple = stmt.op() == SetOperation::SetopNone
+                    && stmt.with_clause.is_none()
+                    && stmt.into_clause.is_none()
+                    && stmt.having_clause.is_none()
+                    && stmt.values_lists.is_empty();
+
+                if !simple {
+                    return None;
+                }
+
+                Some(Self {
+                    row_description: Some(Self::describe(&stmt.target_list)?),
+                    tag: "SELECT 0",
+                })
+            }
+
+            NodeEnum::UpdateStmt(stmt) if stmt.with_clause.is_none() => {
+                Self::returning(&stmt.returning_list, "UPDATE 0")
+            }
+
+            NodeEnum::DeleteStmt(stmt) if stmt.with_clause.is_none() => {
+                Self::returning(&stmt.returning_list, "DELETE 0")
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Columns returned by the statement, if any.
+    pub fn row_description(&self) -> Option<&RowDescription> {
+        self.row_description.as_ref()
+    }
+
+    /// Command tag, e.g. `SELECT 0`.
+    pub fn tag(&self) -> &str {
+        self.tag
+    }
+
+    /// Result of an `UPDATE` or `DELETE`, with or without `RETURNING`.
+    fn returning(returning: &[Node], tag: &'static str) -> Option<Self> {
+        let row_description = if returning.is_empty() {
+            None
+        } else {
+            Some(Self::describe(returning)?)
+        };
+
+        Some(Self {
+            row_description,
+            tag,
+        })
+    }
+
+    /// Name the columns in the target list. We only know the names of
+    /// columns and constants, and describe them as text, since there are no
+    /// values to decode. Function calls can be aggregates, which return a row
+    /// even if none match, and `*` needs the table definition.
+    fn describe(targets: &[Node]) -> Option<RowDescription> {
+        let mut fields = vec![];
+
+        for target in targets {
+            let Some(NodeEnum::ResTarget(ref res)) = target.node else {
+                return None;
+            };
+            fields.push(Field::text(&Self::name(res)?));
+        }
+
+        Some(RowDescription::new(&fields))
+    }
+
+    /// Name of a column in the target list.
+    fn name(res: &ResTarget) -> Option<String> {
+        let name = match res.val.as_ref().and_then(|val| val.node.as_ref())? {
+            NodeEnum::ColumnRef(column) => match column.fields.last()?.node.as_ref()? {
+                NodeEnum::String(name) => name.sval.clone(),
+                _ => return None,
+            },
+            NodeEnum::AConst(_) => UNNAMED.to_string(),
+            _ => return None,
+        };
+
+        if res.name.is_empty() {
+            Some(name)
+        } else {
+            Some(res.name.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::parse;
+
+    use super::*;
+
+    fn no_rows(query: &str) -> Option<NoRows> {
+        NoRows::new(&parse(query).unwrap().protobuf)
+    }
+
+    #[test]
+    fn test_no_rows() {
+        let select =
+            no_rows("SELECT id, sharded.value AS v, 1 FROM sharded WHERE id = ANY('{}')").unwrap();
+        assert_eq!(select.tag(), "SELECT 0");
+        let rd = select.row_description().unwrap();
+        assert_eq!(rd.field(0).unwrap().name, "id");
+        assert_eq!(rd.field(1).unwrap().name, "v");
+        assert_eq!(rd.field(2).unwrap().name, "?column?");
+
+        let update = no_rows("UPDATE sharded SET value = 'a' WHERE id = ANY('{}')").unwrap();
+        assert_eq!(update.tag(), "UPDATE 0");
+        assert!(update.row_description().is_none());
+
+        let delete = no_rows("DELETE FROM sharded WHERE id = ANY('{}') RETURNING id").unwrap();
+        assert_eq!(delete.tag(), "DELETE 0");
+        assert_eq!(
+            delete.row_description().unwrap().field(0).unwrap().name,
+            "id"
+        );
+
+        for query in [
+            "SELECT * FROM sharded WHERE id = ANY('{}')",
+            "SELECT count(*) FROM sharded WHERE id = ANY('{}')",
+            "SELECT id FROM sharded WHERE id = ANY('{}') UNION SELECT 1",
+            "WITH d AS (DELETE FROM t RETURNING id) SELECT id FROM sharded WHERE id = ANY('{}')",
+            "DELETE FROM sharded WHERE id = ANY('{}') RETURNING *",
+            "SELECT 1; SELECT 2",
+        ] {
+            assert!(no_rows(query).is_none(), "{}", query);
+        }
+    }
+}
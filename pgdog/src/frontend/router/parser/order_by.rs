@@ -4,7 +4,7 @@ use std::fmt::Debug;
 
 use crate::net::messages::Vector;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum OrderBy {
     Asc(usize),
     Desc(usize),
@@ -29,9 +29,9 @@ impl OrderBy {
     /// Column index.
     pub fn index(&self) -> Option<usize> {
         match self {
-            OrderBy::Asc(column) => Some(*column - 1),
-            OrderBy::Desc(column) => Some(*column - 1),
-            OrderBy::AscVectorL2(column, _) => Some(*column - 1),
+            OrderBy::Asc(column) => column.checked_sub(1),
+            OrderBy::Desc(column) => column.checked_sub(1),
+            OrderBy::AscVectorL2(column, _) => column.checked_sub(1),
             _ => None,
         }
     }
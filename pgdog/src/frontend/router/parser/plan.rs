@@ -0,0 +1,170 @@
+//! Route plan, logged at debug level.
+//!
+//! Explains a routing decision: the sharding key the parser matched in the
+//! `WHERE` clause and the shard it picked. Key values can contain PII,
+//! so they are redacted unless `log_route_values` is enabled, and so are
+//! values in the logged query text.
+
+use std::{borrow::Cow, fmt::Display};
+
+use pg_query::normalize;
+
+use super::{Key, Route, Shard};
+use crate::net::Bind;
+
+const REDACTED: &str = "<redacted>";
+
+/// Route plan for one statement.
+#[derive(Debug)]
+pub struct RoutePlan {
+    column: Option<String>,
+    /// Keys with their parameter values, if any were bound.
+    keys: Vec<(Key, Option<String>)>,
+    shard: Shard,
+    redact: bool,
+}
+
+impl RoutePlan {
+    /// Explain the route picked by the parser.
+    pub fn new(route: &Route, bind: Option<&Bind>, redact: bool) -> Self {
+        let keys = route
+            .sharding_keys()
+            .iter()
+            .map(|key| {
+                let value = match key {
+                    Key::Parameter { pos, .. } => bind
+                        .and_then(|bind| bind.parameter(*pos).ok().flatten())
+                        .and_then(|param| {
+                            param
+                                .text()
+                                .map(|text| text.to_string())
+                                .or_else(|| param.bigint().map(|value| value.to_string()))
+                        }),
+                    _ => None,
+                };
+                (key.clone(), value)
+            })
+            .collect();
+
+        Self {
+            column: route.sharding_column().map(|column| column.to_string()),
+            keys,
+            shard: route.shard().clone(),
+            redact,
+        }
+    }
+
+    /// Query text with values replaced by placeholders, if redacted.
+    pub fn query(query: &str, redact: bool) -> Cow<'_, str> {
+        if redact {
+            normalize(query)
+                .map(Cow::Owned)
+                .unwrap_or(Cow::Borrowed(REDACTED))
+        } else {
+            Cow::Borrowed(query)
+        }
+    }
+
+    fn value<'a>(&self, value: &'a str) -> &'a str {
+        if self.redact {
+            REDACTED
+        } else {
+            value
+        }
+    }
+
+    fn key(&self, key: &Key, bound: Option<&str>) -> String {
+        match key {
+            Key::Parameter { pos, array } => format!(
+                "parameter(pos={}{}, value={})",
+                pos,
+                if *array { ", array" } else { "" },
+                bound.map(|v| self.value(v)).unwrap_or("none"),
+            ),
+            Key::Constant { value, array } => format!(
+                "constant({}value={})",
+                if *array { "array, " } else { "" },
+                self.value(value),
+            ),
+            Key::Null => "null".into(),
+            Key::Coalesce { value, default } => format!(
+                "coalesce(value={}, default={})",
+                self.key(value, bound),
+                self.key(default, None),
+            ),
+            Key::Element(key) => format!("element({})", self.key(key, bound)),
+        }
+    }
+}
+
+impl Display for RoutePlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(key, value)| self.key(key, value.as_deref()))
+            .collect::<Vec<_>>();
+
+        write!(
+            f,
+            "column={} keys=[{}] shard={:?}",
+            self.column.as_deref().unwrap_or("none"),
+            keys.join(", "),
+            self.shard,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::messages::Parameter;
+
+    #[test]
+    fn test_route_plan() {
+        let bind = Bind::test_params(
+            "",
+            &[Parameter {
+                len: 7,
+                data: "1234567".as_bytes().to_vec(),
+            }],
+        );
+        let route = Route::read(Some(1)).set_sharding_key(Some((
+            "id".into(),
+            vec![
+                Key::Parameter {
+                    pos: 0,
+                    array: false,
+                },
+                Key::Constant {
+                    value: "7654321".into(),
+                    array: false,
+                },
+            ],
+        )));
+
+        let plan = RoutePlan::new(&route, Some(&bind), true).to_string();
+        assert!(plan.contains("column=id"));
+        assert!(plan.contains("parameter(pos=0, value=<redacted>)"));
+        assert!(plan.contains("constant(value=<redacted>)"));
+        assert!(plan.contains("shard=Direct(1)"));
+        assert!(!plan.contains("=1234567"));
+        assert!(!plan.contains("=7654321"));
+
+        let plan = RoutePlan::new(&route, Some(&bind), false).to_string();
+        assert!(plan.contains("parameter(pos=0, value=1234567)"));
+        assert!(plan.contains("constant(value=7654321)"));
+
+        let plan = RoutePlan::new(&Route::read(None), None, true).to_string();
+        assert!(plan.contains("column=none keys=[] shard=All"));
+    }
+
+    #[test]
+    fn test_redacted_query() {
+        let query = "SELECT * FROM sharded WHERE id = 7654321 AND email = 'a@b.c'";
+        let redacted = RoutePlan::query(query, true);
+        assert!(!redacted.contains("7654321"));
+        assert!(!redacted.contains("a@b.c"));
+        assert_eq!(RoutePlan::query(query, false), query);
+    }
+}
@@ -0,0 +1,423 @@
+//! Point lookups routed without pg_query.
+//!
+//! `SELECT a, b FROM users WHERE id = $1` is the hottest query of most sharded
+//! workloads, and parsing it costs more than routing it. The simplest shapes are
+//! recognized here with a tokenizer. Anything else, including comments, aliases,
+//! quoted or uppercase identifiers and schema-qualified tables, goes to the parser.
+
+/// Keywords that can't be used as unquoted table or column names.
+static RESERVED: &[&str] = &[
+    "all",
+    "analyse",
+    "analyze",
+    "and",
+    "any",
+    "array",
+    "as",
+    "asc",
+    "asymmetric",
+    "authorization",
+    "binary",
+    "both",
+    "case",
+    "cast",
+    "check",
+    "collate",
+    "collation",
+    "column",
+    "concurrently",
+    "constraint",
+    "create",
+    "cross",
+    "current_catalog",
+    "current_date",
+    "current_role",
+    "current_schema",
+    "current_time",
+    "current_timestamp",
+    "current_user",
+    "default",
+    "deferrable",
+    "desc",
+    "distinct",
+    "do",
+    "else",
+    "end",
+    "except",
+    "false",
+    "fetch",
+    "for",
+    "foreign",
+    "freeze",
+    "from",
+    "full",
+    "grant",
+    "group",
+    "having",
+    "ilike",
+    "in",
+    "initially",
+    "inner",
+    "intersect",
+    "into",
+    "is",
+    "isnull",
+    "join",
+    "lateral",
+    "leading",
+    "left",
+    "like",
+    "limit",
+    "localtime",
+    "localtimestamp",
+    "natural",
+    "not",
+    "notnull",
+    "null",
+    "offset",
+    "on",
+    "only",
+    "or",
+    "order",
+    "outer",
+    "overlaps",
+    "placing",
+    "primary",
+    "references",
+    "returning",
+    "right",
+    "select",
+    "session_user",
+    "similar",
+    "some",
+    "symmetric",
+    "system_user",
+    "table",
+    "tablesample",
+    "then",
+    "to",
+    "trailing",
+    "true",
+    "union",
+    "unique",
+    "user",
+    "using",
+    "variadic",
+    "verbose",
+    "when",
+    "where",
+    "window",
+    "with",
+];
+
+/// Value the column is compared to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PointValue<'a> {
+    /// `$1`, `$2`, etc.
+    Parameter(i32),
+    /// Integer or string literal, without quotes.
+    Constant(&'a str),
+}
+
+/// `SELECT <columns> FROM <table> WHERE <column> = <value>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointLookup<'a> {
+    pub table: &'a str,
+    pub column: &'a str,
+    pub value: PointValue<'a>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Operator(&'a str),
+    Comma,
+    Semicolon,
+    Parameter(i32),
+    Integer(&'a str),
+    String(&'a str),
+}
+
+impl<'a> PointLookup<'a> {
+    /// Recognize a point lookup. Returns `None` if the query
+    /// is anything else, even if it's a lookup we could route.
+    pub fn new(query: &'a str) -> Option<Self> {
+        let mut tokens = Tokens { query, pos: 0 };
+
+        if !tokens.keyword("select")? {
+            return None;
+        }
+
+        // `*` or a list of columns.
+        match tokens.token()? {
+            Token::Operator("*") => {
+                if !tokens.keyword("from")? {
+                    return None;
+                }
+            }
+            Token::Word(word) => {
+                identifier(word)?;
+                loop {
+                    match tokens.token()? {
+                        Token::Comma => identifier(tokens.word()?)?,
+                        Token::Word(word) if word.eq_ignore_ascii_case("from") => break,
+                        _ => return None,
+                    };
+                }
+            }
+            _ => return None,
+        }
+
+        let table = identifier(tokens.word()?)?;
+        if !tokens.keyword("where")? {
+            return None;
+        }
+        let column = identifier(tokens.word()?)?;
+        if tokens.token()? != Token::Operator("=") {
+            return None;
+        }
+        let value = match tokens.token()? {
+            Token::Parameter(pos) => PointValue::Parameter(pos),
+            Token::Integer(value) | Token::String(value) => PointValue::Constant(value),
+            _ => return None,
+        };
+
+        if !tokens.done() && (tokens.token()? != Token::Semicolon || !tokens.done()) {
+            return None;
+        }
+
+        Some(Self {
+            table,
+            column,
+            value,
+        })
+    }
+}
+
+/// Unquoted identifiers are folded to lowercase by Postgres.
+/// We only accept those already in lowercase, so we don't have to allocate.
+fn identifier(word: &str) -> Option<&str> {
+    if word.bytes().any(|b| b.is_ascii_uppercase()) || RESERVED.contains(&word) {
+        None
+    } else {
+        Some(word)
+    }
+}
+
+struct Tokens<'a> {
+    query: &'a str,
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.query.as_bytes()[self.pos..];
+        self.pos += rest.iter().take_while(|b| b.is_ascii_whitespace()).count();
+    }
+
+    fn done(&mut self) -> bool {
+        self.skip_whitespace();
+        self.pos == self.query.len()
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Option<bool> {
+        Some(self.word()?.eq_ignore_ascii_case(keyword))
+    }
+
+    fn word(&mut self) -> Option<&'a str> {
+        match self.token()? {
+            Token::Word(word) => Some(word),
+            _ => None,
+        }
+    }
+
+    /// Next token, or `None` at the end of the query
+    /// and for anything we don't recognize.
+    fn token(&mut self) -> Option<Token<'a>> {
+        self.skip_whitespace();
+        let bytes = self.query.as_bytes();
+        let start = self.pos;
+        let first = *bytes.get(start)?;
+        let run = |pos: usize, f: fn(u8) -> bool| {
+            pos + bytes[pos..].iter().take_while(|b| f(**b)).count()
+        };
+
+        let token = match first {
+            b'a'..=b'z' | b'A'..=b'Z' | b'_' => {
+                self.pos = run(start, |b| b.is_ascii_alphanumeric() || b == b'_');
+                // `$` is allowed in identifiers, but we don't bother.
+                if bytes
+                    .get(self.pos)
+                    .is_some_and(|b| !b.is_ascii() || *b == b'$')
+                {
+                    return None;
+                }
+                Token::Word(&self.query[start..self.pos])
+            }
+
+            b'0'..=b'9' => {
+                self.pos = run(start, |b| b.is_ascii_digit());
+                let value = &self.query[start..self.pos];
+                // Postgres prints `007` as `7`, and `1e3`, `1.5`, `1_000` aren't
+                // integers we want to deal with.
+                if value.len() > 1 && value.starts_with('0') {
+                    return None;
+                }
+                Token::Integer(value)
+            }
+
+            b'$' => {
+                self.pos = run(start + 1, |b| b.is_ascii_digit());
+                let pos = self.query[start + 1..self.pos].parse().ok()?;
+                if pos == 0 {
+                    return None;
+                }
+                Token::Parameter(pos)
+            }
+
+            // Escapes and doubled quotes are left to the parser.
+            b'\'' => {
+                self.pos = run(start + 1, |b| b != b'\'' && b != b'\\');
+                if bytes.get(self.pos) != Some(&b'\'') {
+                    return None;
+                }
+                self.pos += 1;
+                if bytes.get(self.pos) == Some(&b'\'') {
+                    return None;
+                }
+                Token::String(&self.query[start + 1..self.pos - 1])
+            }
+
+            b',' => {
+                self.pos += 1;
+                Token::Comma
+            }
+
+            b';' => {
+                self.pos += 1;
+                Token::Semicolon
+            }
+
+            // Operators are as long as they can be, e.g. `=>` or `=-`,
+            // which also catches comments, e.g. `--` and `/*`.
+            _ => {
+                self.pos = run(start, |b| b"+-*/<>=~!@#%^&|`?".contains(&b));
+                if self.pos == start {
+                    return None;
+                }
+                Token::Operator(&self.query[start..self.pos])
+            }
+        };
+
+        // `1abc` or `$1abc` are not what they seem.
+        if matches!(token, Token::Integer(_) | Token::Parameter(_))
+            && bytes
+                .get(self.pos)
+                .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'.')
+        {
+            return None;
+        }
+
+        Some(token)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn test_point_lookup() {
+        let lookup = |table, column, value| {
+            Some(PointLookup {
+                table,
+                column,
+                value,
+            })
+        };
+
+        assert_eq!(
+            PointLookup::new("SELECT * FROM users WHERE id = $1"),
+            lookup("users", "id", PointValue::Parameter(1))
+        );
+        assert_eq!(
+            PointLookup::new("select id,email , name from users where tenant_id=12;  "),
+            lookup("users", "tenant_id", PointValue::Constant("12"))
+        );
+        assert_eq!(
+            PointLookup::new("SELECT value FROM sharded\n\tWHERE value = 'a b';"),
+            lookup("sharded", "value", PointValue::Constant("a b"))
+        );
+
+        for query in [
+            "SELECT * FROM users WHERE id = $1 AND email = $2",
+            "SELECT * FROM users WHERE id = $1 LIMIT 1",
+            "SELECT * FROM users WHERE id = $1 FOR UPDATE",
+            "SELECT * FROM users WHERE id > $1",
+            "SELECT * FROM users WHERE id => $1",
+            "SELECT * FROM users WHERE id = -1",
+            "SELECT * FROM users WHERE id = 01",
+            "SELECT * FROM users WHERE id = 1.5",
+            "SELECT * FROM users WHERE id = 1abc",
+            "SELECT * FROM users WHERE id = $0",
+            "SELECT * FROM users WHERE id = $1::bigint",
+            "SELECT * FROM users WHERE id = 'it''s'",
+            "SELECT * FROM users WHERE id = 'a\\'",
+            "SELECT * FROM users WHERE id = E'a'",
+            "SELECT * FROM users WHERE id = 'a",
+            "SELECT * FROM users WHERE id = NULL",
+            "SELECT * FROM users WHERE $1 = id",
+            "SELECT * FROM users WHERE users.id = $1",
+            "SELECT * FROM public.users WHERE id = $1",
+            "SELECT * FROM users u WHERE id = $1",
+            "SELECT * FROM \"Users\" WHERE id = $1",
+            "SELECT * FROM Users WHERE id = $1",
+            "SELECT * FROM users WHERE id = $1 -- comment",
+            "SELECT * FROM users WHERE id = $1 /* comment */",
+            "SELECT * FROM users WHERE id = $1; SELECT 1",
+            "SELECT * FROM users WHERE id = $1;;",
+            "SELECT count(*) FROM users WHERE id = $1",
+            "SELECT DISTINCT id FROM users WHERE id = $1",
+            "SELECT id, FROM users WHERE id = $1",
+            "SELECT id email FROM users WHERE id = $1",
+            "SELECT * FROM user WHERE id = $1",
+            "SELECT * FROM users WHERE order = $1",
+            "SELECT * FROM users, orders WHERE id = $1",
+            "SELECT * FROM users WHERE id = 1 WHERE",
+            "SELECT * FROM users WHERE ïd = 1",
+            "UPDATE users SET id = 1 WHERE id = $1",
+            "SELECT 1",
+            "",
+        ] {
+            assert_eq!(PointLookup::new(query), None, "{}", query);
+        }
+    }
+
+    // Tokenizing vs. parsing a point lookup.
+    #[test]
+    #[ignore]
+    fn test_benchmark_point_lookup() {
+        let query = "SELECT id, email, created_at FROM users WHERE tenant_id = $1";
+        let iterations = 100_000;
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            assert!(PointLookup::new(query).is_some());
+        }
+        let fast_path = start.elapsed();
+
+        let start = Instant::now();
+        for _ in 0..iterations {
+            assert!(pg_query::parse(query).is_ok());
+        }
+        let parser = start.elapsed();
+
+        println!(
+            "bench: fast path {}ns/query, pg_query {}ns/query",
+            fast_path.as_nanos() / iterations,
+            parser.as_nanos() / iterations,
+        );
+    }
+}
@@ -1,22 +1,26 @@
 //! Route queries to correct shards.
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::{
-    backend::{databases::databases, Cluster, ShardingSchema},
-    config::{config, ReadWriteStrategy},
+    backend::{databases::databases, pool::connection::reducer, Cluster, ShardingSchema},
+    config::{config, MultiKeyPolicy, PoolerMode, ReadWriteStrategy, Role, ShardedTable},
     frontend::{
         buffer::BufferedQuery,
         router::{
             context::RouterContext,
             parser::{rewrite::Rewrite, OrderBy, Shard},
             round_robin,
-            sharding::{Centroids, ContextBuilder, Value as ShardingValue},
-            CopyRow,
+            sharding::{composite, Centroids, ContextBuilder, Value as ShardingValue},
+            CopyRow, SearchPath,
         },
         PreparedStatements,
     },
     net::{
-        messages::{Bind, CopyData, Vector},
+        messages::{Bind, CopyData, Query, Vector},
         parameter::ParameterValue,
         Parameters,
     },
@@ -29,10 +33,16 @@ use once_cell::sync::Lazy;
 use pg_query::{
     fingerprint, parse,
     protobuf::{a_const::Val, *},
-    NodeEnum,
+    NodeEnum, NodeRef,
 };
 use regex::Regex;
-use tracing::{debug, trace};
+use tracing::{debug, enabled, trace, Level};
+
+/// Sharding column a query filters on and the keys it's filtered by.
+type ShardingKey = (std::string::String, Vec<Key>);
+
+/// `CURSOR_OPT_HOLD` from `parsenodes.h`.
+const CURSOR_OPT_HOLD: i32 = 0x0020;
 
 static REPLICATION_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(
@@ -48,6 +58,31 @@ pub struct QueryParser {
     routed: bool,
     in_transaction: bool,
     write_override: Option<bool>,
+    // Set with `SET pgdog.shards`, lasts for the whole session.
+    allowed_shards: Option<Vec<usize>>,
+    // Set with `SET pgdog.allow_unfiltered_writes`, lasts for the whole session.
+    allow_unfiltered_writes: bool,
+    // Set with `SET pgdog.route`, lasts for the whole session.
+    route_hint: Option<RouteHint>,
+    // Set with `SET LOCAL pgdog.route`, lasts until the transaction ends.
+    local_route_hint: Option<RouteHint>,
+    // Sharding keys of statements created with `PREPARE`, lasts until `DEALLOCATE`.
+    prepared: HashMap<std::string::String, Option<BindResolver>>,
+    // Cross-shard cursors buffered by the client, last until the transaction ends.
+    cursors: HashSet<std::string::String>,
+    // Shards of cursors declared on one shard, last until the transaction ends.
+    cursor_shards: HashMap<std::string::String, usize>,
+    // Set with a `/* pgdog_role */` comment, lasts for one statement.
+    role: Option<Role>,
+    // Set with a `/* pgdog_max_staleness */` comment, lasts for one statement.
+    max_staleness: Option<Duration>,
+    // Set with a `/* pgdog_pool: analytics */` comment, lasts for one statement.
+    analytics: bool,
+    // Set with `BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY`, lasts until the transaction ends.
+    snapshot: bool,
+    // Shard(s) of the transaction's first statement, which the client
+    // stays connected to, lasts until the transaction ends.
+    transaction_shard: Option<Shard>,
 }
 
 impl Default for QueryParser {
@@ -58,6 +93,18 @@ impl Default for QueryParser {
             routed: false,
             in_transaction: false,
             write_override: None,
+            allowed_shards: None,
+            allow_unfiltered_writes: false,
+            route_hint: None,
+            local_route_hint: None,
+            prepared: HashMap::new(),
+            cursors: HashSet::new(),
+            cursor_shards: HashMap::new(),
+            role: None,
+            max_staleness: None,
+            analytics: false,
+            snapshot: false,
+            transaction_shard: None,
         }
     }
 }
@@ -87,9 +134,141 @@ impl QueryParser {
 
         // If the cluster only has one shard, use direct-to-shard queries.
         if let Command::Query(ref mut query) = self.command {
+            // Patterns configured for misclassified queries override
+            // the read/write split. Hints and comments override them.
+            if let Some(ref buffered) = context.query {
+                if let Some(role) = context.cluster.query_roles().role(buffered.query()) {
+                    // A pattern can match writes too; those stay on the primary.
+                    if role == Role::Primary || !Self::modifies(query, buffered.query())? {
+                        query.set_read_mut(role == Role::Replica);
+                    }
+                }
+            }
+
+            if let Some(hint) = self.local_route_hint.or(self.route_hint) {
+                // Writes can't go to a replica.
+                if hint == RouteHint::Replica {
+                    if let Some(ref buffered) = context.query {
+                        if Self::modifies(query, buffered.query())? {
+                            return Err(Error::WriteToReplica);
+                        }
+                    }
+                }
+                hint.apply(query);
+            }
+
+            // Applied after the read/write split, so it overrides it.
+            if let Some(role) = self.role {
+                query.set_read_mut(role == Role::Replica);
+            }
+
+            if self.max_staleness.is_some() {
+                query.set_max_staleness_mut(self.max_staleness);
+            }
+
+            if self.analytics {
+                query.set_read_mut(true);
+                query.set_analytics_mut(true);
+            }
+
             if !matches!(query.shard(), Shard::Direct(_)) && context.cluster.shards().len() == 1 {
                 query.set_shard_mut(0);
             }
+
+            // Keep the query within the shards allowed for this session.
+            if let Some(ref allowed) = self.allowed_shards {
+                let shard = query
+                    .shard()
+                    .restrict(allowed)
+                    .ok_or_else(|| Error::ExcludedShard(query.shard().clone()))?;
+                query.set_shard_raw_mut(shard);
+            }
+
+            // Every shard is the same as all shards.
+            let shard = query
+                .shard()
+                .clone()
+                .normalize(context.cluster.shards().len());
+            if shard.all() {
+                query.set_sharding_column_mut(None);
+            }
+            query.set_shard_raw_mut(shard);
+        }
+
+        // Catch hashing and configuration bugs before we try to use a shard
+        // that doesn't exist.
+        let shards = context.cluster.shards().len();
+        let routes = match self.command {
+            Command::Query(ref route) => vec![(context.query.as_ref().map(|q| q.query()), route)],
+            Command::Batch(ref statements) => statements
+                .iter()
+                .map(|s| (Some(s.query()), s.route()))
+                .collect(),
+            _ => vec![],
+        };
+        for (statement, route) in routes {
+            if !route.shard().valid(shards) {
+                return Err(RouteError::ShardOutOfRange(route.shard().clone(), shards).into());
+            }
+
+            // Protect large clusters from queries without a sharding key.
+            // DDL, TRUNCATE, etc. have to run on all shards.
+            if let Some(max) = context.cluster.max_fanout_shards() {
+                let fanout = route.shard().count(shards);
+                if fanout > max && statement.map(Self::reads_rows).transpose()? == Some(true) {
+                    return Err(RouteError::FanoutTooWide(fanout, max).into());
+                }
+            }
+        }
+
+        // Tell each shard which keys it has, so it can skip partitions
+        // it doesn't need. Only simple queries can be rewritten.
+        if context.cluster.shard_range_filters() {
+            if let (Some(BufferedQuery::Query(ref query)), Command::Query(ref mut route)) =
+                (&context.query, &mut self.command)
+            {
+                let targets = match route.shard() {
+                    Shard::All => (0..shards).collect(),
+                    Shard::Multi(multi) => multi.clone(),
+                    Shard::Direct(_) => vec![],
+                };
+                if route.is_read() && !targets.is_empty() {
+                    // Filter the ordered-set aggregates' inputs, if the query was rewritten.
+                    let mut queries = route.shard_queries().clone();
+                    let query = queries
+                        .values()
+                        .next()
+                        .cloned()
+                        .unwrap_or_else(|| query.query().to_owned());
+                    queries.extend(range_filters(
+                        &query,
+                        &context.cluster.sharding_schema(),
+                        &targets,
+                    )?);
+                    route.set_shard_queries_mut(queries);
+                }
+            }
+        }
+
+        // The client connects to the shards of the first statement
+        // in a transaction and uses them until it ends.
+        if self.in_transaction && self.transaction_shard.is_none() {
+            if let Command::Query(ref mut route) = self.command {
+                self.transaction_shard = Some(route.shard().clone());
+
+                // Snapshots are taken when the transaction starts on the shards.
+                route.set_read_only_snapshot_mut(self.snapshot);
+            }
+        }
+
+        if enabled!(Level::DEBUG) {
+            if let Command::Query(ref route) = self.command {
+                let redact = !config().config.general.log_route_values;
+                debug!(
+                    "route plan: {}",
+                    RoutePlan::new(route, context.bind, redact)
+                );
+            }
         }
 
         Ok(&self.command)
@@ -106,7 +285,9 @@ impl QueryParser {
     /// Get the route currently determined by the parser.
     pub fn route(&self) -> Route {
         match self.command {
-            Command::Query(ref route) => route.clone(),
+            Command::Query(ref route)
+            | Command::Rewrite(_, ref route)
+            | Command::DeclareCursor { ref route, .. } => route.clone(),
             _ => Route::write(None),
         }
     }
@@ -115,12 +296,225 @@ impl QueryParser {
         self.routed
     }
 
+    /// Send a query that matches no rows to one shard, if the client
+    /// can't answer it. Any shard returns the same empty result.
+    pub fn one_shard(&mut self, shards: usize) -> &Command {
+        if let Command::Query(ref route) = self.command {
+            if route.shard().is_empty() {
+                let shard = self.round_robin(shards);
+                self.command = Command::Query(route.clone().set_shard(shard));
+            }
+        }
+
+        &self.command
+    }
+
+    /// Pick a shard for a query that can go to any of them.
+    fn round_robin(&self, shards: usize) -> usize {
+        match self.allowed_shards {
+            Some(ref allowed) if !allowed.is_empty() => {
+                allowed[round_robin::next() % allowed.len()]
+            }
+            _ => round_robin::next() % shards,
+        }
+    }
+
+    /// `SAVEPOINT`, `RELEASE` or `ROLLBACK TO`, if that's the statement.
+    fn savepoint_statement(ast: &ParseResult) -> Option<&'static str> {
+        let stmt = ast.stmts.first()?.stmt.as_ref()?;
+        let Some(NodeEnum::TransactionStmt(ref stmt)) = stmt.node else {
+            return None;
+        };
+        match stmt.kind() {
+            TransactionStmtKind::TransStmtSavepoint => Some("SAVEPOINT"),
+            TransactionStmtKind::TransStmtRelease => Some("RELEASE"),
+            TransactionStmtKind::TransStmtRollbackTo => Some("ROLLBACK TO"),
+            _ => None,
+        }
+    }
+
+    /// Route `SAVEPOINT`, `RELEASE` and `ROLLBACK TO` to the shard
+    /// the transaction is using. Savepoints can't be coordinated
+    /// across shards, so cross-shard transactions can't use them.
+    /// Before the first statement picks the shard, they wait for it
+    /// with the transaction's `BEGIN`.
+    fn savepoint(
+        &self,
+        statement: &'static str,
+        query: &BufferedQuery,
+        shards: usize,
+    ) -> Result<Command, Error> {
+        if !self.in_transaction {
+            return Err(RouteError::NoTransaction(statement).into());
+        }
+
+        match self.transaction_shard {
+            Some(Shard::Direct(shard)) => Ok(Command::Query(Route::write(Some(shard)))),
+            None if shards == 1 => Ok(Command::Query(Route::write(Some(0)))),
+            None if query.simple() => Ok(Command::Savepoint(query.clone())),
+            None => Err(RouteError::Unsupported(
+                "a savepoint before the first statement of a transaction",
+            )
+            .into()),
+            Some(_) => {
+                Err(RouteError::Unsupported("a savepoint in a cross-shard transaction").into())
+            }
+        }
+    }
+
+    /// Shard used for statements that need the same one every time:
+    /// UNLISTEN has to find the channels LISTEN subscribed to, and catalog
+    /// queries have to see the same OIDs.
+    fn fixed_shard(&self) -> usize {
+        self.allowed_shards
+            .as_ref()
+            .and_then(|allowed| allowed.first().copied())
+            .unwrap_or(0)
+    }
+
+    /// Keep track of statements created with `PREPARE`, so `EXECUTE` can be routed
+    /// using the sharding key in its arguments. `PREPARE` goes to all shards,
+    /// so the statement exists on whichever shard `EXECUTE` needs.
+    fn prepared(
+        &mut self,
+        ast: &pg_query::protobuf::ParseResult,
+        sharding_schema: &ShardingSchema,
+    ) -> Result<Shard, Error> {
+        let mut shard = Shard::All;
+
+        for stmt in &ast.stmts {
+            match stmt.stmt.as_ref().and_then(|stmt| stmt.node.as_ref()) {
+                Some(NodeEnum::PrepareStmt(stmt)) => {
+                    let statement = pg_query::protobuf::ParseResult {
+                        version: ast.version,
+                        stmts: vec![RawStmt {
+                            stmt: stmt.query.clone(),
+                            ..Default::default()
+                        }],
+                    };
+                    self.prepared.insert(
+                        stmt.name.clone(),
+                        BindResolver::new(&statement, sharding_schema),
+                    );
+                }
+
+                Some(NodeEnum::ExecuteStmt(stmt)) if ast.stmts.len() == 1 => {
+                    if let Some(Some(resolver)) = self.prepared.get(&stmt.name) {
+                        shard = resolver.execute(&stmt.params)?;
+                    }
+                }
+
+                Some(NodeEnum::DeallocateStmt(stmt)) => {
+                    if stmt.isall {
+                        self.prepared.clear();
+                    } else {
+                        self.prepared.remove(&stmt.name);
+                    }
+                }
+
+                _ => (),
+            }
+        }
+
+        Ok(shard)
+    }
+
+    /// FETCH, MOVE and CLOSE on a cursor buffered by the client,
+    /// and UPDATE or DELETE `WHERE CURRENT OF` a cursor.
+    fn cursor(&mut self, ast: &pg_query::protobuf::ParseResult) -> Result<Option<Command>, Error> {
+        if ast.stmts.len() != 1 {
+            return Ok(None);
+        }
+        let Some(node) = ast.stmts[0]
+            .stmt
+            .as_ref()
+            .and_then(|stmt| stmt.node.as_ref())
+        else {
+            return Ok(None);
+        };
+
+        match node {
+            NodeEnum::FetchStmt(stmt) if self.cursors.contains(&stmt.portalname) => {
+                Ok(Some(Command::Fetch(Fetch::from(stmt))))
+            }
+
+            NodeEnum::ClosePortalStmt(stmt) if self.cursors.remove(&stmt.portalname) => {
+                Ok(Some(Command::CloseCursor(stmt.portalname.clone())))
+            }
+
+            // CLOSE ALL. Other cursors are on the shards.
+            NodeEnum::ClosePortalStmt(stmt) if stmt.portalname.is_empty() => {
+                self.cursors.clear();
+                self.cursor_shards.clear();
+                Ok(None)
+            }
+
+            NodeEnum::ClosePortalStmt(stmt) => {
+                self.cursor_shards.remove(&stmt.portalname);
+                Ok(None)
+            }
+
+            NodeEnum::UpdateStmt(stmt) => self.current_of(&stmt.where_clause),
+            NodeEnum::DeleteStmt(stmt) => self.current_of(&stmt.where_clause),
+
+            _ => Ok(None),
+        }
+    }
+
+    /// UPDATE or DELETE `WHERE CURRENT OF` a cursor changes the row the cursor
+    /// is on, so it has to go to the shard the cursor was declared on.
+    ///
+    /// Cursors we didn't see declared, e.g. a `refcursor` opened by a function,
+    /// are on the shards of the transaction, if it picked them already.
+    fn current_of(&self, where_clause: &Option<Box<Node>>) -> Result<Option<Command>, Error> {
+        let Some(NodeEnum::CurrentOfExpr(ref expr)) =
+            where_clause.as_ref().and_then(|node| node.node.as_ref())
+        else {
+            return Ok(None);
+        };
+        let name = &expr.cursor_name;
+
+        if self.cursors.contains(name) {
+            return Err(Error::CurrentOfCrossShard(name.clone()));
+        }
+
+        match (self.cursor_shards.get(name), &self.transaction_shard) {
+            (Some(shard), _) => Ok(Some(Command::Query(Route::write(Some(*shard))))),
+            (None, Some(shard)) if self.in_transaction => {
+                Ok(Some(Command::Query(Route::write(shard.clone()))))
+            }
+            (None, None) if self.in_transaction => Ok(None),
+            (None, _) => Err(Error::UnknownCursor(name.clone())),
+        }
+    }
+
+    /// UPDATE and DELETE without a WHERE clause on a sharded table change every row
+    /// on all shards. That's almost always a mistake, so the client has to allow it first.
+    fn unfiltered_write(
+        &self,
+        statement: &'static str,
+        table: Option<&RangeVar>,
+        sharding_schema: &ShardingSchema,
+    ) -> Result<(), Error> {
+        let sharded = table.is_some_and(|table| Self::sharded(&table.relname, sharding_schema));
+        if sharding_schema.shards > 1 && sharded && !self.allow_unfiltered_writes {
+            Err(RouteError::MissingShardingKey(statement).into())
+        } else {
+            Ok(())
+        }
+    }
+
     /// Reset shard.
     pub fn reset(&mut self) {
         self.routed = false;
         self.in_transaction = false;
         self.command = Command::Query(Route::default());
         self.write_override = None;
+        self.local_route_hint = None;
+        self.cursors.clear();
+        self.cursor_shards.clear();
+        self.snapshot = false;
+        self.transaction_shard = None;
     }
 
     fn query(
@@ -132,11 +526,15 @@ impl QueryParser {
         params: &Parameters,
         in_transaction: bool,
     ) -> Result<Command, Error> {
+        self.role = None;
+        self.max_staleness = None;
+        self.analytics = false;
+
         // Replication protocol commands
         // don't have a node in pg_query,
         // so we have to parse them using a regex.
         if self.replication_mode {
-            if query.starts_with("START_REPLICATION") {
+            if query.trim_start().starts_with("START_REPLICATION") {
                 return Ok(Command::StartReplication);
             }
 
@@ -148,7 +546,9 @@ impl QueryParser {
         let shards = cluster.shards().len();
         let read_only = cluster.read_only();
         let write_only = cluster.write_only();
-        let full_prepared_statements = config().config.general.prepared_statements.full();
+        let config = config();
+        let full_prepared_statements = config.config.general.prepared_statements.full();
+        let redact = !config.config.general.log_route_values;
         let sharding_schema = cluster.sharding_schema();
         let dry_run = sharding_schema.tables.dry_run();
         let multi_tenant = cluster.multi_tenant();
@@ -187,6 +587,28 @@ impl QueryParser {
             }
         }
 
+        // Cursors buffered by the client don't exist on the shards,
+        // no matter where the transaction is going. The transaction is connected
+        // to its shards already, so statements after this one can be routed again.
+        if !self.cursors.is_empty() || !self.cursor_shards.is_empty() {
+            let ast = parse(query.query()).map_err(RouteError::parse_failed)?;
+            if let Some(command) = self.cursor(&ast.protobuf)? {
+                self.routed = false;
+                return Ok(command);
+            }
+        }
+
+        // Statements in a cross-shard transaction go to all its shards,
+        // but savepoints can't be coordinated across them.
+        if self.routed
+            && matches!(self.transaction_shard, Some(ref shard) if !matches!(shard, Shard::Direct(_)))
+        {
+            let ast = parse(query.query()).map_err(RouteError::parse_failed)?;
+            if let Some(statement) = Self::savepoint_statement(&ast.protobuf) {
+                return self.savepoint(statement, query, shards);
+            }
+        }
+
         // We already decided where all queries for this
         // transaction are going to go.
         if self.routed && multi_tenant.is_none() {
@@ -231,12 +653,28 @@ impl QueryParser {
             }
         }
 
+        // Point lookups, e.g. `SELECT * FROM users WHERE id = $1`, don't need
+        // the parser if they go to one shard, which is all we'd need it for.
+        if cluster.query_parser_fast_path()
+            && shards > 1
+            && !dry_run
+            && multi_tenant.is_none()
+            && self.write_override.is_none()
+            && !matches!(shard, Shard::Direct(_))
+        {
+            if let Some(command) = self.point_lookup(query.query(), cluster, params, bind)? {
+                return Ok(command);
+            }
+        }
+
         let cache = Cache::get();
 
         // Get the AST from cache or parse the statement live.
         let ast = match query {
             // Only prepared statements (or just extended) are cached.
-            BufferedQuery::Prepared(query) => cache.parse(query.query()).map_err(Error::PgQuery)?,
+            BufferedQuery::Prepared(query) => cache
+                .parse(query.query())
+                .map_err(RouteError::parse_failed)?,
             // Don't cache simple queries.
             //
             // They contain parameter values, which makes the cache
@@ -245,16 +683,27 @@ impl QueryParser {
             // Make your clients use prepared statements
             // or at least send statements with placeholders using the
             // extended protocol.
-            BufferedQuery::Query(query) => Arc::new(parse(query.query()).map_err(Error::PgQuery)?),
+            BufferedQuery::Query(query) => {
+                Arc::new(parse(query.query()).map_err(RouteError::parse_failed)?)
+            }
         };
 
-        debug!("{}", query.query());
-        trace!("{:#?}", ast);
+        if enabled!(Level::DEBUG) {
+            debug!("{}", RoutePlan::query(query.query(), redact));
+        }
+        if !redact {
+            trace!("{:#?}", ast);
+        }
 
         let rewrite = Rewrite::new(ast.clone());
         if rewrite.needs_rewrite() {
             debug!("rewrite needed");
-            return rewrite.rewrite(prepared_statements);
+            let shard = self.prepared(&ast.protobuf, &sharding_schema)?;
+            let mut command = rewrite.rewrite(prepared_statements)?;
+            if let Command::Rewrite(_, ref mut route) = command {
+                route.set_shard_raw_mut(shard);
+            }
+            return Ok(command);
         }
 
         if let Some(multi_tenant) = multi_tenant {
@@ -268,12 +717,17 @@ impl QueryParser {
             return Ok(self.command.clone());
         }
 
+        // Simple protocol queries can contain multiple statements.
+        // Unless a comment picked the shard, route each one separately.
+        if let BufferedQuery::Query(simple) = query {
+            if ast.protobuf.stmts.len() > 1 && shards > 1 && !matches!(shard, Shard::Direct(_)) {
+                return self.batch(simple.query(), &ast, cluster, params, in_transaction);
+            }
+        }
+
         //
         // Get the root AST node.
         //
-        // We don't expect clients to send multiple queries. If they do
-        // only the first one is used for routing.
-        //
         let root = ast
             .protobuf
             .stmts
@@ -283,6 +737,77 @@ impl QueryParser {
             .as_ref()
             .ok_or(Error::EmptyQuery)?;
 
+        // EXPLAIN is routed like the statement it's explaining.
+        // EXPLAIN ANALYZE executes it, so writes need to go to the primary.
+        let (root, explain) = match root.node {
+            Some(NodeEnum::ExplainStmt(ref stmt)) => {
+                (stmt.query.as_deref().ok_or(Error::EmptyQuery)?, true)
+            }
+            _ => (root.as_ref(), false),
+        };
+
+        // DECLARE CURSOR is routed like its query. FETCH and MOVE
+        // follow it because the transaction is already routed.
+        let (root, cursor) = match root.node {
+            Some(NodeEnum::DeclareCursorStmt(ref stmt)) => {
+                (stmt.query.as_deref().ok_or(Error::EmptyQuery)?, Some(stmt))
+            }
+            _ => (root, None),
+        };
+
+        // pg_query doesn't look for tables inside DECLARE CURSOR.
+        let tables = if cursor.is_some() {
+            pg_query::ParseResult::new(
+                pg_query::protobuf::ParseResult {
+                    version: ast.protobuf.version,
+                    stmts: vec![RawStmt {
+                        stmt: Some(Box::new(root.clone())),
+                        ..Default::default()
+                    }],
+                },
+                std::string::String::new(),
+            )
+            .tables()
+        } else {
+            ast.tables()
+        };
+
+        let qualified = tables
+            .iter()
+            .filter_map(|table| table.split_once('.'))
+            .collect::<Vec<_>>();
+        let sharding_schema = Self::visible_tables(sharding_schema, &qualified, cluster, params);
+
+        // The sharding key is a parameter the client didn't bind yet,
+        // e.g. Parse and Describe without Bind. We'll know the shard at Bind,
+        // so don't fail on cross-shard checks until then.
+        let deferred = if bind.is_none() && query.extended() && shards > 1 {
+            BindResolver::new(&ast.protobuf, &sharding_schema).map(|key| key.position())
+        } else {
+            None
+        };
+
+        // Primary or replica, picked with a comment. Writes can't go to a replica.
+        if query.query().contains("pgdog_role") {
+            self.role = super::comment::role(query.query())?;
+            if self.role == Some(Role::Replica) && Self::writes(root)? {
+                return Err(Error::WriteToReplica);
+            }
+        }
+
+        // Analytics replicas, picked with a comment. They only serve reads.
+        if query.query().contains("pgdog_pool") {
+            self.analytics = super::comment::analytics(query.query())?;
+            if self.analytics && Self::writes(root)? {
+                return Err(Error::WriteToAnalytics);
+            }
+        }
+
+        // Replicas further behind than this aren't used.
+        if query.query().contains("pgdog_max_staleness") {
+            self.max_staleness = super::comment::max_staleness(query.query())?;
+        }
+
         let mut command = match root.node {
             // SELECT statements.
             Some(NodeEnum::SelectStmt(ref stmt)) => {
@@ -298,18 +823,36 @@ impl QueryParser {
                     writes.writes = true;
                 }
 
+                // WITH HOLD cursors outlive the transaction,
+                // so the client has to keep its connection.
+                if cursor.is_some_and(|cursor| cursor.options & CURSOR_OPT_HOLD != 0) {
+                    writes.locking_behavior = LockingBehavior::Lock;
+                }
+
+                // `SELECT 1`, `SELECT $1`, etc.
+                let local = !explain && cursor.is_none() && Local::new(stmt).is_some();
+
+                if let (Shard::Direct(direct), Some(cursor)) = (&shard, cursor) {
+                    self.cursor_shards
+                        .insert(cursor.portalname.clone(), *direct);
+                }
+
                 if matches!(shard, Shard::Direct(_)) {
                     self.routed = true;
-                    return Ok(Command::Query(Route::read(shard).set_write(writes)));
+                    return Ok(Command::Query(
+                        Route::read(shard).set_write(writes).set_local(local),
+                    ));
                 }
                 // `SELECT NOW()`, `SELECT 1`, etc.
-                else if ast.tables().is_empty() {
+                else if tables.is_empty() {
                     self.routed = true;
                     return Ok(Command::Query(
-                        Route::read(Some(round_robin::next() % cluster.shards().len()))
-                            .set_write(writes),
+                        Route::read(Some(self.round_robin(cluster.shards().len())))
+                            .set_write(writes)
+                            .set_local(local),
                     ));
                 } else {
+                    let simple = query.simple();
                     let command = Self::select(stmt, &sharding_schema, bind)?;
                     let mut omni = false;
                     if let Command::Query(mut query) = command {
@@ -317,17 +860,92 @@ impl QueryParser {
                         // shard if the table(s) it's touching contain
                         // the same data on all shards.
                         if query.is_all_shards() {
-                            let tables = ast.tables();
                             omni = tables
                                 .iter()
                                 .all(|t| sharding_schema.tables.omnishards().contains(t));
                         }
 
                         if omni {
-                            query.set_shard_mut(round_robin::next() % cluster.shards().len());
+                            query.set_shard_mut(self.round_robin(cluster.shards().len()));
+                        } else if query.is_all_shards() && tables.iter().all(|t| Self::catalog(t)) {
+                            // Replicas could be behind on DDL the client just ran.
+                            query.set_shard_mut(self.fixed_shard());
+                            writes.writes = true;
+                        }
+
+                        let cross_shard = query.is_cross_shard() && deferred.is_none();
+
+                        // Window functions see only the rows on their shard,
+                        // so `row_number() OVER (ORDER BY id)` would restart on every shard.
+                        if cross_shard && Self::windowed(root) {
+                            return Err(Error::CrossShardWindow);
+                        }
+
+                        // Percentiles of each shard's rows can't be combined into the
+                        // percentile of all rows, so shards send us every input value instead.
+                        // Only simple queries returning nothing but these aggregates are rewritten.
+                        if cross_shard && Self::ordered_set(root) {
+                            if query.aggregate().ordered_sets().is_empty()
+                                || !simple
+                                || explain
+                                || cursor.is_some()
+                            {
+                                return Err(Error::CrossShardOrderedSet);
+                            }
+                            let inputs = Node {
+                                node: Some(NodeEnum::SelectStmt(Box::new(
+                                    Aggregate::ordered_set_inputs(stmt),
+                                ))),
+                            }
+                            .deparse()
+                            .map_err(Error::PgQuery)?;
+                            Self::rewrite_all(&mut query, inputs, cluster.shards().len());
+                        }
+
+                        // Shards send us the rows of both sides of INTERSECT and EXCEPT,
+                        // tagged with their side, so we can match them with rows from other shards.
+                        // Only simple queries are rewritten.
+                        if cross_shard && query.set_operator().is_some() {
+                            let sides = SetOperator::sides(stmt)?
+                                .filter(|_| simple && !explain && cursor.is_none())
+                                .ok_or(Error::CrossShardSetOperation)?;
+                            Self::rewrite_all(&mut query, sides, cluster.shards().len());
+                        }
+
+                        // Aggregates without GROUP BY return one row, which has no order.
+                        let one_row = !query.aggregate().is_empty() && stmt.group_clause.is_empty();
+                        if cluster.require_order_by()
+                            && cross_shard
+                            && query.is_unordered()
+                            && !one_row
+                        {
+                            return Err(Error::CrossShardUnordered);
+                        }
+
+                        let query = query.set_write(writes);
+
+                        // Rows fetched from cursors on different shards can't be merged,
+                        // so we run the cursor's query instead and the client serves
+                        // FETCH from the merged result. That doesn't work for cursors
+                        // outliving the transaction or declared with the extended protocol.
+                        if let Some(cursor) = cursor.filter(|_| cross_shard) {
+                            if cursor.options & CURSOR_OPT_HOLD != 0 || !simple {
+                                return Err(Error::CrossShardCursor);
+                            }
+                            self.cursors.insert(cursor.portalname.clone());
+
+                            return Ok(Command::DeclareCursor {
+                                name: cursor.portalname.clone(),
+                                query: root.deparse().map_err(|_| Error::EmptyQuery)?,
+                                route: query,
+                            });
                         }
 
-                        Ok(Command::Query(query.set_write(writes)))
+                        if let (Shard::Direct(shard), Some(cursor)) = (query.shard(), cursor) {
+                            self.cursor_shards.insert(cursor.portalname.clone(), *shard);
+                        }
+
+                        Ok(Command::Query(query))
                     } else {
                         Ok(command)
                     }
@@ -344,16 +962,52 @@ impl QueryParser {
                 return Ok(Command::Deallocate);
             }
             // COPY statements.
-            Some(NodeEnum::CopyStmt(ref stmt)) => Self::copy(stmt, cluster),
+            Some(NodeEnum::CopyStmt(ref stmt)) => Self::copy(stmt, cluster, bind),
             // INSERT statements.
             Some(NodeEnum::InsertStmt(ref stmt)) => Self::insert(stmt, &sharding_schema, bind),
             // UPDATE statements.
-            Some(NodeEnum::UpdateStmt(ref stmt)) => Self::update(stmt, &sharding_schema, bind),
+            Some(NodeEnum::UpdateStmt(ref stmt)) => {
+                if stmt.where_clause.is_none() {
+                    self.unfiltered_write("UPDATE", stmt.relation.as_ref(), &sharding_schema)?;
+                }
+                if let Some(command) = self.current_of(&stmt.where_clause)? {
+                    return Ok(command);
+                }
+                Self::update(stmt, &sharding_schema, bind)
+            }
             // DELETE statements.
-            Some(NodeEnum::DeleteStmt(ref stmt)) => Self::delete(stmt, &sharding_schema, bind),
+            Some(NodeEnum::DeleteStmt(ref stmt)) => {
+                if stmt.where_clause.is_none() {
+                    self.unfiltered_write("DELETE", stmt.relation.as_ref(), &sharding_schema)?;
+                }
+                if let Some(command) = self.current_of(&stmt.where_clause)? {
+                    return Ok(command);
+                }
+                Self::delete(stmt, &sharding_schema, bind)
+            }
+            // LISTEN needs to keep its server connection, so the client
+            // receives notifications. NOTIFY goes to all shards, so a listener
+            // on any one of them gets it exactly once.
+            Some(NodeEnum::ListenStmt(_)) => Ok(Command::Query(
+                Route::write(Some(self.fixed_shard())).set_lock_session(),
+            )),
+            Some(NodeEnum::UnlistenStmt(_)) => {
+                Ok(Command::Query(Route::write(Some(self.fixed_shard()))))
+            }
+            Some(NodeEnum::NotifyStmt(_)) => Ok(Command::Query(Route::write(Shard::All))),
+            // TRUNCATE empties the tables on all shards. It locks them until
+            // the transaction ends, so keep the server connections until then.
+            Some(NodeEnum::TruncateStmt(_)) => Ok(Command::Query(
+                Route::write(Shard::All).set_lock_session().set_two_pc(true),
+            )),
             // Transaction control statements,
             // e.g. BEGIN, COMMIT, etc.
             Some(NodeEnum::TransactionStmt(ref stmt)) => {
+                // Savepoints only exist on the servers of the transaction.
+                if let Some(statement) = Self::savepoint_statement(&ast.protobuf) {
+                    return self.savepoint(statement, query, shards);
+                }
+
                 // Only allow to intercept transaction statements
                 // if they are using the simple protocol.
                 if query.simple() {
@@ -371,6 +1025,7 @@ impl QueryParser {
                         TransactionStmtKind::TransStmtBegin
                         | TransactionStmtKind::TransStmtStart => {
                             self.in_transaction = true;
+                            self.snapshot = Self::read_only_snapshot(stmt);
                             return Ok(Command::StartTransaction(query.clone()));
                         }
                         _ => Ok(Command::Query(Route::write(None))),
@@ -379,12 +1034,74 @@ impl QueryParser {
                     Ok(Command::Query(Route::write(None)))
                 }
             }
+            // Maintenance runs on every shard, since each one has its own copy of the
+            // table. Some commands can't run in a transaction, so we say so instead of
+            // sending them to every shard only to fail.
+            Some(NodeEnum::VacuumStmt(_))
+            | Some(NodeEnum::ClusterStmt(_))
+            | Some(NodeEnum::ReindexStmt(_)) => {
+                if self.in_transaction {
+                    if let Some(command) = Self::non_transactional(root) {
+                        return Err(Error::MaintenanceInTransaction(command));
+                    }
+                }
+                Ok(Command::Query(Route::write(Shard::All)))
+            }
+            // ALTER SYSTEM changes postgresql.auto.conf of the server it runs on.
+            // Replicas and other shards wouldn't get it, so it has to be run
+            // on each server directly.
+            Some(NodeEnum::AlterSystemStmt(_)) => {
+                Err(RouteError::Unsupported("ALTER SYSTEM").into())
+            }
             // All others are not handled.
             // They are sent to all shards concurrently.
             _ => Ok(Command::Query(Route::write(None))),
         }?;
 
-        self.routed = true;
+        // The sharding key is an empty list, so no rows match and the client
+        // answers with an empty result, without querying a shard. If it can't,
+        // any shard returns the same result. Inside a transaction, the first
+        // statement picks the shards for the rest of it, so we pick one now.
+        if let Command::Query(ref mut route) = command {
+            if route.shard().is_empty()
+                && (self.in_transaction || NoRows::new(&ast.protobuf).is_none())
+            {
+                route.set_shard_mut(self.round_robin(cluster.shards().len()));
+            }
+        }
+
+        let deferred = match command {
+            Command::Query(ref mut route) if route.is_cross_shard() => {
+                route.set_deferred_mut(deferred);
+                deferred.is_some()
+            }
+            _ => false,
+        };
+
+        // Route the statement again when the client binds the parameters,
+        // even inside a transaction.
+        self.routed = !deferred;
+
+        // Custom reducer for cross-shard results, picked with a comment.
+        if query.query().contains("pgdog_reducer") {
+            if let Command::Query(ref mut route) = command {
+                if route.is_cross_shard() {
+                    if let Some(name) = super::comment::reducer(query.query())? {
+                        if reducer::get(&name).is_none() {
+                            return Err(Error::UnknownReducer(name));
+                        }
+                        route.set_reducer_mut(Some(name));
+                    }
+                }
+            }
+        }
+
+        // The plan isn't the query's result, so don't try to merge it.
+        if explain {
+            if let Command::Query(ref mut route) = command {
+                route.set_explain_mut();
+            }
+        }
 
         // Overwrite shard using shard we got from a comment, if any.
         if let Shard::Direct(shard) = shard {
@@ -422,13 +1139,17 @@ impl QueryParser {
 
                     // TODO: check routing logic required by config.
                     if manual_route.is_some() {
-                        route.set_shard_mut(round_robin::next() % cluster.shards().len());
+                        route.set_shard_mut(self.round_robin(cluster.shards().len()));
                     }
                 }
             }
         }
 
-        debug!("query router decision: {:#?}", command);
+        // Rewrites and per-shard queries contain values. The route plan
+        // logged once the route is final explains it without them.
+        if !redact {
+            debug!("query router decision: {:#?}", command);
+        }
 
         if dry_run {
             let default_route = Route::write(None);
@@ -491,6 +1212,107 @@ impl QueryParser {
                 }
             }
 
+            "pgdog.shards" => {
+                if stmt.kind() == VariableSetKind::VarReset {
+                    self.allowed_shards = None;
+                } else {
+                    let mut shards = vec![];
+
+                    for node in &stmt.args {
+                        match node.node {
+                            Some(NodeEnum::AConst(AConst {
+                                val: Some(Val::Ival(Integer { ival })),
+                                ..
+                            })) => shards.push(ival as usize),
+
+                            Some(NodeEnum::AConst(AConst {
+                                val: Some(Val::Sval(String { ref sval })),
+                                ..
+                            })) => {
+                                for shard in sval.split(",") {
+                                    shards.push(
+                                        shard
+                                            .trim()
+                                            .parse::<usize>()
+                                            .map_err(|_| Error::SetShard)?,
+                                    );
+                                }
+                            }
+
+                            _ => return Err(Error::SetShard),
+                        }
+                    }
+
+                    if shards.is_empty() || shards.iter().any(|s| *s >= sharding_schema.shards) {
+                        return Err(Error::SetShard);
+                    }
+
+                    shards.sort();
+                    shards.dedup();
+
+                    self.allowed_shards = Some(shards);
+                }
+            }
+
+            "pgdog.allow_unfiltered_writes" => {
+                self.allow_unfiltered_writes = if stmt.kind() == VariableSetKind::VarReset {
+                    false
+                } else {
+                    let value = match stmt.args.first().and_then(|node| node.node.as_ref()) {
+                        Some(NodeEnum::AConst(AConst {
+                            val: Some(Val::Sval(String { sval })),
+                            ..
+                        })) => sval.to_lowercase(),
+                        Some(NodeEnum::AConst(AConst {
+                            val: Some(Val::Ival(Integer { ival })),
+                            ..
+                        })) => ival.to_string(),
+                        _ => return Err(Error::SetUnfilteredWrites),
+                    };
+                    match value.as_str() {
+                        "on" | "true" | "1" => true,
+                        "off" | "false" | "0" => false,
+                        _ => return Err(Error::SetUnfilteredWrites),
+                    }
+                };
+            }
+
+            "pgdog.route" => {
+                if stmt.kind() == VariableSetKind::VarReset {
+                    self.route_hint = None;
+                    self.local_route_hint = None;
+                } else {
+                    let hint = match stmt.args.first().and_then(|node| node.node.as_ref()) {
+                        Some(NodeEnum::AConst(AConst {
+                            val: Some(Val::Sval(String { sval })),
+                            ..
+                        })) => sval.to_string(),
+                        Some(NodeEnum::AConst(AConst {
+                            val: Some(Val::Ival(Integer { ival })),
+                            ..
+                        })) => ival.to_string(),
+                        _ => std::string::String::new(),
+                    }
+                    .parse::<RouteHint>()?;
+                    // Check it now, so the session isn't stuck with a hint
+                    // that fails every query.
+                    if let RouteHint::Shard(shard) = hint {
+                        if shard >= sharding_schema.shards {
+                            return Err(RouteError::ShardOutOfRange(
+                                Shard::Direct(shard),
+                                sharding_schema.shards,
+                            )
+                            .into());
+                        }
+                    }
+                    if stmt.is_local {
+                        self.local_route_hint = Some(hint);
+                    } else {
+                        self.route_hint = Some(hint);
+                    }
+                }
+            }
+
             "pgdog.sharding_key" => {
                 let node = stmt
                     .args
@@ -566,22 +1388,110 @@ impl QueryParser {
         Ok(Command::Query(Route::write(Shard::All).set_read(read_only)))
     }
 
+    /// Values of a composite sharding key, in column order, if the query
+    /// filters on exactly one value for each of its columns.
+    fn composite_key(
+        table: &ShardedTable,
+        where_clause: &WhereClause,
+        params: Option<&Bind>,
+    ) -> Result<Option<Vec<std::string::String>>, Error> {
+        let mut values = vec![];
+        for column in &table.composite {
+            let value = match where_clause.keys(table.name.as_deref(), column).as_slice() {
+                [Key::Constant {
+                    value,
+                    array: false,
+                }] => Some(value.clone()),
+                [Key::Parameter { pos, array: false }] => match params {
+                    Some(params) => composite::parameter(&key::parameter(params, *pos)?),
+                    None => None,
+                },
+                _ => None,
+            };
+            let Some(value) = value else {
+                return Ok(None);
+            };
+            values.push(value);
+        }
+
+        Ok(Some(values))
+    }
+
     fn where_clause(
         sharding_schema: &ShardingSchema,
         where_clause: &WhereClause,
         params: Option<&Bind>,
-    ) -> Result<HashSet<Shard>, Error> {
-        let mut shards = HashSet::new();
+    ) -> Result<(HashSet<Shard>, Option<ShardingKey>), Error> {
+        // Shards of each sharding key the query filters on.
+        let mut keyed_shards = vec![];
         // Complexity: O(number of sharded tables * number of columns in the query)
         for table in sharding_schema.tables().tables() {
             let table_name = table.name.as_deref();
-            let keys = where_clause.keys(table_name, &table.column);
-            for key in keys {
-                match key {
+            if !table.composite.is_empty() {
+                if let Some(values) = Self::composite_key(table, where_clause, params)? {
+                    let shard = composite::shard(table, &values, sharding_schema.shards)?;
+                    keyed_shards.push((
+                        Some((table.composite.join(", "), vec![])),
+                        HashSet::from([shard]),
+                    ));
+                }
+                continue;
+            }
+            let mut shards = HashSet::new();
+            let mut unsure = false;
+            // `COALESCE(column, default) = value` matches rows on the value's shard
+            // and rows with a NULL key, which we can only find if they're stored
+            // on the default's shard.
+            let keys = where_clause
+                .keys(table_name, &table.column)
+                .into_iter()
+                .flat_map(|key| match key {
+                    Key::Coalesce { value, default } if table.coalesce_default => {
+                        vec![*value, *default]
+                    }
+                    Key::Coalesce { .. } => {
+                        unsure = true;
+                        vec![]
+                    }
+                    // `$1 = ANY(tenant_ids)` matches rows on the shard of `$1`
+                    // if all keys in a row belong to its shard.
+                    Key::Element(key) if table.array => vec![*key],
+                    Key::Element(_) => vec![],
+                    // The whole array isn't a key.
+                    _ if table.array => vec![],
+                    key => vec![key],
+                })
+                .collect::<Vec<_>>();
+            if unsure {
+                shards.insert(Shard::All);
+            }
+            // The same parameter can appear more than once,
+            // e.g. `id = $1::int AND id = $1::bigint`, only resolve it once.
+            let mut resolved = HashSet::new();
+            let mut keyed = false;
+            for key in keys.iter().cloned() {
+                match key {
                     Key::Constant { value, array } => {
+                        // `id = ANY('{1,2}')` goes to the shards of its elements.
+                        // Multidimensional arrays aren't split.
                         if array {
-                            shards.insert(Shard::All);
-                            break;
+                            let Some(elements) = array::elements(&value) else {
+                                shards.insert(Shard::All);
+                                break;
+                            };
+                            // `id = ANY('{}')` matches no rows.
+                            if elements.is_empty() {
+                                shards.insert(Shard::Multi(vec![]));
+                            }
+                            for element in elements {
+                                let ctx = ContextBuilder::new(table)
+                                    .data(element.as_str())
+                                    .shards(sharding_schema.shards)
+                                    .build()?;
+                                shards.insert(ctx.apply()?);
+                            }
+                            keyed = true;
+                            continue;
                         }
 
                         let ctx = ContextBuilder::new(table)
@@ -589,33 +1499,219 @@ impl QueryParser {
                             .shards(sharding_schema.shards)
                             .build()?;
                         shards.insert(ctx.apply()?);
+                        keyed = true;
                     }
 
                     Key::Parameter { pos, array } => {
                         // Don't hash individual values yet.
                         // The odds are high this will go to all shards anyway.
                         if array {
+                            // Drivers send empty collections as empty arrays,
+                            // e.g. `id = ANY($1)` with `{}`.
+                            let empty = match params {
+                                Some(params) => key::parameter(params, pos)?.empty_array(),
+                                None => false,
+                            };
+                            if empty {
+                                shards.insert(Shard::Multi(vec![]));
+                                keyed = true;
+                                continue;
+                            }
                             shards.insert(Shard::All);
                             break;
+                        } else if !resolved.insert(pos) {
+                            continue;
                         } else if let Some(params) = params {
-                            if let Some(param) = params.parameter(pos)? {
-                                let value = ShardingValue::from_param(&param, table.data_type)?;
-                                let ctx = ContextBuilder::new(table)
-                                    .value(value)
-                                    .shards(sharding_schema.shards)
-                                    .build()?;
-                                shards.insert(ctx.apply()?);
-                            }
+                            let param = key::parameter(params, pos)?;
+                            let value = ShardingValue::from_param(&param, table.data_type)?;
+                            let ctx = ContextBuilder::new(table)
+                                .value(value)
+                                .shards(sharding_schema.shards)
+                                .build()?;
+                            shards.insert(ctx.apply()?);
+                            keyed = true;
                         }
                     }
 
                     // Null doesn't help.
-                    Key::Null => (),
+                    Key::Null | Key::Coalesce { .. } | Key::Element(_) => (),
+                }
+            }
+
+            if !shards.is_empty() {
+                keyed_shards.push((keyed.then(|| (table.column.clone(), keys)), shards));
+            }
+        }
+
+        let (mut shards, mut column) = Self::multi_key(sharding_schema, keyed_shards)?;
+
+        // `tenant_id = $1 OR customer_id = $2` goes to the shards of both branches.
+        // Branches can use different sharding columns. If one of them doesn't filter
+        // on a sharding key, rows can be anywhere.
+        if shards.is_empty() {
+            for branches in where_clause.disjunctions() {
+                let mut union = HashSet::new();
+                let mut columns = HashSet::new();
+                let mut keys = vec![];
+                let mut keyed = true;
+
+                for branch in &branches {
+                    let (branch_shards, branch_key) =
+                        Self::where_clause(sharding_schema, branch, params)?;
+                    if branch_shards.is_empty() {
+                        keyed = false;
+                        break;
+                    }
+                    union.extend(branch_shards);
+                    let (branch_column, branch_keys) = branch_key.unzip();
+                    columns.insert(branch_column);
+                    keys.extend(branch_keys.unwrap_or_default());
+                }
+
+                if keyed {
+                    shards = union;
+                    column = if columns.len() == 1 {
+                        columns
+                            .into_iter()
+                            .next()
+                            .flatten()
+                            .map(|column| (column, keys))
+                    } else {
+                        None
+                    };
+                    break;
+                }
+            }
+        }
+
+        Ok((shards, column))
+    }
+
+    /// Shards of a query filtering on one or more sharding keys, and the key used.
+    ///
+    /// The filters are all required, so the query goes to the shards
+    /// the keys have in common. If they have none, e.g. `id = 1 AND tenant_id = 2`
+    /// with each on a different shard, `multi_key_policy` decides.
+    fn multi_key(
+        sharding_schema: &ShardingSchema,
+        keyed_shards: Vec<(Option<ShardingKey>, HashSet<Shard>)>,
+    ) -> Result<(HashSet<Shard>, Option<ShardingKey>), Error> {
+        let column = keyed_shards.iter().find_map(|(column, _)| column.clone());
+        let mut keys = keyed_shards.into_iter();
+        let Some((first_column, shards)) = keys.next() else {
+            return Ok((HashSet::new(), None));
+        };
+
+        let mut shard = Self::converge(shards.clone());
+        let mut same = true;
+        for (other_column, other_shards) in keys {
+            let other = Self::converge(other_shards);
+            if other == shard {
+                continue;
+            }
+            same = false;
+
+            if shard.is_empty() || other.is_empty() {
+                shard = Shard::Multi(vec![]);
+                continue;
+            }
+
+            shard = match shard.intersect(&other) {
+                Some(common) => common,
+                None => {
+                    return match sharding_schema.multi_key_policy {
+                        // No shard has rows matching all the keys.
+                        MultiKeyPolicy::Intersect => {
+                            Ok((HashSet::from([Shard::Multi(vec![])]), column))
+                        }
+                        MultiKeyPolicy::Error => Err(Error::ShardingKeyConflict(
+                            first_column.map(|(column, _)| column).unwrap_or_default(),
+                            other_column.map(|(column, _)| column).unwrap_or_default(),
+                        )),
+                        MultiKeyPolicy::Fanout => Ok((HashSet::from([Shard::All]), column)),
+                    };
                 }
+            };
+        }
+
+        if same {
+            Ok((shards, column))
+        } else {
+            Ok((HashSet::from([shard]), column))
+        }
+    }
+
+    /// Route a multi-statement query one statement at a time.
+    ///
+    /// If all statements go to the same shard(s), the query is sent as-is.
+    /// Otherwise, each statement is executed separately on its own shard(s).
+    fn batch(
+        &mut self,
+        query: &str,
+        ast: &pg_query::ParseResult,
+        cluster: &Cluster,
+        params: &Parameters,
+        in_transaction: bool,
+    ) -> Result<Command, Error> {
+        let mut statements = vec![];
+        // Transaction control, SET, etc.
+        let mut other = false;
+
+        for statement in batch::split(query, &ast.protobuf) {
+            let mut parser = QueryParser {
+                allowed_shards: self.allowed_shards.clone(),
+                write_override: self.write_override,
+                ..Default::default()
+            };
+            let command = parser.query(
+                &BufferedQuery::Query(Query::new(statement)),
+                cluster,
+                None,
+                &mut PreparedStatements::default(),
+                params,
+                in_transaction,
+            )?;
+
+            match command {
+                Command::Query(route) => statements.push(Statement::new(statement, route)),
+                _ => other = true,
+            }
+        }
+
+        let shards = statements
+            .iter()
+            .map(|statement| statement.route().shard().clone())
+            .collect::<HashSet<_>>();
+
+        // Each statement gets its own server connection, which only works
+        // outside of a transaction and in transaction mode. Statements can't be
+        // separated from transaction control around them either.
+        let separate =
+            !other && !in_transaction && cluster.pooler_mode() == PoolerMode::Transaction;
+
+        if shards.len() > 1 {
+            if separate {
+                return Ok(Command::Batch(statements));
             }
+            // Sending the whole query to all the shards involved
+            // would execute every statement on each of them.
+            return Err(Error::CrossShardBatch);
         }
 
-        Ok(shards)
+        let shard = Self::converge(shards);
+        let read = statements
+            .iter()
+            .all(|statement| statement.route().is_read());
+        let lock_session = statements
+            .iter()
+            .any(|statement| statement.route().lock_session());
+
+        let mut route = Route::write(shard).set_read(read && !statements.is_empty());
+        if lock_session {
+            route = route.set_lock_session();
+        }
+
+        Ok(Command::Query(route))
     }
 
     fn converge(shards: HashSet<Shard>) -> Shard {
@@ -644,6 +1740,165 @@ impl QueryParser {
         shard
     }
 
+    /// Send the same query to all shards of the route instead of the client's.
+    fn rewrite_all(route: &mut Route, query: std::string::String, shards: usize) {
+        let shards = match route.shard() {
+            Shard::Multi(multi) => multi.clone(),
+            _ => (0..shards).collect(),
+        };
+        route.set_shard_queries_mut(
+            shards
+                .into_iter()
+                .map(|shard| (shard, query.clone()))
+                .collect(),
+        );
+    }
+
+    /// Statement calls a window function, e.g. `rank() OVER (ORDER BY score)`.
+    fn windowed(root: &Node) -> bool {
+        root.node.as_ref().is_some_and(|node| {
+            node.nodes().iter().any(|(node, _, _, _)| match node {
+                NodeRef::FuncCall(func) => func.over.is_some(),
+                NodeRef::SelectStmt(stmt) => !stmt.window_clause.is_empty(),
+                _ => false,
+            })
+        })
+    }
+
+    /// Statement calls an ordered-set aggregate,
+    /// e.g. `percentile_cont(0.5) WITHIN GROUP (ORDER BY score)`.
+    fn ordered_set(root: &Node) -> bool {
+        root.node.as_ref().is_some_and(|node| {
+            node.nodes().iter().any(|(node, _, _, _)| match node {
+                NodeRef::FuncCall(func) => func.agg_within_group,
+                _ => false,
+            })
+        })
+    }
+
+    /// The statement changes data or locks rows, regardless of
+    /// where the read/write strategy would send it.
+    fn writes(root: &Node) -> Result<bool, Error> {
+        match root.node {
+            Some(NodeEnum::SelectStmt(ref stmt)) => {
+                Ok(Self::cte_writes(stmt) || Self::functions(stmt)?.writes)
+            }
+            Some(NodeEnum::VariableShowStmt(_)) => Ok(false),
+            _ => Ok(true),
+        }
+    }
+
+    /// The query is a `SELECT`, `INSERT`, `UPDATE`, `DELETE` or `MERGE`,
+    /// which only need the shards with their rows.
+    fn reads_rows(query: &str) -> Result<bool, Error> {
+        let ast = parse(query).map_err(RouteError::parse_failed)?;
+        Ok(ast.protobuf.stmts.iter().any(|stmt| {
+            matches!(
+                stmt.stmt.as_deref().and_then(|root| root.node.as_ref()),
+                Some(
+                    NodeEnum::SelectStmt(_)
+                        | NodeEnum::InsertStmt(_)
+                        | NodeEnum::UpdateStmt(_)
+                        | NodeEnum::DeleteStmt(_)
+                        | NodeEnum::MergeStmt(_)
+                )
+            )
+        }))
+    }
+
+    /// The query changes data, so it can't be sent to a replica. Transaction control,
+    /// `SET`, etc. are fine. Only queries routed as writes are parsed again to check.
+    fn modifies(route: &Route, query: &str) -> Result<bool, Error> {
+        if route.is_read() {
+            return Ok(false);
+        }
+
+        let ast = parse(query).map_err(RouteError::parse_failed)?;
+        for root in ast
+            .protobuf
+            .stmts
+            .iter()
+            .filter_map(|stmt| stmt.stmt.as_deref())
+        {
+            let modifies = match root.node {
+                Some(
+                    NodeEnum::InsertStmt(_)
+                    | NodeEnum::UpdateStmt(_)
+                    | NodeEnum::DeleteStmt(_)
+                    | NodeEnum::MergeStmt(_),
+                ) => true,
+                Some(NodeEnum::SelectStmt(ref stmt)) => {
+                    !stmt.locking_clause.is_empty() || Self::writes(root)?
+                }
+                _ => false,
+            };
+            if modifies {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// The transaction uses one snapshot for all its statements and doesn't write,
+    /// e.g. `BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY`.
+    fn read_only_snapshot(stmt: &TransactionStmt) -> bool {
+        let mut snapshot = false;
+        let mut read_only = false;
+
+        for option in &stmt.options {
+            let Some(NodeEnum::DefElem(ref elem)) = option.node else {
+                continue;
+            };
+            let value = elem
+                .arg
+                .as_ref()
+                .and_then(|arg| arg.node.as_ref())
+                .and_then(|node| match node {
+                    NodeEnum::AConst(AConst { val: Some(val), .. }) => Some(val),
+                    _ => None,
+                });
+            match (elem.defname.as_str(), value) {
+                ("transaction_isolation", Some(Val::Sval(level))) => {
+                    snapshot = matches!(level.sval.as_str(), "repeatable read" | "serializable");
+                }
+                ("transaction_read_only", Some(Val::Ival(ival))) => read_only = ival.ival != 0,
+                ("transaction_read_only", Some(Val::Boolval(boolval))) => {
+                    read_only = boolval.boolval
+                }
+                _ => (),
+            }
+        }
+
+        snapshot && read_only
+    }
+
+    /// Maintenance command that can't run inside a transaction block:
+    /// VACUUM, CLUSTER of all tables and REINDEX of more than one table or CONCURRENTLY.
+    fn non_transactional(root: &Node) -> Option<&'static str> {
+        match root.node {
+            Some(NodeEnum::VacuumStmt(ref stmt)) if stmt.is_vacuumcmd => Some("VACUUM"),
+            Some(NodeEnum::ClusterStmt(ref stmt)) if stmt.relation.is_none() => {
+                Some("CLUSTER without a table")
+            }
+            Some(NodeEnum::ReindexStmt(ref stmt)) => {
+                let concurrently = stmt.params.iter().any(|param| {
+                    matches!(param.node, Some(NodeEnum::DefElem(ref elem)) if elem.defname == "concurrently")
+                });
+                match stmt.kind() {
+                    ReindexObjectType::ReindexObjectSchema
+                    | ReindexObjectType::ReindexObjectSystem
+                    | ReindexObjectType::ReindexObjectDatabase => {
+                        Some("REINDEX SCHEMA, SYSTEM or DATABASE")
+                    }
+                    _ if concurrently => Some("REINDEX CONCURRENTLY"),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
     fn cte_writes(stmt: &SelectStmt) -> bool {
         if let Some(ref with_clause) = stmt.with_clause {
             for cte in &with_clause.ctes {
@@ -673,17 +1928,94 @@ impl QueryParser {
     }
 
     fn functions(stmt: &SelectStmt) -> Result<FunctionBehavior, Error> {
+        // `FOR UPDATE`, `FOR NO KEY UPDATE`, `FOR SHARE` and `FOR KEY SHARE`
+        // all lock rows, so they need the primary. The clause and its
+        // wait policy, e.g. `SKIP LOCKED`, are sent to the shard(s) as part of the query.
+        let locks_rows = stmt.locking_clause.iter().any(|clause| {
+            matches!(
+                clause.node,
+                Some(NodeEnum::LockingClause(ref clause)) if matches!(
+                    clause.strength(),
+                    LockClauseStrength::LcsForupdate
+                        | LockClauseStrength::LcsFornokeyupdate
+                        | LockClauseStrength::LcsForshare
+                        | LockClauseStrength::LcsForkeyshare
+                )
+            )
+        });
+
+        if locks_rows {
+            return Ok(FunctionBehavior {
+                writes: true,
+                locking_behavior: LockingBehavior::Lock,
+            });
+        }
+
+        let mut behavior = FunctionBehavior::default();
+
         for target in &stmt.target_list {
             if let Ok(func) = Function::try_from(target) {
-                return Ok(func.behavior());
+                let function = func.behavior();
+                behavior.writes |= function.writes;
+                if behavior.locking_behavior == LockingBehavior::None {
+                    behavior.locking_behavior = function.locking_behavior;
+                }
             }
         }
 
-        Ok(if stmt.locking_clause.is_empty() {
-            FunctionBehavior::default()
+        Ok(behavior)
+    }
+
+    /// Sharded tables can be in a specific schema. Schema-qualified tables
+    /// are looked up in their schema, others using the client's search_path.
+    fn visible_tables(
+        sharding_schema: ShardingSchema,
+        qualified: &[(&str, &str)],
+        cluster: &Cluster,
+        params: &Parameters,
+    ) -> ShardingSchema {
+        if sharding_schema.shards > 1 && sharding_schema.tables.has_schemas() {
+            let schema = cluster.schema();
+            let search_path = SearchPath::new(cluster.user(), params, &schema);
+            let mut schemas = search_path.resolve();
+            if schemas.is_empty() {
+                schemas = vec![cluster.user(), "public"];
+            }
+            sharding_schema.resolve(qualified, &schemas)
         } else {
-            FunctionBehavior::writes_only()
-        })
+            sharding_schema
+        }
+    }
+
+    /// Route a point lookup recognized without parsing it. Returns `None` if the
+    /// query isn't one or doesn't go to exactly one shard, so the parser takes it.
+    fn point_lookup(
+        &mut self,
+        query: &str,
+        cluster: &Cluster,
+        params: &Parameters,
+        bind: Option<&Bind>,
+    ) -> Result<Option<Command>, Error> {
+        let Some(lookup) = PointLookup::new(query) else {
+            return Ok(None);
+        };
+
+        // Point lookups don't qualify the table with a schema.
+        let sharding_schema = Self::visible_tables(cluster.sharding_schema(), &[], cluster, params);
+        let where_clause = WhereClause::point_lookup(&lookup);
+        let (shards, column) = Self::where_clause(&sharding_schema, &where_clause, bind)?;
+        let shard = Self::converge(shards);
+        if !matches!(shard, Shard::Direct(_)) {
+            return Ok(None);
+        }
+
+        self.routed = true;
+
+        Ok(Some(Command::Query(
+            Route::select(shard, vec![], Aggregate::none(), Limit::default(), None)
+                .set_sharding_key(column)
+                .set_write(FunctionBehavior::default()),
+        )))
     }
 
     fn select(
@@ -691,13 +2023,82 @@ impl QueryParser {
         sharding_schema: &ShardingSchema,
         params: Option<&Bind>,
     ) -> Result<Command, Error> {
-        let order_by = Self::select_sort(&stmt.sort_clause, params);
+        let order_by = Self::select_sort(&stmt.sort_clause, &stmt.target_list, params);
         let mut shards = HashSet::new();
+
+        // UNION, INTERSECT and EXCEPT. Each side is routed on its own,
+        // and the query goes to all the shards they need.
+        if stmt.op() != SetOperation::SetopNone {
+            for arm in [&stmt.larg, &stmt.rarg].into_iter().flatten() {
+                if let Command::Query(route) = Self::select(arm, sharding_schema, params)? {
+                    // We only compute the set operation of the whole statement.
+                    if route.set_operator().is_some() {
+                        return Err(Error::CrossShardSetOperation);
+                    }
+                    shards.insert(route.shard().clone());
+                }
+            }
+
+            let shard = Self::converge(shards);
+
+            // Shards return the set operation computed on their own rows,
+            // so rows from one side can't be matched with the other side
+            // on another shard. We compute INTERSECT and EXCEPT ourselves.
+            // Concatenating UNION results works fine though.
+            let set_operator =
+                SetOperator::new(stmt).filter(|_| !matches!(shard, Shard::Direct(_)));
+
+            let limit = LimitClause::new(stmt, params).limit_offset()?;
+            let distinct = Distinct::new(stmt).distinct()?;
+
+            return Ok(Command::Query(
+                Route::select(shard, order_by, Aggregate::none(), limit, distinct)
+                    .set_set_operator(set_operator),
+            ));
+        }
         let the_table = Table::try_from(&stmt.from_clause).ok();
-        if let Some(where_clause) =
-            WhereClause::new(the_table.as_ref().map(|t| t.name), &stmt.where_clause)
-        {
-            shards = Self::where_clause(sharding_schema, &where_clause, params)?;
+
+        let mut range_vars = vec![];
+        for node in &stmt.from_clause {
+            Self::range_vars(node, &mut range_vars);
+        }
+
+        // Tables in `FROM a, b` and joins can be referred to by their aliases.
+        let aliases = range_vars
+            .iter()
+            .filter_map(|range_var| {
+                range_var
+                    .alias
+                    .as_ref()
+                    .map(|alias| (alias.aliasname.as_str(), range_var.relname.as_str()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        // Unqualified columns can belong to any of the tables
+        // in a comma join.
+        let default_table = if stmt.from_clause.len() == 1 {
+            the_table.as_ref().map(|t| t.name)
+        } else {
+            None
+        };
+
+        // Subqueries in FROM selecting columns of a table as they are,
+        // e.g. `sub.t` is `users.tenant_id` in `FROM (SELECT tenant_id AS t FROM users) sub`.
+        let derived = DerivedTable::new(&stmt.from_clause);
+
+        let mut column = None;
+        if let Some(mut where_clause) = WhereClause::new(default_table, &stmt.where_clause) {
+            where_clause.resolve_aliases(&aliases);
+            where_clause.resolve_columns(|column| {
+                derived
+                    .iter()
+                    .find(|table| match column.table {
+                        Some(name) => name == table.alias,
+                        None => stmt.from_clause.len() == 1,
+                    })
+                    .and_then(|table| table.resolve(column))
+            });
+            (shards, column) = Self::where_clause(sharding_schema, &where_clause, params)?;
         }
 
         // Shard by vector in ORDER BY clause.
@@ -719,18 +2120,223 @@ impl QueryParser {
             }
         }
 
+        if shards.is_empty() {
+            if let Some((shard, key)) = Self::recursive_ctes(stmt, sharding_schema, params)? {
+                shards.insert(shard);
+                column = key;
+            }
+        }
+
         let shard = Self::converge(shards);
         let aggregates = Aggregate::parse(stmt)?;
         let limit = LimitClause::new(stmt, params).limit_offset()?;
         let distinct = Distinct::new(stmt).distinct()?;
 
-        Ok(Command::Query(Route::select(
-            shard, order_by, aggregates, limit, distinct,
-        )))
+        let locked_tables = Self::locked_tables(stmt, &range_vars, &aliases);
+
+        Ok(Command::Query(
+            Route::select(shard, order_by, aggregates, limit, distinct)
+                .set_sharding_key(column)
+                .set_locked_tables(locked_tables),
+        ))
+    }
+
+    /// Shard of a query selecting only from `WITH RECURSIVE` CTEs that stay on one shard:
+    /// the anchor and the recursive term both filter the same sharded table
+    /// by a key on that shard, e.g.
+    ///
+    /// ```sql
+    /// WITH RECURSIVE tree AS (
+    ///     SELECT * FROM users WHERE tenant_id = 1 AND parent_id IS NULL
+    ///     UNION ALL
+    ///     SELECT u.* FROM users u JOIN tree ON u.parent_id = tree.id WHERE u.tenant_id = 1
+    /// ) SELECT * FROM tree
+    /// ```
+    ///
+    /// Without the filter in the recursive term, rows on other shards could be
+    /// part of the tree, so anything else goes to all shards.
+    fn recursive_ctes(
+        stmt: &SelectStmt,
+        sharding_schema: &ShardingSchema,
+        params: Option<&Bind>,
+    ) -> Result<Option<(Shard, Option<ShardingKey>)>, Error> {
+        let Some(ref with_clause) = stmt.with_clause else {
+            return Ok(None);
+        };
+        if !with_clause.recursive {
+            return Ok(None);
+        }
+
+        let names = with_clause
+            .ctes
+            .iter()
+            .filter_map(|cte| match cte.node {
+                Some(NodeEnum::CommonTableExpr(ref cte)) => Some(cte.ctename.as_str()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        // The query reads the CTEs and nothing else.
+        let mut range_vars = vec![];
+        for node in &stmt.from_clause {
+            if !Self::only_range_vars(node, &mut range_vars) {
+                return Ok(None);
+            }
+        }
+        if range_vars.is_empty()
+            || range_vars.iter().any(|range_var| {
+                !range_var.schemaname.is_empty() || !names.contains(range_var.relname.as_str())
+            })
+        {
+            return Ok(None);
+        }
+
+        let sharded = |stmt: &SelectStmt| {
+            let mut range_vars = vec![];
+            for node in &stmt.from_clause {
+                Self::range_vars(node, &mut range_vars);
+            }
+            range_vars
+                .into_iter()
+                .map(|range_var| range_var.relname.clone())
+                .filter(|name| {
+                    sharding_schema
+                        .tables
+                        .tables()
+                        .iter()
+                        .any(|table| table.name.as_ref() == Some(name))
+                })
+                .collect::<HashSet<_>>()
+        };
+        let route = |stmt: &SelectStmt| -> Result<Option<Route>, Error> {
+            match Self::select(stmt, sharding_schema, params)? {
+                Command::Query(route) if matches!(route.shard(), Shard::Direct(_)) => {
+                    Ok(Some(route))
+                }
+                _ => Ok(None),
+            }
+        };
+
+        let mut found: Option<Route> = None;
+        for cte in &with_clause.ctes {
+            let Some(NodeEnum::CommonTableExpr(ref cte)) = cte.node else {
+                return Ok(None);
+            };
+            let Some(NodeEnum::SelectStmt(ref query)) =
+                cte.ctequery.as_ref().and_then(|query| query.node.as_ref())
+            else {
+                return Ok(None);
+            };
+            let (Some(anchor), Some(recursive), SetOperation::SetopUnion) =
+                (query.larg.as_deref(), query.rarg.as_deref(), query.op())
+            else {
+                return Ok(None);
+            };
+
+            // Both terms read the same sharded table.
+            let tables = sharded(anchor);
+            if tables.len() != 1 || !tables.is_subset(&sharded(recursive)) {
+                return Ok(None);
+            }
+
+            // Both terms are on the same shard, filtered by the same key.
+            let (Some(anchor), Some(recursive)) = (route(anchor)?, route(recursive)?) else {
+                return Ok(None);
+            };
+            for term in [&recursive, found.as_ref().unwrap_or(&anchor)] {
+                if term.shard() != anchor.shard()
+                    || term.sharding_column() != anchor.sharding_column()
+                {
+                    return Ok(None);
+                }
+            }
+            found = Some(anchor);
+        }
+
+        Ok(found.map(|route| {
+            (
+                route.shard().clone(),
+                route
+                    .sharding_column()
+                    .map(|column| (column.to_string(), route.sharding_keys().to_vec())),
+            )
+        }))
+    }
+
+    /// Tables in the `FROM` clause, like [`Self::range_vars`].
+    /// Returns false if it has anything else, e.g. a subquery or a function.
+    fn only_range_vars<'a>(node: &'a Node, range_vars: &mut Vec<&'a RangeVar>) -> bool {
+        match node.node {
+            Some(NodeEnum::RangeVar(ref range_var)) => {
+                range_vars.push(range_var);
+                true
+            }
+            Some(NodeEnum::JoinExpr(ref join)) => {
+                [&join.larg, &join.rarg].into_iter().all(|side| {
+                    side.as_ref()
+                        .is_some_and(|side| Self::only_range_vars(side, range_vars))
+                })
+            }
+            _ => false,
+        }
+    }
+
+    /// Tables in the `FROM` clause, including both sides of joins.
+    fn range_vars<'a>(node: &'a Node, range_vars: &mut Vec<&'a RangeVar>) {
+        match node.node {
+            Some(NodeEnum::RangeVar(ref range_var)) => range_vars.push(range_var),
+            Some(NodeEnum::JoinExpr(ref join)) => {
+                for side in [&join.larg, &join.rarg].into_iter().flatten() {
+                    Self::range_vars(side, range_vars);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Tables locked by the locking clauses, e.g. `FOR UPDATE OF u`.
+    /// `OF` uses the alias if the table has one. Without `OF`,
+    /// rows of all tables in `FROM` are locked.
+    fn locked_tables(
+        stmt: &SelectStmt,
+        range_vars: &[&RangeVar],
+        aliases: &HashMap<&str, &str>,
+    ) -> Vec<std::string::String> {
+        let mut tables = vec![];
+        for clause in &stmt.locking_clause {
+            let Some(NodeEnum::LockingClause(ref clause)) = clause.node else {
+                continue;
+            };
+            let locked = if clause.locked_rels.is_empty() {
+                range_vars
+                    .iter()
+                    .map(|range_var| range_var.relname.as_str())
+                    .collect::<Vec<_>>()
+            } else {
+                clause
+                    .locked_rels
+                    .iter()
+                    .filter_map(|node| match node.node {
+                        Some(NodeEnum::RangeVar(ref range_var)) => {
+                            let name = range_var.relname.as_str();
+                            Some(aliases.get(name).copied().unwrap_or(name))
+                        }
+                        _ => None,
+                    })
+                    .collect()
+            };
+            for table in locked {
+                if !tables.iter().any(|locked| locked == table) {
+                    tables.push(table.to_string());
+                }
+            }
+        }
+
+        tables
     }
 
     /// Parse the `ORDER BY` clause of a `SELECT` statement.
-    fn select_sort(nodes: &[Node], params: Option<&Bind>) -> Vec<OrderBy> {
+    fn select_sort(nodes: &[Node], targets: &[Node], params: Option<&Bind>) -> Vec<OrderBy> {
         let mut order_by = vec![];
         for clause in nodes {
             if let Some(NodeEnum::SortBy(ref sort_by)) = clause.node {
@@ -783,9 +2389,12 @@ impl QueryParser {
                                                 if let Ok(vec) = Value::try_from(&e.node) {
                                                     match vec {
                                                         Value::Placeholder(p) => {
-                                                            if let Some(bind) = params {
+                                                            if let (Some(bind), Some(pos)) = (
+                                                                params,
+                                                                Value::placeholder_position(p),
+                                                            ) {
                                                                 if let Ok(Some(param)) =
-                                                                    bind.parameter((p - 1) as usize)
+                                                                    bind.parameter(pos)
                                                                 {
                                                                     vector = param.vector();
                                                                 }
@@ -816,6 +2425,31 @@ impl QueryParser {
                         }
                     }
 
+                    // `ORDER BY random()` has no order to merge rows by, and neither
+                    // do the columns after it. Shards return rows in random order,
+                    // so concatenating them is as good as it gets.
+                    NodeEnum::FuncCall(func) => {
+                        let volatile = func.funcname.last().is_some_and(|name| {
+                            matches!(
+                                name.node,
+                                Some(NodeEnum::String(String { ref sval })) if Function::volatile(sval)
+                            )
+                        });
+                        if volatile {
+                            break;
+                        }
+
+                        // `ORDER BY count(*)` sorts by the aggregate in the target list,
+                        // which is merged across shards before we sort.
+                        if let Some(position) = Aggregate::target_position(targets, node) {
+                            order_by.push(if asc {
+                                OrderBy::Asc(position + 1)
+                            } else {
+                                OrderBy::Desc(position + 1)
+                            });
+                        }
+                    }
+
                     _ => continue,
                 }
             }
@@ -824,7 +2458,35 @@ impl QueryParser {
         order_by
     }
 
-    fn copy(stmt: &CopyStmt, cluster: &Cluster) -> Result<Command, Error> {
+    fn copy(stmt: &CopyStmt, cluster: &Cluster, params: Option<&Bind>) -> Result<Command, Error> {
+        // COPY (SELECT ...) TO STDOUT goes wherever the query would.
+        // Rows from several shards are sent one after the other.
+        if !stmt.is_from {
+            if let Some(NodeEnum::SelectStmt(ref select)) =
+                stmt.query.as_ref().and_then(|query| query.node.as_ref())
+            {
+                if let Command::Query(route) =
+                    Self::select(select, &cluster.sharding_schema(), params)?
+                {
+                    // COPY rows aren't buffered, so we can't compute
+                    // INTERSECT and EXCEPT across shards.
+                    if route.set_operator().is_some() {
+                        return Err(Error::CrossShardSetOperation);
+                    }
+
+                    // Each shard would send its own CSV header, or binary
+                    // header and trailer, in the middle of the rows.
+                    if route.shard().count(cluster.shards().len()) > 1 && Self::copy_framed(stmt) {
+                        return Err(RouteError::Unsupported(
+                            "COPY TO with HEADER or FORMAT binary on multiple shards",
+                        )
+                        .into());
+                    }
+                    return Ok(Command::Query(Route::write(route.shard().clone())));
+                }
+            }
+        }
+
         let parser = CopyParser::new(stmt, cluster)?;
         if let Some(parser) = parser {
             Ok(Command::Copy(Box::new(parser)))
@@ -833,14 +2495,74 @@ impl QueryParser {
         }
     }
 
-    fn insert(
-        stmt: &InsertStmt,
-        sharding_schema: &ShardingSchema,
+    /// COPY output has a header or a trailer.
+    fn copy_framed(stmt: &CopyStmt) -> bool {
+        stmt.options.iter().any(|option| {
+            let Some(NodeEnum::DefElem(ref elem)) = option.node else {
+                return false;
+            };
+            let arg = elem.arg.as_ref().and_then(|arg| arg.node.as_ref());
+            match elem.defname.to_lowercase().as_str() {
+                "format" => matches!(
+                    arg,
+                    Some(NodeEnum::String(string)) if string.sval.eq_ignore_ascii_case("binary")
+                ),
+                "header" => match arg {
+                    None => true,
+                    Some(NodeEnum::Boolean(boolean)) => boolean.boolval,
+                    Some(NodeEnum::Integer(integer)) => integer.ival != 0,
+                    Some(NodeEnum::String(string)) => {
+                        !matches!(string.sval.to_lowercase().as_str(), "false" | "off" | "0")
+                    }
+                    Some(_) => true,
+                },
+                _ => false,
+            }
+        })
+    }
+
+    fn insert(
+        stmt: &InsertStmt,
+        sharding_schema: &ShardingSchema,
         params: Option<&Bind>,
     ) -> Result<Command, Error> {
         let insert = Insert::new(stmt);
+        if Self::replicated(insert.table(), sharding_schema) {
+            return Ok(Command::Query(Route::write(Shard::All)));
+        }
         let shard = insert.shard(sharding_schema, params)?;
-        Ok(Command::Query(Route::write(shard)))
+        let column = insert.sharding_column(sharding_schema);
+        Ok(Command::Query(
+            Route::write(shard).set_sharding_column(column),
+        ))
+    }
+
+    /// System catalogs, e.g. `pg_catalog.pg_class` or `information_schema.columns`,
+    /// describe the server they are on. Rows from every shard would be duplicates.
+    ///
+    /// Only tables in the catalog schemas are matched, since users can create
+    /// their own tables with names starting with `pg_`.
+    fn catalog(table: &str) -> bool {
+        table
+            .split_once('.')
+            .is_some_and(|(schema, _)| matches!(schema, "pg_catalog" | "information_schema"))
+    }
+
+    /// Rows of the table are split between shards. Tables configured
+    /// without a name are sharded by a column any table can have.
+    fn sharded(table: &str, sharding_schema: &ShardingSchema) -> bool {
+        !sharding_schema.tables.omnishards().contains(table)
+            && sharding_schema
+                .tables
+                .tables()
+                .iter()
+                .any(|sharded| sharded.name.as_deref().is_none_or(|name| name == table))
+    }
+
+    /// Every shard has a copy of the table, so writes
+    /// have to go to all of them.
+    fn replicated(table: Option<Table>, sharding_schema: &ShardingSchema) -> bool {
+        table.is_some_and(|table| sharding_schema.tables.omnishards().contains(table.name))
     }
 
     fn update(
@@ -849,15 +2571,26 @@ impl QueryParser {
         params: Option<&Bind>,
     ) -> Result<Command, Error> {
         let table = stmt.relation.as_ref().map(Table::from);
+        if Self::replicated(table, sharding_schema) {
+            return Ok(Command::Query(Route::write(Shard::All)));
+        }
 
         let where_clause = WhereClause::new(table.map(|t| t.name), &stmt.where_clause);
 
-        if let Some(where_clause) = where_clause {
-            let shards = Self::where_clause(sharding_schema, &where_clause, params)?;
-            return Ok(Command::Query(Route::write(Self::converge(shards))));
-        }
+        let route = if let Some(where_clause) = where_clause {
+            let (shards, column) = Self::where_clause(sharding_schema, &where_clause, params)?;
+            Route::write(Self::converge(shards)).set_sharding_key(column)
+        } else {
+            Route::write(Shard::All)
+        };
 
-        Ok(Command::Query(Route::write(Shard::All)))
+        Ok(Command::Query(Self::joined(
+            route,
+            &stmt.from_clause,
+            &stmt.where_clause,
+            sharding_schema,
+            params,
+        )?))
     }
 
     fn delete(
@@ -866,14 +2599,51 @@ impl QueryParser {
         params: Option<&Bind>,
     ) -> Result<Command, Error> {
         let table = stmt.relation.as_ref().map(Table::from);
+        if Self::replicated(table, sharding_schema) {
+            return Ok(Command::Query(Route::write(Shard::All)));
+        }
         let where_clause = WhereClause::new(table.map(|t| t.name), &stmt.where_clause);
 
-        if let Some(where_clause) = where_clause {
-            let shards = Self::where_clause(sharding_schema, &where_clause, params)?;
-            return Ok(Command::Query(Route::write(Self::converge(shards))));
+        let route = if let Some(where_clause) = where_clause {
+            let (shards, column) = Self::where_clause(sharding_schema, &where_clause, params)?;
+            Route::write(Self::converge(shards)).set_sharding_key(column)
+        } else {
+            Route::write(None)
+        };
+
+        Ok(Command::Query(Self::joined(
+            route,
+            &stmt.using_clause,
+            &stmt.where_clause,
+            sharding_schema,
+            params,
+        )?))
+    }
+
+    /// `UPDATE t ... FROM other` and `DELETE FROM t USING other` only change
+    /// rows of `t` joined with rows of the other tables on the same shard,
+    /// so their keys narrow the route of `t` down too.
+    fn joined(
+        route: Route,
+        from_clause: &[Node],
+        where_clause: &Option<Box<Node>>,
+        sharding_schema: &ShardingSchema,
+        params: Option<&Bind>,
+    ) -> Result<Route, Error> {
+        if from_clause.is_empty() {
+            return Ok(route);
         }
 
-        Ok(Command::Query(Route::write(None)))
+        let stmt = SelectStmt {
+            from_clause: from_clause.to_vec(),
+            where_clause: where_clause.clone(),
+            op: SetOperation::SetopNone.into(),
+            ..Default::default()
+        };
+        match Self::select(&stmt, sharding_schema, params)? {
+            Command::Query(joined) => Ok(route.combine(joined)),
+            _ => Ok(route),
+        }
     }
 }
 
@@ -881,11 +2651,13 @@ impl QueryParser {
 mod test {
 
     use crate::net::{
-        messages::{parse::Parse, Parameter},
-        Close, Format, Sync,
+        messages::{parse::Parse, ErrorResponse, Parameter},
+        Close, Describe, Execute, Format, Sync,
     };
 
     use super::{super::Shard, *};
+    use crate::backend::ShardedTables;
+    use crate::config::{DataType, ShardedTable};
     use crate::frontend::{Buffer, RouterContext};
     use crate::net::messages::Query;
     use crate::net::Parameters;
@@ -959,32 +2731,119 @@ mod test {
         };
     }
 
+    /// Client connection sending statements one at a time. The parser keeps
+    /// its state between them, and outside a transaction, the statement ends it.
+    struct Session {
+        qp: QueryParser,
+        cluster: Cluster,
+        params: Parameters,
+    }
+
+    impl Session {
+        fn new(cluster: Cluster) -> Self {
+            Self {
+                qp: QueryParser::default(),
+                cluster,
+                params: Parameters::default(),
+            }
+        }
+
+        /// Route a simple query.
+        fn parse(&mut self, query: &str, in_transaction: bool) -> Result<Command, Error> {
+            self.parse_buffer(Buffer::from(vec![Query::new(query).into()]), in_transaction)
+        }
+
+        fn parse_buffer(&mut self, buffer: Buffer, in_transaction: bool) -> Result<Command, Error> {
+            let mut stmt = PreparedStatements::default();
+            let context = RouterContext::new(
+                &buffer,
+                &self.cluster,
+                &mut stmt,
+                &self.params,
+                in_transaction,
+            )
+            .unwrap();
+            let command = self.qp.parse(context).cloned();
+            if !in_transaction {
+                self.qp.reset();
+            }
+            command
+        }
+
+        /// Route of a simple query that has to be a query.
+        fn route(&mut self, query: &str, in_transaction: bool) -> Route {
+            match self.parse(query, in_transaction) {
+                Ok(Command::Query(route)) => route,
+                command => panic!("not a query: {:?}", command),
+            }
+        }
+    }
+
+    /// Route a statement with `$1` bound to 11, given the only sharded table,
+    /// and the shard 11 is on.
+    fn route_bound(table: &ShardedTable, query: &str) -> (Route, Shard) {
+        let schema = ShardingSchema {
+            shards: 2,
+            tables: ShardedTables::new(vec![table.clone()], vec![], false),
+            ..Default::default()
+        };
+        let bind = Bind::test_params(
+            "",
+            &[Parameter {
+                len: 2,
+                data: "11".as_bytes().to_vec(),
+            }],
+        );
+        let ast = parse(query).unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+        let command = match stmt.node {
+            Some(NodeEnum::SelectStmt(ref stmt)) => QueryParser::select(stmt, &schema, Some(&bind)),
+            Some(NodeEnum::UpdateStmt(ref stmt)) => QueryParser::update(stmt, &schema, Some(&bind)),
+            Some(NodeEnum::InsertStmt(ref stmt)) => QueryParser::insert(stmt, &schema, Some(&bind)),
+            _ => panic!("unexpected statement"),
+        };
+        let route = match command.unwrap() {
+            Command::Query(route) => route,
+            command => panic!("not a query: {:?}", command),
+        };
+        let shard = ContextBuilder::new(table)
+            .data("11")
+            .shards(2)
+            .build()
+            .unwrap()
+            .apply()
+            .unwrap();
+
+        (route, shard)
+    }
+
     #[test]
     fn test_start_replication() {
-        let query = Query::new(
-            r#"START_REPLICATION SLOT "sharded" LOGICAL 0/1E2C3B0 (proto_version '4', origin 'any', publication_names '"sharded"')"#,
-        );
-        let mut buffer = Buffer::new();
-        buffer.push(query.into());
+        let query = r#"START_REPLICATION SLOT "sharded" LOGICAL 0/1E2C3B0 (proto_version '4', origin 'any', publication_names '"sharded"')"#;
 
-        let mut query_parser = QueryParser::default();
-        query_parser.replication_mode();
+        for query in [query.to_string(), format!("\n  {}", query)] {
+            let mut buffer = Buffer::new();
+            buffer.push(Query::new(query).into());
 
-        let cluster = Cluster::default();
+            let mut query_parser = QueryParser::default();
+            query_parser.replication_mode();
 
-        let command = query_parser
-            .parse(
-                RouterContext::new(
-                    &buffer,
-                    &cluster,
-                    &mut PreparedStatements::default(),
-                    &Parameters::default(),
-                    false,
+            let cluster = Cluster::default();
+
+            let command = query_parser
+                .parse(
+                    RouterContext::new(
+                        &buffer,
+                        &cluster,
+                        &mut PreparedStatements::default(),
+                        &Parameters::default(),
+                        false,
+                    )
+                    .unwrap(),
                 )
-                .unwrap(),
-            )
-            .unwrap();
-        assert!(matches!(command, &Command::StartReplication));
+                .unwrap();
+            assert!(matches!(command, &Command::StartReplication));
+        }
     }
 
     #[test]
@@ -1020,6 +2879,24 @@ mod test {
             ["11".as_bytes(), "test@test.com".as_bytes()]
         );
         assert_eq!(route.shard(), &Shard::direct(1));
+
+        // Identity columns can be set explicitly.
+        for overriding in ["OVERRIDING SYSTEM VALUE", "OVERRIDING USER VALUE"] {
+            let route = parse!(
+                format!(
+                    "INSERT INTO sharded (email, id) {} VALUES ($1, $2)",
+                    overriding
+                ),
+                ["test@test.com".as_bytes(), "11".as_bytes()]
+            );
+            assert_eq!(route.shard(), &Shard::direct(1), "{}", overriding);
+
+            let route = query!(format!(
+                "INSERT INTO sharded (id, email) {} VALUES (1, 'test@test.com') RETURNING id",
+                overriding
+            ));
+            assert_eq!(route.shard(), &Shard::direct(0), "{}", overriding);
+        }
     }
 
     #[test]
@@ -1050,6 +2927,22 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parameter_position() {
+        let route = parse!("SELECT * FROM sharded WHERE id = $1", ["11".as_bytes()]);
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        // $0 can't be bound, so it doesn't pick a shard.
+        let route = parse!("SELECT * FROM sharded WHERE id = $0", ["11".as_bytes()]);
+        assert!(route.is_all_shards());
+
+        let route = parse!(
+            "SELECT * FROM embeddings ORDER BY embedding <-> $0",
+            ["[4.0,5.0,6.0]".as_bytes()]
+        );
+        assert!(route.order_by().is_empty());
+    }
+
     #[test]
     fn test_parse_with_cast() {
         let route = parse!(
@@ -1064,6 +2957,32 @@ mod test {
         assert_eq!(route.shard(), &Shard::Direct(0))
     }
 
+    #[test]
+    fn test_integer_casts() {
+        for id in [1_i64, 11] {
+            let expected = query!(format!("SELECT * FROM sharded WHERE id = {}", id))
+                .shard()
+                .clone();
+
+            for (cast, data) in [
+                ("int2", (id as i16).to_be_bytes().to_vec()),
+                ("int4", (id as i32).to_be_bytes().to_vec()),
+                ("int8", id.to_be_bytes().to_vec()),
+                ("\"char\"", (id as i8).to_be_bytes().to_vec()),
+            ] {
+                let query = format!("SELECT * FROM sharded WHERE id = $1::{}", cast);
+                let route = parse!("test", query.as_str(), [data], &[Format::Binary]);
+                assert_eq!(route.shard(), &expected, "{}", cast);
+
+                let route = parse!(query.as_str(), [id.to_string().as_bytes()]);
+                assert_eq!(route.shard(), &expected, "{}", cast);
+
+                let route = query!(format!("SELECT * FROM sharded WHERE id = {}::{}", id, cast));
+                assert_eq!(route.shard(), &expected, "{}", cast);
+            }
+        }
+    }
+
     #[test]
     fn test_select_for_update() {
         let route = query!("SELECT * FROM sharded WHERE id = $1 FOR UPDATE");
@@ -1077,6 +2996,78 @@ mod test {
         assert!(route.is_write());
     }
 
+    #[test]
+    fn test_select_for_update_of() {
+        let route = query!(
+            "SELECT * FROM sharded s JOIN orders o ON o.sharded_id = s.id WHERE s.id = 11 FOR UPDATE OF s"
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.is_write());
+        assert_eq!(route.locked_tables(), ["sharded"]);
+
+        let route = parse!(
+            "SELECT * FROM myschema.users u JOIN orders ON orders.user_id = u.id WHERE u.tenant_id = $1 FOR UPDATE OF u FOR SHARE OF orders",
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.is_write());
+        assert_eq!(route.locked_tables(), ["users", "orders"]);
+
+        let route = query!("SELECT * FROM sharded, orders WHERE sharded.id = 1 FOR UPDATE");
+        assert_eq!(route.shard(), &Shard::Direct(0));
+        assert!(route.is_write());
+        assert_eq!(route.locked_tables(), ["sharded", "orders"]);
+
+        let route = query!("SELECT * FROM sharded WHERE id = 1");
+        assert!(route.locked_tables().is_empty());
+    }
+
+    #[test]
+    fn test_select_for_update_skip_locked() {
+        for query in [
+            "SELECT * FROM sharded WHERE id = 11 FOR UPDATE SKIP LOCKED",
+            "SELECT * FROM sharded WHERE id = 11 FOR NO KEY UPDATE NOWAIT",
+            "SELECT * FROM sharded WHERE id = 11 LIMIT 10 FOR SHARE SKIP LOCKED",
+            "SELECT count(*) FROM sharded WHERE id = 11 FOR UPDATE",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::Direct(1), "{}", query);
+            assert!(route.is_write(), "{}", query);
+            assert!(route.lock_session(), "{}", query);
+        }
+
+        let route = parse!(
+            "SELECT * FROM sharded WHERE id = $1 FOR UPDATE SKIP LOCKED",
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.is_write());
+        assert!(route.lock_session());
+    }
+
+    #[test]
+    fn test_lock_strengths() {
+        for strength in ["UPDATE", "NO KEY UPDATE", "SHARE", "KEY SHARE"] {
+            let query = format!("SELECT * FROM sharded WHERE id = $1 FOR {}", strength);
+            let route = parse!(query.as_str(), ["11".as_bytes()]);
+            assert_eq!(route.shard(), &Shard::Direct(1), "{}", query);
+            assert!(route.is_write(), "{}", query);
+            assert!(route.lock_session(), "{}", query);
+
+            // Locking a row in a join.
+            let route = query!(format!(
+                "SELECT * FROM sharded s JOIN other o ON o.id = s.id WHERE s.id = 1 FOR {} OF s",
+                strength
+            ));
+            assert!(route.is_write(), "{}", query);
+            assert!(route.lock_session(), "{}", query);
+        }
+
+        let route = query!("SELECT * FROM sharded WHERE id = 1");
+        assert!(route.is_read());
+        assert!(!route.lock_session());
+    }
+
     #[test]
     fn test_omni() {
         let q = "SELECT sharded_omni.* FROM sharded_omni WHERE sharded_omni.id = $1";
@@ -1087,6 +3078,63 @@ mod test {
         assert!(!qp.in_transaction);
     }
 
+    #[test]
+    fn test_listen_notify() {
+        for query in ["LISTEN events", "UNLISTEN events", "UNLISTEN *"] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::Direct(0), "{}", query);
+            assert!(route.is_write());
+        }
+        assert!(query!("LISTEN events").lock_session());
+        assert!(!query!("UNLISTEN events").lock_session());
+
+        for query in ["NOTIFY events", "NOTIFY events, 'payload'"] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::All, "{}", query);
+            assert!(route.is_write());
+            assert!(!route.lock_session());
+        }
+
+        // Follows the shards allowed for the session.
+        let mut session = Session::new(Cluster::new_test());
+        assert!(session.parse("SET pgdog.shards = '1'", false).is_ok());
+        match session.parse("LISTEN events", false).unwrap() {
+            Command::Query(route) => assert_eq!(route.shard(), &Shard::Direct(1)),
+            _ => panic!("not a query"),
+        }
+    }
+
+    #[test]
+    fn test_replicated() {
+        for query in [
+            "SELECT * FROM sharded_replicated",
+            "SELECT * FROM sharded_replicated WHERE id = $1",
+            "SELECT * FROM sharded_omni",
+        ] {
+            let route = query!(query);
+            assert!(matches!(route.shard(), Shard::Direct(_)), "{}", query);
+            assert!(route.is_read());
+        }
+
+        for query in [
+            "INSERT INTO sharded_replicated (id, name) VALUES (1, 'a')",
+            "UPDATE sharded_replicated SET name = 'b' WHERE id = 1",
+            "DELETE FROM sharded_replicated WHERE id = 11",
+            "UPDATE sharded_omni SET name = 'b' WHERE id = 1",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::All, "{}", query);
+            assert!(route.is_write());
+        }
+
+        // Not used for sharding keys.
+        let cluster = Cluster::new_test();
+        assert!(cluster
+            .sharded_tables()
+            .iter()
+            .all(|table| table.name.as_deref() != Some("sharded_replicated")));
+    }
+
     #[test]
     fn test_set() {
         let route = query!(r#"SET "pgdog.shard" TO 1"#);
@@ -1304,6 +3352,32 @@ mod test {
         assert!(route.lock_session());
     }
 
+    #[test]
+    fn test_no_from_clause() {
+        for query in ["SELECT version()", "SELECT pg_sleep(0.1)", "SELECT now()"] {
+            let (command, qp) = command!(query);
+            let Command::Query(route) = command else {
+                panic!("not a query");
+            };
+            assert!(matches!(route.shard(), Shard::Direct(_)), "{}", query);
+            assert!(route.is_read(), "{}", query);
+            assert!(!route.is_local(), "{}", query);
+            assert!(qp.routed);
+        }
+
+        // Functions that can't run on a replica, in any position.
+        for query in [
+            "SELECT txid_current()",
+            "SELECT version(), pg_current_wal_lsn()",
+            "SELECT pg_notify('channel', 'payload')",
+        ] {
+            let route = query!(query);
+            assert!(matches!(route.shard(), Shard::Direct(_)), "{}", query);
+            assert!(route.is_write(), "{}", query);
+            assert!(!route.lock_session(), "{}", query);
+        }
+    }
+
     #[test]
     fn test_write_nolock() {
         let route = query!("SELECT nextval('234')");
@@ -1320,6 +3394,46 @@ mod test {
         assert!(route.is_write());
     }
 
+    #[test]
+    fn test_recursive_cte() {
+        let tree = |recursive_filter: &str| {
+            format!(
+                "WITH RECURSIVE tree AS (
+                    SELECT id, parent_id FROM myschema.users WHERE tenant_id = 11 AND parent_id IS NULL
+                    UNION ALL
+                    SELECT u.id, u.parent_id FROM myschema.users u JOIN tree t ON u.parent_id = t.id {}
+                ) SELECT * FROM tree",
+                recursive_filter
+            )
+        };
+
+        let route = query!(tree("WHERE u.tenant_id = 11"));
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert_eq!(route.sharding_column(), Some("tenant_id"));
+
+        let route = parse!(
+            tree("WHERE u.tenant_id = 11").replace("11", "$1"),
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        for query in [
+            // Recursion follows rows to other shards.
+            tree(""),
+            tree("WHERE u.tenant_id = 1"),
+            tree("WHERE u.id = 11"),
+            // The recursive term reads another table.
+            tree("").replace("FROM myschema.users u JOIN", "FROM sharded u JOIN"),
+            // The query reads another table too.
+            tree("WHERE u.tenant_id = 11").replace("FROM tree", "FROM tree, sharded"),
+            // Not recursive.
+            tree("WHERE u.tenant_id = 11").replace("WITH RECURSIVE", "WITH"),
+        ] {
+            let route = query!(query.clone());
+            assert!(route.is_all_shards(), "{}", query);
+        }
+    }
+
     #[test]
     fn test_function_begin() {
         let (cmd, mut qp) = command!("BEGIN");
@@ -1360,9 +3474,9 @@ mod test {
 
     #[test]
     fn test_comment() {
-        let query = "/* pgdog_shard: 1234 */ SELECT 1234";
+        let query = "/* pgdog_shard: 1 */ SELECT 1234";
         let route = query!(query);
-        assert_eq!(route.shard(), &Shard::Direct(1234));
+        assert_eq!(route.shard(), &Shard::Direct(1));
 
         // Comment is ignored.
         let mut qp = QueryParser::default();
@@ -1392,66 +3506,2542 @@ mod test {
     }
 
     #[test]
-    fn test_limit_offset() {
-        let route = query!("SELECT * FROM users LIMIT 25 OFFSET 5");
-        assert_eq!(route.limit().offset, Some(5));
-        assert_eq!(route.limit().limit, Some(25));
+    fn test_maintenance() {
+        for query in [
+            "ANALYZE users",
+            "ANALYZE",
+            "VACUUM ANALYZE sharded",
+            "VACUUM",
+            "REINDEX TABLE users",
+            "REINDEX INDEX users_pkey",
+            "REINDEX DATABASE pgdog",
+            "CLUSTER users USING users_pkey",
+            "CLUSTER",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::All, "{}", query);
+            assert!(route.is_write(), "{}", query);
+            assert!(!route.two_pc(), "{}", query);
+        }
 
-        let cmd = parse!(
-            "SELECT * FROM users LIMIT $1 OFFSET $2",
-            &["1".as_bytes(), "25".as_bytes(),]
-        );
+        // These can run in a transaction.
+        for query in [
+            "ANALYZE users",
+            "REINDEX TABLE users",
+            "CLUSTER users USING users_pkey",
+        ] {
+            assert!(
+                Session::new(Cluster::new_test()).parse(query, true).is_ok(),
+                "{}",
+                query
+            );
+        }
 
-        assert_eq!(cmd.limit().limit, Some(1));
-        assert_eq!(cmd.limit().offset, Some(25));
+        for query in [
+            "VACUUM users",
+            "CLUSTER",
+            "REINDEX DATABASE pgdog",
+            "REINDEX TABLE CONCURRENTLY users",
+        ] {
+            assert!(
+                matches!(
+                    Session::new(Cluster::new_test()).parse(query, true),
+                    Err(Error::MaintenanceInTransaction(_))
+                ),
+                "{}",
+                query
+            );
+        }
     }
 
     #[test]
-    fn test_close_direct_one_shard() {
-        let cluster = Cluster::new_test_single_shard();
-        let mut qp = QueryParser::default();
+    fn test_truncate() {
+        for query in [
+            "TRUNCATE sharded",
+            "TRUNCATE sharded, sharded_omni, users",
+            "TRUNCATE TABLE sharded, other RESTART IDENTITY CASCADE",
+            "TRUNCATE ONLY sharded CONTINUE IDENTITY RESTRICT",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::All, "{}", query);
+            assert!(route.is_write(), "{}", query);
+            assert!(route.lock_session(), "{}", query);
+            assert!(route.two_pc(), "{}", query);
+        }
 
-        let buf: Buffer = vec![Close::named("test").into(), Sync.into()].into();
-        let mut pp = PreparedStatements::default();
+        // One shard doesn't need two-phase commit.
+        let cluster = Cluster::new_test_single_shard();
+        let mut stmt = PreparedStatements::default();
         let params = Parameters::default();
+        let buffer = Buffer::from(vec![Query::new("TRUNCATE sharded, other").into()]);
+        let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+        let Command::Query(route) = QueryParser::default().parse(context).unwrap().clone() else {
+            panic!("not a query");
+        };
+        assert_eq!(route.shard(), &Shard::Direct(0));
+        assert!(!route.two_pc());
+    }
 
-        let context = RouterContext::new(&buf, &cluster, &mut pp, &params, false).unwrap();
-
-        let cmd = qp.parse(context).unwrap();
+    #[test]
+    fn test_whitespace_and_comments() {
+        for id in ["1", "11"] {
+            let clean = format!("SELECT * FROM sharded WHERE id = {}", id);
+            let expected = query!(clean.clone()).shard().clone();
+            assert!(matches!(expected, Shard::Direct(_)));
+
+            for query in [
+                format!("{};", clean),
+                format!("{} ;  \n", clean),
+                format!("{};;", clean),
+                format!("  \n\t{}", clean),
+                format!("-- foo\n{}", clean),
+                format!("-- foo\n{}; -- bar", clean),
+                format!("/* foo */ {} /* bar */;", clean),
+                format!(
+                    "SELECT * /* foo */ FROM sharded /* bar */ WHERE id /* baz */ = {}",
+                    id
+                ),
+                format!("SELECT *\nFROM sharded -- foo\nWHERE id = {}\n;\n", id),
+            ] {
+                let route = query!(query.clone());
+                assert_eq!(route.shard(), &expected, "{}", query);
+                assert!(route.is_read(), "{}", query);
+            }
 
-        match cmd {
-            Command::Query(route) => assert_eq!(route.shard(), &Shard::Direct(0)),
-            _ => panic!("not a query"),
+            let route = parse!(
+                "-- foo\n SELECT * FROM sharded /* bar */ WHERE id = $1 ;",
+                [id.as_bytes()]
+            );
+            assert_eq!(route.shard(), &expected);
         }
     }
 
     #[test]
-    fn test_distinct() {
-        let route = query!("SELECT DISTINCT * FROM users");
-        let distinct = route.distinct().as_ref().unwrap();
-        assert_eq!(distinct, &DistinctBy::Row);
-
-        let route = query!("SELECT DISTINCT ON(1, email) * FROM users");
-        let distinct = route.distinct().as_ref().unwrap();
-        assert_eq!(
-            distinct,
-            &DistinctBy::Columns(vec![
-                DistinctColumn::Index(0),
-                DistinctColumn::Name(std::string::String::from("email"))
-            ])
+    fn test_volatile_filter() {
+        let route = parse!(
+            "SELECT * FROM sharded WHERE id = $1 AND random() < 0.1",
+            ["11".as_bytes()]
         );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.is_read());
+
+        let route = query!("SELECT * FROM sharded WHERE random() < 0.1 AND id = 1");
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        let route = query!("SELECT * FROM sharded WHERE random() < 0.1");
+        assert_eq!(route.shard(), &Shard::All);
     }
 
     #[test]
-    fn test_any() {
-        let route = query!("SELECT * FROM sharded WHERE id = ANY('{1, 2, 3}')");
-        assert_eq!(route.shard(), &Shard::All);
+    fn test_in_casts() {
+        let query = "SELECT * FROM sharded WHERE id IN ($1::int, $2::int)";
 
+        let route = parse!(query, ["11".as_bytes(), "11".as_bytes()]);
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = parse!(query, ["1".as_bytes(), "11".as_bytes()]);
+        assert!(route.is_cross_shard());
+
+        // The cast doesn't change how the value is hashed.
         let route = parse!(
-            "SELECT * FROM sharded WHERE id = ANY($1)",
-            &["{1, 2, 3}".as_bytes()]
+            "test_in_casts",
+            query,
+            [11_i32.to_be_bytes(), 11_i32.to_be_bytes()],
+            &[Format::Binary]
         );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+    }
 
-        assert_eq!(route.shard(), &Shard::All);
+    #[test]
+    fn test_in_single_value() {
+        for id in ["1", "11"] {
+            let expected = query!(format!("SELECT * FROM sharded WHERE id = {}", id))
+                .shard()
+                .clone();
+            assert!(matches!(expected, Shard::Direct(_)));
+
+            let route = parse!("SELECT * FROM sharded WHERE id IN ($1)", [id.as_bytes()]);
+            assert_eq!(route.shard(), &expected);
+
+            let route = query!(format!("SELECT * FROM sharded WHERE id IN ({})", id));
+            assert_eq!(route.shard(), &expected);
+
+            let route = parse!(
+                "UPDATE sharded SET value = 'a' WHERE id IN ($1)",
+                [id.as_bytes()]
+            );
+            assert_eq!(route.shard(), &expected);
+            // `value IN (column)` is `column = value` too.
+            let route = parse!("SELECT * FROM sharded WHERE $1 IN (id)", [id.as_bytes()]);
+            assert_eq!(route.shard(), &expected);
+
+            let route = query!(format!("DELETE FROM sharded WHERE {} IN (sharded.id)", id));
+            assert_eq!(route.shard(), &expected);
+        }
+    }
+
+    #[test]
+    fn test_empty_list() {
+        // Postgres doesn't allow an empty IN list.
+        assert!(pg_query::parse("SELECT * FROM sharded WHERE id IN ()").is_err());
+
+        // Drivers send empty arrays instead. No rows match, so no shard is queried.
+        let empty = [0_i32, 0, 20]
+            .iter()
+            .flat_map(|field| field.to_be_bytes())
+            .collect::<Vec<_>>();
+        for route in [
+            query!("SELECT id, value FROM sharded WHERE id = ANY('{}')"),
+            query!("SELECT id FROM sharded WHERE id = ANY('{}'::bigint[]) AND value = 'a'"),
+            query!("DELETE FROM sharded WHERE id = ANY('{}')"),
+            parse!(
+                "SELECT id FROM sharded WHERE id = ANY($1)",
+                ["{}".as_bytes()]
+            ),
+            parse!(
+                "",
+                "SELECT id FROM sharded WHERE id = ANY($1)",
+                [empty.as_slice()],
+                &[Format::Binary]
+            ),
+        ] {
+            assert!(route.shard().is_empty(), "{:?}", route);
+        }
+
+        // We can't describe the columns without a shard,
+        // and any one of them returns the same empty result.
+        for route in [
+            query!("SELECT * FROM sharded WHERE id = ANY('{}')"),
+            query!("SELECT count(*) FROM sharded WHERE id = ANY('{}')"),
+        ] {
+            assert!(matches!(route.shard(), Shard::Direct(_)), "{:?}", route);
+        }
+
+        // The transaction needs a shard for its next statements.
+        let mut parser = QueryParser::default();
+        let cluster = Cluster::new_test();
+        let buffer = Buffer::from(vec![
+            Query::new("DELETE FROM sharded WHERE id = ANY('{}')").into()
+        ]);
+        let mut prepared_statements = PreparedStatements::default();
+        let params = Parameters::default();
+        let context =
+            RouterContext::new(&buffer, &cluster, &mut prepared_statements, &params, true).unwrap();
+        let Command::Query(route) = parser.parse(context).unwrap().clone() else {
+            panic!("not a query");
+        };
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+
+        // The client can't answer it, e.g. it's connected already.
+        let (_, mut parser) = command!("DELETE FROM sharded WHERE id = ANY('{}')");
+        let Command::Query(route) = parser.one_shard(2) else {
+            panic!("not a query");
+        };
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+
+        // Not a sharding column.
+        let route = query!("SELECT * FROM sharded WHERE value = ANY('{}')");
+        assert_eq!(route.shard(), &Shard::All);
+
+        // Arrays with elements still go to all shards.
+        let route = parse!(
+            "SELECT * FROM sharded WHERE id = ANY($1)",
+            ["{1,11}".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::All);
+
+        // Another filter on the sharding key picks the shard.
+        let route = query!("SELECT * FROM sharded WHERE id = ANY('{}') OR id = 11");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+    }
+
+    #[test]
+    fn test_equality_and_inequality() {
+        let route = query!("SELECT * FROM sharded WHERE id = 11 AND id > 5");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = parse!(
+            "SELECT * FROM sharded WHERE id = $1 AND id > $2",
+            ["11".as_bytes(), "5".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        for query in [
+            "SELECT * FROM sharded WHERE id > 11",
+            "SELECT * FROM sharded WHERE id <> 11",
+            "SELECT * FROM sharded WHERE id BETWEEN 1 AND 11",
+            "SELECT * FROM sharded WHERE id IS DISTINCT FROM 11",
+        ] {
+            assert_eq!(query!(query).shard(), &Shard::All, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_star_in_where() {
+        let route = query!("SELECT * FROM sharded WHERE sharded.* IS NOT NULL AND id = 1");
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        let route = query!("SELECT * FROM sharded s WHERE s.* = ROW(1, 'a')");
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_shard_out_of_range() {
+        let mut session = Session::new(Cluster::new_test());
+
+        assert!(matches!(
+            session.parse("/* pgdog_shard: 2 */ SELECT 1", false),
+            Err(Error::Route(RouteError::ShardOutOfRange(
+                Shard::Direct(2),
+                2
+            )))
+        ));
+        assert!(matches!(
+            session.parse("/* pgdog_shard: 1234 */ SELECT * FROM sharded", false),
+            Err(Error::Route(RouteError::ShardOutOfRange(
+                Shard::Direct(1234),
+                2
+            )))
+        ));
+        assert!(session
+            .parse("/* pgdog_shard: 1 */ SELECT 1", false)
+            .is_ok());
+
+        assert!(Shard::Multi(vec![0, 1]).valid(2));
+        assert!(!Shard::Multi(vec![0, 2]).valid(2));
+        assert!(Shard::All.valid(2));
+    }
+
+    #[test]
+    fn test_search_path() {
+        let cluster = Cluster::new_test();
+        let mut stmt = PreparedStatements::default();
+        let mut parse = |query: &str, search_path: &[&str]| {
+            let mut params = Parameters::default();
+            params.insert(
+                "search_path",
+                ParameterValue::Tuple(search_path.iter().map(|s| s.to_string()).collect()),
+            );
+            let buffer = Buffer::from(vec![
+                Parse::named("test", query).into(),
+                Bind::test_params(
+                    "test",
+                    &[Parameter {
+                        len: 2,
+                        data: "11".as_bytes().to_vec(),
+                    }],
+                )
+                .into(),
+                Execute::new().into(),
+                Sync.into(),
+            ]);
+            let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+            match QueryParser::default().parse(context).unwrap().clone() {
+                Command::Query(route) => route,
+                command => panic!("not a query: {:?}", command),
+            }
+        };
+
+        let query = "SELECT * FROM users WHERE tenant_id = $1";
+        let route = parse(query, &["myschema", "public"]);
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert_eq!(route.sharding_column(), Some("tenant_id"));
+
+        let route = parse(query, &["$user", "public"]);
+        assert_eq!(route.shard(), &Shard::All);
+
+        let route = parse(
+            "SELECT * FROM myschema.users WHERE tenant_id = $1",
+            &["public"],
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = parse(
+            "UPDATE users SET name = 'a' WHERE tenant_id = $1",
+            &["myschema"],
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = parse(
+            "INSERT INTO users (tenant_id, name) VALUES ($1, 'a')",
+            &["myschema"],
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        // Table in another schema.
+        let route = parse(
+            "SELECT * FROM other.users WHERE tenant_id = $1",
+            &["myschema"],
+        );
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_exists_subquery() {
+        for query in [
+            "SELECT * FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM other)",
+            "SELECT * FROM myschema.users WHERE EXISTS (SELECT 1 FROM other WHERE other.id = users.id) AND tenant_id = $1",
+            "UPDATE myschema.users SET name = 'a' WHERE tenant_id = $1 AND NOT EXISTS (SELECT 1 FROM other)",
+            "DELETE FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM other)",
+        ] {
+            let route = parse!(query, ["11".as_bytes()]);
+            assert_eq!(route.shard(), &Shard::Direct(1), "{}", query);
+        }
+
+        // The subquery alone doesn't pick a shard.
+        let route = parse!(
+            "SELECT * FROM myschema.users WHERE EXISTS (SELECT 1 FROM other WHERE tenant_id = $1)",
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_correlated_subquery() {
+        // The subquery's reference to the outer table isn't a sharding key,
+        // the outer filter picks the shard.
+        for query in [
+            "SELECT * FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id)",
+            "SELECT * FROM myschema.users u WHERE EXISTS (SELECT 1 FROM o WHERE o.tenant_id = u.tenant_id AND o.id = 5) AND u.tenant_id = $1",
+            "SELECT * FROM myschema.users WHERE tenant_id = $1 AND id IN (SELECT user_id FROM o WHERE o.tenant_id = users.tenant_id)",
+            "SELECT *, (SELECT count(*) FROM o WHERE o.tenant_id = users.tenant_id) FROM myschema.users WHERE tenant_id = $1",
+            "UPDATE myschema.users SET n = 1 WHERE tenant_id = $1 AND NOT EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id)",
+            "DELETE FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id)",
+            // Keys inside the subquery don't override the outer one.
+            "SELECT * FROM myschema.users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM o WHERE o.tenant_id = users.tenant_id AND o.tenant_id = 1)",
+            "SELECT * FROM sharded WHERE id = $1 AND EXISTS (SELECT 1 FROM sharded s WHERE s.id = sharded.id AND s.id = 1)",
+        ] {
+            let route = parse!(query, ["11".as_bytes()]);
+            assert_eq!(route.shard(), &Shard::Direct(1), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_update_from() {
+        // The joined table's key picks the shard of the rows to change.
+        for query in [
+            "UPDATE sharded SET value = u.name FROM myschema.users u WHERE u.tenant_id = 11",
+            "UPDATE sharded SET value = 1 FROM myschema.users u WHERE sharded.id = 11 AND u.tenant_id = 11",
+            "DELETE FROM sharded USING myschema.users u WHERE u.tenant_id = 11",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::Direct(1), "{}", query);
+            assert!(route.is_write(), "{}", query);
+        }
+
+        let route =
+            query!("UPDATE sharded SET value = 1 FROM sharded_omni o WHERE o.id = sharded.id");
+        assert_eq!(route.shard(), &Shard::All);
+        assert!(route.is_write());
+    }
+
+    #[test]
+    fn test_order_by_aggregate() {
+        let route = query!(
+            "SELECT tenant_id, count(*) c FROM events GROUP BY tenant_id ORDER BY c DESC LIMIT 10"
+        );
+        assert_eq!(route.aggregate().group_by(), &[0]);
+        assert!(matches!(route.order_by(), [OrderBy::DescColumn(c)] if c == "c"));
+        assert_eq!(route.limit().limit, Some(10));
+        assert!(route.should_buffer());
+
+        for query in [
+            "SELECT tenant_id, count(*) FROM events GROUP BY tenant_id ORDER BY count(*) DESC LIMIT 10",
+            "SELECT tenant_id, COUNT(*) FROM events GROUP BY tenant_id ORDER BY count(*) DESC LIMIT 10",
+            "SELECT tenant_id, count(*) FROM events GROUP BY tenant_id ORDER BY 2 DESC LIMIT 10",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.aggregate().group_by(), &[0], "{}", query);
+            assert!(
+                matches!(route.order_by(), [OrderBy::Desc(2)]),
+                "{}",
+                query
+            );
+        }
+
+        // Not in the target list.
+        let route =
+            query!("SELECT tenant_id, count(*) FROM events GROUP BY tenant_id ORDER BY max(id)");
+        assert!(route.order_by().is_empty());
+    }
+
+    #[test]
+    fn test_prepare_execute() {
+        let mut session = Session::new(Cluster::new_test());
+        let mut parse = |query: &str| match session.parse(query, false).unwrap() {
+            Command::Rewrite(_, route) => Some(route.shard().clone()),
+            _ => None,
+        };
+
+        assert_eq!(
+            parse("PREPARE test AS SELECT * FROM sharded WHERE id = $1"),
+            Some(Shard::All)
+        );
+        assert_eq!(parse("EXECUTE test(1)"), Some(Shard::Direct(0)));
+        assert_eq!(parse("EXECUTE test(11)"), Some(Shard::Direct(1)));
+        assert_eq!(parse("EXECUTE other(11)"), Some(Shard::All));
+
+        parse("PREPARE upd (text, bigint) AS UPDATE sharded SET value = $1 WHERE id = $2");
+        assert_eq!(parse("EXECUTE upd('a', 11)"), Some(Shard::Direct(1)));
+
+        // Not keyed by a parameter.
+        parse("PREPARE test2 AS SELECT * FROM sharded");
+        assert_eq!(parse("EXECUTE test2"), Some(Shard::All));
+
+        assert_eq!(parse("DEALLOCATE test"), None);
+        assert_eq!(parse("EXECUTE test(11)"), Some(Shard::All));
+        assert_eq!(parse("EXECUTE upd('a', 11)"), Some(Shard::Direct(1)));
+        parse("DEALLOCATE ALL");
+        assert_eq!(parse("EXECUTE upd('a', 11)"), Some(Shard::All));
+    }
+
+    #[test]
+    fn test_savepoint() {
+        let mut session = Session::new(Cluster::new_test());
+
+        // Single-shard transaction.
+        let route_of = session.route("SELECT * FROM sharded WHERE id = 11", true);
+        assert_eq!(route_of.shard(), &Shard::Direct(1));
+        for statement in [
+            "SAVEPOINT a",
+            "UPDATE sharded SET value = 'a' WHERE id = 11",
+            "ROLLBACK TO SAVEPOINT a",
+            "RELEASE SAVEPOINT a",
+        ] {
+            let route = session.route(statement, true);
+            assert_eq!(route.shard(), &Shard::Direct(1), "{}", statement);
+            assert!(route.is_write(), "{}", statement);
+        }
+        session.qp.reset();
+
+        // Cross-shard transaction.
+        assert!(session.route("SELECT * FROM sharded", true).is_all_shards());
+        for statement in ["SAVEPOINT a", "RELEASE a", "ROLLBACK TO a"] {
+            let err = session.parse(statement, true).unwrap_err();
+            assert!(
+                matches!(err.route_error(), Some(RouteError::Unsupported(_))),
+                "{}",
+                statement
+            );
+        }
+        session.qp.reset();
+
+        // No statement picked the shard yet: sent after BEGIN.
+        for statement in ["SAVEPOINT a", "ROLLBACK TO a", "RELEASE a"] {
+            assert!(
+                matches!(session.parse(statement, true), Ok(Command::Savepoint(_))),
+                "{}",
+                statement
+            );
+        }
+        let route_of = session.route("SELECT * FROM sharded WHERE id = 11", true);
+        assert_eq!(route_of.shard(), &Shard::Direct(1));
+        session.qp.reset();
+
+        // Unless it's a prepared statement.
+        let buffer = Buffer::from(vec![
+            Parse::new_anonymous("SAVEPOINT a").into(),
+            Bind::test_params("", &[]).into(),
+        ]);
+        assert!(matches!(
+            session
+                .parse_buffer(buffer, true)
+                .unwrap_err()
+                .route_error(),
+            Some(RouteError::Unsupported(_))
+        ));
+        session.qp.reset();
+
+        // Not in a transaction.
+        assert_eq!(
+            session
+                .parse("SAVEPOINT a", false)
+                .unwrap_err()
+                .route_error(),
+            Some(&RouteError::NoTransaction("SAVEPOINT"))
+        );
+    }
+
+    #[test]
+    fn test_route_hint() {
+        let mut session = Session::new(Cluster::new_test());
+        let query = "SELECT * FROM sharded WHERE id = 1";
+
+        // Lasts until the transaction ends.
+        let route = session.route("SET LOCAL pgdog.route = 'shard:1'", true);
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        let route = session.route(query, true);
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert_eq!(session.route("COMMIT", false).shard(), &Shard::Direct(1));
+        assert_eq!(session.route(query, false).shard(), &Shard::Direct(0));
+
+        // Lasts for the whole session.
+        session.route("SET pgdog.route TO 'all'", false);
+        for _ in 0..2 {
+            let route = session.route(query, false);
+            assert!(route.is_all_shards());
+            assert!(route.sharding_column().is_none());
+        }
+
+        session.route("SET pgdog.route TO 'primary'", false);
+        let route = session.route(query, false);
+        assert!(route.is_write());
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        // Transaction hint wins.
+        session.route("SET LOCAL pgdog.route TO 'replica'", true);
+        assert!(session.route(query, true).is_read());
+        session.route("COMMIT", false);
+        assert!(session.route(query, false).is_write());
+
+        session.route("RESET pgdog.route", false);
+        let route = session.route(query, false);
+        assert!(route.is_read());
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        assert!(matches!(
+            session.parse("SET pgdog.route TO 'shard:2'", false),
+            Err(Error::Route(RouteError::ShardOutOfRange(
+                Shard::Direct(2),
+                2
+            )))
+        ));
+        // The invalid hint isn't kept.
+        assert_eq!(session.route(query, false).shard(), &Shard::Direct(0));
+
+        // Writes can't go to a replica.
+        session.route("SET pgdog.route TO 'replica'", false);
+        for write in [
+            "UPDATE sharded SET value = 'a' WHERE id = 1",
+            "DELETE FROM sharded WHERE id = 1",
+            "SELECT * FROM sharded WHERE id = 1 FOR UPDATE",
+        ] {
+            assert!(
+                matches!(session.parse(write, false), Err(Error::WriteToReplica)),
+                "{}",
+                write
+            );
+        }
+        assert!(session.route(query, false).is_read());
+        session.route("RESET pgdog.route", false);
+        assert!(matches!(
+            session.parse("SET pgdog.route TO 'leader'", false),
+            Err(Error::SetRoute(_))
+        ));
+    }
+
+    #[test]
+    fn test_unfiltered_writes() {
+        let mut session = Session::new(Cluster::new_test());
+
+        assert!(matches!(
+            session.parse("DELETE FROM sharded", false),
+            Err(Error::Route(RouteError::MissingShardingKey("DELETE")))
+        ));
+        assert!(matches!(
+            session.parse("UPDATE sharded SET email = 'a'", false),
+            Err(Error::Route(RouteError::MissingShardingKey("UPDATE")))
+        ));
+        // Writes that aren't filtered by the sharding key are still fine.
+        assert!(session
+            .parse("DELETE FROM sharded WHERE email = 'a'", false)
+            .is_ok());
+        // So are tables that aren't sharded.
+        for query in [
+            "DELETE FROM unsharded",
+            "UPDATE sharded_omni SET email = 'a'",
+            "DELETE FROM sharded_replicated",
+        ] {
+            assert!(session.parse(query, false).is_ok(), "{}", query);
+        }
+
+        assert!(session
+            .parse("SET pgdog.allow_unfiltered_writes TO on", false)
+            .is_ok());
+        let Ok(Command::Query(route)) = session.parse("DELETE FROM sharded", false) else {
+            panic!("delete not allowed");
+        };
+        assert_eq!(route.shard(), &Shard::All);
+        assert!(route.is_write());
+        assert!(session
+            .parse("UPDATE sharded SET email = 'a'", false)
+            .is_ok());
+
+        assert!(session
+            .parse("RESET pgdog.allow_unfiltered_writes", false)
+            .is_ok());
+        assert!(session.parse("DELETE FROM sharded", false).is_err());
+
+        assert!(matches!(
+            session.parse("SET pgdog.allow_unfiltered_writes TO 'maybe'", false),
+            Err(Error::SetUnfilteredWrites)
+        ));
+
+        // Unsharded clusters don't need it.
+        assert!(Session::new(Cluster::new_test_single_shard())
+            .parse("DELETE FROM sharded", false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_reducer_comment() {
+        reducer::register("test_router", reducer::Concat);
+
+        let route = query!("/* pgdog_reducer: test_router */ SELECT * FROM sharded");
+        assert_eq!(route.reducer(), Some("test_router"));
+        assert!(route.should_buffer());
+
+        // Single shard doesn't need one.
+        let route = query!("/* pgdog_reducer: test_router */ SELECT * FROM sharded WHERE id = 1");
+        assert!(route.reducer().is_none());
+        assert!(query!("SELECT * FROM sharded").reducer().is_none());
+
+        let cluster = Cluster::new_test();
+        let mut stmt = PreparedStatements::default();
+        let params = Parameters::default();
+        let buffer = Buffer::from(vec![Query::new(
+            "/* pgdog_reducer: test_router_missing */ SELECT * FROM sharded",
+        )
+        .into()]);
+        let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+        assert!(matches!(
+            QueryParser::default().parse(context),
+            Err(Error::UnknownReducer(name)) if name == "test_router_missing"
+        ));
+    }
+
+    #[test]
+    fn test_role_comment() {
+        let route = query!("SELECT * FROM sharded WHERE id = 1");
+        assert!(route.is_read());
+
+        for query in [
+            "/* pgdog_role: primary */ SELECT * FROM sharded WHERE id = 1",
+            "SELECT * FROM sharded WHERE id = 1 /* pgdog_role: Primary */",
+        ] {
+            let route = query!(query);
+            assert!(route.is_write(), "{}", query);
+            assert_eq!(route.shard(), &Shard::Direct(0), "{}", query);
+        }
+
+        let route = query!("/* pgdog_role: replica */ SELECT * FROM sharded WHERE id = 1");
+        assert!(route.is_read());
+        let route = query!("/* pgdog_role: primary */ SELECT 1");
+        assert!(route.is_write());
+        let route = query!("/* pgdog_role: primary */ INSERT INTO sharded (id) VALUES (1)");
+        assert!(route.is_write());
+
+        let mut session = Session::new(Cluster::new_test());
+
+        for query in [
+            "/* pgdog_role: replica */ INSERT INTO sharded (id) VALUES (1)",
+            "/* pgdog_role: replica */ UPDATE sharded SET value = 'a' WHERE id = 1",
+            "/* pgdog_role: replica */ SELECT * FROM sharded WHERE id = 1 FOR UPDATE",
+            "/* pgdog_role: replica */ SELECT nextval('seq')",
+        ] {
+            assert!(
+                matches!(session.parse(query, false), Err(Error::WriteToReplica)),
+                "{}",
+                query
+            );
+        }
+
+        assert!(matches!(
+            session.parse("/* pgdog_role: leader */ SELECT 1", false),
+            Err(Error::UnknownRole(role)) if role == "leader"
+        ));
+    }
+
+    #[test]
+    fn test_read_only_snapshot() {
+        let cluster = Cluster::new_test();
+
+        for (begin, select, snapshot) in [
+            (
+                "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY",
+                "SELECT * FROM sharded",
+                true,
+            ),
+            (
+                "START TRANSACTION READ ONLY, ISOLATION LEVEL SERIALIZABLE",
+                "SELECT * FROM sharded WHERE id IN (1, 11)",
+                true,
+            ),
+            (
+                "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY",
+                "SELECT * FROM sharded WHERE id = 1",
+                false,
+            ),
+            (
+                "BEGIN ISOLATION LEVEL REPEATABLE READ",
+                "SELECT * FROM sharded",
+                false,
+            ),
+            (
+                "BEGIN ISOLATION LEVEL READ COMMITTED READ ONLY",
+                "SELECT * FROM sharded",
+                false,
+            ),
+            ("BEGIN", "SELECT * FROM sharded", false),
+        ] {
+            let (_, mut qp) = command!(begin);
+            let buffer = Buffer::from(vec![Query::new(select).into()]);
+            let mut stmts = PreparedStatements::default();
+            let params = Parameters::default();
+            let context = RouterContext::new(&buffer, &cluster, &mut stmts, &params, true).unwrap();
+            let Command::Query(route) = qp.parse(context).unwrap().clone() else {
+                panic!("not a query");
+            };
+            assert_eq!(
+                route.read_only_snapshot(),
+                snapshot,
+                "{}; {}",
+                begin,
+                select
+            );
+        }
+    }
+
+    #[test]
+    fn test_analytics_comment() {
+        let route = query!("SELECT * FROM sharded WHERE id = 1");
+        assert!(!route.is_analytics());
+
+        let route = query!("/* pgdog_pool: analytics */ SELECT * FROM sharded WHERE id = 1");
+        assert!(route.is_analytics());
+        assert!(route.is_read());
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        let route = query!("SELECT count(*) FROM sharded /* pgdog_pool: analytics */");
+        assert!(route.is_analytics());
+        assert!(route.is_read());
+
+        let mut session = Session::new(Cluster::new_test());
+
+        for query in [
+            "/* pgdog_pool: analytics */ INSERT INTO sharded (id) VALUES (1)",
+            "/* pgdog_pool: analytics */ UPDATE sharded SET value = 'a' WHERE id = 1",
+            "/* pgdog_pool: analytics */ DELETE FROM sharded WHERE id = 1",
+            "/* pgdog_pool: analytics */ SELECT * FROM sharded WHERE id = 1 FOR UPDATE",
+        ] {
+            assert!(
+                matches!(session.parse(query, false), Err(Error::WriteToAnalytics)),
+                "{}",
+                query
+            );
+        }
+
+        assert!(matches!(
+            session.parse("/* pgdog_pool: reporting */ SELECT 1", false),
+            Err(Error::UnknownPool(pool)) if pool == "reporting"
+        ));
+    }
+
+    #[test]
+    fn test_max_staleness_comment() {
+        let route = query!("SELECT * FROM sharded WHERE id = 1");
+        assert_eq!(route.max_staleness(), None);
+
+        let route = query!("/* pgdog_max_staleness: 500 */ SELECT * FROM sharded WHERE id = 1");
+        assert!(route.is_read());
+        assert_eq!(route.max_staleness(), Some(Duration::from_millis(500)));
+
+        let route = query!("SELECT 1 /* pgdog_max_staleness: 0 */");
+        assert_eq!(route.max_staleness(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_double_negation() {
+        let route = query!("SELECT * FROM sharded WHERE NOT NOT (id = 1)");
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        let route = query!("SELECT * FROM sharded WHERE NOT (id = 1)");
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_collate() {
+        let route = parse!(
+            r#"SELECT * FROM myschema.users WHERE tenant_id COLLATE "C" = $1"#,
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert_eq!(route.sharding_column(), Some("tenant_id"));
+    }
+
+    #[test]
+    fn test_alter_system() {
+        let mut session = Session::new(Cluster::new_test());
+
+        for query in [
+            "ALTER SYSTEM SET work_mem = '64MB'",
+            "ALTER SYSTEM RESET work_mem",
+            "ALTER SYSTEM RESET ALL",
+        ] {
+            let err = session.parse(query, false).unwrap_err();
+            assert!(
+                matches!(err, Error::Route(RouteError::Unsupported("ALTER SYSTEM"))),
+                "{}",
+                query
+            );
+            let response = ErrorResponse::from(err.route_error().unwrap());
+            assert_eq!(response.code, "0A000");
+            assert!(response
+                .detail
+                .unwrap()
+                .contains("connect to each server directly"));
+        }
+    }
+
+    #[test]
+    fn test_parse_failed() {
+        let mut session = Session::new(Cluster::new_test());
+
+        for buffer in [
+            Buffer::from(vec![Query::new("SELEKT 1").into()]),
+            Buffer::from(vec![
+                Parse::named("test", "SELECT * FROM").into(),
+                Sync.into(),
+            ]),
+        ] {
+            let err = session.parse_buffer(buffer, false).unwrap_err();
+            assert!(matches!(
+                err.route_error(),
+                Some(RouteError::ParseFailed(_))
+            ));
+            assert_eq!(
+                ErrorResponse::from(err.route_error().unwrap()).code,
+                "42601"
+            );
+        }
+    }
+
+    #[test]
+    fn test_limit_offset() {
+        let route = query!("SELECT * FROM users LIMIT 25 OFFSET 5");
+        assert_eq!(route.limit().offset, Some(5));
+        assert_eq!(route.limit().limit, Some(25));
+
+        let cmd = parse!(
+            "SELECT * FROM users LIMIT $1 OFFSET $2",
+            &["1".as_bytes(), "25".as_bytes(),]
+        );
+
+        assert_eq!(cmd.limit().limit, Some(1));
+        assert_eq!(cmd.limit().offset, Some(25));
+    }
+
+    #[test]
+    fn test_close_direct_one_shard() {
+        let cluster = Cluster::new_test_single_shard();
+        let mut qp = QueryParser::default();
+
+        let buf: Buffer = vec![Close::named("test").into(), Sync.into()].into();
+        let mut pp = PreparedStatements::default();
+        let params = Parameters::default();
+
+        let context = RouterContext::new(&buf, &cluster, &mut pp, &params, false).unwrap();
+
+        let cmd = qp.parse(context).unwrap();
+
+        match cmd {
+            Command::Query(route) => assert_eq!(route.shard(), &Shard::Direct(0)),
+            _ => panic!("not a query"),
+        }
+    }
+
+    #[test]
+    fn test_distinct() {
+        let route = query!("SELECT DISTINCT * FROM users");
+        let distinct = route.distinct().as_ref().unwrap();
+        assert_eq!(distinct, &DistinctBy::Row);
+
+        let route = query!("SELECT DISTINCT ON(1, email) * FROM users");
+        let distinct = route.distinct().as_ref().unwrap();
+        assert_eq!(
+            distinct,
+            &DistinctBy::Columns(vec![
+                DistinctColumn::Index(0),
+                DistinctColumn::Name(std::string::String::from("email"))
+            ])
+        );
+
+        let route = query!("SELECT DISTINCT status FROM events ORDER BY status");
+        assert_eq!(route.distinct(), &Some(DistinctBy::Row));
+        assert!(matches!(route.order_by(), [OrderBy::AscColumn(name)] if name == "status"));
+        assert!(route.should_buffer());
+    }
+
+    #[test]
+    fn test_any() {
+        let route = query!("SELECT * FROM sharded WHERE id = ANY('{1, 2, 3}')");
+        assert_eq!(route.shard(), &Shard::All);
+
+        for query in [
+            "SELECT * FROM sharded WHERE id = ANY('{1}')",
+            "SELECT * FROM sharded WHERE id = ANY('{1,NULL}'::bigint[])",
+            "SELECT * FROM sharded WHERE id = ANY('{\"1\", 1}'::int[]) AND value = 'a'",
+            "DELETE FROM sharded WHERE id = ANY('{1}')",
+        ] {
+            assert_eq!(query!(query).shard(), &Shard::Direct(0), "{}", query);
+        }
+        let route = query!("SELECT * FROM sharded WHERE id = ANY('{11,1}'::int[])");
+        assert_eq!(route.shard(), &Shard::All);
+
+        // Multidimensional arrays aren't split.
+        let route = query!("SELECT * FROM sharded WHERE id = ANY('{{1},{1}}'::int[])");
+        assert_eq!(route.shard(), &Shard::All);
+
+        let route = parse!(
+            "SELECT * FROM sharded WHERE id = ANY($1)",
+            &["{1, 2, 3}".as_bytes()]
+        );
+
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_copy_query() {
+        let route = query!("COPY (SELECT * FROM sharded WHERE id = 11) TO STDOUT");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.is_write());
+
+        let route = parse!(
+            "COPY (SELECT * FROM sharded WHERE id = $1) TO STDOUT",
+            &["1".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        // Not keyed: rows from all shards.
+        let route = query!("COPY (SELECT * FROM sharded) TO STDOUT (FORMAT CSV)");
+        assert_eq!(route.shard(), &Shard::All);
+        let route = query!("COPY (SELECT * FROM sharded) TO STDOUT (FORMAT CSV, HEADER false)");
+        assert_eq!(route.shard(), &Shard::All);
+
+        // Headers and trailers can only come from one shard.
+        let route = query!("COPY (SELECT * FROM sharded WHERE id = 1) TO STDOUT CSV HEADER");
+        assert_eq!(route.shard(), &Shard::Direct(0));
+        let cluster = Cluster::new_test();
+        for query in [
+            "COPY (SELECT * FROM sharded) TO STDOUT CSV HEADER",
+            "COPY (SELECT * FROM sharded) TO STDOUT (FORMAT csv, HEADER)",
+            "COPY (SELECT * FROM sharded) TO STDOUT (FORMAT binary)",
+        ] {
+            let buffer = Buffer::from(vec![Query::new(query).into()]);
+            let mut stmt = PreparedStatements::default();
+            let params = Parameters::default();
+            let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+            assert!(
+                matches!(
+                    QueryParser::default().parse(context),
+                    Err(Error::Route(RouteError::Unsupported(_)))
+                ),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_any_array_literal() {
+        let table = ShardedTable {
+            name: Some("sharded".into()),
+            column: "id".into(),
+            data_type: DataType::Bigint,
+            ..Default::default()
+        };
+        let schema = ShardingSchema {
+            shards: 4,
+            tables: ShardedTables::new(vec![table.clone()], vec![], false),
+            ..Default::default()
+        };
+        let shard = |id: &str| {
+            ContextBuilder::new(&table)
+                .data(id)
+                .shards(4)
+                .build()
+                .unwrap()
+                .apply()
+                .unwrap()
+        };
+        let ast = parse("SELECT * FROM sharded WHERE id = ANY('{1, \"2\", 3}'::int[])").unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+        let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+            panic!("not a select");
+        };
+        let Command::Query(route) = QueryParser::select(&stmt, &schema, None).unwrap() else {
+            panic!("not a query");
+        };
+
+        let mut expected = ["1", "2", "3"]
+            .into_iter()
+            .map(|id| match shard(id) {
+                Shard::Direct(shard) => shard,
+                shard => panic!("not direct: {}", shard),
+            })
+            .collect::<Vec<_>>();
+        expected.sort();
+        expected.dedup();
+        let expected = match expected.as_slice() {
+            [shard] => Shard::Direct(*shard),
+            _ => Shard::Multi(expected),
+        };
+        let mut shard = route.shard().clone();
+        if let Shard::Multi(ref mut shards) = shard {
+            shards.sort();
+        }
+        assert_eq!(shard, expected);
+    }
+
+    #[test]
+    fn test_local() {
+        let route = query!("SELECT 1");
+        assert!(route.is_local());
+        assert!(route.is_read());
+
+        let route = query!("SELECT count(*) FROM t");
+        assert!(!route.is_local());
+
+        let route = query!("SELECT now()");
+        assert!(!route.is_local());
+    }
+
+    #[test]
+    fn test_values() {
+        let route = query!("VALUES (1), (2)");
+        assert!(route.is_local());
+        assert!(route.is_read());
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+
+        let route = query!("VALUES (1), (now())");
+        assert!(!route.is_local());
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+    }
+
+    #[test]
+    fn test_should_buffer() {
+        let route = query!("SELECT * FROM sharded WHERE id = 1 ORDER BY id");
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+        assert!(!route.order_by().is_empty());
+        assert!(!route.should_buffer());
+
+        let route = query!("SELECT DISTINCT id, count(*) FROM sharded WHERE id = 1 GROUP BY id");
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+        assert!(!route.should_buffer());
+
+        let route = query!("SELECT * FROM sharded ORDER BY id");
+        assert!(route.is_all_shards());
+        assert!(route.should_buffer());
+
+        let route = query!("SELECT count(*) FROM sharded");
+        assert!(route.is_all_shards());
+        assert!(route.should_buffer());
+
+        let route = query!("SELECT * FROM sharded");
+        assert!(!route.should_buffer());
+    }
+
+    #[test]
+    fn test_explain() {
+        let route = parse!(
+            "EXPLAIN SELECT * FROM sharded WHERE id = $1",
+            ["1".as_bytes()]
+        );
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+        assert!(route.is_read());
+
+        let route = query!("EXPLAIN ANALYZE INSERT INTO sharded (id) VALUES (1)");
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+        assert!(route.is_write());
+
+        let route = query!("EXPLAIN SELECT * FROM sharded ORDER BY id");
+        assert!(route.is_all_shards());
+        assert!(!route.should_buffer());
+
+        let route = query!("EXPLAIN SELECT 1");
+        assert!(!route.is_local());
+    }
+
+    #[test]
+    fn test_limit_with_ties() {
+        let route = query!("SELECT * FROM sharded ORDER BY id FETCH FIRST 10 ROWS WITH TIES");
+        assert_eq!(route.limit().limit, Some(10));
+        assert!(route.limit().with_ties);
+
+        let route = query!("SELECT * FROM sharded ORDER BY id LIMIT 10");
+        assert!(!route.limit().with_ties);
+
+        let mut query_parser = QueryParser::default();
+        let buffer = Buffer::from(vec![Query::new(
+            "SELECT * FROM sharded FETCH FIRST 10 ROWS WITH TIES",
+        )
+        .into()]);
+        let cluster = Cluster::new_test();
+        let mut stmt = PreparedStatements::default();
+        let params = Parameters::default();
+        let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+        assert!(matches!(
+            query_parser.parse(context),
+            Err(Error::Route(RouteError::ParseFailed(_)))
+        ));
+    }
+
+    #[test]
+    fn test_set_shards() {
+        let mut session = Session::new(Cluster::new_test());
+
+        assert!(session.parse("SET pgdog.shards = '1'", false).is_ok());
+
+        // All-shard query is narrowed down.
+        match session.parse("SELECT * FROM sharded", false).unwrap() {
+            Command::Query(route) => assert_eq!(route.shard(), &Shard::Direct(1)),
+            _ => panic!("not a query"),
+        }
+
+        // Round robin only picks allowed shards.
+        for _ in 0..4 {
+            match session.parse("SELECT 1", false).unwrap() {
+                Command::Query(route) => assert_eq!(route.shard(), &Shard::Direct(1)),
+                _ => panic!("not a query"),
+            }
+        }
+
+        // Query for an excluded shard.
+        assert!(matches!(
+            session.parse("SELECT * FROM sharded WHERE id = 1", false),
+            Err(Error::ExcludedShard(Shard::Direct(0)))
+        ));
+
+        assert!(session.parse("RESET pgdog.shards", false).is_ok());
+
+        match session.parse("SELECT * FROM sharded", false).unwrap() {
+            Command::Query(route) => assert_eq!(route.shard(), &Shard::All),
+            _ => panic!("not a query"),
+        }
+
+        // Shard out of range.
+        assert!(matches!(
+            session.parse("SET pgdog.shards = '1,2'", false),
+            Err(Error::SetShard)
+        ));
+        assert!(matches!(
+            session.parse("SET pgdog.shards = 'one'", false),
+            Err(Error::SetShard)
+        ));
+    }
+
+    #[test]
+    fn test_same_parameter_different_casts() {
+        let route = parse!(
+            "SELECT * FROM sharded WHERE id = $1::int AND id = $1::bigint",
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = parse!(
+            "test",
+            "SELECT * FROM sharded WHERE id = $1::int AND id = $1::bigint",
+            [[0, 0, 0, 0, 0, 0, 0, 1]],
+            &[Format::Binary]
+        );
+        assert!(matches!(route.shard(), Shard::Direct(_)));
+    }
+
+    #[test]
+    fn test_comma_join() {
+        let route = parse!(
+            "SELECT * FROM sharded s, other o WHERE s.id = $1 AND o.user_id = s.id",
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = query!("SELECT * FROM other o, sharded s WHERE s.id = 11");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = query!("SELECT * FROM sharded s WHERE id = 11");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        // Not the sharded table.
+        let route = query!("SELECT * FROM sharded s, other o WHERE o.id = 11");
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_lateral_join() {
+        let route = parse!(
+            "SELECT * FROM sharded s, LATERAL (SELECT * FROM other o WHERE o.user_id = s.id LIMIT 5) l WHERE s.id = $1",
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = query!(
+            "SELECT * FROM sharded s JOIN LATERAL (SELECT count(*) FROM other WHERE other.id = s.id) c ON true WHERE s.id = 1"
+        );
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        // The subquery's alias isn't the sharded table.
+        let route = query!(
+            "SELECT * FROM sharded s, LATERAL (SELECT id FROM other WHERE other.user_id = s.id) s2 WHERE s2.id = 1"
+        );
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_subquery_alias() {
+        for query in [
+            "SELECT * FROM (SELECT id AS i, * FROM sharded) sub WHERE sub.i = $1",
+            "SELECT * FROM (SELECT s.id AS i FROM sharded s) sub WHERE i = $1",
+            "SELECT * FROM (SELECT * FROM sharded) sub WHERE sub.id = $1",
+            "SELECT * FROM (SELECT id, email FROM sharded WHERE email IS NOT NULL) sub WHERE sub.id = $1",
+            "SELECT * FROM other o JOIN (SELECT id AS i FROM sharded) sub ON sub.i = o.user_id WHERE sub.i = $1",
+        ] {
+            let route = parse!(query, ["11".as_bytes()]);
+            assert_eq!(route.shard(), &Shard::Direct(1), "{}", query);
+            assert_eq!(route.sharding_column(), Some("id"), "{}", query);
+        }
+
+        // Expressions, LIMIT and other columns aren't the sharding key.
+        for query in [
+            "SELECT * FROM (SELECT id + 1 AS i FROM sharded) sub WHERE sub.i = $1",
+            "SELECT * FROM (SELECT id + 1 AS id, * FROM sharded) sub WHERE sub.id = $1",
+            "SELECT * FROM (SELECT id AS i FROM sharded LIMIT 10) sub WHERE sub.i = $1",
+            "SELECT * FROM (SELECT email AS id FROM sharded) sub WHERE sub.id = $1",
+            "SELECT * FROM (SELECT id AS i FROM other) sub WHERE sub.i = $1",
+        ] {
+            let route = parse!(query, ["11".as_bytes()]);
+            assert_eq!(route.shard(), &Shard::All, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_coalesce() {
+        // Rows with a NULL key could be anywhere.
+        let route = query!("SELECT * FROM sharded WHERE COALESCE(id, 1) = 11");
+        assert_eq!(route.shard(), &Shard::All);
+
+        let schema = |coalesce_default| ShardingSchema {
+            shards: 2,
+            tables: ShardedTables::new(
+                vec![ShardedTable {
+                    name: Some("sharded".into()),
+                    column: "id".into(),
+                    data_type: DataType::Bigint,
+                    coalesce_default,
+                    ..Default::default()
+                }],
+                vec![],
+                false,
+            ),
+            ..Default::default()
+        };
+        let select = |query: &str, coalesce_default| {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let bind = Bind::test_params(
+                "",
+                &[Parameter {
+                    len: 2,
+                    data: "11".as_bytes().to_vec(),
+                }],
+            );
+            match QueryParser::select(&stmt, &schema(coalesce_default), Some(&bind)).unwrap() {
+                Command::Query(route) => route.shard().clone(),
+                _ => panic!("not a query"),
+            }
+        };
+
+        // Value and default go to different shards.
+        for query in [
+            "SELECT * FROM sharded WHERE COALESCE(id, 1) = 11",
+            "SELECT * FROM sharded WHERE $1 = COALESCE(sharded.id, 1)",
+        ] {
+            match select(query, true) {
+                Shard::Multi(mut shards) => {
+                    shards.sort();
+                    assert_eq!(shards, vec![0, 1], "{}", query);
+                }
+                shard => panic!("expected multi-shard route, got {}: {}", shard, query),
+            }
+            assert_eq!(select(query, false), Shard::All, "{}", query);
+        }
+
+        // Same shard.
+        assert_eq!(
+            select("SELECT * FROM sharded WHERE COALESCE(id, 11) = $1", true),
+            Shard::Direct(1)
+        );
+    }
+
+    #[test]
+    fn test_or_sharding_columns() {
+        let table = |column: &str| ShardedTable {
+            name: Some("accounts".into()),
+            column: column.into(),
+            data_type: DataType::Bigint,
+            ..Default::default()
+        };
+        let schema = ShardingSchema {
+            shards: 3,
+            tables: ShardedTables::new(
+                vec![table("tenant_id"), table("customer_key")],
+                vec![],
+                false,
+            ),
+            ..Default::default()
+        };
+        let select = |query: &str| {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let bind = Bind::test_params(
+                "",
+                &[
+                    Parameter {
+                        len: 1,
+                        data: "1".as_bytes().to_vec(),
+                    },
+                    Parameter {
+                        len: 1,
+                        data: "2".as_bytes().to_vec(),
+                    },
+                ],
+            );
+            match QueryParser::select(&stmt, &schema, Some(&bind)).unwrap() {
+                Command::Query(route) => route.shard().clone().normalize(3),
+                command => panic!("not a query: {:?}", command),
+            }
+        };
+
+        // Find a customer on another shard than tenant 1.
+        let tenant = select("SELECT * FROM accounts WHERE tenant_id = 1");
+        let (customer, shard) = (1..100)
+            .map(|customer| {
+                let shard = select(&format!(
+                    "SELECT * FROM accounts WHERE customer_key = {}",
+                    customer
+                ));
+                (customer, shard)
+            })
+            .find(|(_, shard)| shard != &tenant)
+            .unwrap();
+        let (Shard::Direct(tenant), Shard::Direct(shard)) = (tenant, shard) else {
+            panic!("not direct");
+        };
+        let mut expected = vec![tenant, shard];
+        expected.sort();
+
+        for query in [
+            format!(
+                "SELECT * FROM accounts WHERE tenant_id = 1 OR customer_key = {}",
+                customer
+            ),
+            format!(
+                "SELECT * FROM accounts WHERE status = 'a' AND (customer_key = {} OR tenant_id = 1)",
+                customer
+            ),
+            format!(
+                "SELECT * FROM accounts WHERE tenant_id = 1 OR (customer_key = {} AND status = 'a')",
+                customer
+            ),
+        ] {
+            assert_eq!(select(&query), Shard::Multi(expected.clone()), "{}", query);
+        }
+
+        // Parameters work the same way.
+        assert_eq!(
+            select("SELECT * FROM accounts WHERE tenant_id = $1 OR customer_key = $2"),
+            select("SELECT * FROM accounts WHERE tenant_id = 1 OR customer_key = 2"),
+        );
+
+        // Both branches on the same shard.
+        assert_eq!(
+            select("SELECT * FROM accounts WHERE tenant_id = 1 OR customer_key = 1"),
+            select("SELECT * FROM accounts WHERE tenant_id = 1"),
+        );
+
+        // One of the branches isn't filtering on a sharding key.
+        for query in [
+            "SELECT * FROM accounts WHERE tenant_id = 1 OR status = 'a'",
+            "SELECT * FROM accounts WHERE tenant_id = 1 OR customer_key > 5",
+            "SELECT * FROM accounts WHERE tenant_id = 1 OR NOT customer_key = 2",
+        ] {
+            assert_eq!(select(query), Shard::All, "{}", query);
+        }
+
+        let route = query!("SELECT * FROM sharded WHERE id = 11 OR id = 11");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert_eq!(route.sharding_column(), Some("id"));
+        assert_eq!(
+            route.sharding_keys(),
+            &[
+                Key::Constant {
+                    value: "11".into(),
+                    array: false
+                },
+                Key::Constant {
+                    value: "11".into(),
+                    array: false
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_operator_class() {
+        use crate::config::Hasher;
+
+        let select = |data_type, hasher, tenant: &str| {
+            let schema = ShardingSchema {
+                shards: 2,
+                tables: ShardedTables::new(
+                    vec![ShardedTable {
+                        name: Some("accounts".into()),
+                        column: "tenant_id".into(),
+                        data_type,
+                        hasher,
+                        ..Default::default()
+                    }],
+                    vec![],
+                    false,
+                ),
+                ..Default::default()
+            };
+            let ast = parse("SELECT * FROM accounts WHERE tenant_id = $1").unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let bind = Bind::test_params(
+                "",
+                &[Parameter {
+                    len: tenant.len() as i32,
+                    data: tenant.as_bytes().to_vec(),
+                }],
+            );
+            match QueryParser::select(&stmt, &schema, Some(&bind)).unwrap() {
+                Command::Query(route) => route.shard().clone(),
+                command => panic!("not a query: {:?}", command),
+            }
+        };
+
+        // Default operator classes hash like the data type's default.
+        for (data_type, hasher, tenant) in [
+            (DataType::Bigint, Hasher::Int8Ops, "11"),
+            (DataType::Varchar, Hasher::TextOps, "tenant"),
+            (
+                DataType::Uuid,
+                Hasher::UuidOps,
+                "1a9e3b4c-5d6f-4a8b-9c0d-1e2f3a4b5c6d",
+            ),
+        ] {
+            let expected = select(data_type.clone(), Hasher::Postgres, tenant);
+            assert!(matches!(expected, Shard::Direct(_)));
+            assert_eq!(select(data_type, hasher, tenant), expected);
+        }
+    }
+
+    #[test]
+    fn test_array_sharding_column() {
+        let table = |array| ShardedTable {
+            name: Some("groups".into()),
+            column: "tenant_ids".into(),
+            data_type: DataType::Bigint,
+            array,
+            ..Default::default()
+        };
+        let schema = |array| ShardingSchema {
+            shards: 3,
+            tables: ShardedTables::new(vec![table(array)], vec![], false),
+            ..Default::default()
+        };
+        let select = |query: &str, array| {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let bind = Bind::test_params(
+                "",
+                &[Parameter {
+                    len: 1,
+                    data: "5".as_bytes().to_vec(),
+                }],
+            );
+            match QueryParser::select(&stmt, &schema(array), Some(&bind)).unwrap() {
+                Command::Query(route) => route.shard().clone(),
+                command => panic!("not a query: {:?}", command),
+            }
+        };
+        let expected = ContextBuilder::new(&table(true))
+            .data("5")
+            .shards(3)
+            .build()
+            .unwrap()
+            .apply()
+            .unwrap();
+        assert!(matches!(expected, Shard::Direct(_)));
+
+        for query in [
+            "SELECT * FROM groups WHERE $1 = ANY(tenant_ids)",
+            "SELECT * FROM groups g WHERE $1::bigint = ANY(g.tenant_ids) AND name = 'a'",
+            "SELECT * FROM groups WHERE 5 = ANY(tenant_ids)",
+        ] {
+            assert_eq!(select(query, true), expected, "{}", query);
+
+            // Elements can be on different shards.
+            assert_eq!(select(query, false), Shard::All, "{}", query);
+        }
+
+        // Not a single key.
+        for query in [
+            "SELECT * FROM groups WHERE tenant_ids = $1",
+            "SELECT * FROM groups WHERE tenant_ids @> ARRAY[$1]",
+            "SELECT * FROM groups WHERE tenant_ids = ANY($1)",
+        ] {
+            assert_eq!(select(query, true), Shard::All, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_composite_sharding_key() {
+        let schema = ShardingSchema {
+            shards: 3,
+            tables: ShardedTables::new(
+                vec![ShardedTable {
+                    name: Some("ledger".into()),
+                    composite: vec!["account_id".into(), "region".into()],
+                    ..Default::default()
+                }],
+                vec![],
+                false,
+            ),
+            ..Default::default()
+        };
+        let select = |query: &str| {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let bind = Bind::test_params(
+                "",
+                &[
+                    Parameter {
+                        len: 4,
+                        data: "acme".as_bytes().to_vec(),
+                    },
+                    Parameter {
+                        len: 2,
+                        data: "42".as_bytes().to_vec(),
+                    },
+                ],
+            );
+            match QueryParser::select(&stmt, &schema, Some(&bind)).unwrap() {
+                Command::Query(route) => route.shard().clone(),
+                command => panic!("not a query: {:?}", command),
+            }
+        };
+        // hash(concat(42, 'acme'))
+        let expected =
+            Shard::Direct(crate::frontend::router::sharding::varchar(b"42acme") as usize % 3);
+
+        for query in [
+            "SELECT * FROM ledger WHERE account_id = 42 AND region = 'acme'",
+            "SELECT * FROM ledger WHERE region = 'acme' AND account_id = 42",
+            "SELECT * FROM ledger l WHERE l.region = $1 AND l.account_id = $2",
+        ] {
+            assert_eq!(select(query), expected, "{}", query);
+        }
+
+        // Every column needs exactly one value.
+        for query in [
+            "SELECT * FROM ledger WHERE account_id = 42",
+            "SELECT * FROM ledger WHERE account_id = 42 AND region IN ('acme', 'globex')",
+            "SELECT * FROM ledger WHERE (account_id = 42 OR account_id = 43) AND region = 'acme'",
+        ] {
+            assert_eq!(select(query), Shard::All, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_partitioned_parent() {
+        // `events` is partitioned by `created_at` on each shard.
+        // Shards prune partitions themselves, we only need the parent's key.
+        let table = ShardedTable {
+            name: Some("events".into()),
+            column: "tenant_id".into(),
+            data_type: DataType::Bigint,
+            ..Default::default()
+        };
+
+        for query in [
+            "SELECT * FROM events WHERE tenant_id = $1 AND created_at >= '2024-01-01' AND created_at < '2024-02-01'",
+            "SELECT * FROM ONLY events WHERE tenant_id = $1",
+            "SELECT count(*) FROM events e WHERE e.tenant_id = $1 AND e.created_at > now() - interval '1 day'",
+            "UPDATE events SET payload = '{}' WHERE created_at < '2024-01-01' AND tenant_id = $1",
+            "INSERT INTO events (tenant_id, created_at, payload) VALUES ($1, now(), '{}')",
+        ] {
+            let (route, expected) = route_bound(&table, query);
+            assert_eq!(route.shard(), &expected, "{}", query);
+            assert_eq!(route.sharding_column(), Some("tenant_id"), "{}", query);
+        }
+
+        // The partition key isn't the sharding key.
+        let (route, _) = route_bound(
+            &table,
+            "SELECT * FROM events WHERE created_at >= '2024-01-01'",
+        );
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_view_sharding() {
+        // CREATE VIEW active_users AS SELECT * FROM users WHERE active
+        let table = ShardedTable {
+            name: Some("users".into()),
+            column: "tenant_id".into(),
+            data_type: DataType::Bigint,
+            views: vec!["active_users".into()],
+            ..Default::default()
+        };
+
+        for query in [
+            "SELECT * FROM users WHERE tenant_id = $1",
+            "SELECT * FROM active_users WHERE tenant_id = $1",
+            "SELECT u.email FROM active_users u WHERE u.tenant_id = $1",
+        ] {
+            let (route, expected) = route_bound(&table, query);
+            assert_eq!(route.shard(), &expected, "{}", query);
+            assert_eq!(route.sharding_column(), Some("tenant_id"), "{}", query);
+        }
+
+        // Views that aren't in the config aren't sharded.
+        let (route, _) = route_bound(&table, "SELECT * FROM inactive_users WHERE tenant_id = $1");
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_parameter_out_of_range() {
+        let cluster = Cluster::new_test();
+        let mut stmt = PreparedStatements::default();
+        let params = Parameters::default();
+        let mut parse = |query: &str| {
+            let bind = Bind::test_params(
+                "",
+                &[
+                    Parameter {
+                        len: 1,
+                        data: "1".as_bytes().to_vec(),
+                    },
+                    Parameter {
+                        len: 1,
+                        data: "a".as_bytes().to_vec(),
+                    },
+                ],
+            );
+            let buffer = Buffer::from(vec![Parse::new_anonymous(query).into(), bind.into()]);
+            let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+            QueryParser::default().parse(context).cloned()
+        };
+
+        for query in [
+            "SELECT * FROM sharded WHERE id = $3",
+            "SELECT * FROM sharded WHERE value = $2 AND id = $3",
+            "UPDATE sharded SET value = $2 WHERE id = $3",
+            "DELETE FROM sharded WHERE id = $3",
+            "INSERT INTO sharded (id, value) VALUES ($3, $2)",
+        ] {
+            assert_eq!(
+                parse(query).unwrap_err().route_error(),
+                Some(&RouteError::ParameterOutOfRange(3, 2)),
+                "{}",
+                query
+            );
+        }
+
+        // Literal keys don't use the bound parameters.
+        let mut stmt = PreparedStatements::default();
+        let mut parse_unbound = |query: &str| {
+            let buffer = Buffer::from(vec![
+                Parse::new_anonymous(query).into(),
+                Bind::test_params("", &[]).into(),
+            ]);
+            let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+            QueryParser::default().parse(context).cloned()
+        };
+        let Ok(Command::Query(route)) =
+            parse_unbound("INSERT INTO sharded (id, value) VALUES (11, 'a')")
+        else {
+            panic!("not a query");
+        };
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        // `$1` is the value, not the key.
+        let Ok(Command::Query(route)) = parse("INSERT INTO sharded (id, value) VALUES (11, $1)")
+        else {
+            panic!("not a query");
+        };
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let Ok(Command::Query(route)) = parse("SELECT * FROM sharded WHERE id = $1 AND value = $2")
+        else {
+            panic!("not a query");
+        };
+        assert_eq!(route.shard(), &Shard::Direct(0));
+    }
+
+    #[test]
+    fn test_sharding_column() {
+        for query in [
+            "SELECT * FROM sharded WHERE id = 11",
+            "UPDATE sharded SET email = 'test' WHERE id = 11",
+            "DELETE FROM sharded WHERE id = 11",
+            "INSERT INTO sharded (id, email) VALUES (11, 'test')",
+        ] {
+            let route = query!(query);
+            assert!(!route.is_all_shards(), "{}", query);
+            assert_eq!(route.sharding_column(), Some("id"), "{}", query);
+        }
+
+        let route = parse!("SELECT * FROM sharded WHERE id = $1", ["11".as_bytes()]);
+        assert_eq!(route.sharding_column(), Some("id"));
+
+        for query in [
+            "SELECT * FROM sharded",
+            "SELECT * FROM sharded WHERE email = 'test'",
+            "SELECT * FROM sharded WHERE id = 1 OR id = 11",
+            "SELECT * FROM sharded WHERE id IN (1, 11)",
+            "UPDATE sharded SET email = 'test' WHERE email = 'a'",
+            "SELECT * FROM sharded WHERE COALESCE(id, 1) = 11",
+        ] {
+            let route = query!(query);
+            assert!(route.is_all_shards(), "{}", query);
+            assert_eq!(route.sharding_column(), None, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_system_columns() {
+        let route = query!("SELECT * FROM sharded WHERE ctid = '(0,1)'");
+        assert!(route.is_all_shards());
+        assert!(route.is_read());
+
+        let route = query!("SELECT * FROM sharded WHERE ctid = '(0,1)' AND id = 11");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+    }
+
+    #[test]
+    fn test_aggregate_buffering() {
+        let route = query!("SELECT * FROM sharded");
+        assert!(route.aggregate().is_empty());
+        assert!(!route.should_buffer());
+
+        let route = query!("SELECT COUNT(*) FROM sharded");
+        assert!(!route.aggregate().is_empty());
+        assert!(route.should_buffer());
+
+        let route = query!("SELECT email FROM sharded GROUP BY 1");
+        assert!(route.should_buffer());
+    }
+
+    #[test]
+    fn test_declare_cursor() {
+        let route = parse!(
+            "DECLARE c CURSOR FOR SELECT * FROM sharded WHERE id = $1",
+            ["11".as_bytes()]
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.is_read());
+        assert!(!route.lock_session());
+
+        let (command, qp) = command!("DECLARE c CURSOR FOR SELECT * FROM sharded WHERE id = 11");
+        assert!(qp.routed);
+        match command {
+            Command::Query(route) => assert_eq!(route.shard(), &Shard::Direct(1)),
+            _ => panic!("not a query"),
+        }
+
+        let route = query!("DECLARE c CURSOR WITH HOLD FOR SELECT * FROM sharded WHERE id = 11");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.lock_session());
+
+        let route = query!("DECLARE c CURSOR FOR SELECT 1");
+        assert!(!route.is_local());
+
+        let buffer = Buffer::from(vec![Query::new(
+            "DECLARE c CURSOR WITH HOLD FOR SELECT * FROM sharded",
+        )
+        .into()]);
+        let cluster = Cluster::new_test();
+        let mut stmt = PreparedStatements::default();
+        let params = Parameters::default();
+        let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+        assert!(matches!(
+            QueryParser::default().parse(context),
+            Err(Error::CrossShardCursor)
+        ));
+    }
+
+    #[test]
+    fn test_buffered_cursor() {
+        let mut session = Session::new(Cluster::new_test());
+
+        match session
+            .parse(
+                "DECLARE c CURSOR FOR SELECT * FROM sharded ORDER BY id DESC",
+                true,
+            )
+            .unwrap()
+        {
+            Command::DeclareCursor { name, query, route } => {
+                assert_eq!(name, "c");
+                assert_eq!(query, "SELECT * FROM sharded ORDER BY id DESC");
+                assert!(route.is_all_shards());
+                assert!(!route.order_by().first().unwrap().asc());
+            }
+            command => panic!("not a cursor: {:?}", command),
+        }
+
+        match session.parse("FETCH BACKWARD 2 FROM c", true).unwrap() {
+            Command::Fetch(fetch) => {
+                assert_eq!(fetch.name, "c");
+                assert_eq!(fetch.direction, Direction::Backward(2));
+                assert!(!fetch.moves);
+            }
+            command => panic!("not a fetch: {:?}", command),
+        }
+        assert!(matches!(
+            session.parse("MOVE NEXT IN c", true).unwrap(),
+            Command::Fetch(Fetch { moves: true, .. })
+        ));
+
+        // Cursors on one shard are fetched from the shard.
+        assert!(matches!(
+            session.parse("FETCH 1 FROM other", true).unwrap(),
+            Command::Query(_)
+        ));
+
+        assert!(
+            matches!(session.parse("CLOSE c", true).unwrap(), Command::CloseCursor(name) if name == "c")
+        );
+        assert!(matches!(
+            session.parse("FETCH 1 FROM c", true).unwrap(),
+            Command::Query(_)
+        ));
+
+        // Cursors go away with the transaction.
+        session
+            .parse(
+                "DECLARE c CURSOR FOR SELECT * FROM sharded ORDER BY id",
+                true,
+            )
+            .unwrap();
+        session.qp.reset();
+        assert!(matches!(
+            session.parse("FETCH 1 FROM c", true).unwrap(),
+            Command::Query(_)
+        ));
+    }
+
+    #[test]
+    fn test_current_of() {
+        let mut session = Session::new(Cluster::new_test());
+
+        session
+            .parse(
+                "DECLARE b CURSOR FOR SELECT * FROM sharded ORDER BY id",
+                true,
+            )
+            .unwrap();
+        // Fetching from the buffered cursor routes the transaction again.
+        assert!(matches!(
+            session.parse("FETCH 1 FROM b", true),
+            Ok(Command::Fetch(_))
+        ));
+        session
+            .parse(
+                "DECLARE c CURSOR FOR SELECT * FROM myschema.users WHERE tenant_id = 11",
+                true,
+            )
+            .unwrap();
+
+        for query in [
+            "DELETE FROM myschema.users WHERE CURRENT OF c",
+            "UPDATE myschema.users SET email = 'a' WHERE CURRENT OF c",
+        ] {
+            match session.parse(query, true) {
+                Ok(Command::Query(route)) => {
+                    assert_eq!(route.shard(), &Shard::Direct(1), "{}", query);
+                    assert!(route.is_write());
+                }
+                command => panic!("not a query: {:?}", command),
+            }
+        }
+
+        assert!(matches!(
+            session.parse("DELETE FROM sharded WHERE CURRENT OF b", true),
+            Err(Error::CurrentOfCrossShard(name)) if name == "b"
+        ));
+        // Cursors we didn't see declared are on the transaction's shard.
+        match session.parse("UPDATE sharded SET v = 1 WHERE CURRENT OF unknown", true) {
+            Ok(Command::Query(route)) => assert_eq!(route.shard(), &Shard::Direct(1)),
+            command => panic!("not a query: {:?}", command),
+        }
+
+        // The cursor is gone after CLOSE.
+        session.parse("CLOSE c", true).unwrap();
+        assert!(!session.qp.cursor_shards.contains_key("c"));
+    }
+
+    #[test]
+    fn test_current_of_refcursor() {
+        let update = "UPDATE sharded SET v = 1 WHERE CURRENT OF refcur";
+
+        // Cursors only exist in transactions.
+        assert!(matches!(
+            Session::new(Cluster::new_test()).parse(update, false),
+            Err(Error::UnknownCursor(name)) if name == "refcur"
+        ));
+
+        // The transaction hasn't picked its shards yet.
+        match Session::new(Cluster::new_test()).parse(update, true) {
+            Ok(Command::Query(route)) => assert!(route.is_write()),
+            command => panic!("not a query: {:?}", command),
+        }
+
+        // A function opened the cursor on the transaction's shard.
+        let mut session = Session::new(Cluster::new_test());
+        let select = "SELECT open_cursor('refcur') FROM sharded WHERE id = 11";
+        session.parse(select, true).unwrap();
+        match session.parse(update, true) {
+            Ok(Command::Query(route)) => {
+                assert_eq!(route.shard(), &Shard::Direct(1));
+                assert!(route.is_write());
+            }
+            command => panic!("not a query: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_ordered_set_aggregates() {
+        let route = query!(
+            "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM sharded WHERE id = 11"
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.shard_queries().is_empty());
+
+        // Shards return the input values and we compute the aggregates.
+        let route = query!(
+            "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM sharded WHERE v > 0"
+        );
+        assert!(route.is_all_shards());
+        assert_eq!(route.aggregate().ordered_sets().len(), 1);
+        assert_eq!(
+            route.shard_queries(),
+            &std::collections::BTreeMap::from([
+                (0, "SELECT v FROM sharded WHERE v > 0".to_string()),
+                (1, "SELECT v FROM sharded WHERE v > 0".to_string()),
+            ])
+        );
+
+        let route =
+            query!("SELECT mode() WITHIN GROUP (ORDER BY v) FROM sharded WHERE id IN (1, 11)");
+        assert!(route.is_all_shards());
+        assert_eq!(route.shard_queries().len(), 2);
+
+        // Can't be computed from the input values. Only simple queries are rewritten.
+        let extended = "SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM sharded";
+        for buffer in [
+            "SELECT id, percentile_disc(0.9) WITHIN GROUP (ORDER BY v) FROM sharded GROUP BY id",
+            "SELECT round(percentile_cont(0.5) WITHIN GROUP (ORDER BY v)) FROM sharded",
+            "SELECT * FROM (SELECT percentile_cont(0.5) WITHIN GROUP (ORDER BY v) FROM sharded) t",
+        ]
+        .into_iter()
+        .map(|query| Buffer::from(vec![Query::new(query).into()]))
+        .chain([Buffer::from(vec![
+            Parse::named("", extended).into(),
+            Bind::test_params("", &[]).into(),
+        ])]) {
+            assert!(
+                matches!(
+                    Session::new(Cluster::new_test()).parse_buffer(buffer.clone(), false),
+                    Err(Error::CrossShardOrderedSet)
+                ),
+                "{:?}",
+                buffer
+            );
+        }
+
+        // Regular aggregates are merged.
+        assert!(query!("SELECT count(*) FROM sharded").is_all_shards());
+    }
+
+    #[test]
+    fn test_window_functions() {
+        let route =
+            query!("SELECT id, row_number() OVER (ORDER BY created_at) FROM sharded WHERE id = 11");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        for query in [
+            "SELECT id, row_number() OVER (ORDER BY created_at) FROM sharded",
+            "SELECT id, sum(v) OVER w FROM sharded WINDOW w AS (PARTITION BY id)",
+            "SELECT rank() OVER () + 1 FROM sharded WHERE id IN (1, 11)",
+            "SELECT * FROM (SELECT lag(v) OVER (ORDER BY id) FROM sharded) t",
+        ] {
+            assert!(
+                matches!(
+                    Session::new(Cluster::new_test()).parse(query, false),
+                    Err(Error::CrossShardWindow)
+                ),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_order_by_random() {
+        let route = query!("SELECT * FROM sharded WHERE id = 11 ORDER BY random() LIMIT 5");
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(!route.should_buffer());
+
+        for query in [
+            "SELECT * FROM sharded ORDER BY random() LIMIT 5",
+            "SELECT * FROM sharded ORDER BY random(), id",
+        ] {
+            let route = query!(query);
+            assert!(route.is_all_shards(), "{}", query);
+            assert!(route.order_by().is_empty(), "{}", query);
+            assert!(!route.should_buffer(), "{}", query);
+        }
+
+        // Ties are broken randomly, rows are still sorted by id.
+        let route = query!("SELECT * FROM sharded ORDER BY id, random()");
+        assert_eq!(route.order_by().len(), 1);
+        assert!(matches!(&route.order_by()[0], OrderBy::AscColumn(column) if column == "id"));
+    }
+
+    #[test]
+    fn test_require_order_by() {
+        let mut cluster = Cluster::new_test();
+        cluster.set_require_order_by(true);
+        let mut session = Session::new(cluster);
+
+        assert!(matches!(
+            session.parse("SELECT * FROM sharded", false),
+            Err(Error::CrossShardUnordered)
+        ));
+        assert!(matches!(
+            session.parse("SELECT id, count(*) FROM sharded GROUP BY id", false),
+            Err(Error::CrossShardUnordered)
+        ));
+
+        for query in [
+            "SELECT * FROM sharded ORDER BY id",
+            "SELECT * FROM sharded WHERE id = 11",
+            "SELECT count(*) FROM sharded",
+        ] {
+            assert!(session.parse(query, false).is_ok(), "{}", query);
+        }
+
+        // Opt-in only.
+        let route = query!("SELECT * FROM sharded");
+        assert!(route.is_unordered());
+    }
+
+    #[test]
+    fn test_catalog() {
+        for query in [
+            "SELECT * FROM pg_catalog.pg_class",
+            "SELECT * FROM information_schema.columns WHERE table_name = 'sharded'",
+            "SELECT c.relname FROM pg_catalog.pg_class c \
+            JOIN pg_catalog.pg_namespace n ON n.oid = c.relnamespace",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::Direct(0), "{}", query);
+            assert!(route.is_write(), "{}", query);
+        }
+
+        for query in [
+            "SELECT * FROM pg_catalog.pg_class JOIN sharded ON sharded.id = pg_class.oid",
+            "SELECT * FROM public.pg_things",
+            "SELECT * FROM pg_things",
+        ] {
+            let route = query!(query);
+            assert_eq!(route.shard(), &Shard::All, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_max_fanout_shards() {
+        let mut cluster = Cluster::new_test();
+        cluster.set_max_fanout_shards(Some(1));
+        let mut session = Session::new(cluster);
+
+        for query in [
+            "SELECT * FROM sharded",
+            "SELECT * FROM sharded WHERE id IN (1, 11)",
+            "UPDATE sharded SET value = 'a' WHERE value = 'b'",
+        ] {
+            assert!(
+                matches!(
+                    session.parse(query, false),
+                    Err(Error::Route(RouteError::FanoutTooWide(2, 1)))
+                ),
+                "{}",
+                query
+            );
+        }
+
+        for query in [
+            "SELECT * FROM sharded WHERE id = 11",
+            "SELECT 1",
+            "INSERT INTO sharded (id, value) VALUES (1, 'a')",
+            // Has to run on all shards.
+            "CREATE INDEX ON sharded (value)",
+            "TRUNCATE sharded",
+            "VACUUM sharded",
+            "NOTIFY channel",
+        ] {
+            assert!(session.parse(query, false).is_ok(), "{}", query);
+        }
+
+        let mut cluster = Cluster::new_test();
+        cluster.set_max_fanout_shards(Some(2));
+        assert!(Session::new(cluster)
+            .parse("SELECT * FROM sharded", false)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_multi_key_policy() {
+        let query = |id, tenant_id| {
+            format!(
+                "SELECT * FROM sharded s JOIN myschema.users u ON u.id = s.id \
+                 WHERE s.id IN ({}) AND u.tenant_id = {}",
+                id, tenant_id
+            )
+        };
+
+        for policy in [
+            MultiKeyPolicy::Intersect,
+            MultiKeyPolicy::Error,
+            MultiKeyPolicy::Fanout,
+        ] {
+            let mut cluster = Cluster::new_test();
+            cluster.set_multi_key_policy(policy);
+            let mut session = Session::new(cluster);
+
+            // The keys agree, or have a shard in common.
+            for query in [query("11", 11), query("1, 11", 11)] {
+                let Ok(Command::Query(route)) = session.parse(&query, false) else {
+                    panic!("not a query: {}", query);
+                };
+                assert_eq!(route.shard(), &Shard::Direct(1), "{:?}", policy);
+            }
+
+            // Each key is on a different shard.
+            let conflict = session.parse(&query("1", 11), false);
+            match policy {
+                MultiKeyPolicy::Intersect => {
+                    let Ok(Command::Query(route)) = conflict else {
+                        panic!("not a query");
+                    };
+                    // No rows match, any one shard will do.
+                    assert!(matches!(route.shard(), Shard::Direct(_)));
+                }
+                MultiKeyPolicy::Error => assert!(matches!(
+                    conflict,
+                    Err(Error::ShardingKeyConflict(ref a, ref b)) if a == "id" && b == "tenant_id"
+                )),
+                MultiKeyPolicy::Fanout => {
+                    let Ok(Command::Query(route)) = conflict else {
+                        panic!("not a query");
+                    };
+                    assert_eq!(route.shard(), &Shard::All);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_query_roles() {
+        let mut cluster = Cluster::new_test();
+        cluster.set_query_roles(QueryRoles::new(&[
+            crate::config::QueryRole {
+                database: None,
+                pattern: "FROM audit_log".into(),
+                role: Role::Primary,
+            },
+            crate::config::QueryRole {
+                database: None,
+                pattern: "reports".into(),
+                role: Role::Replica,
+            },
+        ]));
+        let mut session = Session::new(cluster);
+
+        for query in [
+            "SELECT * FROM audit_log WHERE created_at > now() - interval '1 day'",
+            "SELECT count(*) FROM audit_log",
+        ] {
+            assert!(session.route(query, false).is_write(), "{}", query);
+        }
+        assert!(session
+            .route("SELECT * FROM sharded WHERE id = 1", false)
+            .is_read());
+        assert!(session.route("SELECT * FROM reports", false).is_read());
+
+        // Writes matching a replica pattern stay on the primary.
+        for query in [
+            "UPDATE reports SET done = true WHERE id = 1",
+            "DELETE FROM reports WHERE id = 1",
+            "SELECT * FROM reports FOR UPDATE",
+        ] {
+            assert!(session.route(query, false).is_write(), "{}", query);
+        }
+
+        // Comments take precedence.
+        assert!(session
+            .route("/* pgdog_role: replica */ SELECT * FROM audit_log", false)
+            .is_read());
+    }
+
+    #[test]
+    fn test_point_lookup_fast_path() {
+        let cluster = Cluster::new_test();
+        let mut fast_path = Cluster::new_test();
+        fast_path.set_query_parser_fast_path(true);
+        let mut stmt = PreparedStatements::default();
+        let params = Parameters::default();
+
+        let mut route = |cluster: &Cluster, query: &str, param: Option<&str>| {
+            let buffer = match param {
+                Some(param) => Buffer::from(vec![
+                    Parse::named("test", query).into(),
+                    Bind::test_params(
+                        "test",
+                        &[Parameter {
+                            len: param.len() as i32,
+                            data: param.as_bytes().to_vec(),
+                        }],
+                    )
+                    .into(),
+                ]),
+                None => Buffer::from(vec![Query::new(query).into()]),
+            };
+            let context = RouterContext::new(&buffer, cluster, &mut stmt, &params, false).unwrap();
+            let mut qp = QueryParser::default();
+            let command = qp.parse(context).map(|command| command.clone());
+            (command, qp.routed())
+        };
+
+        for (query, param) in [
+            ("SELECT * FROM sharded WHERE id = 1", None),
+            ("SELECT * FROM sharded WHERE id = 11", None),
+            ("select id, value from sharded where id = 11;", None),
+            ("SELECT * FROM sharded WHERE id = '11'", None),
+            ("SELECT * FROM sharded WHERE id = 12345678901", None),
+            ("SELECT * FROM sharded WHERE id = $1", Some("1")),
+            ("SELECT * FROM sharded WHERE id = $1", Some("11")),
+            ("SELECT * FROM users WHERE tenant_id = $1", Some("11")),
+            ("SELECT * FROM sharded WHERE ctid = '(0,1)'", None),
+            ("SELECT * FROM sharded WHERE value = 'a'", None),
+            ("SELECT * FROM sharded WHERE id = $1", None),
+            ("SELECT * FROM sharded WHERE id = 'not a number'", None),
+            ("SELECT * FROM sharded WHERE id = 1 LIMIT 1", None),
+        ] {
+            let expected = route(&cluster, query, param);
+            let actual = route(&fast_path, query, param);
+
+            match (expected, actual) {
+                ((Ok(Command::Query(expected)), routed), (Ok(Command::Query(actual)), fast)) => {
+                    assert_eq!(actual, expected, "{}", query);
+                    assert_eq!(routed, fast, "{}", query);
+                }
+                ((Err(expected), _), (Err(actual), _)) => {
+                    assert_eq!(expected.to_string(), actual.to_string(), "{}", query);
+                }
+                (expected, actual) => panic!("{}: {:?} != {:?}", query, expected, actual),
+            }
+        }
+    }
+
+    #[test]
+    fn test_deferred_until_bind() {
+        let mut cluster = Cluster::new_test();
+        cluster.set_require_order_by(true);
+        let mut stmt = PreparedStatements::default();
+        let params = Parameters::default();
+        let mut qp = QueryParser::default();
+
+        let parse = Parse::named("test", "SELECT * FROM sharded WHERE id = $1");
+
+        // Parse and Describe, no parameters yet.
+        let buffer = Buffer::from(vec![
+            parse.clone().into(),
+            Describe::new_statement("test").into(),
+            Sync::new().into(),
+        ]);
+        let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, true).unwrap();
+        let route = match qp.parse(context).unwrap() {
+            Command::Query(route) => route.clone(),
+            _ => panic!("not a query"),
+        };
+        assert!(route.is_cross_shard());
+        assert_eq!(route.deferred(), Some(0));
+        assert!(!qp.routed());
+
+        // Bind and Execute, in the same transaction.
+        let buffer = Buffer::from(vec![
+            parse.into(),
+            Bind::test_params(
+                "test",
+                &[Parameter {
+                    len: 2,
+                    data: "11".as_bytes().to_vec(),
+                }],
+            )
+            .into(),
+            Execute::new().into(),
+            Sync::new().into(),
+        ]);
+        let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, true).unwrap();
+        let route = match qp.parse(context).unwrap() {
+            Command::Query(route) => route.clone(),
+            _ => panic!("not a query"),
+        };
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert_eq!(route.deferred(), None);
+        assert!(qp.routed());
+
+        // Simple protocol has nothing to bind.
+        let route = query!("SELECT * FROM sharded WHERE id = $1");
+        assert_eq!(route.deferred(), None);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let route = query!(
+            "SELECT id FROM sharded WHERE id = 11 INTERSECT SELECT id FROM sharded WHERE id = 11"
+        );
+        assert_eq!(route.shard(), &Shard::Direct(1));
+
+        let route = query!(
+            "SELECT id FROM sharded WHERE id = 1 EXCEPT SELECT id FROM sharded WHERE id = 1 ORDER BY 1"
+        );
+        assert_eq!(route.shard(), &Shard::Direct(0));
+
+        // UNION merges rows from all shards involved.
+        let route = query!(
+            "SELECT id FROM sharded WHERE id = 1 UNION SELECT id FROM sharded WHERE id = 11"
+        );
+        assert!(route.is_all_shards());
+        assert_eq!(route.distinct(), &Some(DistinctBy::Row));
+
+        let route = query!(
+            "SELECT id FROM sharded WHERE id = 1 UNION ALL SELECT id FROM sharded WHERE id = 11"
+        );
+        assert!(route.is_all_shards());
+        assert!(route.distinct().is_none());
+
+        // Shards send us the rows of both sides of INTERSECT and EXCEPT,
+        // and we match them across shards.
+        let route = query!(
+            "SELECT id FROM sharded WHERE id = 1 INTERSECT SELECT id FROM sharded WHERE id = 11 ORDER BY 1"
+        );
+        assert!(route.is_all_shards());
+        assert_eq!(route.set_operator(), Some(SetOperator::Intersect));
+        assert!(route.should_buffer());
+        assert_eq!(route.shard_queries().len(), 2);
+        for query in route.shard_queries().values() {
+            assert_eq!(
+                query,
+                "SELECT *, 1 AS __pgdog_side FROM (SELECT id FROM sharded WHERE id = 1) __pgdog_side_1 \
+                 UNION ALL SELECT *, 2 AS __pgdog_side FROM (SELECT id FROM sharded WHERE id = 11) __pgdog_side_2"
+            );
+        }
+
+        let route =
+            query!("SELECT id FROM sharded EXCEPT ALL SELECT id FROM sharded WHERE id = 11");
+        assert_eq!(route.set_operator(), Some(SetOperator::ExceptAll));
+        assert_eq!(route.shard_queries().len(), 2);
+
+        // One shard computes it itself.
+        let route = query!(
+            "SELECT id FROM sharded WHERE id = 11 INTERSECT SELECT id FROM sharded WHERE id = 11"
+        );
+        assert!(route.set_operator().is_none());
+        assert!(route.shard_queries().is_empty());
+
+        // Queries we can't rewrite.
+        for query in [
+            "SELECT id FROM sharded INTERSECT SELECT id FROM sharded EXCEPT SELECT id FROM sharded",
+            "(SELECT id FROM sharded LIMIT 1) INTERSECT SELECT id FROM sharded WHERE id = 11",
+            "EXPLAIN SELECT id FROM sharded INTERSECT SELECT id FROM sharded WHERE id = 11",
+            "COPY (SELECT id FROM sharded INTERSECT SELECT id FROM sharded) TO STDOUT",
+        ] {
+            let buffer = Buffer::from(vec![Query::new(query).into()]);
+            let cluster = Cluster::new_test();
+            let mut stmt = PreparedStatements::default();
+            let params = Parameters::default();
+            let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+            assert!(
+                matches!(
+                    QueryParser::default().parse(context),
+                    Err(Error::CrossShardSetOperation)
+                ),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_batch() {
+        let (command, _) =
+            command!("SELECT * FROM sharded WHERE id = 1; UPDATE sharded SET v = 1 WHERE id = 11");
+        match command {
+            Command::Batch(statements) => {
+                assert_eq!(statements.len(), 2);
+                assert_eq!(statements[0].query(), "SELECT * FROM sharded WHERE id = 1");
+                assert_eq!(statements[0].route().shard(), &Shard::Direct(0));
+                assert!(statements[0].route().is_read());
+                assert_eq!(statements[1].route().shard(), &Shard::Direct(1));
+                assert!(statements[1].route().is_write());
+            }
+            _ => panic!("not a batch"),
+        }
+
+        // Same shard, sent as-is.
+        let route =
+            query!("SELECT * FROM sharded WHERE id = 1; SELECT * FROM sharded WHERE id = 1");
+        assert_eq!(route.shard(), &Shard::Direct(0));
+        assert!(route.is_read());
+
+        // Transaction control can't be separated from the statements.
+        let route = query!(
+            "BEGIN; UPDATE sharded SET v = 1 WHERE id = 1; UPDATE sharded SET v = 1 WHERE id = 1; COMMIT"
+        );
+        assert_eq!(route.shard(), &Shard::Direct(0));
+        assert!(route.is_write());
+
+        let buffer = Buffer::from(vec![Query::new(
+            "BEGIN; INSERT INTO sharded (id) VALUES (1); INSERT INTO sharded (id) VALUES (11); COMMIT",
+        )
+        .into()]);
+        let cluster = Cluster::new_test();
+        let mut stmt = PreparedStatements::default();
+        let params = Parameters::default();
+        let context = RouterContext::new(&buffer, &cluster, &mut stmt, &params, false).unwrap();
+        assert!(matches!(
+            QueryParser::default().parse(context),
+            Err(Error::CrossShardBatch)
+        ));
     }
 }
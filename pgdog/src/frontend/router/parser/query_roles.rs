@@ -0,0 +1,94 @@
+//! Roles forced by configured query patterns.
+//!
+//! The read/write split can get a statement wrong, e.g. a `SELECT` calling
+//! a function that writes, or reading a table that has to be fresh.
+//! Operators can send queries matching a regular expression to the primary
+//! or the replicas instead.
+
+use std::sync::Arc;
+
+use pg_query::normalize;
+use regex::Regex;
+use tracing::warn;
+
+use crate::config::{QueryRole, Role};
+
+/// Patterns and the role they send queries to, in configuration order.
+#[derive(Debug, Clone, Default)]
+pub struct QueryRoles {
+    roles: Arc<Vec<(Regex, Role)>>,
+}
+
+impl QueryRoles {
+    /// Compile the patterns. Invalid ones are skipped.
+    pub fn new<'a>(roles: impl IntoIterator<Item = &'a QueryRole>) -> Self {
+        let roles = roles
+            .into_iter()
+            .filter_map(|role| match Regex::new(&role.pattern) {
+                Ok(regex) => Some((regex, role.role)),
+                Err(err) => {
+                    warn!(
+                        "query role pattern \"{}\" is invalid: {}",
+                        role.pattern, err
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            roles: Arc::new(roles),
+        }
+    }
+
+    /// No patterns are configured.
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty()
+    }
+
+    /// Role of the first pattern matching the query. Values are replaced
+    /// with placeholders first, so patterns don't depend on them.
+    pub fn role(&self, query: &str) -> Option<Role> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let normalized = normalize(query).ok();
+        let query = normalized.as_deref().unwrap_or(query);
+
+        self.roles
+            .iter()
+            .find(|(regex, _)| regex.is_match(query))
+            .map(|(_, role)| *role)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_query_roles() {
+        let role = |pattern: &str, role| QueryRole {
+            database: None,
+            pattern: pattern.into(),
+            role,
+        };
+        let roles = QueryRoles::new(&[
+            role("(?i)from audit_log", Role::Primary),
+            role("invalid(", Role::Replica),
+            role(r"WHERE id = \$1$", Role::Replica),
+        ]);
+
+        assert_eq!(
+            roles.role("SELECT * FROM audit_log WHERE id = 5"),
+            Some(Role::Primary)
+        );
+        assert_eq!(
+            roles.role("SELECT * FROM users WHERE id = 5"),
+            Some(Role::Replica)
+        );
+        assert_eq!(roles.role("SELECT * FROM users"), None);
+        assert_eq!(QueryRoles::default().role("SELECT 1"), None);
+    }
+}
@@ -0,0 +1,274 @@
+//! Shard range filters.
+//!
+//! A query without a sharding key goes to every shard. With range sharding,
+//! each shard only has the keys in its range, so adding the range to the query's
+//! `WHERE` clause doesn't change its rows but lets Postgres prune partitions
+//! and use indexes on the sharding key.
+
+use std::collections::BTreeMap;
+
+use pg_query::{
+    parse,
+    protobuf::{BoolExpr, BoolExprType, SetOperation},
+    Node, NodeEnum,
+};
+
+use super::{Error, Table};
+use crate::{
+    backend::ShardingSchema,
+    config::{FlexibleType, ShardedMapping},
+    frontend::router::sharding::{Mapping, Tables},
+};
+
+/// Add each shard's range to a cross-shard `SELECT` on a range-sharded table.
+///
+/// Returns the query to send to each shard. Shards without a range aren't included
+/// and get the original query. Queries we can't rewrite safely, e.g. joins,
+/// `UNION` or multiple statements, return no queries at all.
+pub fn range_filters(
+    query: &str,
+    schema: &ShardingSchema,
+    shards: &[usize],
+) -> Result<BTreeMap<usize, String>, Error> {
+    let mut queries = BTreeMap::new();
+    let ast = parse(query).map_err(Error::PgQuery)?;
+
+    let [stmt] = ast.protobuf.stmts.as_slice() else {
+        return Ok(queries);
+    };
+    let Some(NodeEnum::SelectStmt(ref select)) = stmt.stmt.as_ref().and_then(|s| s.node.as_ref())
+    else {
+        return Ok(queries);
+    };
+    if select.op() != SetOperation::SetopNone {
+        return Ok(queries);
+    }
+    let [from] = select.from_clause.as_slice() else {
+        return Ok(queries);
+    };
+    let Some(NodeEnum::RangeVar(ref range_var)) = from.node else {
+        return Ok(queries);
+    };
+
+    let tables = Tables::new(schema);
+    // Table::from uses the alias, we need the table's name.
+    let Some(table) = tables.sharded(Table {
+        name: &range_var.relname,
+        schema: None,
+    }) else {
+        return Ok(queries);
+    };
+    let Some(Mapping::Range(ref mappings)) = table.mapping else {
+        return Ok(queries);
+    };
+
+    // The alias replaces the table name, if there is one.
+    let relation = range_var
+        .alias
+        .as_ref()
+        .map(|alias| alias.aliasname.as_str())
+        .unwrap_or(range_var.relname.as_str());
+    let column = format!("{}.{}", identifier(relation), identifier(&table.column));
+
+    for shard in shards {
+        let ranges = mappings
+            .iter()
+            .filter(|mapping| mapping.shard == *shard)
+            .filter_map(|mapping| range(&column, mapping))
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            continue;
+        }
+
+        // Rows with a NULL key can be on any shard.
+        let filter = format!("{} IS NULL OR {}", column, ranges.join(" OR "));
+        let filter = parse(&format!("SELECT WHERE {}", filter)).map_err(Error::PgQuery)?;
+        let Some(NodeEnum::SelectStmt(filter)) = filter.protobuf.stmts[0]
+            .stmt
+            .as_ref()
+            .and_then(|stmt| stmt.node.clone())
+        else {
+            continue;
+        };
+
+        let mut rewritten = ast.protobuf.clone();
+        if let Some(NodeEnum::SelectStmt(ref mut select)) = rewritten.stmts[0]
+            .stmt
+            .as_mut()
+            .and_then(|stmt| stmt.node.as_mut())
+        {
+            let filter = filter.where_clause;
+            select.where_clause = match select.where_clause.take() {
+                Some(existing) => Some(Box::new(Node {
+                    node: Some(NodeEnum::BoolExpr(Box::new(BoolExpr {
+                        boolop: BoolExprType::AndExpr.into(),
+                        args: vec![*existing, *filter.ok_or(Error::EmptyQuery)?],
+                        location: -1,
+                        ..Default::default()
+                    }))),
+                })),
+                None => filter,
+            };
+        }
+
+        queries.insert(*shard, rewritten.deparse().map_err(Error::PgQuery)?);
+    }
+
+    Ok(queries)
+}
+
+/// Bounds of one range, e.g. `(id >= 1 AND id < 100)`.
+fn range(column: &str, mapping: &ShardedMapping) -> Option<String> {
+    let start = mapping
+        .start
+        .as_ref()
+        .map(|start| format!("{} >= {}", column, literal(start)));
+    let end = mapping
+        .end
+        .as_ref()
+        .map(|end| format!("{} < {}", column, literal(end)));
+
+    match (start, end) {
+        (Some(start), Some(end)) => Some(format!("({} AND {})", start, end)),
+        (Some(bound), None) | (None, Some(bound)) => Some(format!("({})", bound)),
+        (None, None) => None,
+    }
+}
+
+fn identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Strings are compared byte by byte, like we do when picking the shard,
+/// and not using the column's collation.
+fn literal(value: &FlexibleType) -> String {
+    match value {
+        FlexibleType::Integer(integer) => integer.to_string(),
+        FlexibleType::String(string) => {
+            format!("'{}' COLLATE \"C\"", string.replace('\'', "''"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backend::ShardedTables,
+        config::{DataType, ShardedMappingKind, ShardedTable},
+    };
+
+    fn schema() -> ShardingSchema {
+        let mapping = |start: Option<i64>, end: Option<i64>, shard| ShardedMapping {
+            database: "pgdog".into(),
+            column: "id".into(),
+            table: Some("sharded".into()),
+            kind: ShardedMappingKind::Range,
+            start: start.map(FlexibleType::Integer),
+            end: end.map(FlexibleType::Integer),
+            shard,
+            ..Default::default()
+        };
+        let mappings = [
+            mapping(None, Some(100), 0),
+            mapping(Some(100), Some(200), 1),
+            mapping(Some(200), None, 1),
+        ];
+
+        ShardingSchema {
+            shards: 3,
+            tables: ShardedTables::new(
+                vec![ShardedTable {
+                    database: "pgdog".into(),
+                    name: Some("sharded".into()),
+                    column: "id".into(),
+                    mapping: Mapping::new(&mappings),
+                    ..Default::default()
+                }],
+                vec![],
+                false,
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_range_filters() {
+        let queries = range_filters(
+            "SELECT * FROM sharded WHERE value = 'a' ORDER BY id",
+            &schema(),
+            &[0, 1, 2],
+        )
+        .unwrap();
+
+        // Shard 2 has no range.
+        assert_eq!(queries.len(), 2);
+        assert_eq!(
+            queries[&0],
+            "SELECT * FROM sharded WHERE value = 'a' AND (sharded.id IS NULL OR sharded.id < 100) ORDER BY id"
+        );
+        assert_eq!(
+            queries[&1],
+            "SELECT * FROM sharded WHERE value = 'a' AND (sharded.id IS NULL OR (sharded.id >= 100 AND sharded.id < 200) OR sharded.id >= 200) ORDER BY id"
+        );
+
+        let queries = range_filters("SELECT count(*) FROM sharded s", &schema(), &[1]).unwrap();
+        assert_eq!(
+            queries[&1],
+            "SELECT count(*) FROM sharded s WHERE s.id IS NULL OR (s.id >= 100 AND s.id < 200) OR s.id >= 200"
+        );
+    }
+
+    #[test]
+    fn test_range_filters_strings() {
+        let mapping = ShardedMapping {
+            database: "pgdog".into(),
+            column: "name".into(),
+            table: Some("users".into()),
+            kind: ShardedMappingKind::Range,
+            start: Some(FlexibleType::String("a".into())),
+            end: Some(FlexibleType::String("n".into())),
+            shard: 0,
+            ..Default::default()
+        };
+        let schema = ShardingSchema {
+            shards: 2,
+            tables: ShardedTables::new(
+                vec![ShardedTable {
+                    database: "pgdog".into(),
+                    name: Some("users".into()),
+                    column: "name".into(),
+                    data_type: DataType::Varchar,
+                    mapping: Mapping::new(&[mapping]),
+                    ..Default::default()
+                }],
+                vec![],
+                false,
+            ),
+            ..Default::default()
+        };
+
+        let queries = range_filters("SELECT * FROM users", &schema, &[0, 1]).unwrap();
+        assert_eq!(
+            queries[&0],
+            "SELECT * FROM users WHERE users.name IS NULL OR (users.name >= 'a' COLLATE \"C\" AND users.name < 'n' COLLATE \"C\")"
+        );
+    }
+
+    #[test]
+    fn test_range_filters_skipped() {
+        for query in [
+            "SELECT * FROM unsharded",
+            "SELECT * FROM sharded JOIN other ON sharded.id = other.id",
+            "SELECT * FROM sharded UNION SELECT * FROM sharded",
+            "SELECT * FROM sharded; SELECT * FROM sharded",
+            "SELECT 1",
+        ] {
+            assert!(
+                range_filters(query, &schema(), &[0, 1]).unwrap().is_empty(),
+                "{}",
+                query
+            );
+        }
+    }
+}
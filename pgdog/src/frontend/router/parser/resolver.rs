@@ -0,0 +1,259 @@
+//! Resolve shards for many Bind messages of the same prepared statement.
+//!
+//! Clients pipelining Bind/Execute pairs for one statement don't need
+//! the query parsed for each one. We find the parameter holding the sharding key
+//! once and hash its value for every Bind.
+
+use std::collections::BTreeMap;
+
+use pg_query::{protobuf::ParseResult, Node, NodeEnum};
+
+use crate::{
+    backend::ShardingSchema,
+    config::ShardedTable,
+    frontend::router::sharding::{ContextBuilder, Tables, Value as ShardingValue},
+    net::Bind,
+};
+
+use super::{key, Error, Insert, Key, Shard, Table, Value, WhereClause};
+
+/// Sharding key position in a prepared statement.
+#[derive(Debug, Clone)]
+pub struct BindResolver {
+    table: ShardedTable,
+    position: usize,
+    shards: usize,
+}
+
+impl BindResolver {
+    /// Find the parameter used as the sharding key.
+    ///
+    /// Returns `None` if the statement isn't sharded by exactly one parameter,
+    /// e.g. it uses constants, arrays or multiple sharding keys. Each Bind
+    /// should go through the query router in that case.
+    pub fn new(ast: &ParseResult, schema: &ShardingSchema) -> Option<Self> {
+        let node = ast.stmts.first()?.stmt.as_ref()?.node.as_ref()?;
+
+        match node {
+            NodeEnum::SelectStmt(stmt) => {
+                if stmt.from_clause.len() != 1 {
+                    return None;
+                }
+                let table = Table::try_from(&stmt.from_clause).ok();
+                Self::where_clause(
+                    WhereClause::new(table.map(|t| t.name), &stmt.where_clause)?,
+                    schema,
+                )
+            }
+
+            NodeEnum::UpdateStmt(stmt) => {
+                let table = stmt.relation.as_ref().map(Table::from);
+                Self::where_clause(
+                    WhereClause::new(table.map(|t| t.name), &stmt.where_clause)?,
+                    schema,
+                )
+            }
+
+            NodeEnum::DeleteStmt(stmt) => {
+                let table = stmt.relation.as_ref().map(Table::from);
+                Self::where_clause(
+                    WhereClause::new(table.map(|t| t.name), &stmt.where_clause)?,
+                    schema,
+                )
+            }
+
+            NodeEnum::InsertStmt(stmt) => {
+                let insert = Insert::new(stmt);
+                let columns = insert.columns();
+                let tables = Tables::new(schema);
+                let key = tables.key(insert.table()?, &columns)?;
+                let tuples = insert.tuples();
+                if tuples.len() != 1 {
+                    return None;
+                }
+                match tuples.first()?.get(key.position)? {
+                    Value::Placeholder(number) => Some(Self {
+                        table: key.table.clone(),
+                        position: Value::placeholder_position(*number)?,
+                        shards: schema.shards,
+                    }),
+                    _ => None,
+                }
+            }
+
+            _ => None,
+        }
+    }
+
+    fn where_clause(where_clause: WhereClause, schema: &ShardingSchema) -> Option<Self> {
+        let mut found = None;
+
+        for table in schema.tables().tables() {
+            for key in where_clause.keys(table.name.as_deref(), &table.column) {
+                match key {
+                    Key::Parameter { pos, array: false } if found.is_none() => {
+                        found = Some(Self {
+                            table: table.clone(),
+                            position: pos,
+                            shards: schema.shards,
+                        });
+                    }
+                    _ => return None,
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Position of the sharding key parameter.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Get the shard for one Bind.
+    pub fn shard(&self, bind: &Bind) -> Result<Shard, Error> {
+        let param = key::parameter(bind, self.position)?;
+        let value = ShardingValue::from_param(&param, self.table.data_type)?;
+        let ctx = ContextBuilder::new(&self.table)
+            .value(value)
+            .shards(self.shards)
+            .build()?;
+        Ok(ctx.apply()?)
+    }
+
+    /// Get the shard for `EXECUTE name(args)`. Arguments are constants,
+    /// passed in the order of the prepared statement's parameters.
+    pub fn execute(&self, args: &[Node]) -> Result<Shard, Error> {
+        let data = match args.get(self.position).map(Value::try_from) {
+            Some(Ok(Value::Integer(value))) => value.to_string(),
+            Some(Ok(Value::String(value))) => value.to_string(),
+            _ => return Ok(Shard::All),
+        };
+        let ctx = ContextBuilder::new(&self.table)
+            .data(data.as_str())
+            .shards(self.shards)
+            .build()?;
+        Ok(ctx.apply()?)
+    }
+
+    /// Group Binds by shard. Each group holds the positions of its Binds,
+    /// in the order they were received.
+    pub fn group<'a>(
+        &self,
+        binds: impl IntoIterator<Item = &'a Bind>,
+    ) -> Result<BTreeMap<Shard, Vec<usize>>, Error> {
+        let mut groups = BTreeMap::<Shard, Vec<usize>>::new();
+
+        for (position, bind) in binds.into_iter().enumerate() {
+            groups.entry(self.shard(bind)?).or_default().push(position);
+        }
+
+        Ok(groups)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::parse;
+
+    use super::*;
+    use crate::{backend::Cluster, net::bind::Parameter};
+
+    fn resolver(query: &str) -> Option<BindResolver> {
+        let ast = parse(query).unwrap();
+        BindResolver::new(&ast.protobuf, &Cluster::new_test().sharding_schema())
+    }
+
+    fn bind(id: i64) -> Bind {
+        let id = id.to_string();
+        Bind::test_params(
+            "test",
+            &[Parameter {
+                len: id.len() as i32,
+                data: id.as_bytes().to_vec(),
+            }],
+        )
+    }
+
+    #[test]
+    fn test_resolver() {
+        for query in [
+            "SELECT * FROM sharded WHERE id = $1",
+            "UPDATE sharded SET value = 'a' WHERE id = $1",
+            "DELETE FROM sharded WHERE id = $1",
+            "INSERT INTO sharded (id, value) VALUES ($1, 'a')",
+            "INSERT INTO sharded (id, value) OVERRIDING SYSTEM VALUE VALUES ($1, 'a')",
+        ] {
+            let found = resolver(query).expect(query);
+            assert_eq!(found.position(), 0);
+            assert_eq!(
+                found.shard(&bind(1)).unwrap(),
+                Shard::Direct(0),
+                "{}",
+                query
+            );
+            assert_eq!(
+                found.shard(&bind(11)).unwrap(),
+                Shard::Direct(1),
+                "{}",
+                query
+            );
+        }
+
+        let insert = resolver("INSERT INTO sharded (value, id) VALUES ($2, $1)").unwrap();
+        assert_eq!(insert.position(), 0);
+
+        for query in [
+            "SELECT * FROM sharded WHERE id = 1",
+            "SELECT * FROM sharded WHERE id = ANY($1)",
+            "SELECT * FROM sharded WHERE id = $1 OR id = $2",
+            "SELECT * FROM sharded",
+            "SELECT * FROM unsharded WHERE id = $1",
+            "INSERT INTO sharded (id) VALUES ($1), ($2)",
+        ] {
+            assert!(resolver(query).is_none(), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_execute() {
+        let resolver = resolver("SELECT * FROM sharded WHERE value = $1 AND id = $2").unwrap();
+        for (args, shard) in [
+            ("('a', 1)", Shard::Direct(0)),
+            ("('a', 11)", Shard::Direct(1)),
+            ("('a', '11')", Shard::Direct(1)),
+            ("('a', 11::bigint)", Shard::Direct(1)),
+            ("('a', NULL)", Shard::All),
+            ("('a')", Shard::All),
+        ] {
+            let ast = parse(&format!("EXECUTE test{}", args)).unwrap();
+            let Some(NodeEnum::ExecuteStmt(ref stmt)) =
+                ast.protobuf.stmts[0].stmt.as_ref().unwrap().node
+            else {
+                panic!("not an execute");
+            };
+            assert_eq!(resolver.execute(&stmt.params).unwrap(), shard, "{}", args);
+        }
+    }
+
+    #[test]
+    fn test_group() {
+        let resolver = resolver("SELECT * FROM sharded WHERE id = $1").unwrap();
+        let binds = (0..100).map(bind).collect::<Vec<_>>();
+        let groups = resolver.group(&binds).unwrap();
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups.values().map(|group| group.len()).sum::<usize>(), 100);
+
+        for (shard, group) in &groups {
+            assert!(group.windows(2).all(|pair| pair[0] < pair[1]));
+            for position in group {
+                assert_eq!(&resolver.shard(&binds[*position]).unwrap(), shard);
+            }
+        }
+
+        assert!(groups[&Shard::Direct(0)].contains(&1));
+        assert!(groups[&Shard::Direct(1)].contains(&11));
+    }
+}
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use pg_query::{NodeEnum, ParseResult};
 
-use super::{Command, Error};
+use super::{Command, Error, Route};
 use crate::frontend::PreparedStatements;
 use crate::net::Parse;
 
@@ -66,6 +66,7 @@ impl Rewrite {
 
         Ok(Command::Rewrite(
             ast.deparse().map_err(|_| Error::EmptyQuery)?,
+            Route::write(None),
         ))
     }
 }
@@ -83,7 +84,7 @@ mod test {
         let mut prepared_statements = PreparedStatements::new();
         let queries = rewrite.rewrite(&mut prepared_statements).unwrap();
         match queries {
-            Command::Rewrite(queries) => assert_eq!(queries, "BEGIN; PREPARE __pgdog_1 AS SELECT $1, $2, $3; PREPARE __pgdog_2 AS SELECT * FROM my_table WHERE id = $1; COMMIT"),
+            Command::Rewrite(queries, _) => assert_eq!(queries, "BEGIN; PREPARE __pgdog_1 AS SELECT $1, $2, $3; PREPARE __pgdog_2 AS SELECT * FROM my_table WHERE id = $1; COMMIT"),
             _ => panic!("not a rewrite"),
         }
     }
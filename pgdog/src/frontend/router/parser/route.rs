@@ -1,6 +1,8 @@
-use std::fmt::Display;
+use std::{borrow::Cow, collections::BTreeMap, fmt::Display, time::Duration};
 
-use super::{Aggregate, DistinctBy, FunctionBehavior, Limit, LockingBehavior, OrderBy};
+use super::{
+    Aggregate, DistinctBy, FunctionBehavior, Key, Limit, LockingBehavior, OrderBy, SetOperator,
+};
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq, Hash, Default)]
 pub enum Shard {
@@ -29,9 +31,88 @@ impl Shard {
         matches!(self, Shard::All)
     }
 
+    /// No shard has rows for the query, e.g. the sharding key is an empty list.
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Shard::Multi(shards) if shards.is_empty())
+    }
+
     pub fn direct(shard: usize) -> Self {
         Self::Direct(shard)
     }
+
+    /// Check that the shard(s) exist in a cluster
+    /// with this many shards.
+    pub fn valid(&self, shards: usize) -> bool {
+        match self {
+            Self::All => true,
+            Self::Direct(shard) => *shard < shards,
+            Self::Multi(multi) => multi.iter().all(|shard| *shard < shards),
+        }
+    }
+
+    /// Number of shards the query goes to, in a cluster
+    /// with this many shards.
+    pub fn count(&self, shards: usize) -> usize {
+        match self {
+            Self::All => shards,
+            Self::Direct(_) => 1,
+            Self::Multi(multi) => multi.len(),
+        }
+    }
+
+    /// Limit the shard(s) to the allowed set.
+    /// Returns `None` if none of them are allowed.
+    pub fn restrict(&self, allowed: &[usize]) -> Option<Shard> {
+        if self.is_empty() {
+            return Some(self.clone());
+        }
+
+        let shards = match self {
+            Self::All => allowed.to_vec(),
+            Self::Direct(shard) => vec![*shard],
+            Self::Multi(shards) => shards.clone(),
+        };
+
+        let mut shards = shards
+            .into_iter()
+            .filter(|shard| allowed.contains(shard))
+            .collect::<Vec<_>>();
+
+        match shards.len() {
+            0 => None,
+            1 => shards.pop().map(Self::Direct),
+            _ => Some(Self::Multi(shards)),
+        }
+    }
+
+    /// Replace a list of every shard in the cluster with [`Shard::All`],
+    /// and a list of one shard with [`Shard::Direct`].
+    pub fn normalize(self, shards: usize) -> Shard {
+        match self {
+            Self::Multi(mut multi) => {
+                multi.sort();
+                multi.dedup();
+                if shards > 0 && multi.len() == shards && multi.iter().all(|s| *s < shards) {
+                    Self::All
+                } else if multi.len() == 1 {
+                    Self::Direct(multi[0])
+                } else {
+                    Self::Multi(multi)
+                }
+            }
+            shard => shard,
+        }
+    }
+
+    /// Shard(s) both routes go to.
+    /// Returns `None` if they have none in common.
+    pub fn intersect(&self, other: &Shard) -> Option<Shard> {
+        match (self, other) {
+            (Self::All, shard) | (shard, Self::All) => Some(shard.clone()),
+            (shard, Self::Direct(other)) => shard.restrict(&[*other]),
+            (shard, Self::Multi(other)) => shard.restrict(other),
+        }
+    }
 }
 
 impl From<Option<usize>> for Shard {
@@ -44,9 +125,27 @@ impl From<Option<usize>> for Shard {
     }
 }
 
+/// Value of a route attribute, e.g. for a tracing span.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Attribute {
+    Text(Cow<'static, str>),
+    Integer(i64),
+    Bool(bool),
+}
+
+impl Display for Attribute {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Text(text) => write!(f, "{}", text),
+            Self::Integer(integer) => write!(f, "{}", integer),
+            Self::Bool(bool) => write!(f, "{}", bool),
+        }
+    }
+}
+
 /// Path a query should take and any transformations
 /// that should be applied along the way.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub struct Route {
     shard: Shard,
     read: bool,
@@ -55,16 +154,23 @@ pub struct Route {
     limit: Limit,
     lock_session: bool,
     distinct: Option<DistinctBy>,
+    set_operator: Option<SetOperator>,
+    local: bool,
+    sharding_column: Option<String>,
+    sharding_keys: Vec<Key>,
+    deferred: Option<usize>,
+    reducer: Option<String>,
+    two_pc: bool,
+    shard_queries: BTreeMap<usize, String>,
+    max_staleness: Option<Duration>,
+    locked_tables: Vec<String>,
+    analytics: bool,
+    read_only_snapshot: bool,
 }
 
 impl Display for Route {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "shard={}, role={}",
-            self.shard,
-            if self.read { "replica" } else { "primary" }
-        )
+        write!(f, "shard={}, role={}", self.shard, self.role())
     }
 }
 
@@ -131,6 +237,12 @@ impl Route {
         self.is_all_shards() || self.is_multi_shard()
     }
 
+    /// Rows from multiple shards are returned in the order
+    /// the shards send them.
+    pub fn is_unordered(&self) -> bool {
+        self.is_cross_shard() && self.order_by.is_empty()
+    }
+
     pub fn order_by(&self) -> &[OrderBy] {
         &self.order_by
     }
@@ -143,13 +255,24 @@ impl Route {
         self.shard = Shard::Direct(shard);
     }
 
+    pub fn set_shard_raw_mut(&mut self, shard: Shard) {
+        self.shard = shard;
+    }
+
     pub fn set_shard(mut self, shard: usize) -> Self {
         self.set_shard_mut(shard);
         self
     }
 
+    /// Rows need to be buffered and merged across shards.
+    /// A single shard handles ORDER BY, aggregates and DISTINCT itself.
     pub fn should_buffer(&self) -> bool {
-        !self.order_by().is_empty() || !self.aggregate().is_empty() || self.distinct().is_some()
+        self.is_cross_shard()
+            && (!self.order_by().is_empty()
+                || !self.aggregate().is_empty()
+                || self.distinct().is_some()
+                || self.set_operator().is_some()
+                || self.reducer().is_some())
     }
 
     pub fn limit(&self) -> &Limit {
@@ -188,7 +311,425 @@ impl Route {
         self.lock_session
     }
 
+    /// Changes on all shards should be committed together,
+    /// using two-phase commit. Doesn't apply if only one shard is used.
+    pub fn two_pc(&self) -> bool {
+        self.two_pc && self.is_cross_shard()
+    }
+
+    pub fn set_two_pc(mut self, two_pc: bool) -> Self {
+        self.two_pc = two_pc;
+        self
+    }
+
     pub fn distinct(&self) -> &Option<DistinctBy> {
         &self.distinct
     }
+
+    /// INTERSECT or EXCEPT computed with the rows from all shards.
+    pub fn set_operator(&self) -> Option<SetOperator> {
+        self.set_operator
+    }
+
+    pub fn set_set_operator(mut self, set_operator: Option<SetOperator>) -> Self {
+        self.set_operator = set_operator;
+        self
+    }
+
+    /// Rows returned are a plan, not the result of the query,
+    /// so there is nothing to sort, aggregate or deduplicate.
+    pub fn set_explain_mut(&mut self) {
+        self.order_by.clear();
+        self.aggregate = Aggregate::none();
+        self.limit = Limit::default();
+        self.distinct = None;
+        self.set_operator = None;
+    }
+
+    /// The query doesn't need a backend to be answered.
+    /// The shard is still set, in case we can't evaluate it ourselves.
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+
+    pub fn set_local(mut self, local: bool) -> Self {
+        self.local = local;
+        self
+    }
+
+    /// Column whose value picked the shard(s).
+    /// Not set if the query goes to all shards.
+    pub fn sharding_column(&self) -> Option<&str> {
+        self.sharding_column.as_deref()
+    }
+
+    pub fn set_sharding_column(mut self, column: Option<String>) -> Self {
+        self.set_sharding_column_mut(column);
+        self
+    }
+
+    pub fn set_sharding_column_mut(&mut self, column: Option<String>) {
+        self.sharding_column = if self.is_all_shards() { None } else { column };
+    }
+
+    /// Keys the query filtered the sharding column by, e.g. `$1`
+    /// in `WHERE id = $1`. Unlike the column, they are kept if they
+    /// match rows on all shards, e.g. `id IN (1, 11)`.
+    pub fn sharding_keys(&self) -> &[Key] {
+        &self.sharding_keys
+    }
+
+    /// Set the sharding column and the keys it was filtered by.
+    pub fn set_sharding_key(mut self, key: Option<(String, Vec<Key>)>) -> Self {
+        let (column, keys) = key.unzip();
+        self.sharding_keys = keys.unwrap_or_default();
+        self.set_sharding_column_mut(column);
+        self
+    }
+
+    /// Position of the parameter holding the sharding key, if the client
+    /// didn't bind it yet, e.g. it only sent Parse and Describe.
+    /// The shard will be picked when Bind arrives.
+    pub fn deferred(&self) -> Option<usize> {
+        self.deferred
+    }
+
+    pub fn set_deferred(mut self, position: Option<usize>) -> Self {
+        self.set_deferred_mut(position);
+        self
+    }
+
+    pub fn set_deferred_mut(&mut self, position: Option<usize>) {
+        self.deferred = position;
+    }
+
+    /// Name of the custom reducer combining rows from all shards.
+    pub fn reducer(&self) -> Option<&str> {
+        self.reducer.as_deref()
+    }
+
+    pub fn set_reducer(mut self, reducer: Option<String>) -> Self {
+        self.set_reducer_mut(reducer);
+        self
+    }
+
+    pub fn set_reducer_mut(&mut self, reducer: Option<String>) {
+        self.reducer = reducer;
+    }
+
+    /// Query to send to a shard instead of the client's, e.g. with
+    /// the shard's range added to the `WHERE` clause. Shards not
+    /// in the map get the original query.
+    pub fn shard_queries(&self) -> &BTreeMap<usize, String> {
+        &self.shard_queries
+    }
+
+    pub fn set_shard_queries(mut self, queries: BTreeMap<usize, String>) -> Self {
+        self.set_shard_queries_mut(queries);
+        self
+    }
+
+    pub fn set_shard_queries_mut(&mut self, queries: BTreeMap<usize, String>) {
+        self.shard_queries = queries;
+    }
+
+    /// Read from a replica only if it's at most this far behind
+    /// the primary. Otherwise, the primary is used.
+    pub fn max_staleness(&self) -> Option<Duration> {
+        self.max_staleness
+    }
+
+    pub fn set_max_staleness(mut self, max_staleness: Option<Duration>) -> Self {
+        self.set_max_staleness_mut(max_staleness);
+        self
+    }
+
+    pub fn set_max_staleness_mut(&mut self, max_staleness: Option<Duration>) {
+        self.max_staleness = max_staleness;
+    }
+
+    /// Tables whose rows are locked by `FOR UPDATE`, `FOR SHARE`, etc.
+    pub fn locked_tables(&self) -> &[String] {
+        &self.locked_tables
+    }
+
+    pub fn set_locked_tables(mut self, tables: Vec<String>) -> Self {
+        self.set_locked_tables_mut(tables);
+        self
+    }
+
+    pub fn set_locked_tables_mut(&mut self, tables: Vec<String>) {
+        self.locked_tables = tables;
+    }
+
+    /// Read from the analytics replicas, instead of the primary or the other replicas.
+    pub fn is_analytics(&self) -> bool {
+        self.analytics
+    }
+
+    pub fn set_analytics_mut(&mut self, analytics: bool) {
+        self.analytics = analytics;
+    }
+
+    /// Start the transaction on all shards with their snapshots taken together,
+    /// so a read-only transaction sees the shards as close to one point in time as possible.
+    pub fn read_only_snapshot(&self) -> bool {
+        self.read_only_snapshot && self.is_cross_shard()
+    }
+
+    pub fn set_read_only_snapshot_mut(&mut self, read_only_snapshot: bool) {
+        self.read_only_snapshot = read_only_snapshot;
+    }
+
+    fn role(&self) -> &'static str {
+        if !self.read {
+            "primary"
+        } else if self.analytics {
+            "analytics"
+        } else {
+            "replica"
+        }
+    }
+
+    /// Describe the route with key/value pairs, e.g. to annotate a tracing span.
+    /// Only a query going to some, but not all, shards allocates.
+    pub fn attributes(&self, shards: usize) -> [(&'static str, Attribute); 4] {
+        let shard = match self.shard {
+            Shard::Direct(shard) => Attribute::Integer(shard as i64),
+            Shard::All => Attribute::Text(Cow::Borrowed("all")),
+            Shard::Multi(_) => Attribute::Text(Cow::Owned(self.shard.to_string())),
+        };
+        let role = self.role();
+
+        [
+            ("pgdog.shard", shard),
+            ("pgdog.role", Attribute::Text(Cow::Borrowed(role))),
+            (
+                "pgdog.fanout",
+                Attribute::Integer(self.shard.count(shards) as i64),
+            ),
+            ("pgdog.buffered", Attribute::Bool(self.should_buffer())),
+        ]
+    }
+
+    /// Merge the routes of two tables used by the same statement.
+    ///
+    /// The statement goes to the shards both routes have in common. If there are none,
+    /// the keys don't match on any shard, so it goes to all of them to be safe.
+    /// It's a write if either route is, and rows are merged if either route needs it.
+    pub fn combine(self, other: Route) -> Route {
+        let shard = self.shard.intersect(&other.shard).unwrap_or(Shard::All);
+
+        let mut route = Route {
+            shard,
+            read: self.read && other.read,
+            order_by: if self.order_by.is_empty() {
+                other.order_by
+            } else {
+                self.order_by
+            },
+            aggregate: if self.aggregate.is_empty() {
+                other.aggregate
+            } else {
+                self.aggregate
+            },
+            limit: if self.limit.limit.is_none() && self.limit.offset.is_none() {
+                other.limit
+            } else {
+                self.limit
+            },
+            lock_session: self.lock_session || other.lock_session,
+            distinct: self.distinct.or(other.distinct),
+            set_operator: self.set_operator.or(other.set_operator),
+            local: self.local && other.local,
+            sharding_column: None,
+            sharding_keys: vec![],
+            deferred: self.deferred.or(other.deferred),
+            reducer: self.reducer.or(other.reducer),
+            two_pc: self.two_pc || other.two_pc,
+            shard_queries: BTreeMap::new(),
+            max_staleness: match (self.max_staleness, other.max_staleness) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+            locked_tables: {
+                let mut tables = self.locked_tables;
+                for table in other.locked_tables {
+                    if !tables.contains(&table) {
+                        tables.push(table);
+                    }
+                }
+                tables
+            },
+            analytics: self.analytics || other.analytics,
+            read_only_snapshot: self.read_only_snapshot || other.read_only_snapshot,
+        };
+        route.set_sharding_column_mut(self.sharding_column.or(other.sharding_column));
+
+        route
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_restrict() {
+        let allowed = [1, 2];
+
+        assert_eq!(
+            Shard::All.restrict(&allowed),
+            Some(Shard::Multi(vec![1, 2]))
+        );
+        assert_eq!(
+            Shard::Multi(vec![0, 1]).restrict(&allowed),
+            Some(Shard::Direct(1))
+        );
+        assert_eq!(
+            Shard::Multi(vec![0, 1, 2, 3]).restrict(&allowed),
+            Some(Shard::Multi(vec![1, 2]))
+        );
+        assert_eq!(Shard::Direct(2).restrict(&allowed), Some(Shard::Direct(2)));
+        assert_eq!(Shard::Direct(0).restrict(&allowed), None);
+        assert_eq!(Shard::Multi(vec![0, 3]).restrict(&allowed), None);
+    }
+
+    #[test]
+    fn test_count() {
+        assert_eq!(Shard::All.count(4), 4);
+        assert_eq!(Shard::Direct(3).count(4), 1);
+        assert_eq!(Shard::Multi(vec![0, 2]).count(4), 2);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let route = Route::read(Some(1));
+        assert_eq!(
+            route.attributes(4),
+            [
+                ("pgdog.shard", Attribute::Integer(1)),
+                ("pgdog.role", Attribute::Text("replica".into())),
+                ("pgdog.fanout", Attribute::Integer(1)),
+                ("pgdog.buffered", Attribute::Bool(false)),
+            ]
+        );
+
+        let route = Route::select(
+            Shard::All,
+            vec![OrderBy::Asc(1)],
+            Aggregate::default(),
+            Limit::default(),
+            None,
+        )
+        .set_read(false);
+        let attributes = route.attributes(4);
+        assert_eq!(attributes[0].1.to_string(), "all");
+        assert_eq!(attributes[1].1.to_string(), "primary");
+        assert_eq!(attributes[2].1, Attribute::Integer(4));
+        assert_eq!(attributes[3].1, Attribute::Bool(true));
+
+        let route = Route::write(Shard::Multi(vec![0, 2]));
+        let attributes = route.attributes(4);
+        assert_eq!(attributes[0].1.to_string(), "[0, 2]");
+        assert_eq!(attributes[2].1, Attribute::Integer(2));
+        assert_eq!(attributes[3].1, Attribute::Bool(false));
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(Shard::Multi(vec![0, 1, 2, 3]).normalize(4), Shard::All);
+        assert_eq!(Shard::Multi(vec![3, 1, 0, 2, 1]).normalize(4), Shard::All);
+        assert_eq!(
+            Shard::Multi(vec![0, 1, 3]).normalize(4),
+            Shard::Multi(vec![0, 1, 3])
+        );
+        assert_eq!(Shard::Multi(vec![2, 2]).normalize(4), Shard::Direct(2));
+        assert_eq!(Shard::Direct(0).normalize(1), Shard::Direct(0));
+        assert_eq!(Shard::All.normalize(4), Shard::All);
+    }
+
+    #[test]
+    fn test_intersect() {
+        assert_eq!(
+            Shard::All.intersect(&Shard::Direct(1)),
+            Some(Shard::Direct(1))
+        );
+        assert_eq!(Shard::All.intersect(&Shard::All), Some(Shard::All));
+        assert_eq!(
+            Shard::Multi(vec![0, 1, 2]).intersect(&Shard::Multi(vec![1, 2, 3])),
+            Some(Shard::Multi(vec![1, 2]))
+        );
+        assert_eq!(Shard::Direct(0).intersect(&Shard::Direct(1)), None);
+    }
+
+    #[test]
+    fn test_combine() {
+        let read = Route::read(Some(1)).set_sharding_column(Some("id".into()));
+        let write = Route::write(Some(1));
+
+        let route = read.clone().combine(write.clone());
+        assert_eq!(route.shard(), &Shard::Direct(1));
+        assert!(route.is_write());
+        assert_eq!(route.sharding_column(), Some("id"));
+        assert_eq!(write.combine(read).shard(), &Shard::Direct(1));
+
+        let route = Route::read(Shard::Multi(vec![0, 1]))
+            .combine(Route::read(Shard::All).set_lock_session());
+        assert_eq!(route.shard(), &Shard::Multi(vec![0, 1]));
+        assert!(route.is_read());
+        assert!(route.lock_session());
+
+        // Either route needs rows merged.
+        let sorted = Route::select(
+            Shard::All,
+            vec![OrderBy::Asc(1)],
+            Aggregate::new_count(1),
+            Limit::default(),
+            None,
+        );
+        let route = Route::read(Shard::All).combine(sorted);
+        assert!(route.should_buffer());
+        assert_eq!(route.order_by().len(), 1);
+        assert_eq!(route.aggregate().len(), 1);
+
+        // No shard in common.
+        let route = Route::read(Some(0)).combine(Route::read(Some(1)));
+        assert_eq!(route.shard(), &Shard::All);
+    }
+
+    #[test]
+    fn test_eq() {
+        use std::collections::HashSet;
+
+        use crate::net::messages::Vector;
+
+        let route = || {
+            Route::select(
+                Shard::All,
+                vec![
+                    OrderBy::Desc(1),
+                    OrderBy::AscVectorL2Column("embedding".into(), Vector::from(&[1.0, 2.0][..])),
+                ],
+                Aggregate::new_count(1),
+                Limit {
+                    limit: Some(10),
+                    offset: Some(5),
+                    with_ties: false,
+                },
+                Some(DistinctBy::Row),
+            )
+            .set_sharding_column(Some("id".into()))
+        };
+
+        assert_eq!(route(), route());
+        assert_ne!(route(), route().set_lock_session());
+        assert_ne!(route(), route().set_read(false));
+        assert_ne!(Route::read(Some(0)), Route::read(Some(1)));
+        assert_eq!(Route::write(None), Route::write(Shard::All));
+
+        let routes = HashSet::from([route(), route(), Route::read(Some(0))]);
+        assert_eq!(routes.len(), 2);
+        assert!(routes.contains(&route()));
+    }
 }
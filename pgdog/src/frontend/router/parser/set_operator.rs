@@ -0,0 +1,152 @@
+//! INTERSECT and EXCEPT across shards.
+//!
+//! A shard can only match rows it has, so instead of the set operation,
+//! shards return the rows of both sides, tagged with the side they're from.
+//! We compute the set operation once all shards sent their rows.
+
+use pg_query::{
+    protobuf::{SelectStmt, SetOperation},
+    Node, NodeEnum,
+};
+
+use super::Error;
+
+/// Column with the side of the set operation a row is from,
+/// returned by shards after the other columns.
+pub static SIDE_COLUMN: &str = "__pgdog_side";
+
+/// Set operation computed with the rows from all shards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SetOperator {
+    Intersect,
+    IntersectAll,
+    Except,
+    ExceptAll,
+}
+
+impl SetOperator {
+    /// Set operation of the statement, unless it's a UNION
+    /// or not a set operation at all.
+    pub fn new(stmt: &SelectStmt) -> Option<Self> {
+        match (stmt.op(), stmt.all) {
+            (SetOperation::SetopIntersect, false) => Some(Self::Intersect),
+            (SetOperation::SetopIntersect, true) => Some(Self::IntersectAll),
+            (SetOperation::SetopExcept, false) => Some(Self::Except),
+            (SetOperation::SetopExcept, true) => Some(Self::ExceptAll),
+            _ => None,
+        }
+    }
+
+    /// Query sent to the shards, returning the rows of both sides of the statement,
+    /// with the side in the last column: 1 for the left side, 2 for the right one.
+    ///
+    /// `ORDER BY` and `LIMIT` of the statement are applied to the result by us,
+    /// but we can't skip rows for `OFFSET`, since shards normally do that.
+    ///
+    /// Returns `None` if shards can't return the rows of a side as they are,
+    /// because it's a set operation itself or has its own `ORDER BY` or `LIMIT`.
+    pub fn sides(stmt: &SelectStmt) -> Result<Option<String>, Error> {
+        let (Some(left), Some(right)) = (stmt.larg.as_deref(), stmt.rarg.as_deref()) else {
+            return Ok(None);
+        };
+
+        if stmt.with_clause.is_some() || stmt.limit_offset.is_some() {
+            return Ok(None);
+        }
+
+        let mut sides = vec![];
+        for (number, side) in [left, right].into_iter().enumerate() {
+            let whole = side.op() == SetOperation::SetopNone
+                && side.sort_clause.is_empty()
+                && side.limit_count.is_none()
+                && side.limit_offset.is_none();
+            if !whole {
+                return Ok(None);
+            }
+
+            let query = Node {
+                node: Some(NodeEnum::SelectStmt(Box::new(side.clone()))),
+            }
+            .deparse()
+            .map_err(Error::PgQuery)?;
+            sides.push(format!(
+                "SELECT *, {} AS {} FROM ({}) {}_{}",
+                number + 1,
+                SIDE_COLUMN,
+                query,
+                SIDE_COLUMN,
+                number + 1
+            ));
+        }
+
+        Ok(Some(sides.join(" UNION ALL ")))
+    }
+
+    /// How many times a row is returned, given how many times
+    /// it's on the left and right sides of the set operation.
+    pub fn count(&self, left: usize, right: usize) -> usize {
+        match self {
+            Self::Intersect => (left > 0 && right > 0) as usize,
+            Self::IntersectAll => left.min(right),
+            Self::Except => (left > 0 && right == 0) as usize,
+            Self::ExceptAll => left.saturating_sub(right),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use pg_query::parse;
+
+    use super::*;
+
+    fn stmt(query: &str) -> SelectStmt {
+        match parse(query).unwrap().protobuf.stmts[0]
+            .stmt
+            .as_ref()
+            .unwrap()
+            .node
+        {
+            Some(NodeEnum::SelectStmt(ref stmt)) => *stmt.clone(),
+            _ => panic!("not a select"),
+        }
+    }
+
+    #[test]
+    fn test_set_operator() {
+        let intersect = stmt("SELECT id FROM a INTERSECT SELECT id FROM b WHERE id = 1");
+        assert_eq!(SetOperator::new(&intersect), Some(SetOperator::Intersect));
+        assert_eq!(
+            SetOperator::sides(&intersect).unwrap().unwrap(),
+            "SELECT *, 1 AS __pgdog_side FROM (SELECT id FROM a) __pgdog_side_1 \
+             UNION ALL SELECT *, 2 AS __pgdog_side FROM (SELECT id FROM b WHERE id = 1) __pgdog_side_2"
+        );
+
+        assert_eq!(
+            SetOperator::new(&stmt("SELECT 1 EXCEPT ALL SELECT 2")),
+            Some(SetOperator::ExceptAll)
+        );
+        assert!(SetOperator::new(&stmt("SELECT 1 UNION SELECT 2")).is_none());
+        assert!(SetOperator::new(&stmt("SELECT 1")).is_none());
+
+        for query in [
+            "SELECT 1 INTERSECT SELECT 2 INTERSECT SELECT 3",
+            "(SELECT id FROM a ORDER BY id LIMIT 1) INTERSECT SELECT id FROM b",
+            "WITH c AS (SELECT 1) SELECT * FROM c INTERSECT SELECT 1",
+            "SELECT id FROM a INTERSECT SELECT id FROM b OFFSET 1",
+        ] {
+            assert!(
+                SetOperator::sides(&stmt(query)).unwrap().is_none(),
+                "{}",
+                query
+            );
+        }
+
+        assert_eq!(SetOperator::Intersect.count(3, 2), 1);
+        assert_eq!(SetOperator::IntersectAll.count(3, 2), 2);
+        assert_eq!(SetOperator::Except.count(3, 2), 0);
+        assert_eq!(SetOperator::Except.count(3, 0), 1);
+        assert_eq!(SetOperator::ExceptAll.count(3, 2), 1);
+        assert_eq!(SetOperator::ExceptAll.count(1, 2), 0);
+    }
+}
@@ -17,9 +17,19 @@ pub enum Value<'a> {
     Placeholder(i32),
     Vector(Vector),
     Function(&'a str),
+    /// `DEFAULT` in `INSERT ... VALUES`.
+    Default,
 }
 
 impl Value<'_> {
+    /// Position of the `$N` placeholder in the Bind message.
+    ///
+    /// The parser only produces numbered parameters and they start at 1.
+    /// Anything else, e.g. `$0`, doesn't refer to a parameter.
+    pub fn placeholder_position(number: i32) -> Option<usize> {
+        usize::try_from(number).ok()?.checked_sub(1)
+    }
+
     /// Get vector if it's a vector.
     #[cfg(test)]
     pub(crate) fn vector(self) -> Option<Vector> {
@@ -80,6 +90,7 @@ impl<'a> TryFrom<&'a Option<NodeEnum>> for Value<'a> {
         match value {
             Some(NodeEnum::AConst(a_const)) => Ok(a_const.into()),
             Some(NodeEnum::ParamRef(param_ref)) => Ok(Value::Placeholder(param_ref.number)),
+            Some(NodeEnum::SetToDefault(_)) => Ok(Value::Default),
             Some(NodeEnum::FuncCall(func)) => {
                 if let Some(Node {
                     node: Some(NodeEnum::String(sval)),
@@ -121,4 +132,13 @@ mod test {
         let vector = Value::try_from(&node).unwrap();
         assert_eq!(vector.vector().unwrap()[0], 1.0.into());
     }
+
+    #[test]
+    fn test_placeholder_position() {
+        assert_eq!(Value::placeholder_position(1), Some(0));
+        assert_eq!(Value::placeholder_position(5), Some(4));
+        assert_eq!(Value::placeholder_position(0), None);
+        assert_eq!(Value::placeholder_position(-1), None);
+        assert_eq!(Value::placeholder_position(i32::MIN), None);
+    }
 }
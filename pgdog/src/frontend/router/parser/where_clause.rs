@@ -4,11 +4,14 @@ use pg_query::{
     protobuf::{a_const::Val, *},
     NodeEnum,
 };
-use std::string::String;
+use std::{collections::HashMap, string::String};
 
-use super::Key;
+use super::{point_lookup::PointValue, Key, PointLookup, Value};
 
-#[derive(Debug)]
+/// Columns every table has, maintained by Postgres.
+static SYSTEM_COLUMNS: &[&str] = &["ctid", "xmin", "xmax", "cmin", "cmax", "tableoid"];
+
+#[derive(Debug, Clone)]
 pub struct Column<'a> {
     /// Table name if fully qualified.
     /// Can be an alias.
@@ -17,14 +20,31 @@ pub struct Column<'a> {
     pub name: &'a str,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Output<'a> {
-    Parameter { pos: i32, array: bool },
-    Value { value: String, array: bool },
-    Int { value: i32, array: bool },
+    Parameter {
+        pos: i32,
+        array: bool,
+    },
+    Value {
+        value: String,
+        array: bool,
+    },
+    Int {
+        value: i32,
+        array: bool,
+    },
     Column(Column<'a>),
+    /// Array column inside `ANY`, e.g. `$1 = ANY(tenant_ids)`.
+    Elements(Column<'a>),
     NullCheck(Column<'a>),
+    Coalesce {
+        column: Column<'a>,
+        default: Box<Output<'a>>,
+    },
     Filter(Vec<Output<'a>>, Vec<Output<'a>>),
+    /// Branches of an `OR`, each one a list of `AND`ed outputs.
+    Or(Vec<Vec<Output<'a>>>),
 }
 
 /// Parse `WHERE` clause of a statement looking for sharding keys.
@@ -49,10 +69,114 @@ impl<'a> WhereClause<'a> {
         Some(Self { output })
     }
 
+    /// `WHERE column = value` of a point lookup recognized without an AST.
+    pub fn point_lookup(lookup: &PointLookup<'a>) -> WhereClause<'a> {
+        let column = Output::Column(Column {
+            table: Some(lookup.table),
+            name: lookup.column,
+        });
+        let value = match lookup.value {
+            PointValue::Parameter(pos) => Output::Parameter { pos, array: false },
+            PointValue::Constant(value) => Output::Value {
+                value: value.to_string(),
+                array: false,
+            },
+        };
+
+        // System columns are different on every shard
+        // and can't be sharding keys.
+        let output = if SYSTEM_COLUMNS.contains(&lookup.column) {
+            vec![]
+        } else {
+            vec![Output::Filter(vec![column], vec![value])]
+        };
+
+        Self { output }
+    }
+
+    /// Branches of each `OR` in the clause, e.g. `tenant_id = $1` and
+    /// `customer_id = $2` in `WHERE tenant_id = $1 OR customer_id = $2`.
+    ///
+    /// Rows can match any branch, so keys are only useful if every branch has one.
+    /// They aren't returned by [`WhereClause::keys`] for that reason.
+    pub fn disjunctions(&self) -> Vec<Vec<WhereClause<'a>>> {
+        self.output
+            .iter()
+            .filter_map(|output| match output {
+                Output::Or(branches) => Some(
+                    branches
+                        .iter()
+                        .map(|output| WhereClause {
+                            output: output.clone(),
+                        })
+                        .collect(),
+                ),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Replace table aliases with table names,
+    /// e.g. `u.id` becomes `users.id` in `FROM users u`.
+    pub fn resolve_aliases(&mut self, aliases: &HashMap<&str, &'a str>) {
+        self.resolve_columns(|column| {
+            column
+                .table
+                .and_then(|table| aliases.get(table))
+                .map(|name| Column {
+                    table: Some(name),
+                    name: column.name,
+                })
+        });
+    }
+
+    /// Replace columns with the ones returned by `resolve`, e.g. columns
+    /// of a subquery in `FROM` with the columns of the table it selects from.
+    pub fn resolve_columns(&mut self, resolve: impl Fn(&Column<'a>) -> Option<Column<'a>>) {
+        for output in self.output.iter_mut() {
+            Self::resolve_column(output, &resolve);
+        }
+    }
+
+    fn resolve_column(
+        output: &mut Output<'a>,
+        resolve: &impl Fn(&Column<'a>) -> Option<Column<'a>>,
+    ) {
+        match output {
+            Output::Column(column)
+            | Output::Elements(column)
+            | Output::NullCheck(column)
+            | Output::Coalesce { column, .. } => {
+                if let Some(resolved) = resolve(column) {
+                    *column = resolved;
+                }
+            }
+
+            Output::Filter(left, right) => {
+                for output in left.iter_mut().chain(right.iter_mut()) {
+                    Self::resolve_column(output, resolve);
+                }
+            }
+
+            Output::Or(branches) => {
+                for output in branches.iter_mut().flatten() {
+                    Self::resolve_column(output, resolve);
+                }
+            }
+
+            _ => (),
+        }
+    }
+
     pub fn keys(&self, table_name: Option<&str>, column_name: &str) -> Vec<Key> {
         let mut keys = vec![];
         for output in &self.output {
-            keys.extend(Self::search_for_keys(output, table_name, column_name));
+            for key in Self::search_for_keys(output, table_name, column_name) {
+                // Repeated predicates, e.g. `id = $1 AND id = $1`, are the same key.
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
         }
         keys
     }
@@ -74,7 +198,7 @@ impl<'a> WhereClause<'a> {
                 array: *array,
             }),
             Output::Parameter { pos, array } => Some(Key::Parameter {
-                pos: *pos as usize - 1,
+                pos: Value::placeholder_position(*pos)?,
                 array: *array,
             }),
             Output::Value { value, array } => Some(Key::Constant {
@@ -93,6 +217,20 @@ impl<'a> WhereClause<'a> {
             let right = right.as_slice();
 
             match (&left, &right) {
+                (&[Output::Coalesce { column, default }], &[value])
+                | (&[value], &[Output::Coalesce { column, default }]) => {
+                    if Self::column_match(column, table_name, column_name) {
+                        if let (Some(value), Some(default)) =
+                            (Self::get_key(value), Self::get_key(default))
+                        {
+                            keys.push(Key::Coalesce {
+                                value: Box::new(value),
+                                default: Box::new(default),
+                            });
+                        }
+                    }
+                }
+
                 // TODO: Handle something like
                 // id = (SELECT 5) which is stupid but legal SQL.
                 (&[Output::Column(ref column)], output) => {
@@ -104,6 +242,7 @@ impl<'a> WhereClause<'a> {
                         }
                     }
                 }
+                // `value = column`, or `value IN (column)`.
                 (output, &[Output::Column(ref column)]) => {
                     if Self::column_match(column, table_name, column_name) {
                         for output in output.iter() {
@@ -113,6 +252,15 @@ impl<'a> WhereClause<'a> {
                         }
                     }
                 }
+                (output, &[Output::Elements(ref column)]) => {
+                    if Self::column_match(column, table_name, column_name) {
+                        for output in output.iter() {
+                            if let Some(key) = Self::get_key(output) {
+                                keys.push(Key::Element(Box::new(key)));
+                            }
+                        }
+                    }
+                }
 
                 _ => {
                     for output in left {
@@ -145,9 +293,48 @@ impl<'a> WhereClause<'a> {
         None
     }
 
+    /// Conjuncts like `TRUE` or `1 = 1` don't filter anything
+    /// and can be ignored.
+    fn trivially_true(node: &Node) -> bool {
+        match node.node {
+            Some(NodeEnum::AConst(AConst {
+                val: Some(Val::Boolval(Boolean { boolval })),
+                ..
+            })) => boolval,
+
+            Some(NodeEnum::AExpr(ref expr)) => {
+                if expr.kind() != AExprKind::AexprOp || Self::string(expr.name.first()) != Some("=")
+                {
+                    return false;
+                }
+
+                let constant =
+                    |node: &Option<Box<Node>>| match node.as_ref().and_then(|n| n.node.as_ref()) {
+                        Some(NodeEnum::AConst(AConst {
+                            val: Some(val),
+                            isnull: false,
+                            ..
+                        })) => Some(val.clone()),
+                        _ => None,
+                    };
+
+                match (constant(&expr.lexpr), constant(&expr.rexpr)) {
+                    (Some(left), Some(right)) => left == right,
+                    _ => false,
+                }
+            }
+
+            _ => false,
+        }
+    }
+
     fn parse(table_name: Option<&'a str>, node: &'a Node, array: bool) -> Vec<Output<'a>> {
         let mut keys = vec![];
 
+        if Self::trivially_true(node) {
+            return keys;
+        }
+
         match node.node {
             Some(NodeEnum::NullTest(ref null_test)) => {
                 // Only check for IS NULL, IS NOT NULL definitely doesn't help.
@@ -163,37 +350,66 @@ impl<'a> WhereClause<'a> {
                 }
             }
 
-            Some(NodeEnum::BoolExpr(ref expr)) => {
-                // Only AND expressions can really be asserted.
-                // OR needs both sides to be evaluated and either one
-                // can direct to a shard. Most cases, this will end up on all shards.
-                if expr.boolop() != BoolExprType::AndExpr {
-                    return keys;
+            Some(NodeEnum::BoolExpr(ref expr)) => match expr.boolop() {
+                BoolExprType::AndExpr => {
+                    for arg in &expr.args {
+                        keys.extend(Self::parse(table_name, arg, array));
+                    }
                 }
 
-                for arg in &expr.args {
-                    keys.extend(Self::parse(table_name, arg, array));
+                // OR needs a key in every branch. Each one
+                // can direct to a different shard.
+                BoolExprType::OrExpr => {
+                    keys.push(Output::Or(
+                        expr.args
+                            .iter()
+                            .map(|arg| Self::parse(table_name, arg, array))
+                            .collect(),
+                    ));
                 }
-            }
+
+                // `NOT NOT x` is `x`. An odd number of NOTs excludes rows,
+                // which can be on any shard, so it doesn't help.
+                BoolExprType::NotExpr => {
+                    if let Some(NodeEnum::BoolExpr(ref inner)) =
+                        expr.args.first().and_then(|arg| arg.node.as_ref())
+                    {
+                        if inner.boolop() == BoolExprType::NotExpr {
+                            if let Some(arg) = inner.args.first() {
+                                keys.extend(Self::parse(table_name, arg, array));
+                            }
+                        }
+                    }
+                }
+
+                _ => (),
+            },
 
             Some(NodeEnum::AExpr(ref expr)) => {
                 let kind = expr.kind();
-                if matches!(
-                    kind,
-                    AExprKind::AexprOp | AExprKind::AexprIn | AExprKind::AexprOpAny
-                ) {
-                    let op = Self::string(expr.name.first());
-                    if let Some(op) = op {
-                        if op != "=" {
-                            return keys;
-                        }
+                // Only equality picks a shard. Inequalities, e.g. `>`, `<>`,
+                // `LIKE` or `BETWEEN`, filter rows that can be on any shard,
+                // so they don't help with routing even next to an equality.
+                let equality = match kind {
+                    AExprKind::AexprOp | AExprKind::AexprIn | AExprKind::AexprOpAny => {
+                        // Operators can be schema-qualified, e.g. `OPERATOR(pg_catalog.=)`.
+                        Self::string(expr.name.last()) == Some("=")
                     }
+                    AExprKind::AexprNotDistinct => true,
+                    _ => false,
+                };
+                if !equality {
+                    return keys;
                 }
                 let array = matches!(kind, AExprKind::AexprOpAny);
                 if let Some(ref left) = expr.lexpr {
                     if let Some(ref right) = expr.rexpr {
-                        let left = Self::parse(table_name, left, array);
-                        let right = Self::parse(table_name, right, array);
+                        // Only the right side of `ANY` is an array.
+                        let left = Self::parse(table_name, left, false);
+                        let mut right = Self::parse(table_name, right, array);
+                        if let (true, [Output::Column(column)]) = (array, right.as_slice()) {
+                            right = vec![Output::Elements(column.clone())];
+                        }
 
                         keys.push(Output::Filter(left, right));
                     }
@@ -221,6 +437,15 @@ impl<'a> WhereClause<'a> {
             }
 
             Some(NodeEnum::ColumnRef(ref column)) => {
+                // `*` and `table.*` aren't columns.
+                if column
+                    .fields
+                    .iter()
+                    .any(|field| matches!(field.node, Some(NodeEnum::AStar(_))))
+                {
+                    return keys;
+                }
+
                 let name = Self::string(column.fields.last());
                 let table = Self::string(column.fields.iter().rev().nth(1));
                 let table = if let Some(table) = table {
@@ -230,10 +455,33 @@ impl<'a> WhereClause<'a> {
                 };
 
                 if let Some(name) = name {
+                    // System columns are different on every shard
+                    // and can't be sharding keys.
+                    if SYSTEM_COLUMNS.contains(&name) {
+                        return keys;
+                    }
+
                     return vec![Output::Column(Column { name, table })];
                 }
             }
 
+            // `COALESCE(column, default)`.
+            Some(NodeEnum::CoalesceExpr(ref expr)) => {
+                if let [column, default] = expr.args.as_slice() {
+                    let column = Self::parse(table_name, column, array).pop();
+                    let mut default = Self::parse(table_name, default, array);
+
+                    if let (Some(Output::Column(column)), Some(default), true) =
+                        (column, default.pop(), default.is_empty())
+                    {
+                        keys.push(Output::Coalesce {
+                            column,
+                            default: Box::new(default),
+                        });
+                    }
+                }
+            }
+
             Some(NodeEnum::ParamRef(ref param)) => {
                 keys.push(Output::Parameter {
                     pos: param.number,
@@ -247,9 +495,40 @@ impl<'a> WhereClause<'a> {
                 }
             }
 
+            // Casts are ignored. Values are interpreted using
+            // the sharding column's data type instead.
             Some(NodeEnum::TypeCast(ref cast)) => {
-                if let Some(ref arg) = cast.arg {
-                    keys.extend(Self::parse(table_name, arg, array));
+                keys.extend(Self::coerced(table_name, &cast.arg, array));
+            }
+
+            // Coercions of enums, domains and binary-compatible types.
+            // They are casts too, just written by the analyzer.
+            Some(NodeEnum::CoerceViaIo(ref coerce)) => {
+                keys.extend(Self::coerced(table_name, &coerce.arg, array));
+            }
+            Some(NodeEnum::RelabelType(ref relabel)) => {
+                keys.extend(Self::coerced(table_name, &relabel.arg, array));
+            }
+            Some(NodeEnum::CoerceToDomain(ref coerce)) => {
+                keys.extend(Self::coerced(table_name, &coerce.arg, array));
+            }
+
+            // `tenant_id COLLATE "C" = $1`. Byte-wise collations only change
+            // how values are sorted. Others can be nondeterministic, e.g.
+            // case-insensitive, and match values that hash differently.
+            Some(NodeEnum::CollateClause(ref collate)) => {
+                let name = collate.collname.last().and_then(|name| match name.node {
+                    Some(NodeEnum::String(ref name)) => Some(name.sval.as_str()),
+                    _ => None,
+                });
+                if matches!(name, Some("C" | "POSIX" | "default")) {
+                    keys.extend(Self::coerced(table_name, &collate.arg, array));
+                }
+            }
+            // `default`, `C` and `POSIX` have fixed OIDs.
+            Some(NodeEnum::CollateExpr(ref collate)) => {
+                if matches!(collate.coll_oid, 100 | 950 | 951) {
+                    keys.extend(Self::coerced(table_name, &collate.arg, array));
                 }
             }
 
@@ -258,6 +537,17 @@ impl<'a> WhereClause<'a> {
 
         keys
     }
+
+    /// Parse the argument of a cast or coercion.
+    fn coerced(
+        table_name: Option<&'a str>,
+        arg: &'a Option<Box<Node>>,
+        array: bool,
+    ) -> Vec<Output<'a>> {
+        arg.as_deref()
+            .map(|arg| Self::parse(table_name, arg, array))
+            .unwrap_or_default()
+    }
 }
 
 #[cfg(test)]
@@ -265,6 +555,7 @@ mod test {
     use pg_query::parse;
 
     use super::*;
+    use crate::config::ShardedTable;
 
     #[test]
     fn test_where_clause() {
@@ -328,6 +619,75 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_in_single_value() {
+        for (query, key) in [
+            (
+                "SELECT * FROM users WHERE tenant_id IN ($1)",
+                Key::Parameter {
+                    pos: 0,
+                    array: false,
+                },
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id IN ($2::bigint)",
+                Key::Parameter {
+                    pos: 1,
+                    array: false,
+                },
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id IN (5)",
+                Key::Constant {
+                    value: "5".into(),
+                    array: false,
+                },
+            ),
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+            if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+                let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+                let keys = where_.keys(Some("users"), "tenant_id");
+                assert_eq!(keys, vec![key], "{}", query);
+            } else {
+                panic!("not a select");
+            }
+        }
+    }
+
+    #[test]
+    fn test_in_casts() {
+        for query in [
+            "SELECT * FROM users WHERE tenant_id IN ($1::int, $2::int)",
+            "SELECT * FROM users WHERE tenant_id IN ($1::bigint, $2)",
+            "SELECT * FROM users WHERE tenant_id IN (CAST($1 AS int), $2::int::bigint)",
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                vec![
+                    Key::Parameter {
+                        pos: 0,
+                        array: false
+                    },
+                    Key::Parameter {
+                        pos: 1,
+                        array: false
+                    },
+                ],
+                "{}",
+                query
+            );
+        }
+    }
+
     #[test]
     fn test_any() {
         let query = "SELECT * FROM users WHERE tenant_id = ANY($1)";
@@ -366,4 +726,682 @@ mod test {
             panic!("not a select");
         }
     }
+
+    #[test]
+    fn test_coalesce() {
+        let query = "SELECT * FROM users WHERE COALESCE(tenant_id, 0) = $1";
+        let ast = parse(query).unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+        if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                vec![Key::Coalesce {
+                    value: Box::new(Key::Parameter {
+                        pos: 0,
+                        array: false
+                    }),
+                    default: Box::new(Key::Constant {
+                        value: "0".into(),
+                        array: false
+                    }),
+                }]
+            );
+            assert!(where_.keys(Some("users"), "id").is_empty());
+        } else {
+            panic!("not a select");
+        }
+    }
+
+    #[test]
+    fn test_system_columns() {
+        let query = "SELECT * FROM users WHERE ctid = '(0,1)' AND users.xmin = $1";
+        let ast = parse(query).unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+        if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert!(where_.keys(Some("users"), "ctid").is_empty());
+            assert!(where_.keys(Some("users"), "xmin").is_empty());
+        } else {
+            panic!("not a select");
+        }
+    }
+
+    fn has_column(output: &[Output]) -> bool {
+        output.iter().any(|output| match output {
+            Output::Column(_) | Output::NullCheck(_) | Output::Coalesce { .. } => true,
+            Output::Filter(left, right) => has_column(left) || has_column(right),
+            _ => false,
+        })
+    }
+
+    #[test]
+    fn test_volatile_filter() {
+        for query in [
+            "SELECT * FROM users WHERE tenant_id = $1 AND random() < 0.1",
+            "SELECT * FROM users WHERE random() < 0.1 AND tenant_id = $1",
+            "SELECT * FROM users WHERE tenant_id = $1 AND random() = 0.5",
+            "SELECT * FROM users WHERE tenant_id = $1 AND (random() < 0.1 OR random() > 0.9)",
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false
+                }],
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_parameter_position() {
+        for (query, expected) in [
+            (
+                "SELECT * FROM users WHERE tenant_id = $1",
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false,
+                }],
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id = $3::bigint",
+                vec![Key::Parameter {
+                    pos: 2,
+                    array: false,
+                }],
+            ),
+            // Not a parameter we can look up in Bind.
+            ("SELECT * FROM users WHERE tenant_id = $0", vec![]),
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                expected,
+                "{}",
+                query
+            );
+        }
+
+        // Named parameters aren't Postgres syntax.
+        assert!(parse("SELECT * FROM users WHERE tenant_id = :tenant").is_err());
+    }
+
+    #[test]
+    fn test_inequality() {
+        for (query, expected) in [
+            (
+                "SELECT * FROM users WHERE tenant_id = $1 AND tenant_id > $2",
+                1,
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id >= $2 AND tenant_id = $1",
+                1,
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id < 5 AND tenant_id <= 6",
+                0,
+            ),
+            ("SELECT * FROM users WHERE tenant_id <> $1", 0),
+            ("SELECT * FROM users WHERE tenant_id NOT IN ($1, $2)", 0),
+            ("SELECT * FROM users WHERE tenant_id BETWEEN $1 AND $2", 0),
+            ("SELECT * FROM users WHERE tenant_id IS DISTINCT FROM $1", 0),
+            (
+                "SELECT * FROM users WHERE tenant_id IS NOT DISTINCT FROM $1",
+                1,
+            ),
+            ("SELECT * FROM users WHERE tenant_id LIKE $1", 0),
+            ("SELECT * FROM users WHERE tenant_id = ALL($1)", 0),
+            (
+                "SELECT * FROM users WHERE tenant_id OPERATOR(pg_catalog.=) $1",
+                1,
+            ),
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+            if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+                let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+                let keys = where_.keys(Some("users"), "tenant_id");
+                assert_eq!(keys.len(), expected, "{}", query);
+                if expected == 1 {
+                    assert_eq!(
+                        keys[0],
+                        Key::Parameter {
+                            pos: 0,
+                            array: false
+                        },
+                        "{}",
+                        query
+                    );
+                }
+            } else {
+                panic!("not a select");
+            }
+        }
+    }
+
+    #[test]
+    fn test_star() {
+        for query in [
+            "SELECT * FROM users WHERE users.* = ROW(1, 'a')",
+            "SELECT * FROM users u WHERE u.* IS NOT NULL AND u.* = $1",
+            "SELECT * FROM users WHERE ROW(users.*) = ROW($1, $2)",
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+            if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+                let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+                assert!(!has_column(&where_.output), "{}", query);
+                assert!(
+                    where_.keys(Some("users"), "tenant_id").is_empty(),
+                    "{}",
+                    query
+                );
+                assert!(where_.keys(Some("users"), "*").is_empty(), "{}", query);
+                assert!(where_.keys(Some("u"), "*").is_empty(), "{}", query);
+            } else {
+                panic!("not a select");
+            }
+        }
+    }
+
+    #[test]
+    fn test_trivially_true() {
+        for query in [
+            "SELECT * FROM users WHERE tenant_id = $1 AND 1 = 1",
+            "SELECT * FROM users WHERE tenant_id = $1 AND TRUE",
+            "SELECT * FROM users WHERE 'a' = 'a' AND tenant_id = $1",
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+            if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+                let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+                assert_eq!(where_.output.len(), 1, "{}", query);
+                let keys = where_.keys(Some("users"), "tenant_id");
+                assert_eq!(
+                    keys,
+                    vec![Key::Parameter {
+                        pos: 0,
+                        array: false
+                    }],
+                    "{}",
+                    query
+                );
+            } else {
+                panic!("not a select");
+            }
+        }
+
+        // Not trivially true, so it stays.
+        let query = "SELECT * FROM users WHERE tenant_id = $1 AND 1 = 2";
+        let ast = parse(query).unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+        if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(where_.output.len(), 2);
+        } else {
+            panic!("not a select");
+        }
+    }
+
+    #[test]
+    fn test_exists() {
+        for query in [
+            "SELECT * FROM users WHERE tenant_id = $1 AND EXISTS (SELECT 1 FROM other)",
+            "SELECT * FROM users WHERE NOT EXISTS (SELECT 1 FROM other WHERE tenant_id = 5) AND tenant_id = $1",
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+            if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+                let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+                // The subquery filters rows, it doesn't add outputs.
+                assert_eq!(where_.output.len(), 1, "{}", query);
+                assert_eq!(
+                    where_.keys(Some("users"), "tenant_id"),
+                    vec![Key::Parameter {
+                        pos: 0,
+                        array: false
+                    }],
+                    "{}",
+                    query
+                );
+            } else {
+                panic!("not a select");
+            }
+        }
+    }
+
+    #[test]
+    fn test_quoted_columns() {
+        let mut quoted = ShardedTable {
+            name: Some("\"Users\"".into()),
+            column: "\"TenantId\"".into(),
+            ..Default::default()
+        };
+        quoted.unquote();
+
+        for (query, table, column) in [
+            (
+                r#"SELECT * FROM users WHERE "users"."tenant_id" = $1"#,
+                "users",
+                "tenant_id",
+            ),
+            (
+                r#"SELECT * FROM users WHERE "tenant_id" = $1"#,
+                "users",
+                "tenant_id",
+            ),
+            (
+                r#"SELECT * FROM "Users" WHERE "Users"."TenantId" = $1"#,
+                quoted.name.as_deref().unwrap(),
+                quoted.column.as_str(),
+            ),
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+            if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+                let where_ = WhereClause::new(Some(table), &stmt.where_clause).unwrap();
+                assert_eq!(
+                    where_.keys(Some(table), column),
+                    vec![Key::Parameter {
+                        pos: 0,
+                        array: false
+                    }],
+                    "{}",
+                    query
+                );
+            } else {
+                panic!("not a select");
+            }
+        }
+
+        // Unquoted identifiers are folded to lowercase.
+        let query = r#"SELECT * FROM "Users" WHERE "Users".TenantId = $1"#;
+        let ast = parse(query).unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+        if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+            let where_ = WhereClause::new(Some("Users"), &stmt.where_clause).unwrap();
+            assert!(where_.keys(Some("Users"), "TenantId").is_empty());
+            assert_eq!(where_.keys(Some("Users"), "tenantid").len(), 1);
+        } else {
+            panic!("not a select");
+        }
+    }
+
+    #[test]
+    fn test_disjunctions() {
+        let query = "SELECT * FROM users u WHERE u.tenant_id = $1 OR (customer_key = 5 AND a = 1)";
+        let ast = parse(query).unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+        if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+            let mut where_ = WhereClause::new(None, &stmt.where_clause).unwrap();
+            where_.resolve_aliases(&HashMap::from([("u", "users")]));
+
+            // Any branch can match, so they aren't keys.
+            assert!(where_.keys(Some("users"), "tenant_id").is_empty());
+
+            let disjunctions = where_.disjunctions();
+            assert_eq!(disjunctions.len(), 1);
+            let [left, right] = disjunctions[0].as_slice() else {
+                panic!("expected two branches");
+            };
+            assert_eq!(
+                left.keys(Some("users"), "tenant_id"),
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false
+                }]
+            );
+            assert!(left.keys(Some("users"), "customer_key").is_empty());
+            assert_eq!(
+                right.keys(Some("users"), "customer_key"),
+                vec![Key::Constant {
+                    value: "5".into(),
+                    array: false
+                }]
+            );
+        } else {
+            panic!("not a select");
+        }
+    }
+
+    #[test]
+    fn test_resolve_aliases() {
+        let query = "SELECT * FROM users u, orders o WHERE u.tenant_id = $1 AND o.tenant_id = $2";
+        let ast = parse(query).unwrap();
+        let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+
+        if let Some(NodeEnum::SelectStmt(stmt)) = stmt.node {
+            let mut where_ = WhereClause::new(None, &stmt.where_clause).unwrap();
+            assert!(where_.keys(Some("users"), "tenant_id").is_empty());
+
+            where_.resolve_aliases(&HashMap::from([("u", "users"), ("o", "orders")]));
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false
+                }]
+            );
+            assert_eq!(
+                where_.keys(Some("orders"), "tenant_id"),
+                vec![Key::Parameter {
+                    pos: 1,
+                    array: false
+                }]
+            );
+        } else {
+            panic!("not a select");
+        }
+    }
+
+    #[test]
+    fn test_coercions() {
+        let param = Node {
+            node: Some(NodeEnum::ParamRef(ParamRef {
+                number: 1,
+                ..Default::default()
+            })),
+        };
+        let coercions = [
+            NodeEnum::CoerceViaIo(Box::new(CoerceViaIo {
+                arg: Some(Box::new(param.clone())),
+                ..Default::default()
+            })),
+            NodeEnum::RelabelType(Box::new(RelabelType {
+                arg: Some(Box::new(param.clone())),
+                ..Default::default()
+            })),
+            NodeEnum::CoerceToDomain(Box::new(CoerceToDomain {
+                arg: Some(Box::new(param.clone())),
+                ..Default::default()
+            })),
+            NodeEnum::CollateExpr(Box::new(CollateExpr {
+                arg: Some(Box::new(param.clone())),
+                coll_oid: 950,
+                ..Default::default()
+            })),
+        ];
+
+        for query in [
+            "SELECT * FROM users WHERE tenant_id = $1::tenant_status",
+            "SELECT * FROM users WHERE tenant_id = CAST($1 AS tenant_domain)",
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(mut stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false
+                }],
+                "{}",
+                query
+            );
+
+            for coercion in &coercions {
+                let Some(NodeEnum::AExpr(ref mut expr)) = stmt.where_clause.as_mut().unwrap().node
+                else {
+                    panic!("not an expression");
+                };
+                expr.rexpr = Some(Box::new(Node {
+                    node: Some(coercion.clone()),
+                }));
+
+                let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+                assert_eq!(
+                    where_.keys(Some("users"), "tenant_id"),
+                    vec![Key::Parameter {
+                        pos: 0,
+                        array: false
+                    }],
+                    "{:?}",
+                    coercion
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_reversed_in() {
+        let keys = |query: &str| {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            WhereClause::new(Some("users"), &stmt.where_clause)
+                .unwrap()
+                .keys(Some("users"), "tenant_id")
+        };
+
+        assert_eq!(
+            keys("SELECT * FROM users WHERE $1 IN (tenant_id)"),
+            vec![Key::Parameter {
+                pos: 0,
+                array: false
+            }]
+        );
+        assert_eq!(
+            keys("SELECT * FROM users WHERE 5 IN (users.tenant_id) AND id = 1"),
+            vec![Key::Constant {
+                value: "5".into(),
+                array: false
+            }]
+        );
+
+        // Matches either column, so the key could be in any of them.
+        assert!(keys("SELECT * FROM users WHERE $1 IN (tenant_id, owner_id)").is_empty());
+        assert!(keys("SELECT * FROM users WHERE $1 NOT IN (tenant_id)").is_empty());
+    }
+
+    #[test]
+    fn test_any_array_column() {
+        let keys = |query: &str| {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+            WhereClause::new(Some("groups"), &stmt.where_clause)
+                .unwrap()
+                .keys(Some("groups"), "tenant_ids")
+        };
+
+        assert_eq!(
+            keys("SELECT * FROM groups WHERE $1 = ANY(tenant_ids)"),
+            vec![Key::Element(Box::new(Key::Parameter {
+                pos: 0,
+                array: false
+            }))]
+        );
+        assert_eq!(
+            keys("SELECT * FROM groups WHERE tenant_ids = ANY($1)"),
+            vec![Key::Parameter {
+                pos: 0,
+                array: true
+            }]
+        );
+    }
+
+    #[test]
+    fn test_collate() {
+        for query in [
+            r#"SELECT * FROM users WHERE tenant_id = $1 COLLATE "C""#,
+            r#"SELECT * FROM users WHERE tenant_id COLLATE "C" = $1"#,
+            r#"SELECT * FROM users WHERE users.tenant_id COLLATE pg_catalog."default" = $1::text COLLATE "C""#,
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false
+                }],
+                "{}",
+                query
+            );
+        }
+
+        // Nondeterministic collations can match values that hash elsewhere.
+        for query in [
+            r#"SELECT * FROM users WHERE tenant_id COLLATE "und-x-icu" = $1"#,
+            r#"SELECT * FROM users WHERE tenant_id = $1 COLLATE case_insensitive"#,
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert!(
+                where_.keys(Some("users"), "tenant_id").is_empty(),
+                "{}",
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn test_double_negation() {
+        for (query, keyed) in [
+            ("SELECT * FROM users WHERE NOT NOT (tenant_id = $1)", true),
+            (
+                "SELECT * FROM users WHERE NOT NOT NOT NOT tenant_id = $1",
+                true,
+            ),
+            ("SELECT * FROM users WHERE NOT (tenant_id = $1)", false),
+            (
+                "SELECT * FROM users WHERE NOT NOT NOT (tenant_id = $1)",
+                false,
+            ),
+            (
+                "SELECT * FROM users WHERE NOT NOT (tenant_id = $1) AND NOT (id = 2)",
+                true,
+            ),
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            let keys = where_.keys(Some("users"), "tenant_id");
+            if keyed {
+                assert_eq!(
+                    keys,
+                    vec![Key::Parameter {
+                        pos: 0,
+                        array: false
+                    }],
+                    "{}",
+                    query
+                );
+            } else {
+                assert!(keys.is_empty(), "{}", query);
+            }
+        }
+    }
+
+    #[test]
+    fn test_duplicate_keys() {
+        for (query, expected) in [
+            (
+                "SELECT * FROM users WHERE tenant_id = $1 AND tenant_id = $1",
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false,
+                }],
+            ),
+            (
+                "SELECT * FROM users WHERE (tenant_id = $1 OR tenant_id = $1) AND $1 = tenant_id",
+                vec![Key::Parameter {
+                    pos: 0,
+                    array: false,
+                }],
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id = 1 AND tenant_id = 1",
+                vec![Key::Constant {
+                    value: "1".into(),
+                    array: false,
+                }],
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id = $1 AND tenant_id = $2",
+                vec![
+                    Key::Parameter {
+                        pos: 0,
+                        array: false,
+                    },
+                    Key::Parameter {
+                        pos: 1,
+                        array: false,
+                    },
+                ],
+            ),
+            (
+                "SELECT * FROM users WHERE tenant_id = $1 AND tenant_id = ANY($1)",
+                vec![
+                    Key::Parameter {
+                        pos: 0,
+                        array: false,
+                    },
+                    Key::Parameter {
+                        pos: 0,
+                        array: true,
+                    },
+                ],
+            ),
+        ] {
+            let ast = parse(query).unwrap();
+            let stmt = ast.protobuf.stmts.first().cloned().unwrap().stmt.unwrap();
+            let Some(NodeEnum::SelectStmt(stmt)) = stmt.node else {
+                panic!("not a select");
+            };
+
+            let where_ = WhereClause::new(Some("users"), &stmt.where_clause).unwrap();
+            assert_eq!(
+                where_.keys(Some("users"), "tenant_id"),
+                expected,
+                "{}",
+                query
+            );
+        }
+    }
 }
@@ -30,7 +30,10 @@ impl<'a> ContextBuilder<'a> {
             value: None,
             hasher: match table.hasher {
                 HasherConfig::Sha1 => Hasher::Sha1,
-                HasherConfig::Postgres => Hasher::Postgres,
+                HasherConfig::Postgres
+                | HasherConfig::Int8Ops
+                | HasherConfig::TextOps
+                | HasherConfig::UuidOps => Hasher::Postgres,
             },
             ranges: Ranges::new(&table.mapping),
             lists: Lists::new(&table.mapping),
@@ -7,6 +7,7 @@ use crate::{
 };
 
 // pub mod context;
+pub mod composite;
 pub mod context;
 pub mod context_builder;
 pub mod error;
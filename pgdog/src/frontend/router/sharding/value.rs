@@ -81,11 +81,7 @@ impl<'a> Value<'a> {
 
     pub fn valid(&self) -> bool {
         match self.data_type {
-            DataType::Bigint => match self.data {
-                Data::Text(text) => text.parse::<i64>().is_ok(),
-                Data::Binary(data) => [2, 4, 8].contains(&data.len()),
-                Data::Integer(_) => true,
-            },
+            DataType::Bigint => Self::bigint(&self.data).is_ok(),
             DataType::Uuid => match self.data {
                 Data::Text(text) => Uuid::from_str(text).is_ok(),
                 Data::Binary(data) => data.len() == 16,
@@ -102,21 +98,30 @@ impl<'a> Value<'a> {
 
     pub fn integer(&self) -> Result<Option<i64>, Error> {
         if self.data_type == DataType::Bigint {
-            match self.data {
-                Data::Integer(int) => Ok(Some(int)),
-                Data::Text(text) => Ok(Some(text.parse()?)),
-                Data::Binary(data) => match data.len() {
-                    2 => Ok(Some(i16::from_be_bytes(data.try_into()?) as i64)),
-                    4 => Ok(Some(i32::from_be_bytes(data.try_into()?) as i64)),
-                    8 => Ok(Some(i64::from_be_bytes(data.try_into()?))),
-                    _ => return Err(Error::IntegerSize),
-                },
-            }
+            Ok(Some(Self::bigint(&self.data)?))
         } else {
             Ok(None)
         }
     }
 
+    /// Decode an integer of any width, e.g. `$1::int2` or `$1::int8`,
+    /// so the same number always hashes the same way.
+    fn bigint(data: &Data) -> Result<i64, Error> {
+        match *data {
+            Data::Integer(int) => Ok(int),
+            // Postgres allows whitespace around integers.
+            Data::Text(text) => Ok(text.trim().parse()?),
+            Data::Binary(data) => match data.len() {
+                // "char"
+                1 => Ok(i8::from_be_bytes(data.try_into()?) as i64),
+                2 => Ok(i16::from_be_bytes(data.try_into()?) as i64),
+                4 => Ok(i32::from_be_bytes(data.try_into()?) as i64),
+                8 => Ok(i64::from_be_bytes(data.try_into()?)),
+                _ => Err(Error::IntegerSize),
+            },
+        }
+    }
+
     pub fn varchar(&self) -> Result<Option<&str>, Error> {
         if self.data_type == DataType::Varchar {
             match self.data {
@@ -131,16 +136,7 @@ impl<'a> Value<'a> {
 
     pub fn hash(&self, hasher: Hasher) -> Result<Option<u64>, Error> {
         match self.data_type {
-            DataType::Bigint => match self.data {
-                Data::Text(text) => Ok(Some(hasher.bigint(text.parse()?))),
-                Data::Binary(data) => Ok(Some(hasher.bigint(match data.len() {
-                    2 => i16::from_be_bytes(data.try_into()?) as i64,
-                    4 => i32::from_be_bytes(data.try_into()?) as i64,
-                    8 => i64::from_be_bytes(data.try_into()?),
-                    _ => return Err(Error::IntegerSize),
-                }))),
-                Data::Integer(int) => Ok(Some(hasher.bigint(int))),
-            },
+            DataType::Bigint => Ok(Some(hasher.bigint(Self::bigint(&self.data)?))),
 
             DataType::Uuid => match self.data {
                 Data::Text(text) => Ok(Some(hasher.uuid(Uuid::from_str(text)?))),
@@ -157,3 +153,40 @@ impl<'a> Value<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_integer_widths() {
+        let (int1, int2, int4, int8) = (
+            (-5_i8).to_be_bytes(),
+            (-5_i16).to_be_bytes(),
+            (-5_i32).to_be_bytes(),
+            (-5_i64).to_be_bytes(),
+        );
+        let values = [
+            Value::new(Data::Integer(-5), DataType::Bigint),
+            Value::new("-5", DataType::Bigint),
+            Value::new(" -5 ", DataType::Bigint),
+            Value::new(&int1[..], DataType::Bigint),
+            Value::new(&int2[..], DataType::Bigint),
+            Value::new(&int4[..], DataType::Bigint),
+            Value::new(&int8[..], DataType::Bigint),
+        ];
+
+        for value in &values {
+            assert!(value.valid());
+            assert_eq!(value.integer().unwrap(), Some(-5));
+            assert_eq!(
+                value.hash(Hasher::Postgres).unwrap(),
+                values[0].hash(Hasher::Postgres).unwrap()
+            );
+        }
+
+        let value = Value::new(&[0_u8, 0, 5][..], DataType::Bigint);
+        assert!(!value.valid());
+        assert!(matches!(value.integer(), Err(Error::IntegerSize)));
+    }
+}
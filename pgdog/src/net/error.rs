@@ -64,6 +64,9 @@ pub enum Error {
     #[error("not a uuid")]
     NotUuid(#[from] uuid::Error),
 
+    #[error("not a bytea")]
+    NotBytea,
+
     #[error("not a timestamptz")]
     NotTimestampTz,
 
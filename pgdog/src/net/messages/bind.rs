@@ -83,6 +83,15 @@ impl<'a> ParameterWithFormat<'a> {
         Self::decode(self)
     }
 
+    /// The parameter is an array without elements, e.g. `{}`.
+    pub fn empty_array(&self) -> bool {
+        match self.format {
+            Format::Text => self.text().is_some_and(|text| text.trim() == "{}"),
+            // Number of dimensions is the first field.
+            Format::Binary => self.parameter.data.get(0..4) == Some(&[0, 0, 0, 0][..]),
+        }
+    }
+
     /// Get decoded value.
     pub fn decode<T: FromDataType>(&self) -> Option<T> {
         T::decode(&self.parameter.data, self.format).ok()
@@ -160,6 +169,11 @@ impl Bind {
             .map(|parameter| ParameterWithFormat { parameter, format }))
     }
 
+    /// Number of parameters.
+    pub(crate) fn params_len(&self) -> usize {
+        self.params.len()
+    }
+
     /// Rename this Bind message to a different prepared statement.
     pub fn rename(mut self, name: impl ToString) -> Self {
         self.statement = Bytes::from(name.to_string() + "\0");
@@ -182,6 +196,11 @@ impl Bind {
     pub fn codes(&self) -> &[Format] {
         &self.codes
     }
+
+    /// Result column format codes, if any.
+    pub fn results(&self) -> &[i16] {
+        &self.results
+    }
 }
 
 #[cfg(test)]
@@ -51,6 +51,10 @@ impl Data {
             is_null: true,
         }
     }
+
+    pub fn is_null(&self) -> bool {
+        self.is_null
+    }
 }
 
 /// DataRow message.
@@ -159,6 +163,11 @@ impl DataRow {
         self
     }
 
+    /// Remove the last column.
+    pub fn pop(&mut self) -> Option<Data> {
+        self.columns.pop()
+    }
+
     /// Create data row from columns.
     pub fn from_columns(columns: Vec<impl ToDataRowColumn>) -> Self {
         let mut dr = Self::new();
@@ -174,6 +183,13 @@ impl DataRow {
         self.columns.get(index).cloned().map(|d| d.data)
     }
 
+    /// Column at index is NULL.
+    pub fn is_null(&self, index: usize) -> bool {
+        self.columns
+            .get(index)
+            .is_some_and(|column| column.is_null())
+    }
+
     /// Get integer at index with text/binary encoding.
     pub fn get_int(&self, index: usize, text: bool) -> Option<i64> {
         self.get::<i64>(index, if text { Format::Text } else { Format::Binary })
@@ -0,0 +1,109 @@
+use super::*;
+
+/// BYTEA, compared byte by byte like Postgres does.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Eq, Ord, Hash, Default)]
+pub struct Bytea(pub Bytes);
+
+impl FromDataType for Bytea {
+    fn decode(bytes: &[u8], encoding: Format) -> Result<Self, Error> {
+        match encoding {
+            Format::Binary => Ok(Self(Bytes::copy_from_slice(bytes))),
+
+            // Hex, e.g. `\x0aff`, or escape, e.g. `a\012\\`, depending on `bytea_output`.
+            Format::Text => {
+                if let Some(hex) = bytes.strip_prefix(b"\\x") {
+                    if hex.len() % 2 != 0 {
+                        return Err(Error::NotBytea);
+                    }
+                    let decoded = hex
+                        .chunks(2)
+                        .map(|pair| Ok(hex_digit(pair[0])? << 4 | hex_digit(pair[1])?))
+                        .collect::<Result<Vec<u8>, Error>>()?;
+                    Ok(Self(decoded.into()))
+                } else {
+                    let mut decoded = Vec::with_capacity(bytes.len());
+                    let mut iter = bytes.iter();
+                    while let Some(byte) = iter.next() {
+                        if *byte != b'\\' {
+                            decoded.push(*byte);
+                            continue;
+                        }
+                        match iter.next() {
+                            Some(b'\\') => decoded.push(b'\\'),
+                            Some(first) => {
+                                let octal = [Some(first), iter.next(), iter.next()]
+                                    .into_iter()
+                                    .try_fold(0u16, |acc, digit| match digit {
+                                        Some(digit @ b'0'..=b'7') => {
+                                            Some(acc * 8 + (digit - b'0') as u16)
+                                        }
+                                        _ => None,
+                                    })
+                                    .filter(|octal| *octal <= u8::MAX as u16)
+                                    .ok_or(Error::NotBytea)?;
+                                decoded.push(octal as u8);
+                            }
+                            None => return Err(Error::NotBytea),
+                        }
+                    }
+                    Ok(Self(decoded.into()))
+                }
+            }
+        }
+    }
+
+    fn encode(&self, encoding: Format) -> Result<Bytes, Error> {
+        match encoding {
+            Format::Binary => Ok(self.0.clone()),
+            Format::Text => {
+                let mut hex = String::with_capacity(2 + self.0.len() * 2);
+                hex.push_str("\\x");
+                for byte in self.0.iter() {
+                    hex.push_str(&format!("{:02x}", byte));
+                }
+                Ok(Bytes::from(hex))
+            }
+        }
+    }
+}
+
+fn hex_digit(digit: u8) -> Result<u8, Error> {
+    match digit {
+        b'0'..=b'9' => Ok(digit - b'0'),
+        b'a'..=b'f' => Ok(digit - b'a' + 10),
+        b'A'..=b'F' => Ok(digit - b'A' + 10),
+        _ => Err(Error::NotBytea),
+    }
+}
+
+impl ToDataRowColumn for Bytea {
+    fn to_data_row_column(&self) -> Data {
+        self.encode(Format::Text).unwrap().into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bytea() {
+        let bytes = Bytea(Bytes::from_static(&[0x00, 0x0a, 0xff, b'\\']));
+
+        assert_eq!(Bytea::decode(b"\\x000aff5c", Format::Text).unwrap(), bytes);
+        assert_eq!(Bytea::decode(b"\\x000AFF5C", Format::Text).unwrap(), bytes);
+        assert_eq!(
+            Bytea::decode(b"\\000\\012\\377\\\\", Format::Text).unwrap(),
+            bytes
+        );
+        assert_eq!(
+            Bytea::decode(&[0x00, 0x0a, 0xff, b'\\'], Format::Binary).unwrap(),
+            bytes
+        );
+        assert_eq!(bytes.encode(Format::Text).unwrap(), "\\x000aff5c");
+
+        for invalid in ["\\x0", "\\xzz", "\\400", "\\01", "a\\"] {
+            assert!(Bytea::decode(invalid.as_bytes(), Format::Text).is_err());
+        }
+    }
+}
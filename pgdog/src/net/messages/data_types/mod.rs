@@ -6,6 +6,7 @@ use bytes::Bytes;
 
 pub mod array;
 pub mod bigint;
+pub mod bytea;
 pub mod integer;
 pub mod interval;
 pub mod numeric;
@@ -15,6 +16,7 @@ pub mod timestamptz;
 pub mod uuid;
 pub mod vector;
 
+pub use bytea::Bytea;
 pub use interval::Interval;
 pub use numeric::Numeric;
 pub use timestamp::Timestamp;
@@ -48,6 +50,8 @@ pub enum Datum {
     Numeric(Numeric),
     /// Vector
     Vector(Vector),
+    /// BYTEA.
+    Bytea(Bytea),
     /// We don't know.
     Unknown(Bytes),
     /// NULL.
@@ -69,6 +73,7 @@ impl ToDataRowColumn for Datum {
             Uuid(uuid) => uuid.to_data_row_column(),
             Numeric(num) => num.to_data_row_column(),
             Vector(vector) => vector.to_data_row_column(),
+            Bytea(bytea) => bytea.to_data_row_column(),
             Unknown(bytes) => bytes.clone().into(),
             Null => Data::null(),
         }
@@ -103,6 +108,7 @@ impl Datum {
         match data_type {
             DataType::Bigint => Ok(Datum::Bigint(i64::decode(bytes, encoding)?)),
             DataType::Integer => Ok(Datum::Integer(i32::decode(bytes, encoding)?)),
+            DataType::SmallInt => Ok(Datum::SmallInt(i16::decode(bytes, encoding)?)),
             DataType::Text => Ok(Datum::Text(String::decode(bytes, encoding)?)),
             DataType::Interval => Ok(Datum::Interval(Interval::decode(bytes, encoding)?)),
             DataType::Numeric | DataType::DoublePrecision | DataType::Real => {
@@ -112,6 +118,7 @@ impl Datum {
             DataType::Timestamp => Ok(Datum::Timestamp(Timestamp::decode(bytes, encoding)?)),
             DataType::TimestampTz => Ok(Datum::TimestampTz(TimestampTz::decode(bytes, encoding)?)),
             DataType::Vector => Ok(Datum::Vector(Vector::decode(bytes, encoding)?)),
+            DataType::Bytea => Ok(Datum::Bytea(Bytea::decode(bytes, encoding)?)),
             _ => Ok(Datum::Unknown(Bytes::copy_from_slice(bytes))),
         }
     }
@@ -126,6 +133,7 @@ impl Datum {
             Datum::Integer(i) => i.encode(format),
             Datum::Uuid(uuid) => uuid.encode(format),
             Datum::Text(s) => s.encode(format),
+            Datum::Bytea(bytea) => bytea.encode(format),
             _ => Err(Error::UnexpectedPayload),
         }
     }
@@ -149,4 +157,5 @@ pub enum DataType {
     Other(i32),
     Uuid,
     Vector,
+    Bytea,
 }
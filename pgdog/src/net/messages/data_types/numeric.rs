@@ -86,6 +86,44 @@ impl Ord for Numeric {
 
 impl Eq for Numeric {}
 
+impl Numeric {
+    /// Decode a NUMERIC column. In binary, it's a list of base-10000 digits,
+    /// not a float like REAL and DOUBLE PRECISION.
+    pub fn decode_numeric(mut bytes: &[u8], encoding: Format) -> Result<Self, Error> {
+        if encoding == Format::Text || bytes.len() < 8 {
+            return Self::decode(bytes, encoding);
+        }
+
+        let ndigits = bytes.get_i16();
+        let weight = bytes.get_i16() as i32;
+        let sign = bytes.get_u16();
+        let _dscale = bytes.get_u16();
+
+        if bytes.len() != ndigits.max(0) as usize * 2 {
+            return Err(Error::WrongSizeBinary(bytes.len()));
+        }
+
+        let data = match sign {
+            0xC000 => f64::NAN,
+            0xD000 => f64::INFINITY,
+            0xF000 => f64::NEG_INFINITY,
+            _ => {
+                let mut data = 0.0;
+                for i in 0..ndigits as i32 {
+                    data += bytes.get_i16() as f64 * 10_000_f64.powi(weight - i);
+                }
+                if sign == 0x4000 {
+                    -data
+                } else {
+                    data
+                }
+            }
+        };
+
+        Ok(Self { data })
+    }
+}
+
 impl FromDataType for Numeric {
     fn decode(mut bytes: &[u8], encoding: Format) -> Result<Self, Error> {
         match encoding {
@@ -171,3 +209,26 @@ impl Serialize for Numeric {
         serializer.serialize_f64(self.data)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode_numeric() {
+        // 12345.678: digits 1, 2345, 6780 with the first one in the 10000s.
+        let mut binary = vec![];
+        for part in [3_i16, 1, 0, 3, 1, 2345, 6780] {
+            binary.extend(part.to_be_bytes());
+        }
+        let numeric = Numeric::decode_numeric(&binary, Format::Binary).unwrap();
+        assert!((*numeric - 12345.678).abs() < 1e-9);
+
+        binary[4..6].copy_from_slice(&0x4000_u16.to_be_bytes());
+        let numeric = Numeric::decode_numeric(&binary, Format::Binary).unwrap();
+        assert!((*numeric + 12345.678).abs() < 1e-9);
+
+        let numeric = Numeric::decode_numeric(b"-0.5", Format::Text).unwrap();
+        assert_eq!(*numeric, -0.5);
+    }
+}
@@ -64,6 +64,12 @@ impl FromDataType for Timestamp {
                 }
 
                 if let Some(time) = time {
+                    // The offset, e.g. "+00" or "-05:30", follows the time.
+                    let (time, offset) = match time.find(['+', '-']) {
+                        Some(pos) => (&time[..pos], Some(&time[pos..])),
+                        None => (time, None),
+                    };
+
                     let mut parts = time.split(":");
                     assign!(result, hour, parts);
                     assign!(result, minute, parts);
@@ -71,18 +77,21 @@ impl FromDataType for Timestamp {
                     if let Some(seconds) = parts.next() {
                         let mut parts = seconds.split(".");
                         assign!(result, second, parts);
-                        let micros = parts.next();
-                        if let Some(micros) = micros {
-                            let neg = micros.find('-').is_some();
-                            let mut parts = micros.split(&['-', '+']);
-                            assign!(result, micros, parts);
-                            if let Some(offset) = parts.next() {
-                                let offset: i8 = bigint(offset)?.try_into().unwrap();
-                                let offset = if neg { -offset } else { offset };
-                                result.offset = Some(offset);
-                            }
+                        if let Some(fraction) = parts.next() {
+                            // ".5" is 500000 microseconds.
+                            let micros = format!("{:0<6.6}", fraction);
+                            result.micros = bigint(&micros)?.try_into().unwrap();
                         }
-                        assign!(result, micros, parts);
+                    }
+
+                    if let Some(offset) = offset {
+                        let hours = offset[1..].split(":").next().unwrap_or_default();
+                        let hours: i8 = bigint(hours)?.try_into().unwrap();
+                        result.offset = Some(if offset.starts_with('-') {
+                            -hours
+                        } else {
+                            hours
+                        });
                     }
                 }
 
@@ -116,5 +125,19 @@ mod test {
         assert_eq!(ts.minute, 51);
         assert_eq!(ts.second, 42);
         assert_eq!(ts.micros, 798425);
+        assert_eq!(ts.offset, None);
+
+        let ts = Timestamp::decode(b"2025-03-05 14:51:42.5", Format::Text).unwrap();
+        assert_eq!(ts.second, 42);
+        assert_eq!(ts.micros, 500000);
+
+        let ts = Timestamp::decode(b"2025-03-05 14:51:42-05", Format::Text).unwrap();
+        assert_eq!(ts.second, 42);
+        assert_eq!(ts.micros, 0);
+        assert_eq!(ts.offset, Some(-5));
+
+        let ts = Timestamp::decode(b"2025-03-05 14:51:42.25+02", Format::Text).unwrap();
+        assert_eq!(ts.micros, 250000);
+        assert_eq!(ts.offset, Some(2));
     }
 }
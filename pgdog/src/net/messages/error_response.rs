@@ -128,6 +128,51 @@ impl ErrorResponse {
         }
     }
 
+    /// Cursor wasn't declared or was closed already.
+    pub fn no_cursor(name: &str) -> Self {
+        Self {
+            severity: "ERROR".into(),
+            code: "34000".into(),
+            message: format!("cursor \"{}\" does not exist", name),
+            ..Default::default()
+        }
+    }
+
+    /// Cursors without `WITH HOLD` need a transaction.
+    pub fn cursor_outside_transaction() -> Self {
+        Self {
+            severity: "ERROR".into(),
+            code: "25P01".into(),
+            message: "DECLARE CURSOR can only be used in transaction blocks".into(),
+            ..Default::default()
+        }
+    }
+
+    /// Some shards of a cross-shard read failed,
+    /// so the client gets rows from the other shards only.
+    pub fn partial_result(failed: &[(usize, ErrorResponse)]) -> Self {
+        let shards = failed
+            .iter()
+            .map(|(shard, _)| shard.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let errors = failed
+            .iter()
+            .map(|(shard, error)| format!("shard {}: {}", shard, error))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Self {
+            severity: "WARNING".into(),
+            code: "01000".into(),
+            message: format!(
+                "shards {} failed, returning rows from the other shards",
+                shards
+            ),
+            detail: Some(errors),
+            ..Default::default()
+        }
+    }
+
     pub fn no_transaction() -> Self {
         Self {
             severity: "WARNING".into(),
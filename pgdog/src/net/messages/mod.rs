@@ -2,6 +2,7 @@
 pub mod auth;
 pub mod backend_key;
 pub mod bind;
+pub mod bind_complete;
 pub mod close;
 pub mod close_complete;
 pub mod command_complete;
@@ -16,6 +17,7 @@ pub mod error_response;
 pub mod execute;
 pub mod flush;
 pub mod hello;
+pub mod no_data;
 pub mod notice_response;
 pub mod parameter_description;
 pub mod parameter_status;
@@ -33,6 +35,7 @@ pub mod terminate;
 pub use auth::{Authentication, Password};
 pub use backend_key::BackendKeyData;
 pub use bind::{Bind, Format, Parameter, ParameterWithFormat};
+pub use bind_complete::BindComplete;
 pub use close::Close;
 pub use close_complete::CloseComplete;
 pub use command_complete::CommandComplete;
@@ -47,6 +50,7 @@ pub use error_response::ErrorResponse;
 pub use execute::Execute;
 pub use flush::Flush;
 pub use hello::Startup;
+pub use no_data::NoData;
 pub use notice_response::NoticeResponse;
 pub use parameter_description::ParameterDescription;
 pub use parameter_status::ParameterStatus;
@@ -123,7 +127,7 @@ impl std::fmt::Debug for Message {
                 Source::Backend => ParameterStatus::from_bytes(self.payload()).unwrap().fmt(f),
             },
             '1' => ParseComplete::from_bytes(self.payload()).unwrap().fmt(f),
-            '2' => f.debug_struct("BindComplete").finish(),
+            '2' => BindComplete::from_bytes(self.payload()).unwrap().fmt(f),
             '3' => f.debug_struct("CloseComplete").finish(),
             'E' => match self.source {
                 Source::Frontend => f.debug_struct("Execute").finish(),
@@ -0,0 +1,27 @@
+//! NoData (B) message.
+use super::code;
+use super::prelude::*;
+
+#[derive(Debug, Clone)]
+pub struct NoData;
+
+impl FromBytes for NoData {
+    fn from_bytes(mut bytes: Bytes) -> Result<Self, Error> {
+        code!(bytes, 'n');
+        let _len = bytes.get_i32();
+        Ok(Self)
+    }
+}
+
+impl ToBytes for NoData {
+    fn to_bytes(&self) -> Result<Bytes, Error> {
+        let payload = Payload::named(self.code());
+        Ok(payload.freeze())
+    }
+}
+
+impl Protocol for NoData {
+    fn code(&self) -> char {
+        'n'
+    }
+}
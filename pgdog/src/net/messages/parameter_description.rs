@@ -6,6 +6,13 @@ pub struct ParameterDescription {
     params: Vec<i32>,
 }
 
+impl ParameterDescription {
+    /// Describe parameters with the given type OIDs.
+    pub fn new(params: Vec<i32>) -> Self {
+        Self { params }
+    }
+}
+
 impl FromBytes for ParameterDescription {
     fn from_bytes(mut bytes: Bytes) -> Result<Self, Error> {
         code!(bytes, 't');
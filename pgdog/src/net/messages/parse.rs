@@ -86,6 +86,14 @@ impl Parse {
         parse
     }
 
+    /// Same prepared statement with a different query.
+    pub fn with_query(&self, query: &str) -> Parse {
+        let mut parse = self.clone();
+        parse.query = Bytes::from(query.to_string() + "\0");
+        parse.original = None;
+        parse
+    }
+
     pub fn data_types(&self) -> DataTypesIter<'_> {
         DataTypesIter {
             data_types: &self.data_types,
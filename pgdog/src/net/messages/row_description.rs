@@ -8,6 +8,10 @@ use crate::net::c_string_buf;
 use super::{code, DataType};
 use super::{prelude::*, Format};
 
+/// OIDs below this one are assigned to built-in objects
+/// (`FirstNormalObjectId` in `transam.h`).
+const FIRST_NORMAL_OID: i32 = 16384;
+
 /// Column field description.
 #[derive(Clone, Debug, PartialEq)]
 pub struct Field {
@@ -67,6 +71,19 @@ impl Field {
         }
     }
 
+    /// Integer field.
+    pub fn integer(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            table_oid: 0,
+            column: 0,
+            type_oid: 23,
+            type_size: 4,
+            type_modifier: -1,
+            format: 0, // We always use text format.
+        }
+    }
+
     pub fn bigint(name: &str) -> Self {
         Self {
             name: name.into(),
@@ -79,11 +96,25 @@ impl Field {
         }
     }
 
+    /// Double precision field.
+    pub fn double(name: &str) -> Self {
+        Self {
+            name: name.into(),
+            table_oid: 0,
+            column: 0,
+            type_oid: 701,
+            type_size: 8,
+            type_modifier: -1,
+            format: 0, // We always use text format.
+        }
+    }
+
     /// Get the column data type.
     #[inline]
     pub fn data_type(&self) -> DataType {
         match self.type_oid {
             16 => DataType::Bool,
+            17 => DataType::Bytea,
             20 => DataType::Bigint,
             23 => DataType::Integer,
             21 => DataType::SmallInt,
@@ -141,6 +172,9 @@ impl RowDescription {
     }
 
     /// Check if the two row descriptions are materially the same.
+    ///
+    /// Types created by users, e.g. enums, get different OIDs
+    /// in each database, so only built-in types are compared.
     pub fn equivalent(&self, other: &RowDescription) -> bool {
         if self.fields.len() != other.fields.len() {
             return false;
@@ -151,7 +185,8 @@ impl RowDescription {
                 return false;
             }
 
-            if a.type_oid != b.type_oid {
+            let builtin = a.type_oid < FIRST_NORMAL_OID && b.type_oid < FIRST_NORMAL_OID;
+            if builtin && a.type_oid != b.type_oid {
                 return false;
             }
         }